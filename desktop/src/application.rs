@@ -25,6 +25,7 @@ pub enum Message {
 
     // Audio manager
     MuteInput(bool),
+    DeafenOutput(bool),
 
     // Voice client message bus
     ExecuteVoiceCommand(VoiceCommand),
@@ -35,6 +36,9 @@ pub enum Message {
     // Keyboard events
     KeyPressed(iced::keyboard::Key),
 
+    // File dropped on the window, attached to the next chat message
+    FileDropped(std::path::PathBuf),
+
     // Config persistence
     PeriodicConfigSave,
     WindowCloseRequested(iced::window::Id),
@@ -114,13 +118,11 @@ impl Application {
 
     pub fn subscription(&self) -> Subscription<Message> {
         Subscription::batch([
-            // Keyboard events
-            iced::event::listen().filter_map(|event| {
-                if let iced::Event::Keyboard(iced::keyboard::Event::KeyPressed { key, .. }) = event {
-                    Some(Message::KeyPressed(key))
-                } else {
-                    None
-                }
+            // Keyboard events and dropped files
+            iced::event::listen().filter_map(|event| match event {
+                iced::Event::Keyboard(iced::keyboard::Event::KeyPressed { key, .. }) => Some(Message::KeyPressed(key)),
+                iced::Event::Window(iced::window::Event::FileDropped(path)) => Some(Message::FileDropped(path)),
+                _ => None,
             }),
             iced::time::every(Duration::from_secs(5)).map(|_| Message::ExecuteVoiceCommand(VoiceCommand::Ping)),
             iced::time::every(Duration::from_millis(500)).map(|_| Message::ExecuteVoiceCommand(VoiceCommand::GetVoiceStats)),
@@ -141,6 +143,7 @@ impl Application {
                         management_addr: format!("{}:9001", config.server.address),
                         voice_addr: format!("{}:9002", config.server.address),
                         username: config.server.username.clone(),
+                        password: None,
                     }
                 )
             )
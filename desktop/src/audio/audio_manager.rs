@@ -1,4 +1,5 @@
 use cpal::Stream;
+use cpal::traits::StreamTrait;
 use std::sync::{Arc};
 use std::sync::atomic::{AtomicBool, Ordering};
 use arc_swap::ArcSwap;
@@ -21,6 +22,7 @@ pub struct AudioManager {
     input_receiver_task: Option<JoinHandle<()>>,
     output_streams: std::collections::HashMap<u64, Stream>,
     is_input_muted: Arc<AtomicBool>,
+    is_output_deafened: bool,
     notification_player: Option<Arc<NotificationPlayer>>,
     notification_stream: Option<Stream>,
 }
@@ -35,6 +37,7 @@ impl AudioManager {
             input_receiver_task: None,
             output_streams: std::collections::HashMap::new(),
             is_input_muted: Arc::new(AtomicBool::new(false)),
+            is_output_deafened: false,
             notification_player: None,
             notification_stream: None,
         }
@@ -120,6 +123,10 @@ impl AudioManager {
         let output_handle = create_output_stream(config.audio.output_device.clone(), audio_source)?;
         info!("Created output stream for user {} at {} Hz", user_id, config.audio.output_device.sample_rate.clone());
 
+        if self.is_output_deafened {
+            output_handle.pause()?;
+        }
+
         // Store the output handle
         self.output_streams.insert(user_id, output_handle);
 
@@ -191,4 +198,26 @@ impl AudioManager {
         self.is_input_muted.store(false, Ordering::Relaxed);
         info!("Input unmuted");
     }
+
+    /// Pause playback of all users, streams created while deafened start paused.
+    /// Notifications keep playing.
+    pub fn deafen_output(&mut self) {
+        self.is_output_deafened = true;
+        for (user_id, stream) in &self.output_streams {
+            if let Err(e) = stream.pause() {
+                error!("Failed to pause output stream for user {}: {}", user_id, e);
+            }
+        }
+        info!("Output deafened");
+    }
+
+    pub fn undeafen_output(&mut self) {
+        self.is_output_deafened = false;
+        for (user_id, stream) in &self.output_streams {
+            if let Err(e) = stream.play() {
+                error!("Failed to resume output stream for user {}: {}", user_id, e);
+            }
+        }
+        info!("Output undeafened");
+    }
 }
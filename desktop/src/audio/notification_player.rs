@@ -44,6 +44,7 @@ impl NotificationPlayer {
             ("leave_voice", include_bytes!("../../resources/sounds/leave_voice.wav")),
             ("mute", include_bytes!("../../resources/sounds/mute.wav")),
             ("unmute", include_bytes!("../../resources/sounds/unmute.wav")),
+            ("mention", include_bytes!("../../resources/sounds/mention.wav")),
         ];
 
         let mut sounds = HashMap::new();
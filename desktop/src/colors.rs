@@ -20,6 +20,10 @@ pub fn text_secondary() -> Color {
     Color::from_rgb8(76, 76, 76)
 }
 
+pub fn text_link() -> Color {
+    Color::from_rgb8(88, 166, 255)
+}
+
 // Status colors
 pub fn color_error() -> Color {
     Color::from_rgb8(228, 66, 69)
@@ -42,6 +46,10 @@ pub fn slider_thumb() -> Color {
     Color::from_rgb8(242, 242, 242)
 }
 
+pub fn mention_bg() -> Color {
+    Color::from_rgba8(250, 166, 26, 0.1)
+}
+
 pub fn text_selection() -> Color {
     Color::from_rgba8(242, 242, 242, 0.1)
 }
@@ -17,10 +17,50 @@ impl Icons {
         Self::icon_fill('\u{E328}', Some(color), size)
     }
 
+    pub fn speaker_high_fill<'a, Message>(color: Color, size: u16) -> Element<'a, Message> {
+        Self::icon_fill('\u{E44A}', Some(color), size)
+    }
+
+    pub fn speaker_slash_fill<'a, Message>(color: Color, size: u16) -> Element<'a, Message> {
+        Self::icon_fill('\u{E45A}', Some(color), size)
+    }
+
     pub fn chat_teardrop_dots_fill<'a, Message>(color: Color, size: u16) -> Element<'a, Message> {
         Self::icon_fill('\u{E176}', Some(color), size)
     }
 
+    pub fn arrow_bend_up_left_fill<'a, Message>(color: Color, size: u16) -> Element<'a, Message> {
+        Self::icon_fill('\u{E024}', Some(color), size)
+    }
+
+    pub fn pencil_simple_fill<'a, Message>(color: Color, size: u16) -> Element<'a, Message> {
+        Self::icon_fill('\u{E3B4}', Some(color), size)
+    }
+
+    pub fn trash_fill<'a, Message>(color: Color, size: u16) -> Element<'a, Message> {
+        Self::icon_fill('\u{E4A6}', Some(color), size)
+    }
+
+    pub fn x_circle_fill<'a, Message>(color: Color, size: u16) -> Element<'a, Message> {
+        Self::icon_fill('\u{E4F8}', Some(color), size)
+    }
+
+    pub fn envelope_simple_fill<'a, Message>(color: Color, size: u16) -> Element<'a, Message> {
+        Self::icon_fill('\u{E218}', Some(color), size)
+    }
+
+    pub fn smiley_fill<'a, Message>(color: Color, size: u16) -> Element<'a, Message> {
+        Self::icon_fill('\u{E436}', Some(color), size)
+    }
+
+    pub fn download_simple_fill<'a, Message>(color: Color, size: u16) -> Element<'a, Message> {
+        Self::icon_fill('\u{E20C}', Some(color), size)
+    }
+
+    pub fn paperclip_fill<'a, Message>(color: Color, size: u16) -> Element<'a, Message> {
+        Self::icon_fill('\u{E39A}', Some(color), size)
+    }
+
     pub fn arrow_right_solid<'a, Message>(color: Color, size: u16) -> Element<'a, Message> {
         Self::icon_solid('\u{E06C}', Some(color), size)
     }
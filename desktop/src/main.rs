@@ -10,6 +10,7 @@ mod audio;
 mod colors;
 mod config;
 mod icons;
+mod markdown;
 mod view;
 mod widgets;
 mod state;
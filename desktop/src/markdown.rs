@@ -0,0 +1,194 @@
+//! Lightweight markdown for chat messages: **bold**, *italics*, `inline code`, code blocks and links.
+//! Anything that does not parse as markdown is shown as typed.
+
+use crate::application::Message;
+use crate::colors::{text_link, text_primary, DARK_CONTAINER_BACKGROUND};
+use iced::font::{Style as FontStyle, Weight};
+use iced::widget::container::Style;
+use iced::widget::text::Span;
+use iced::widget::{column, container, rich_text, span, text};
+use iced::{border, Background, Element, Font, Length};
+
+/// Part of a message, either text split into styled pieces or a fenced code block
+#[derive(Debug, Clone, PartialEq)]
+enum Block {
+    Text(Vec<Inline>),
+    Code(String),
+}
+
+/// Text sharing one style, `link` is set for URLs
+#[derive(Debug, Clone, PartialEq, Default)]
+struct Inline {
+    text: String,
+    bold: bool,
+    italic: bool,
+    code: bool,
+    link: Option<String>,
+}
+
+/// Characters left out at the end of a URL, so that "see https://example.com." links without the dot
+const URL_TRAILING_PUNCTUATION: &[char] = &['.', ',', ';', ':', '!', '?', ')', '\'', '"', '*', '_'];
+
+/// Splits a message into text and code blocks. A fence left open is shown as text.
+fn parse(message: &str) -> Vec<Block> {
+    let mut blocks = Vec::new();
+    let mut lines = Vec::new();
+    let mut fence: Option<(&str, Vec<&str>)> = None; // Opening line and the code lines after it
+
+    for line in message.split('\n') {
+        let is_fence = line.trim_start().starts_with("```");
+
+        if let Some((_, code)) = fence.as_mut() {
+            if is_fence {
+                blocks.push(Block::Code(code.join("\n")));
+                fence = None;
+            } else {
+                code.push(line);
+            }
+            continue;
+        }
+
+        // Text after the opening fence names the language, which is not highlighted
+        if let Some(code) = line.trim().strip_prefix("```").and_then(|rest| rest.strip_suffix("```")) {
+            push_text(&mut blocks, &mut lines);
+            blocks.push(Block::Code(code.to_string()));
+        } else if is_fence {
+            push_text(&mut blocks, &mut lines);
+            fence = Some((line, Vec::new()));
+        } else {
+            lines.push(line);
+        }
+    }
+
+    if let Some((opening, code)) = fence {
+        lines.push(opening);
+        lines.extend(code);
+    }
+    push_text(&mut blocks, &mut lines);
+
+    blocks
+}
+
+fn push_text(blocks: &mut Vec<Block>, lines: &mut Vec<&str>) {
+    if !lines.is_empty() {
+        blocks.push(Block::Text(parse_inline(&lines.join("\n"))));
+        lines.clear();
+    }
+}
+
+/// Splits text into styled pieces. Markers only take effect when closed later in the text.
+fn parse_inline(text: &str) -> Vec<Inline> {
+    let mut pieces = Vec::new();
+    let mut current = Inline::default();
+    let mut italic_marker = None;
+    let mut i = 0;
+
+    while let Some(c) = text[i..].chars().next() {
+        let rest = &text[i..];
+        let prev = text[..i].chars().next_back();
+        let next = rest[c.len_utf8()..].chars().next();
+
+        if c == '`' {
+            if let Some(end) = rest[1..].find('`').filter(|&end| end > 0) {
+                flush(&mut pieces, &mut current);
+                pieces.push(Inline { text: rest[1..=end].to_string(), code: true, ..Inline::default() });
+                i += end + 2;
+                continue;
+            }
+        } else if rest.starts_with("**") && (current.bold || rest[2..].contains("**")) {
+            flush(&mut pieces, &mut current);
+            current.bold = !current.bold;
+            i += 2;
+            continue;
+        } else if c == '*' || c == '_' {
+            // Underscores only count at word edges, so that snake_case stays as typed
+            let word_edge = |other: Option<char>| c == '*' || !other.is_some_and(char::is_alphanumeric);
+            let closes = italic_marker == Some(c) && word_edge(next);
+            let opens = italic_marker.is_none()
+                && word_edge(prev)
+                && next.is_some_and(|next| !next.is_whitespace() && next != c)
+                && rest[1..].contains(c);
+
+            if closes || opens {
+                flush(&mut pieces, &mut current);
+                current.italic = opens;
+                italic_marker = opens.then_some(c);
+                i += 1;
+                continue;
+            }
+        } else if (rest.starts_with("https://") || rest.starts_with("http://"))
+            && !prev.is_some_and(char::is_alphanumeric)
+        {
+            let end = rest.find(char::is_whitespace).unwrap_or(rest.len());
+            let url = rest[..end].trim_end_matches(URL_TRAILING_PUNCTUATION);
+            if url.split_once("://").is_some_and(|(_, address)| !address.is_empty()) {
+                flush(&mut pieces, &mut current);
+                pieces.push(Inline { text: url.to_string(), link: Some(url.to_string()), ..current.clone() });
+                i += url.len();
+                continue;
+            }
+        }
+
+        current.text.push(c);
+        i += c.len_utf8();
+    }
+
+    flush(&mut pieces, &mut current);
+    pieces
+}
+
+/// Ends the current piece, keeping its style for the next one
+fn flush(pieces: &mut Vec<Inline>, current: &mut Inline) {
+    if !current.text.is_empty() {
+        let text = std::mem::take(&mut current.text);
+        pieces.push(Inline { text, ..current.clone() });
+    }
+}
+
+/// Renders a chat message, clicking a link produces `on_link` with its URL
+pub fn view<'a>(message: &str, on_link: fn(String) -> Message) -> Element<'a, Message> {
+    let mut blocks = column!().spacing(4);
+
+    for block in parse(message) {
+        let element: Element<'a, Message> = match block {
+            Block::Text(pieces) => rich_text(pieces.into_iter().map(piece_span).collect::<Vec<_>>())
+                .size(14)
+                .color(text_primary())
+                .on_link_click(on_link)
+                .into(),
+            Block::Code(code) => container(text(code).font(Font::MONOSPACE).size(13).color(text_primary()))
+                .padding(8)
+                .width(Length::Fill)
+                .style(|_theme| Style {
+                    background: Some(Background::Color(DARK_CONTAINER_BACKGROUND)),
+                    border: border::rounded(8),
+                    ..Style::default()
+                })
+                .into(),
+        };
+        blocks = blocks.push(element);
+    }
+
+    blocks.into()
+}
+
+fn piece_span<'a>(piece: Inline) -> Span<'a, String> {
+    let font = if piece.code {
+        Font::MONOSPACE
+    } else {
+        Font {
+            weight: if piece.bold { Weight::Semibold } else { Weight::Normal },
+            style: if piece.italic { FontStyle::Italic } else { FontStyle::Normal },
+            ..Font::with_name("Rubik")
+        }
+    };
+
+    let mut result = span(piece.text).font(font);
+    if piece.code {
+        result = result.background(DARK_CONTAINER_BACKGROUND).border(border::rounded(4));
+    }
+    if let Some(link) = piece.link {
+        result = result.color(text_link()).underline(true).link(link);
+    }
+    result
+}
@@ -1,4 +1,4 @@
-use std::collections::HashSet;
+use std::collections::HashMap;
 use iced::Task;
 use tracing::{error, info};
 use voiceapp_sdk::ClientEvent;
@@ -11,12 +11,34 @@ use crate::state::voice_client::VoiceCommandResult;
 pub struct AudioManagerState {
     audio_manager: AudioManager,
     user_id: u64,
-    users_in_voice: HashSet<u64>,
+    voice_channels: HashMap<u64, u64>, // user_id -> channel_id
 }
 
 impl AudioManagerState {
     pub fn new(audio_manager: AudioManager) -> Self {
-        Self { audio_manager, user_id: 0, users_in_voice: HashSet::new() }
+        Self { audio_manager, user_id: 0, voice_channels: HashMap::new() }
+    }
+
+    /// Channel the current user is in, if any
+    fn current_channel(&self) -> Option<u64> {
+        self.voice_channels.get(&self.user_id).copied()
+    }
+
+    /// Other users in the given channel
+    fn users_in_channel(&self, channel_id: u64) -> Vec<u64> {
+        self.voice_channels
+            .iter()
+            .filter(|(user_id, channel)| **channel == channel_id && **user_id != self.user_id)
+            .map(|(user_id, _)| *user_id)
+            .collect()
+    }
+
+    fn create_output_streams_for_channel(&mut self, channel_id: u64) {
+        for user_id in self.users_in_channel(channel_id) {
+            if let Err(e) = self.audio_manager.create_output_stream_for_user(user_id) {
+                error!("Failed to create output stream for user {}: {}", user_id, e);
+            }
+        }
     }
 }
 
@@ -33,50 +55,79 @@ impl State for AudioManagerState {
             Message::VoiceCommandResult(VoiceCommandResult::Connect(Ok((user_id, _, _)))) => {
                 self.user_id = user_id
             },
-            Message::VoiceCommandResult(VoiceCommandResult::JoinVoiceChannel(Ok(()))) => {
+            Message::ServerEventReceived(ClientEvent::ParticipantsList { participants, .. }) => {
+                self.voice_channels = participants
+                    .iter()
+                    .filter_map(|p| p.channel_id.map(|channel_id| (p.user_id, channel_id)))
+                    .collect();
+            },
+            Message::ServerEventReceived(ClientEvent::Reconnected { user_id, channel_id }) => {
+                self.user_id = user_id;
+                // Other users may have new ids after the server restarted
+                self.audio_manager.remove_all_output_streams();
+
+                if let Some(channel_id) = channel_id {
+                    self.voice_channels.insert(user_id, channel_id);
+                    self.create_output_streams_for_channel(channel_id);
+                } else {
+                    self.voice_channels.remove(&user_id);
+                    self.audio_manager.stop_recording();
+                }
+            },
+            Message::VoiceCommandResult(VoiceCommandResult::JoinVoiceChannel(Ok(channel_id))) => {
                 self.audio_manager.play_notification("join_voice");
 
-                // Start recording
-                if let Err(e) = self.audio_manager.start_recording() {
+                if self.current_channel().is_some() {
+                    // Moving between channels: drop streams of the previous channel
+                    self.audio_manager.remove_all_output_streams();
+                } else if let Err(e) = self.audio_manager.start_recording() {
                     error!("Failed to start recording: {}", e);
                 }
 
-                // Create output streams for all users currently in voice
-                for user_id in self.users_in_voice.clone() {
-                    if let Err(e) = self.audio_manager.create_output_stream_for_user(user_id) {
-                        error!("Failed to create output stream for user {}: {}", user_id, e);
-                    }
-                };
+                // Create output streams for all users currently in the channel
+                self.create_output_streams_for_channel(channel_id);
 
-                self.users_in_voice.insert(self.user_id);
+                self.voice_channels.insert(self.user_id, channel_id);
             },
             Message::VoiceCommandResult(VoiceCommandResult::LeaveVoiceChannel(Ok(()))) => {
                 self.audio_manager.play_notification("leave_voice");
                 self.audio_manager.stop_recording();
                 self.audio_manager.remove_all_output_streams();
-                self.users_in_voice.remove(&self.user_id);
+                self.voice_channels.remove(&self.user_id);
             },
-            Message::ServerEventReceived(ClientEvent::UserJoinedVoice { user_id }) => {
-                // Create output stream for new user in voice
-                self.users_in_voice.insert(user_id);
+            Message::ServerEventReceived(ClientEvent::UserJoinedVoice { user_id, channel_id }) => {
+                let previous_channel = self.voice_channels.insert(user_id, channel_id);
+                let Some(current_channel) = self.current_channel() else {
+                    return Task::none();
+                };
 
-                if self.users_in_voice.contains(&self.user_id) {
+                if channel_id == current_channel && previous_channel != Some(current_channel) {
+                    // Create output stream for new user in our channel
                     self.audio_manager.play_notification("join_voice");
                     if let Err(e) = self.audio_manager.create_output_stream_for_user(user_id) {
                         error!("Failed to create output stream for user {}: {}", user_id, e);
                     };
+                } else if channel_id != current_channel && previous_channel == Some(current_channel) {
+                    // User moved away from our channel
+                    self.audio_manager.play_notification("leave_voice");
+                    self.audio_manager.remove_output_stream_for_user(user_id);
                 }
             },
             Message::ServerEventReceived(ClientEvent::UserLeftVoice { user_id }) => {
-                if self.users_in_voice.contains(&self.user_id) {
+                let channel_id = self.voice_channels.remove(&user_id);
+
+                if channel_id.is_some() && channel_id == self.current_channel() {
                     self.audio_manager.play_notification("leave_voice");
                     self.audio_manager.remove_output_stream_for_user(user_id);
                 }
-
-                self.users_in_voice.remove(&user_id);
+            },
+            Message::ServerEventReceived(ClientEvent::UserSentMessage { user_id, mentions, .. }) => {
+                if user_id != self.user_id && mentions.contains(&self.user_id) {
+                    self.audio_manager.play_notification("mention");
+                }
             },
             Message::SettingsPage(SettingsPageMessage::SelectInputDevice(device_id)) => {
-                if self.users_in_voice.contains(&self.user_id) {
+                if self.current_channel().is_some() {
                     self.audio_manager.stop_recording();
                     // TODO: error handling
                     let _ = self.audio_manager.start_recording();
@@ -91,17 +142,10 @@ impl State for AudioManagerState {
 
                 self.audio_manager.play_notification("unmute");
 
-                if self.users_in_voice.contains(&self.user_id) {
+                if let Some(channel_id) = self.current_channel() {
                     self.audio_manager.remove_all_output_streams();
-                    // Create output streams for all users currently in voice except user itself
-                    for user_id in self.users_in_voice.clone() {
-                        if user_id != self.user_id {
-                            if let Err(e) = self.audio_manager.create_output_stream_for_user(user_id) {
-                                error!("Failed to create output stream for user {}: {}", user_id, e);
-                            }
-                        }
-
-                    };
+                    // Create output streams for all users in our channel except user itself
+                    self.create_output_streams_for_channel(channel_id);
                 }
 
                 info!("Selected output device: {}", device_id);
@@ -115,9 +159,18 @@ impl State for AudioManagerState {
                     self.audio_manager.play_notification("unmute");
                 }
             }
+            Message::DeafenOutput(deafened) => {
+                if deafened {
+                    self.audio_manager.deafen_output();
+                    self.audio_manager.play_notification("mute");
+                } else {
+                    self.audio_manager.undeafen_output();
+                    self.audio_manager.play_notification("unmute");
+                }
+            }
             _ => {}
         }
 
         Task::none()
     }
-}
\ No newline at end of file
+}
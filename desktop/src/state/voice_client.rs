@@ -1,20 +1,38 @@
+use std::path::{Path, PathBuf};
 use std::sync::Arc;
 use iced::Task;
 use tracing::error;
-use voiceapp_sdk::Client;
+use voiceapp_sdk::{Attachment, ChatHistoryMessage, Client, LoginRejectReason, SdkError};
 use crate::application::Message;
 use crate::state::State;
 
+/// Chat messages fetched per history request
+const CHAT_HISTORY_PAGE_LEN: u16 = 50;
+
 #[derive(Debug, Clone)]
 pub enum VoiceCommand {
     Connect {
         management_addr: String,
         voice_addr: String,
         username: String,
+        password: Option<String>,
     },
-    JoinVoiceChannel,
+    JoinVoiceChannel(u64),  // channel_id
     LeaveVoiceChannel,
-    SendChatMessage(String),
+    SendChatMessage(String, Option<u64>, Vec<u64>),  // (message, reply_to, attachment ids)
+    UploadAttachment(PathBuf),  // File to attach to the next chat message
+    LoadAttachmentPreview(Attachment),  // Image shown inline in the chat
+    SaveAttachment(Attachment),
+    EditChatMessage(u64, String),  // (message_id, message)
+    DeleteChatMessage(u64),  // message_id
+    SetReaction(u64, String, bool),  // (message_id, emoji, reacted)
+    SendDirectMessage(u64, String),  // (user_id, message)
+    SetTyping(bool),  // is_typing
+    MarkRead(u64),  // Newest message_id seen
+    LoadChatHistory(Option<u64>),  // Messages before this timestamp, the newest ones if None
+    KickUser(u64),  // user_id
+    BanUser(u64),  // user_id
+    ServerMuteUser(u64, bool),  // (user_id, muted)
     Ping,
     GetVoiceStats,
 }
@@ -22,9 +40,16 @@ pub enum VoiceCommand {
 #[derive(Debug, Clone)]
 pub enum VoiceCommandResult {
     Connect(Result<(u64, String, String), String>),  // Ok((user_id, address, username))
-    JoinVoiceChannel(Result<(), String>),
+    JoinVoiceChannel(Result<u64, String>),  // Ok(channel_id)
     LeaveVoiceChannel(Result<(), String>),
     SendChatMessage(Result<(), String>),
+    UploadAttachment(Result<Attachment, String>),
+    AttachmentPreview(u64, Result<Vec<u8>, String>),  // (attachment_id, Ok(image data))
+    SaveAttachment(Result<PathBuf, String>),  // Ok(where the file was saved)
+    ChatAction(Result<(), String>),  // Editing, deleting or reacting to a message
+    SendDirectMessage(u64, String, Result<u64, String>),  // (user_id, message, Ok(timestamp))
+    ChatHistory(Result<(Vec<ChatHistoryMessage>, bool), String>),  // Ok((messages oldest first, has_more))
+    Moderate(Result<(), String>),
     Ping(Result<u64, String>),  // RTT in milliseconds
     VoiceStats(u64, u64),  // (bytes_sent, bytes_received)
 }
@@ -46,27 +71,28 @@ impl VoiceClientState {
                 management_addr,
                 voice_addr,
                 username,
+                password,
             } => {
                 // Extract server address from management_addr (remove port)
                 let server_addr = management_addr.split(':').next().unwrap_or("").to_string();
                 let username_clone = username.clone();
 
                 Task::perform(
-                    async move { client.connect(&management_addr, &voice_addr, &username).await },
+                    async move { client.connect(&management_addr, &voice_addr, &username, password.as_deref()).await },
                     |result| {
                         Message::VoiceCommandResult(VoiceCommandResult::Connect(
                             result
                                 .map(|user_id| (user_id, server_addr, username_clone))
-                                .map_err(|e| e.to_string()),
+                                .map_err(connect_error_message),
                         ))
                     },
                 )
             }
-            VoiceCommand::JoinVoiceChannel => Task::perform(
-                async move { client.join_channel().await },
-                |result| {
+            VoiceCommand::JoinVoiceChannel(channel_id) => Task::perform(
+                async move { client.join_channel(channel_id).await },
+                move |result| {
                     Message::VoiceCommandResult(VoiceCommandResult::JoinVoiceChannel(
-                        result.map_err(|e| e.to_string()),
+                        result.map(|_| channel_id).map_err(|e| e.to_string()),
                     ))
                 },
             ),
@@ -78,14 +104,104 @@ impl VoiceClientState {
                     ))
                 },
             ),
-            VoiceCommand::SendChatMessage(message) => Task::perform(
-                async move { client.send_message(&message).await },
+            VoiceCommand::SendChatMessage(message, reply_to, attachments) => Task::perform(
+                async move { client.send_message(&message, reply_to, &attachments).await },
                 |result| {
                     Message::VoiceCommandResult(VoiceCommandResult::SendChatMessage(
+                        result.map(|_| ()).map_err(|e| e.to_string()),
+                    ))
+                },
+            ),
+            VoiceCommand::UploadAttachment(path) => Task::perform(
+                async move {
+                    let name = path.file_name().and_then(|name| name.to_str()).unwrap_or("file").to_string();
+                    let data = tokio::fs::read(&path).await.map_err(|e| e.to_string())?;
+                    client.upload_attachment(&name, mime_type(&path), &data).await.map_err(|e| e.to_string())
+                },
+                |result| Message::VoiceCommandResult(VoiceCommandResult::UploadAttachment(result)),
+            ),
+            VoiceCommand::LoadAttachmentPreview(attachment) => {
+                let attachment_id = attachment.attachment_id;
+
+                Task::perform(
+                    async move { client.download_attachment(&attachment).await },
+                    move |result| {
+                        Message::VoiceCommandResult(VoiceCommandResult::AttachmentPreview(
+                            attachment_id,
+                            result.map_err(|e| e.to_string()),
+                        ))
+                    },
+                )
+            }
+            VoiceCommand::SaveAttachment(attachment) => Task::perform(
+                async move {
+                    let data = client.download_attachment(&attachment).await.map_err(|e| e.to_string())?;
+                    let path = save_path(&attachment);
+                    tokio::fs::write(&path, data).await.map_err(|e| e.to_string())?;
+                    Ok(path)
+                },
+                |result| Message::VoiceCommandResult(VoiceCommandResult::SaveAttachment(result)),
+            ),
+            VoiceCommand::EditChatMessage(message_id, message) => Task::perform(
+                async move { client.edit_message(message_id, &message).await },
+                |result| Message::VoiceCommandResult(VoiceCommandResult::ChatAction(result.map_err(|e| e.to_string()))),
+            ),
+            VoiceCommand::DeleteChatMessage(message_id) => Task::perform(
+                async move { client.delete_message(message_id).await },
+                |result| Message::VoiceCommandResult(VoiceCommandResult::ChatAction(result.map_err(|e| e.to_string()))),
+            ),
+            VoiceCommand::SetReaction(message_id, emoji, reacted) => Task::perform(
+                async move { client.set_reaction(message_id, &emoji, reacted).await },
+                |result| Message::VoiceCommandResult(VoiceCommandResult::ChatAction(result.map_err(|e| e.to_string()))),
+            ),
+            VoiceCommand::SendDirectMessage(user_id, message) => {
+                let message_clone = message.clone();
+
+                Task::perform(
+                    async move { client.send_direct_message(user_id, &message_clone).await },
+                    move |result| {
+                        Message::VoiceCommandResult(VoiceCommandResult::SendDirectMessage(
+                            user_id,
+                            message,
+                            result.map_err(|e| e.to_string()),
+                        ))
+                    },
+                )
+            }
+            VoiceCommand::SetTyping(is_typing) => Task::future(async move {
+                if let Err(e) = client.set_typing(is_typing).await {
+                    error!("Failed to send typing state: {}", e);
+                }
+
+                Message::None
+            }),
+            VoiceCommand::MarkRead(message_id) => Task::future(async move {
+                if let Err(e) = client.mark_read(message_id).await {
+                    error!("Failed to send read marker: {}", e);
+                }
+
+                Message::None
+            }),
+            VoiceCommand::LoadChatHistory(before) => Task::perform(
+                async move { client.chat_history(before, None, CHAT_HISTORY_PAGE_LEN).await },
+                |result| {
+                    Message::VoiceCommandResult(VoiceCommandResult::ChatHistory(
                         result.map_err(|e| e.to_string()),
                     ))
                 },
             ),
+            VoiceCommand::KickUser(user_id) => Task::perform(
+                async move { client.kick_user(user_id, "").await },
+                |result| Message::VoiceCommandResult(VoiceCommandResult::Moderate(result.map_err(|e| e.to_string()))),
+            ),
+            VoiceCommand::BanUser(user_id) => Task::perform(
+                async move { client.ban_user(user_id, "", false).await },
+                |result| Message::VoiceCommandResult(VoiceCommandResult::Moderate(result.map_err(|e| e.to_string()))),
+            ),
+            VoiceCommand::ServerMuteUser(user_id, muted) => Task::perform(
+                async move { client.server_mute_user(user_id, muted).await },
+                |result| Message::VoiceCommandResult(VoiceCommandResult::Moderate(result.map_err(|e| e.to_string()))),
+            ),
             VoiceCommand::Ping => Task::perform(
                 async move { client.ping().await },
                 |result| {
@@ -102,6 +218,59 @@ impl VoiceClientState {
     }
 }
 
+/// Converts a connect failure into text shown on the login page
+fn connect_error_message(error: SdkError) -> String {
+    match error {
+        SdkError::AuthenticationFailed => "Wrong password".to_string(),
+        SdkError::LoginRejected(reason) => match reason {
+            LoginRejectReason::InvalidUsername => "Username is empty or too long".to_string(),
+            LoginRejectReason::NameTaken => "Username is already taken".to_string(),
+            LoginRejectReason::ServerFull => "Server is full".to_string(),
+            LoginRejectReason::Banned => "You are banned from this server".to_string(),
+            reason => format!("Login rejected: {}", reason),
+        },
+        SdkError::ProtocolVersionMismatch { .. } => "Server version is incompatible with this client".to_string(),
+        error => error.to_string(),
+    }
+}
+
+/// MIME type sent with an uploaded file, guessed from its extension
+fn mime_type(path: &Path) -> &'static str {
+    let extension = path.extension().and_then(|extension| extension.to_str()).unwrap_or_default();
+    match extension.to_ascii_lowercase().as_str() {
+        "png" => "image/png",
+        "jpg" | "jpeg" => "image/jpeg",
+        "gif" => "image/gif",
+        "webp" => "image/webp",
+        "bmp" => "image/bmp",
+        "txt" | "log" => "text/plain",
+        _ => "application/octet-stream",
+    }
+}
+
+/// Where a saved attachment goes: the user's Downloads directory, or the working directory without one.
+/// The attachment id is prepended instead of overwriting a file with the same name.
+fn save_path(attachment: &Attachment) -> PathBuf {
+    let downloads = std::env::var_os("USERPROFILE")
+        .or_else(|| std::env::var_os("HOME"))
+        .map(|home| PathBuf::from(home).join("Downloads"))
+        .filter(|dir| dir.is_dir())
+        .unwrap_or_default();
+
+    // The server refuses names with directories, this only guards against other servers
+    let name = Path::new(&attachment.name)
+        .file_name()
+        .and_then(|name| name.to_str())
+        .map_or_else(|| format!("attachment-{}", attachment.attachment_id), str::to_string);
+
+    let path = downloads.join(&name);
+    if path.exists() {
+        downloads.join(format!("{}-{}", attachment.attachment_id, name))
+    } else {
+        path
+    }
+}
+
 impl State for VoiceClientState {
     fn init(&mut self) -> Task<Message> {
         Task::run(self.voice_client.event_stream(), |e| Message::ServerEventReceived(e))
@@ -120,6 +289,16 @@ impl State for VoiceClientState {
                     Message::None
                 })
             }
+            Message::DeafenOutput(deafened) => {
+                let voice_client = self.voice_client.clone();
+                Task::future(async move {
+                    if let Err(e) = voice_client.send_deafen_state(deafened).await {
+                        error!("Failed to send deafen state: {}", e);
+                    }
+
+                    Message::None
+                })
+            }
             _ => Task::none()
         }
     }
@@ -20,6 +20,7 @@ pub struct LoginPage {
     config: Arc<ArcSwap<AppConfig>>,
     voice_url: String,
     username: String,
+    password: String,
     form_filled: bool,
     login_error: String,
 }
@@ -28,6 +29,7 @@ pub struct LoginPage {
 pub enum LoginPageMessage {
     VoiceUrlChanged(String),
     UsernameChanged(String),
+    PasswordChanged(String),
     LoginSubmitted,
 }
 
@@ -45,6 +47,7 @@ impl LoginPage {
             config,
             voice_url: loaded_config.server.address.clone(),
             username: loaded_config.server.username.clone(),
+            password: String::new(),
             form_filled: false,
             login_error: "".to_string(),
         };
@@ -71,7 +74,8 @@ impl LoginPage {
                     "Voice server IP",
                     &mut self.voice_url.clone(),
                     LoginPageMessage::VoiceUrlChanged,
-                    LoginPageMessage::LoginSubmitted
+                    LoginPageMessage::LoginSubmitted,
+                    false
                 ),
                 Widgets::input_with_submit(
                     "Username",
@@ -81,6 +85,13 @@ impl LoginPage {
                     LoginPageMessage::LoginSubmitted.into(),
                     262,
                     48
+                ),
+                self.input(
+                    "Password (optional)",
+                    &mut self.password.clone(),
+                    LoginPageMessage::PasswordChanged,
+                    LoginPageMessage::LoginSubmitted,
+                    true
                 )
             )
             .spacing(8),
@@ -115,6 +126,7 @@ impl LoginPage {
         value: &mut String,
         message: fn(String) -> LoginPageMessage,
         submit_message: LoginPageMessage,
+        secure: bool,
     ) -> iced::widget::Container<'_, Message> {
         let container_style = |_theme: &iced::Theme| Style {
             background: Some(Background::Color(DARK_CONTAINER_BACKGROUND)),
@@ -126,6 +138,7 @@ impl LoginPage {
             text_input(placeholder, value)
                 .on_input(move |t| message(t).into())
                 .on_submit(submit_message.into())
+                .secure(secure)
                 .padding(0)
                 .style(|_theme, _status| text_input::Style {
                     background: Background::Color(Color::TRANSPARENT),
@@ -161,6 +174,7 @@ impl View for LoginPage {
                         management_addr: format!("{}:9001", config.server.address),
                         voice_addr: format!("{}:9002", config.server.address),
                         username: config.server.username.clone(),
+                        password: None,
                     }
                 )
             )
@@ -193,6 +207,15 @@ impl View for LoginPage {
 
                         Task::none()
                     }
+                    LoginPageMessage::PasswordChanged(content) => {
+                        if !self.login_error.is_empty() {
+                            self.login_error.clear();
+                        }
+
+                        self.password = content;
+
+                        Task::none()
+                    }
                     LoginPageMessage::LoginSubmitted => {
                         if self.form_filled {
                             // TODO: inputs should be blocked (buttons as well)
@@ -201,6 +224,7 @@ impl View for LoginPage {
                                     management_addr: format!("{}:9001", self.voice_url),
                                     voice_addr: format!("{}:9002", self.voice_url),
                                     username: self.username.clone(),
+                                    password: Some(self.password.clone()).filter(|p| !p.is_empty()),
                                 },
                             ))
                         } else {
@@ -1,6 +1,7 @@
 use crate::application::{Message, ViewType};
-use crate::colors::{color_alert, color_error, color_success, divider_bg, slider_bg, slider_thumb, text_chat_header, text_primary, text_secondary, DARK_CONTAINER_BACKGROUND};
+use crate::colors::{color_alert, color_error, color_success, divider_bg, mention_bg, slider_bg, slider_thumb, text_chat_header, text_primary, text_secondary, text_selection, DARK_CONTAINER_BACKGROUND};
 use crate::icons::Icons;
+use crate::markdown;
 use crate::widgets::Widgets;
 use chrono::{DateTime, Local, Utc};
 use iced::alignment::{Horizontal, Vertical};
@@ -9,17 +10,18 @@ use iced::widget::button::Status;
 use iced::widget::container::Style;
 use iced::widget::rule::FillMode;
 use iced::widget::scrollable::{Direction, Rail, Scrollbar, Scroller};
-use iced::widget::{button, column, container, mouse_area, row, rule, scrollable, slider, space, stack, text, Container, Id, Scrollable};
+use iced::widget::{button, column, container, image, mouse_area, row, rule, scrollable, slider, space, stack, text, Container, Id, Scrollable};
 use iced::{border, font, Alignment, Background, Border, Color, Element, Font, Length, Padding, Task, Theme};
-use std::collections::{BTreeMap, HashMap};
+use std::collections::{BTreeMap, HashMap, HashSet};
 use std::sync::Arc;
+use std::time::{Duration, Instant};
 use arc_swap::ArcSwap;
 use iced::font::{Family, Stretch, Weight};
 use iced::mouse::Interaction;
 use iced::widget::slider::{Handle, HandleShape};
 use iced_aw::{DropDown};
 use tracing::{debug, warn};
-use voiceapp_sdk::{ParticipantInfo, ClientEvent};
+use voiceapp_sdk::{Attachment, ChannelInfo, ParticipantInfo, ClientEvent, ModerationAction, Permissions, Reaction, Role, TYPING_INTERVAL_MS};
 use crate::config::AppConfig;
 use crate::state::voice_client::{VoiceCommand, VoiceCommandResult};
 use crate::view::view::View;
@@ -29,8 +31,22 @@ pub struct ChatMessage {
     pub username: String,
     pub message: String,
     pub time: String,
+    pub reply_to: Option<u64>,
+    pub edited: bool,
+    pub mentions_me: bool,
+    pub reactions: Vec<Reaction>,
+    pub attachments: Vec<Attachment>,
 }
 
+/// Emojis offered by the reaction picker
+const REACTION_EMOJIS: [&str; 6] = ["👍", "❤️", "😂", "🎉", "😮", "😢"];
+
+/// Files the server accepts on one message
+const MAX_ATTACHMENTS: usize = 10;
+
+/// Larger images are only offered for saving, not downloaded to show a preview
+const MAX_PREVIEW_SIZE: u64 = 8 * 1024 * 1024;
+
 impl ChatMessage {
     pub fn new(username: String, message: String, timestamp: u64) -> Self {
         let time = Self::format_time(timestamp);
@@ -38,6 +54,64 @@ impl ChatMessage {
             username,
             message,
             time,
+            reply_to: None,
+            edited: false,
+            mentions_me: false,
+            reactions: Vec::new(),
+            attachments: Vec::new(),
+        }
+    }
+
+    pub fn with_reply_to(mut self, reply_to: Option<u64>) -> Self {
+        self.reply_to = reply_to;
+        self
+    }
+
+    pub fn with_edited(mut self, edited: bool) -> Self {
+        self.edited = edited;
+        self
+    }
+
+    pub fn with_mentions_me(mut self, mentions_me: bool) -> Self {
+        self.mentions_me = mentions_me;
+        self
+    }
+
+    pub fn with_reactions(mut self, reactions: Vec<Reaction>) -> Self {
+        self.reactions = reactions;
+        self
+    }
+
+    pub fn with_attachments(mut self, attachments: Vec<Attachment>) -> Self {
+        self.attachments = attachments;
+        self
+    }
+
+    fn has_reacted(&self, username: &str, emoji: &str) -> bool {
+        self.reactions
+            .iter()
+            .filter(|reaction| reaction.emoji == emoji)
+            .any(|reaction| reaction.usernames.iter().any(|name| name.eq_ignore_ascii_case(username)))
+    }
+
+    /// Adds or removes `username` from the users who reacted with `emoji`, names compare like on the server
+    fn set_reaction(&mut self, username: &str, emoji: &str, reacted: bool) {
+        let position = self.reactions.iter().position(|reaction| reaction.emoji == emoji);
+        match (position, reacted) {
+            (Some(index), true) => {
+                let usernames = &mut self.reactions[index].usernames;
+                if !usernames.iter().any(|name| name.eq_ignore_ascii_case(username)) {
+                    usernames.push(username.to_string());
+                }
+            }
+            (Some(index), false) => {
+                self.reactions[index].usernames.retain(|name| !name.eq_ignore_ascii_case(username));
+                if self.reactions[index].usernames.is_empty() {
+                    self.reactions.remove(index);
+                }
+            }
+            (None, true) => self.reactions.push(Reaction::new(emoji.to_string(), vec![username.to_string()])),
+            (None, false) => {}
         }
     }
 
@@ -54,9 +128,30 @@ impl ChatMessage {
 pub struct RoomPage {
     user_id: u64,
     muted: bool,
+    deafened: bool,
     chat_message: String,
     participants: HashMap<u64, ParticipantInfo>,
-    chat_history: BTreeMap<u64, ChatMessage>,
+    server_muted: HashSet<u64>,
+    channels: BTreeMap<u64, ChannelInfo>,
+    chat_history: BTreeMap<(u64, u64), ChatMessage>, // Keyed by (timestamp, message_id), server notices have id 0
+    message_timestamps: HashMap<u64, u64>, // message_id -> timestamp, to find messages in chat_history
+    oldest_history_timestamp: Option<u64>, // Oldest message fetched from the server history
+    history_has_more: bool,
+    history_loading: bool,
+    hovered_message: Option<u64>,
+    reaction_picker: Option<u64>, // Message the reaction picker is open for
+    reply_to: Option<u64>, // Message the composed message replies to
+    editing: Option<u64>, // Message whose text is being edited in the input
+    pending_attachments: Vec<Attachment>, // Uploaded files sent with the next room chat message
+    uploads_in_progress: usize,
+    attachment_previews: HashMap<u64, Option<image::Handle>>, // attachment_id -> image, None while downloading
+    direct_messages: HashMap<u64, Vec<ChatMessage>>, // user_id -> direct messages with that user, oldest first
+    direct_peer: Option<u64>, // User whose direct messages are shown instead of the room chat
+    unread_direct: HashSet<u64>, // Users who sent direct messages not seen yet
+    typing_users: HashSet<u64>,
+    typing_sent_at: Option<Instant>, // When the server was last told we are typing
+    read_markers: HashMap<u64, u64>, // user_id -> newest message_id the user has read
+    read_marker: u64, // Newest message_id we told the server we have read
     volume_per_user: HashMap<u64, u8>,
     selected_user_settings: Option<u64>,
     overlay_visible: bool,
@@ -68,12 +163,30 @@ pub struct RoomPage {
 #[derive(Debug, Clone)]
 pub enum RoomPageMessage {
     MuteToggle,
+    DeafenToggle,
     JoinLeaveToggle,
+    ChannelClicked(u64),
     ChatMessageChanged(String),
     ChatMessageSubmitted,
+    ChatScrolled(f32),  // Relative vertical offset, 0 at the top
+    MessageHovered(u64),
+    MessageUnhovered(u64),
+    ReplyClicked(u64),
+    EditClicked(u64),
+    DeleteClicked(u64),
+    ReactionPickerToggled(u64),
+    ReactionToggled(u64, String),  // (message_id, emoji)
+    LinkClicked(String),
+    AttachmentSaveClicked(Attachment),
+    ComposeCancelled,
+    DirectMessageOpened(u64),
+    DirectMessageClosed,
     UserClicked(u64),
     UserSettingsDismissed,
-    UserVolumeChanged(u64, u8)
+    UserVolumeChanged(u64, u8),
+    ServerMuteToggle(u64),
+    KickUser(u64),
+    BanUser(u64),
 }
 
 impl Into<Message> for RoomPageMessage {
@@ -89,9 +202,30 @@ impl RoomPage {
         Self {
             user_id: 0,
             muted: false,
+            deafened: false,
             chat_message: String::new(),
             participants: HashMap::new(),
+            server_muted: HashSet::new(),
+            channels: BTreeMap::new(),
             chat_history: BTreeMap::new(),
+            message_timestamps: HashMap::new(),
+            oldest_history_timestamp: None,
+            history_has_more: false,
+            history_loading: false,
+            hovered_message: None,
+            reaction_picker: None,
+            reply_to: None,
+            editing: None,
+            pending_attachments: Vec::new(),
+            uploads_in_progress: 0,
+            attachment_previews: HashMap::new(),
+            direct_messages: HashMap::new(),
+            direct_peer: None,
+            unread_direct: HashSet::new(),
+            typing_users: HashSet::new(),
+            typing_sent_at: None,
+            read_markers: HashMap::new(),
+            read_marker: 0,
             volume_per_user: config.audio.users_volumes.clone(),
             selected_user_settings: None,
             overlay_visible: false,
@@ -109,30 +243,38 @@ impl RoomPage {
             snap: false,
         };
 
-        let participants_in_voice: Vec<_> =
-            self.participants.values().filter(|i| i.in_voice).collect();
-        let participants_in_chat: Vec<_> =
-            self.participants.values().filter(|i| !i.in_voice).collect();
-
         let mut sidebar_elements = Vec::new();
-        sidebar_elements.extend(self.render_members_section("IN VOICE", participants_in_voice));
-        sidebar_elements.extend(self.render_members_section("IN CHAT", participants_in_chat));
+        for channel in self.channels.values() {
+            let participants_in_channel: Vec<_> = self
+                .participants
+                .values()
+                .filter(|i| i.channel_id == Some(channel.channel_id))
+                .collect();
+
+            sidebar_elements.extend(self.render_members_section(
+                &channel.name.to_uppercase(),
+                participants_in_channel,
+                Some(RoomPageMessage::ChannelClicked(channel.channel_id).into()),
+            ));
+        }
+
+        let participants_in_chat: Vec<_> =
+            self.participants.values().filter(|i| !i.in_voice()).collect();
+        sidebar_elements.extend(self.render_members_section("IN CHAT", participants_in_chat, None));
 
         let mut sidebar_column = iced::widget::Column::new();
         for element in sidebar_elements {
             sidebar_column = sidebar_column.push(element);
         }
 
-        let is_in_voice = self
-            .participants
-            .get(&self.user_id)
-            .map(|p| p.in_voice)
-            .unwrap_or(false);
+        let is_in_voice = self.is_in_voice();
 
         let disconnect_button = container(
             Widgets::container_button(
                 container(
-                    text(if is_in_voice {
+                    text(if !is_in_voice && !self.has_permission(Permissions::JOIN_VOICE) {
+                        "Listening not allowed"
+                    } else if is_in_voice {
                         "Leave voice"
                     } else {
                         "Join voice"
@@ -176,7 +318,11 @@ impl RoomPage {
             ..Padding::default()
         });
 
-        let mute_slider = container(Self::mute_slider(self.muted))
+        let sliders = column!(Self::mute_slider(self.muted), Self::deafen_slider(self.deafened))
+            .spacing(12)
+            .align_x(Alignment::Center);
+
+        let voice_controls = container(sliders)
             .padding(Padding {
                 bottom: 24.0,
                 left: 16.0,
@@ -188,7 +334,7 @@ impl RoomPage {
 
         let left_sidebar = container(sidebar_column.push(space::vertical()).push(
             if self.is_in_voice() {
-                mute_slider
+                voice_controls
             } else {
                 container("")
             },
@@ -197,14 +343,27 @@ impl RoomPage {
         .height(Length::Fill);
 
         let mut messages_column = column!();
-        for chat_msg in self.chat_history.values() {
-            messages_column = messages_column.push(Self::chat_message(
-                chat_msg.username.clone(),
-                chat_msg.message.clone(),
-                chat_msg.time.clone(),
-            ));
+        match self.direct_peer {
+            Some(user_id) => {
+                for chat_msg in self.direct_messages.get(&user_id).into_iter().flatten() {
+                    messages_column = messages_column.push(self.chat_message(0, chat_msg));
+                }
+            }
+            None => {
+                for (&(_, message_id), chat_msg) in &self.chat_history {
+                    messages_column = messages_column.push(self.chat_message(message_id, chat_msg));
+                }
+            }
         }
 
+        let input_placeholder = if !self.has_permission(Permissions::CHAT) {
+            "You cannot chat here".to_string()
+        } else if let Some(user_id) = self.direct_peer {
+            format!("Message {}...", self.username_of(user_id))
+        } else {
+            "Send message...".to_string()
+        };
+
         let messages_container = Scrollable::with_direction(
             container(messages_column)
                 .align_y(Alignment::End)
@@ -217,6 +376,7 @@ impl RoomPage {
             Direction::Vertical(Scrollbar::new().width(4).margin(2).scroller_width(2)),
         )
         .id(Id::new("chat_area_scroll"))
+        .on_scroll(|viewport| RoomPageMessage::ChatScrolled(viewport.relative_offset().y).into())
         .height(Length::Fill)
         .style(|theme, status| {
             let rail = Rail {
@@ -241,12 +401,15 @@ impl RoomPage {
         });
 
         let chat_area = container(column!(
+            self.direct_banner(),
             messages_container,
+            self.typing_banner(),
+            self.compose_banner(),
             container(Widgets::input_with_submit(
-                "Send message...",
+                &input_placeholder,
                 &mut self.chat_message.clone(),
                 |v| RoomPageMessage::ChatMessageChanged(v).into(),
-                !self.chat_message.is_empty(),
+                !self.chat_message.is_empty() || !self.pending_attachments.is_empty(),
                 RoomPageMessage::ChatMessageSubmitted.into(),
                 Length::Fill,
                 48
@@ -301,22 +464,409 @@ impl RoomPage {
             .height(Length::Fill)
     }
 
-    fn chat_message<'a>(username: String, message: String, time: String) -> Container<'a, Message> {
+    /// A chat message with the message it replies to, hovering it shows reply, edit and delete buttons.
+    /// `message_id` is 0 for server notices and direct messages, which have no buttons.
+    fn chat_message(&self, message_id: u64, chat_msg: &ChatMessage) -> Element<'_, Message> {
+        let time = if chat_msg.edited { format!("{} (edited)", chat_msg.time) } else { chat_msg.time.clone() };
+        let mut header = row!(text(chat_msg.username.clone()).color(text_chat_header()).size(12), space::horizontal())
+            .spacing(8)
+            .align_y(Alignment::Center);
+
+        if message_id != 0 && self.hovered_message == Some(message_id) {
+            if self.has_permission(Permissions::CHAT) {
+                header = header.push(
+                    Widgets::icon_button(Icons::smiley_fill(text_secondary(), 14))
+                        .on_press(RoomPageMessage::ReactionPickerToggled(message_id).into()),
+                );
+                header = header.push(
+                    Widgets::icon_button(Icons::arrow_bend_up_left_fill(text_secondary(), 14))
+                        .on_press(RoomPageMessage::ReplyClicked(message_id).into()),
+                );
+            }
+            if self.is_own_message(chat_msg) {
+                header = header.push(
+                    Widgets::icon_button(Icons::pencil_simple_fill(text_secondary(), 14))
+                        .on_press(RoomPageMessage::EditClicked(message_id).into()),
+                );
+            }
+            if self.is_own_message(chat_msg) || self.has_permission(Permissions::MODERATE) {
+                header = header.push(
+                    Widgets::icon_button(Icons::trash_fill(color_error(), 14))
+                        .on_press(RoomPageMessage::DeleteClicked(message_id).into()),
+                );
+            }
+        }
+
+        let mut body = column!().spacing(4);
+        if let Some(reply_to) = chat_msg.reply_to {
+            let quote = match self.find_chat_message(reply_to) {
+                // Messages may carry only files
+                Some(original) if original.message.is_empty() && !original.attachments.is_empty() => {
+                    format!("↪ {}: {}", original.username, original.attachments[0].name)
+                }
+                Some(original) => format!("↪ {}: {}", original.username, Self::excerpt(&original.message)),
+                None => "↪ Original message is unavailable".to_string(),
+            };
+            body = body.push(text(quote).color(text_secondary()).size(12));
+        }
+
+        body = body.push(header.push(text(time).color(text_chat_header()).size(12)));
+        if !chat_msg.message.is_empty() {
+            body = body.push(markdown::view(&chat_msg.message, |url| RoomPageMessage::LinkClicked(url).into()));
+        }
+        for attachment in &chat_msg.attachments {
+            body = body.push(self.attachment_view(attachment));
+        }
+
+        if message_id != 0 {
+            if let Some(reactions) = self.reactions_row(message_id, chat_msg) {
+                body = body.push(reactions);
+            }
+        }
+
+        // Users whose read marker stopped at this message
+        let mut seen_by: Vec<_> = self
+            .read_markers
+            .iter()
+            .filter(|&(&user_id, &marker)| message_id != 0 && marker == message_id && user_id != self.user_id)
+            .filter_map(|(user_id, _)| self.participants.get(user_id).map(|p| p.username.clone()))
+            .collect();
+        if !seen_by.is_empty() {
+            seen_by.sort();
+            body = body.push(text(format!("Seen by {}", seen_by.join(", "))).color(text_secondary()).size(10));
+        }
+
+        let content = container(body).padding(8).width(Length::Fill);
+        let content = if chat_msg.mentions_me {
+            content.style(|_theme| Style {
+                background: Some(Background::Color(mention_bg())),
+                border: border::rounded(8),
+                ..Style::default()
+            })
+        } else {
+            content
+        };
+
+        if message_id == 0 {
+            return content.into();
+        }
+
+        mouse_area(content)
+            .on_enter(RoomPageMessage::MessageHovered(message_id).into())
+            .on_exit(RoomPageMessage::MessageUnhovered(message_id).into())
+            .into()
+    }
+
+    /// Reaction chips with their counts, ours highlighted, and the picker if it is open for this message.
+    /// Clicking a chip or a picked emoji toggles our reaction.
+    fn reactions_row(&self, message_id: u64, chat_msg: &ChatMessage) -> Option<Element<'_, Message>> {
+        let picker_open = self.reaction_picker == Some(message_id);
+        if chat_msg.reactions.is_empty() && !picker_open {
+            return None;
+        }
+
+        let own_username = self.participants.get(&self.user_id).map(|p| p.username.as_str()).unwrap_or_default();
+        let mut chips = row!().spacing(4).align_y(Alignment::Center);
+        for reaction in &chat_msg.reactions {
+            let reacted = chat_msg.has_reacted(own_username, &reaction.emoji);
+            let chip = container(text(format!("{} {}", reaction.emoji, reaction.usernames.len())).size(12))
+                .padding(Padding { top: 2.0, right: 8.0, bottom: 2.0, left: 8.0 })
+                .style(move |_theme| Style {
+                    background: Some(Background::Color(DARK_CONTAINER_BACKGROUND)),
+                    border: if reacted {
+                        border::rounded(12).color(text_secondary()).width(1)
+                    } else {
+                        border::rounded(12)
+                    },
+                    text_color: Some(text_primary()),
+                    ..Style::default()
+                });
+            chips = chips.push(
+                Widgets::container_button(chip)
+                    .on_press(RoomPageMessage::ReactionToggled(message_id, reaction.emoji.clone()).into()),
+            );
+        }
+
+        if picker_open {
+            let mut picker = row!().spacing(2);
+            for emoji in REACTION_EMOJIS {
+                picker = picker.push(
+                    Widgets::container_button(container(text(emoji).size(16)).padding(4).style(|_theme| Style {
+                        background: Some(Background::Color(text_selection())),
+                        border: border::rounded(8),
+                        ..Style::default()
+                    }))
+                    .on_press(RoomPageMessage::ReactionToggled(message_id, emoji.to_string()).into()),
+                );
+            }
+            chips = chips.push(picker);
+        }
+
+        Some(chips.into())
+    }
+
+    /// An attached file with a button saving it to disk, images are previewed above it once downloaded
+    fn attachment_view(&self, attachment: &Attachment) -> Element<'_, Message> {
+        let file = container(
+            row!(
+                Icons::paperclip_fill(text_secondary(), 14),
+                text(attachment.name.clone()).color(text_primary()).size(13),
+                text(Self::format_size(attachment.size)).color(text_secondary()).size(12),
+                space::horizontal(),
+                Widgets::icon_button(Icons::download_simple_fill(text_secondary(), 14))
+                    .on_press(RoomPageMessage::AttachmentSaveClicked(attachment.clone()).into()),
+            )
+            .spacing(8)
+            .align_y(Alignment::Center),
+        )
+        .padding(Padding { top: 4.0, right: 8.0, bottom: 4.0, left: 8.0 })
+        .max_width(400)
+        .style(|_theme| Style {
+            background: Some(Background::Color(DARK_CONTAINER_BACKGROUND)),
+            border: border::rounded(8),
+            ..Style::default()
+        });
+
+        match self.attachment_previews.get(&attachment.attachment_id) {
+            Some(Some(handle)) => {
+                let preview = container(image(handle.clone())).max_width(400).max_height(300);
+                column!(preview, file).spacing(4).into()
+            }
+            _ => file.into(),
+        }
+    }
+
+    /// Downloads images attached to a message to preview them, each only once
+    fn load_previews(&mut self, attachments: &[Attachment]) -> Task<Message> {
+        let mut tasks = Vec::new();
+        for attachment in attachments {
+            if !attachment.is_image()
+                || attachment.size > MAX_PREVIEW_SIZE
+                || self.attachment_previews.contains_key(&attachment.attachment_id)
+            {
+                continue;
+            }
+
+            self.attachment_previews.insert(attachment.attachment_id, None);
+            let command = VoiceCommand::LoadAttachmentPreview(attachment.clone());
+            tasks.push(Task::done(Message::ExecuteVoiceCommand(command)));
+        }
+        Task::batch(tasks)
+    }
+
+    fn format_size(size: u64) -> String {
+        match size {
+            0..1024 => format!("{} B", size),
+            1024..1_048_576 => format!("{:.1} KB", size as f64 / 1024.0),
+            _ => format!("{:.1} MB", size as f64 / 1_048_576.0),
+        }
+    }
+
+    /// Opens a link from a chat message in the default browser
+    fn open_link(url: &str) {
+        #[cfg(target_os = "windows")]
+        let result = std::process::Command::new("rundll32").args(["url.dll,FileProtocolHandler", url]).spawn();
+        #[cfg(target_os = "macos")]
+        let result = std::process::Command::new("open").arg(url).spawn();
+        #[cfg(not(any(target_os = "windows", target_os = "macos")))]
+        let result = std::process::Command::new("xdg-open").arg(url).spawn();
+
+        match result {
+            // Reap the opener once it exits
+            Ok(mut child) => {
+                std::thread::spawn(move || child.wait());
+            }
+            Err(e) => warn!("Failed to open link {}: {}", url, e),
+        }
+    }
+
+    /// Shows which message is being replied to or edited and the files attached to it, with a button to cancel
+    fn compose_banner(&self) -> Element<'_, Message> {
+        let mut labels = Vec::new();
+        match (self.editing, self.reply_to) {
+            (Some(_), _) => labels.push("Editing message".to_string()),
+            (None, Some(reply_to)) => labels.push(match self.find_chat_message(reply_to) {
+                Some(original) => format!("Replying to {}", original.username),
+                None => "Replying to a message".to_string(),
+            }),
+            (None, None) => {}
+        }
+        if !self.pending_attachments.is_empty() {
+            let names: Vec<_> = self.pending_attachments.iter().map(|attachment| attachment.name.as_str()).collect();
+            labels.push(format!("Attached {}", names.join(", ")));
+        }
+        if self.uploads_in_progress > 0 {
+            labels.push(format!("Uploading {} file(s)...", self.uploads_in_progress));
+        }
+        if labels.is_empty() {
+            return column!().into();
+        }
+        let label = labels.join(" · ");
+
         container(
-            column!(
-                row!(
-                    text(username).color(text_chat_header()).size(12),
-                    space::horizontal(),
-                    text(time).color(text_chat_header()).size(12)
-                ),
-                text(message).color(text_primary()).size(14)
+            row!(
+                text(label).color(text_secondary()).size(12),
+                space::horizontal(),
+                Widgets::icon_button(Icons::x_circle_fill(text_secondary(), 14))
+                    .on_press(RoomPageMessage::ComposeCancelled.into()),
             )
-            .spacing(4),
+            .align_y(Alignment::Center),
         )
-        .padding(8)
+        .padding(Padding {
+            right: 32.0,
+            bottom: 8.0,
+            left: 32.0,
+            top: 0.0,
+        })
+        .into()
+    }
+
+    /// Shows who is typing in the room chat
+    fn typing_banner(&self) -> Element<'_, Message> {
+        if self.direct_peer.is_some() {
+            return column!().into();
+        }
+
+        let mut names: Vec<_> = self
+            .typing_users
+            .iter()
+            .filter_map(|user_id| self.participants.get(user_id).map(|p| p.username.clone()))
+            .collect();
+        names.sort();
+
+        let label = match names.as_slice() {
+            [] => return column!().into(),
+            [name] => format!("{} is typing...", name),
+            [first, second] => format!("{} and {} are typing...", first, second),
+            _ => "Several people are typing...".to_string(),
+        };
+
+        container(text(label).color(text_secondary()).size(12))
+            .padding(Padding {
+                right: 32.0,
+                bottom: 8.0,
+                left: 32.0,
+                top: 0.0,
+            })
+            .into()
+    }
+
+    /// Tells the server we have read the room chat up to its newest message, if it moved forward
+    fn mark_read(&mut self) -> Task<Message> {
+        let newest = self.chat_history.keys().rev().map(|&(_, message_id)| message_id).find(|&id| id != 0);
+        match newest {
+            Some(message_id) if self.direct_peer.is_none() && message_id > self.read_marker => {
+                self.read_marker = message_id;
+                Task::done(Message::ExecuteVoiceCommand(VoiceCommand::MarkRead(message_id)))
+            }
+            _ => Task::none(),
+        }
+    }
+
+    /// Shows whose direct messages are open, with a button back to the room chat
+    fn direct_banner(&self) -> Element<'_, Message> {
+        let Some(user_id) = self.direct_peer else {
+            return column!().into();
+        };
+
+        container(
+            row!(
+                Icons::envelope_simple_fill(text_secondary(), 14),
+                text(format!("Direct messages with {}", self.username_of(user_id))).color(text_secondary()).size(12),
+                space::horizontal(),
+                Widgets::icon_button(Icons::x_circle_fill(text_secondary(), 14))
+                    .on_press(RoomPageMessage::DirectMessageClosed.into()),
+            )
+            .spacing(8)
+            .align_y(Alignment::Center),
+        )
+        .padding(Padding {
+            right: 32.0,
+            bottom: 8.0,
+            left: 32.0,
+            top: 16.0,
+        })
+        .into()
+    }
+
+    fn username_of(&self, user_id: u64) -> String {
+        self.participants.get(&user_id).map(|p| p.username.clone()).unwrap_or_else(|| format!("#{}", user_id))
+    }
+
+    /// First line of a message, shortened to fit in a reply quote
+    fn excerpt(message: &str) -> String {
+        const MAX_CHARS: usize = 60;
+        let line = message.lines().next().unwrap_or_default();
+        if line.chars().count() > MAX_CHARS || line.len() < message.len() {
+            format!("{}…", line.chars().take(MAX_CHARS).collect::<String>())
+        } else {
+            line.to_string()
+        }
+    }
+
+    /// Messages are ours if they carry our username, like the server decides who may edit them
+    fn is_own_message(&self, chat_msg: &ChatMessage) -> bool {
+        self.participants
+            .get(&self.user_id)
+            .is_some_and(|user| user.username.eq_ignore_ascii_case(&chat_msg.username))
+    }
+
+    fn find_chat_message(&self, message_id: u64) -> Option<&ChatMessage> {
+        let timestamp = self.message_timestamps.get(&message_id)?;
+        self.chat_history.get(&(*timestamp, message_id))
+    }
+
+    fn find_chat_message_mut(&mut self, message_id: u64) -> Option<&mut ChatMessage> {
+        let timestamp = self.message_timestamps.get(&message_id)?;
+        self.chat_history.get_mut(&(*timestamp, message_id))
+    }
+
+    fn insert_chat_message(&mut self, message_id: u64, timestamp: u64, chat_msg: ChatMessage) {
+        self.message_timestamps.insert(message_id, timestamp);
+        self.chat_history.insert((timestamp, message_id), chat_msg);
+    }
+
+    fn remove_chat_message(&mut self, message_id: u64) {
+        if let Some(timestamp) = self.message_timestamps.remove(&message_id) {
+            self.chat_history.remove(&(timestamp, message_id));
+        }
+        if self.reply_to == Some(message_id) {
+            self.reply_to = None;
+        }
+        if self.reaction_picker == Some(message_id) {
+            self.reaction_picker = None;
+        }
+        if self.editing == Some(message_id) {
+            self.editing = None;
+            self.chat_message.clear();
+        }
     }
 
     fn mute_slider<'a>(muted: bool) -> iced::widget::Button<'a, Message> {
+        Self::toggle_slider(
+            muted,
+            Icons::microphone_slash_fill,
+            Icons::microphone_fill,
+            RoomPageMessage::MuteToggle,
+        )
+    }
+
+    fn deafen_slider<'a>(deafened: bool) -> iced::widget::Button<'a, Message> {
+        Self::toggle_slider(
+            deafened,
+            Icons::speaker_slash_fill,
+            Icons::speaker_high_fill,
+            RoomPageMessage::DeafenToggle,
+        )
+    }
+
+    /// Slider with the `off_icon` highlighted on the left when `off` is set, `on_icon` on the right otherwise
+    fn toggle_slider<'a>(
+        off: bool,
+        off_icon: fn(Color, u16) -> Element<'a, Message>,
+        on_icon: fn(Color, u16) -> Element<'a, Message>,
+        message: RoomPageMessage,
+    ) -> iced::widget::Button<'a, Message> {
         let inner_circle_style = |_theme: &iced::Theme| Style {
             background: Some(Background::Color(slider_thumb())),
             border: border::rounded(30),
@@ -331,7 +881,7 @@ impl RoomPage {
 
         let inner_circle = container("").width(12).height(12).style(inner_circle_style);
 
-        let inner_circle_position = if muted {
+        let inner_circle_position = if off {
             Horizontal::Left
         } else {
             Horizontal::Right
@@ -344,35 +894,43 @@ impl RoomPage {
             .center_y(14)
             .style(outer_container_style);
 
-        let icon_left_color = if muted {
+        let icon_left_color = if off {
             color_alert()
         } else {
             text_secondary()
         };
 
-        let icon_right_color = if muted {
+        let icon_right_color = if off {
             text_secondary()
         } else {
             color_success()
         };
 
         let row = row!(
-            Icons::microphone_slash_fill(icon_left_color, 24),
+            off_icon(icon_left_color, 24),
             outer_container,
-            Icons::microphone_fill(icon_right_color, 24),
+            on_icon(icon_right_color, 24),
         );
 
         Widgets::container_button(container(row.spacing(8).align_y(Vertical::Center)))
-            .on_press(RoomPageMessage::MuteToggle.into())
+            .on_press(message.into())
     }
 
     fn member<'a>(
         username: &str,
+        role: Role,
         in_voice: bool,
         muted: bool,
+        deafened: bool,
+        server_muted: bool,
+        unread_direct: bool,
     ) -> Container<'a, Message> {
         let icon = if in_voice {
-            if muted {
+            if server_muted {
+                Icons::microphone_slash_fill(color_alert(), 16)
+            } else if deafened {
+                Icons::speaker_slash_fill(color_error(), 16)
+            } else if muted {
                 Icons::microphone_slash_fill(color_error(), 16)
             } else {
                 Icons::microphone_fill(color_success(), 16)
@@ -381,17 +939,32 @@ impl RoomPage {
             Icons::chat_teardrop_dots_fill(text_secondary(), 16)
         };
 
+        let badge = match role {
+            Role::Admin => "ADMIN",
+            Role::Moderator => "MOD",
+            Role::Guest => "GUEST",
+            _ => "",
+        };
+
         let username_owned = username.to_string();
-        container(
-            row!(
-                icon,
-                container(text(username_owned).size(14).color(text_primary())).padding(Padding {
-                    top: 1.2,
-                    ..Padding::default()
-                })
-            )
-            .spacing(8),
+        let mut member_row = row!(
+            icon,
+            container(text(username_owned).size(14).color(text_primary())).padding(Padding {
+                top: 1.2,
+                ..Padding::default()
+            }),
+            container(text(badge).size(10).color(text_secondary())).padding(Padding {
+                top: 4.0,
+                ..Padding::default()
+            })
         )
+        .spacing(8);
+
+        if unread_direct {
+            member_row = member_row.push(space::horizontal()).push(Icons::envelope_simple_fill(color_alert(), 16));
+        }
+
+        container(member_row)
         .padding(Padding {
             top: 8.0,
             right: 12.0,
@@ -401,12 +974,15 @@ impl RoomPage {
         .width(Length::Fill)
     }
 
+    /// Renders a titled list of members. Sections with a title action (voice channels)
+    /// are shown even when empty, so that they can be joined.
     fn render_members_section<'a>(
         &self,
         title: &str,
         participants: Vec<&'a ParticipantInfo>,
+        on_title_press: Option<Message>,
     ) -> Vec<Element<'a, Message>> {
-        if participants.is_empty() {
+        if participants.is_empty() && on_title_press.is_none() {
             return Vec::new();
         }
 
@@ -420,25 +996,40 @@ impl RoomPage {
 
         // Add title
         let title_owned = title.to_string();
-        elements.push(
-            container(text(title_owned).size(12).color(text_secondary()))
-                .padding(Padding {
-                    top: 16.0,
-                    right: 16.0,
-                    bottom: 4.0,
-                    left: 16.0,
-                })
-                .width(Length::Fill)
-                .into(),
-        );
+        let title_container = container(text(title_owned).size(12).color(text_secondary()))
+            .padding(Padding {
+                top: 16.0,
+                right: 16.0,
+                bottom: 4.0,
+                left: 16.0,
+            })
+            .width(Length::Fill);
+
+        match on_title_press {
+            Some(message) => elements.push(
+                mouse_area(title_container)
+                    .on_press(message)
+                    .interaction(Interaction::Pointer)
+                    .into(),
+            ),
+            None => elements.push(title_container.into()),
+        }
+
+        if participants.is_empty() {
+            return elements;
+        }
 
         // Add members
         let mut members_column = iced::widget::Column::new();
         for participant in participants {
             let member_container = mouse_area(Self::member(
                 &participant.username,
-                participant.in_voice,
+                participant.role,
+                participant.in_voice(),
                 participant.is_muted,
+                participant.is_deafened,
+                self.server_muted.contains(&participant.user_id),
+                self.unread_direct.contains(&participant.user_id),
             )).on_right_press(RoomPageMessage::UserClicked(participant.user_id).into()).interaction(Interaction::Pointer);
 
             let user_volume_value = if let Some(user_volume) = self.volume_per_user.get(&participant.user_id) {
@@ -467,13 +1058,33 @@ impl RoomPage {
                     },
                 });
 
+            let mut settings_column = column!(
+                text("User volume").font(bold).size(12),
+                row!(user_volume_slider, text(user_volume_value).font(bold).size(12)).spacing(4)
+            ).spacing(8);
+
+            if self.has_permission(Permissions::CHAT) {
+                settings_column = settings_column.push(
+                    Widgets::container_button(container(text("Message").size(12).color(text_primary())).padding(4))
+                        .on_press(RoomPageMessage::DirectMessageOpened(participant.user_id).into()),
+                );
+            }
+
+            // Moderation actions are only offered for users of a lower role
+            if self.can_moderate(participant) {
+                let server_muted = self.server_muted.contains(&participant.user_id);
+                settings_column = settings_column.push(row!(
+                    Self::moderation_button(
+                        if server_muted { "Unmute" } else { "Mute" },
+                        RoomPageMessage::ServerMuteToggle(participant.user_id),
+                    ),
+                    Self::moderation_button("Kick", RoomPageMessage::KickUser(participant.user_id)),
+                    Self::moderation_button("Ban", RoomPageMessage::BanUser(participant.user_id)),
+                ).spacing(4));
+            }
+
             let member_settings = container(
-                container(
-                    column!(
-                        text("User volume").font(bold).size(12),
-                        row!(user_volume_slider, text(user_volume_value).font(bold).size(12)).spacing(4)
-                    ).spacing(8)
-                )
+                container(settings_column)
                     .padding(12)
                     .style(|_theme| {
                         Style {
@@ -516,11 +1127,69 @@ impl RoomPage {
         elements
     }
 
+    /// Small text button for the member dropdown
+    fn moderation_button<'a>(label: &'a str, message: RoomPageMessage) -> iced::widget::Button<'a, Message> {
+        Widgets::container_button(container(text(label).size(12).color(color_alert())).padding(4))
+            .on_press(message.into())
+    }
+
+    /// Returns true if our role grants `permission`
+    fn has_permission(&self, permission: Permissions) -> bool {
+        self.participants
+            .get(&self.user_id)
+            .is_some_and(|p| p.permissions.contains(permission))
+    }
+
+    /// Returns true if we may moderate `target`, the server applies the same rule
+    fn can_moderate(&self, target: &ParticipantInfo) -> bool {
+        let rank = |role: Role| match role {
+            Role::Admin => 3,
+            Role::Moderator => 2,
+            Role::Member => 1,
+            _ => 0,
+        };
+
+        self.participants.get(&self.user_id).is_some_and(|me| {
+            me.permissions.contains(Permissions::MODERATE) && rank(me.role) > rank(target.role)
+        })
+    }
+
     fn is_in_voice(&self) -> bool {
+        self.current_channel().is_some()
+    }
+
+    fn current_channel(&self) -> Option<u64> {
         self.participants
             .get(&self.user_id)
-            .map(|p| p.in_voice)
-            .unwrap_or(false)
+            .and_then(|p| p.channel_id)
+    }
+
+    /// Show a notice from the server in the chat
+    fn add_server_message(&mut self, message: String) {
+        let mut timestamp = Self::now_ms();
+
+        // Notices share message id 0, keep one shown in the same millisecond as another
+        while self.chat_history.contains_key(&(timestamp, 0)) {
+            timestamp += 1;
+        }
+        self.chat_history.insert((timestamp, 0), ChatMessage::new("Server".to_string(), message, timestamp));
+    }
+
+    fn now_ms() -> u64 {
+        std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .map(|duration| duration.as_millis() as u64)
+            .unwrap_or_default()
+    }
+
+    /// Adds a direct message to the conversation with `user_id`, scrolling down if it is open
+    fn push_direct_message(&mut self, user_id: u64, chat_msg: ChatMessage) -> Task<Message> {
+        self.direct_messages.entry(user_id).or_default().push(chat_msg);
+
+        if self.direct_peer != Some(user_id) {
+            return Task::none();
+        }
+        iced::widget::operation::snap_to(Id::new("chat_area_scroll"), scrollable::RelativeOffset::END)
     }
 
     fn format_bytes(bytes: u64) -> String {
@@ -590,6 +1259,10 @@ impl View for RoomPage {
         match message {
             Message::RoomPage(room_message) => match room_message {
                 RoomPageMessage::MuteToggle => {
+                    if self.server_muted.contains(&self.user_id) || !self.has_permission(Permissions::SPEAK) {
+                        return Task::none();
+                    }
+
                     self.muted = !self.muted;
 
                     if let Some(user) = self.participants.get_mut(&self.user_id) {
@@ -598,6 +1271,15 @@ impl View for RoomPage {
 
                     return Task::done(Message::MuteInput(self.muted));
                 }
+                RoomPageMessage::DeafenToggle => {
+                    self.deafened = !self.deafened;
+
+                    if let Some(user) = self.participants.get_mut(&self.user_id) {
+                        user.is_deafened = self.deafened;
+                    }
+
+                    return Task::done(Message::DeafenOutput(self.deafened));
+                }
                 RoomPageMessage::JoinLeaveToggle => {
                     if self.is_in_voice() {
                         return Task::done(Message::ExecuteVoiceCommand(
@@ -605,24 +1287,150 @@ impl View for RoomPage {
                         ));
                     }
 
-                    return Task::done(Message::ExecuteVoiceCommand(
-                        VoiceCommand::JoinVoiceChannel,
-                    ));
+                    // Join the first channel by default
+                    if let Some(channel_id) = self.channels.keys().next() {
+                        return Task::done(Message::ExecuteVoiceCommand(
+                            VoiceCommand::JoinVoiceChannel(*channel_id),
+                        ));
+                    }
+                }
+                RoomPageMessage::ChannelClicked(channel_id) => {
+                    if self.current_channel() != Some(channel_id) {
+                        return Task::done(Message::ExecuteVoiceCommand(
+                            VoiceCommand::JoinVoiceChannel(channel_id),
+                        ));
+                    }
                 }
                 RoomPageMessage::ChatMessageChanged(value) => {
                     if value.len() <= 2000 {
                         self.chat_message = value;
                     }
+
+                    // Only new room chat messages are announced, repeated while typing but not on every key
+                    if self.direct_peer.is_some() || self.editing.is_some() {
+                        return Task::none();
+                    }
+                    if self.chat_message.is_empty() {
+                        if self.typing_sent_at.take().is_some() {
+                            return Task::done(Message::ExecuteVoiceCommand(VoiceCommand::SetTyping(false)));
+                        }
+                    } else if self
+                        .typing_sent_at
+                        .is_none_or(|sent| sent.elapsed() >= Duration::from_millis(u64::from(TYPING_INTERVAL_MS)))
+                    {
+                        self.typing_sent_at = Some(Instant::now());
+                        return Task::done(Message::ExecuteVoiceCommand(VoiceCommand::SetTyping(true)));
+                    }
                 }
                 RoomPageMessage::ChatMessageSubmitted => {
-                    if !self.chat_message.is_empty() {
-                        let message = self.chat_message.clone();
-                        self.chat_message.clear();
-                        return Task::done(Message::ExecuteVoiceCommand(
-                            VoiceCommand::SendChatMessage(message),
-                        ));
+                    // The server rejects blank messages, unless they carry files
+                    let sends_attachments = self.direct_peer.is_none()
+                        && self.editing.is_none()
+                        && !self.pending_attachments.is_empty();
+                    if !self.chat_message.trim().is_empty() || sends_attachments {
+                        // The server stops the typing indicator when the message arrives
+                        self.typing_sent_at = None;
+                        if self.chat_message.trim().is_empty() {
+                            self.chat_message.clear();
+                        }
+                        let message = std::mem::take(&mut self.chat_message);
+                        let command = match (self.direct_peer, self.editing.take()) {
+                            (Some(user_id), _) => VoiceCommand::SendDirectMessage(user_id, message),
+                            (None, Some(message_id)) => VoiceCommand::EditChatMessage(message_id, message),
+                            (None, None) => {
+                                let attachments = std::mem::take(&mut self.pending_attachments)
+                                    .into_iter()
+                                    .map(|attachment| attachment.attachment_id)
+                                    .collect();
+                                VoiceCommand::SendChatMessage(message, self.reply_to.take(), attachments)
+                            }
+                        };
+                        return Task::done(Message::ExecuteVoiceCommand(command));
                     }
                 },
+                RoomPageMessage::MessageHovered(message_id) => {
+                    self.hovered_message = Some(message_id);
+                }
+                RoomPageMessage::MessageUnhovered(message_id) => {
+                    if self.hovered_message == Some(message_id) {
+                        self.hovered_message = None;
+                    }
+                }
+                RoomPageMessage::ReplyClicked(message_id) => {
+                    if self.editing.take().is_some() {
+                        self.chat_message.clear();
+                    }
+                    self.reply_to = Some(message_id);
+                }
+                RoomPageMessage::EditClicked(message_id) => {
+                    if let Some(chat_msg) = self.find_chat_message(message_id) {
+                        self.chat_message = chat_msg.message.clone();
+                        self.editing = Some(message_id);
+                        self.reply_to = None;
+                    }
+                }
+                RoomPageMessage::DeleteClicked(message_id) => {
+                    return Task::done(Message::ExecuteVoiceCommand(VoiceCommand::DeleteChatMessage(message_id)));
+                }
+                RoomPageMessage::ReactionPickerToggled(message_id) => {
+                    let open = self.reaction_picker != Some(message_id);
+                    self.reaction_picker = open.then_some(message_id);
+                }
+                RoomPageMessage::ReactionToggled(message_id, emoji) => {
+                    self.reaction_picker = None;
+                    let own_username = self.username_of(self.user_id);
+                    let Some(chat_msg) = self.find_chat_message(message_id) else {
+                        return Task::none();
+                    };
+
+                    // The server refuses adding a reaction twice, so picking one we already have removes it
+                    let reacted = !chat_msg.has_reacted(&own_username, &emoji);
+                    let command = VoiceCommand::SetReaction(message_id, emoji, reacted);
+                    return Task::done(Message::ExecuteVoiceCommand(command));
+                }
+                RoomPageMessage::LinkClicked(url) => {
+                    Self::open_link(&url);
+                }
+                RoomPageMessage::AttachmentSaveClicked(attachment) => {
+                    return Task::done(Message::ExecuteVoiceCommand(VoiceCommand::SaveAttachment(attachment)));
+                }
+                RoomPageMessage::ComposeCancelled => {
+                    if self.editing.take().is_some() {
+                        self.chat_message.clear();
+                    }
+                    self.reply_to = None;
+                    self.pending_attachments.clear();
+                }
+                RoomPageMessage::DirectMessageOpened(user_id) => {
+                    self.selected_user_settings = None;
+                    if self.editing.take().is_some() {
+                        self.chat_message.clear();
+                    }
+                    self.reply_to = None;
+                    self.direct_peer = Some(user_id);
+                    self.unread_direct.remove(&user_id);
+                    return iced::widget::operation::snap_to(
+                        Id::new("chat_area_scroll"),
+                        scrollable::RelativeOffset::END,
+                    );
+                }
+                RoomPageMessage::DirectMessageClosed => {
+                    self.direct_peer = None;
+                    return Task::batch([
+                        iced::widget::operation::snap_to(Id::new("chat_area_scroll"), scrollable::RelativeOffset::END),
+                        self.mark_read(),
+                    ]);
+                }
+                RoomPageMessage::ChatScrolled(offset) => {
+                    // Fetch older messages once the top of the room chat is reached
+                    let in_room_chat = self.direct_peer.is_none();
+                    if in_room_chat && offset <= 0.0 && self.history_has_more && !self.history_loading {
+                        self.history_loading = true;
+                        return Task::done(Message::ExecuteVoiceCommand(VoiceCommand::LoadChatHistory(
+                            self.oldest_history_timestamp,
+                        )));
+                    }
+                }
                 RoomPageMessage::UserClicked(user_id) => {
                     self.selected_user_settings = Some(user_id);
                 }
@@ -632,22 +1440,38 @@ impl View for RoomPage {
                 RoomPageMessage::UserVolumeChanged(user_id, volume) => {
                     self.volume_per_user.insert(user_id, volume);
                 }
+                RoomPageMessage::ServerMuteToggle(user_id) => {
+                    self.selected_user_settings = None;
+                    let muted = !self.server_muted.contains(&user_id);
+                    return Task::done(Message::ExecuteVoiceCommand(VoiceCommand::ServerMuteUser(user_id, muted)));
+                }
+                RoomPageMessage::KickUser(user_id) => {
+                    self.selected_user_settings = None;
+                    return Task::done(Message::ExecuteVoiceCommand(VoiceCommand::KickUser(user_id)));
+                }
+                RoomPageMessage::BanUser(user_id) => {
+                    self.selected_user_settings = None;
+                    return Task::done(Message::ExecuteVoiceCommand(VoiceCommand::BanUser(user_id)));
+                }
             },
             Message::VoiceCommandResult(result) => match result {
                 VoiceCommandResult::JoinVoiceChannel(status) => {
-                    if status.is_ok() {
-                        if let Some(user) = self.participants.get_mut(&self.user_id) {
-                            user.in_voice = true;
-                            user.is_muted = false;
+                    match status {
+                        Ok(channel_id) => {
+                            // Without the speak permission the server keeps us muted
+                            let can_speak = self.has_permission(Permissions::SPEAK);
+                            if let Some(user) = self.participants.get_mut(&self.user_id) {
+                                user.channel_id = Some(channel_id);
+                                user.is_muted = !can_speak;
+                            }
                         }
-                    } else {
-                        warn!("Failed to join voice: {}", status.err().unwrap());
+                        Err(e) => warn!("Failed to join voice: {}", e),
                     }
                 }
                 VoiceCommandResult::LeaveVoiceChannel(status) => {
                     if status.is_ok() {
                         if let Some(user) = self.participants.get_mut(&self.user_id) {
-                            user.in_voice = false;
+                            user.channel_id = None;
                             user.is_muted = false;
                         }
                     } else {
@@ -656,7 +1480,91 @@ impl View for RoomPage {
                 }
                 VoiceCommandResult::SendChatMessage(status) => {
                     if let Err(e) = status {
-                        warn!("Failed to send message: {}", e);
+                        self.add_server_message(format!("Could not send message: {}", e));
+                    }
+                }
+                VoiceCommandResult::UploadAttachment(result) => {
+                    self.uploads_in_progress = self.uploads_in_progress.saturating_sub(1);
+                    match result {
+                        Ok(attachment) => self.pending_attachments.push(attachment),
+                        Err(e) => self.add_server_message(format!("Could not attach file: {}", e)),
+                    }
+                }
+                VoiceCommandResult::AttachmentPreview(attachment_id, result) => match result {
+                    Ok(data) => {
+                        self.attachment_previews.insert(attachment_id, Some(image::Handle::from_bytes(data)));
+                    }
+                    Err(e) => {
+                        warn!("Failed to load preview of attachment {}: {}", attachment_id, e);
+                        self.attachment_previews.remove(&attachment_id);
+                    }
+                },
+                VoiceCommandResult::SaveAttachment(result) => match result {
+                    Ok(path) => self.add_server_message(format!("Saved {}", path.display())),
+                    Err(e) => self.add_server_message(format!("Could not save file: {}", e)),
+                },
+                VoiceCommandResult::ChatAction(status) => {
+                    if let Err(e) = status {
+                        self.add_server_message(format!("Could not change message: {}", e));
+                    }
+                }
+                VoiceCommandResult::SendDirectMessage(user_id, message, result) => {
+                    let chat_msg = match result {
+                        Ok(timestamp) => {
+                            ChatMessage::new(self.username_of(self.user_id), message, timestamp)
+                        }
+                        Err(e) => ChatMessage::new(
+                            "Server".to_string(),
+                            format!("Could not send direct message: {}", e),
+                            Self::now_ms(),
+                        ),
+                    };
+                    return self.push_direct_message(user_id, chat_msg);
+                }
+                VoiceCommandResult::ChatHistory(result) => {
+                    self.history_loading = false;
+                    match result {
+                        Ok((messages, has_more)) => {
+                            let first_page = self.oldest_history_timestamp.is_none();
+                            match messages.first() {
+                                Some(oldest)
+                                    if self.oldest_history_timestamp.is_some_and(|t| t <= oldest.timestamp) => {}
+                                oldest => {
+                                    // Only pages reaching further back tell whether there is more to load
+                                    self.oldest_history_timestamp = oldest.map(|m| m.timestamp);
+                                    self.history_has_more = has_more;
+                                }
+                            }
+
+                            let mut previews = Vec::new();
+                            for message in messages {
+                                previews.push(self.load_previews(&message.attachments));
+                                let chat_msg = ChatMessage::new(message.username, message.message, message.timestamp)
+                                    .with_reply_to(message.reply_to)
+                                    .with_edited(message.edited)
+                                    .with_reactions(message.reactions)
+                                    .with_attachments(message.attachments);
+                                self.insert_chat_message(message.message_id, message.timestamp, chat_msg);
+                            }
+
+                            if first_page {
+                                return Task::batch([
+                                    iced::widget::operation::snap_to(
+                                        Id::new("chat_area_scroll"),
+                                        scrollable::RelativeOffset::END,
+                                    ),
+                                    self.mark_read(),
+                                    Task::batch(previews),
+                                ]);
+                            }
+                            return Task::batch([self.mark_read(), Task::batch(previews)]);
+                        }
+                        Err(e) => warn!("Failed to load chat history: {}", e),
+                    }
+                }
+                VoiceCommandResult::Moderate(status) => {
+                    if let Err(e) = status {
+                        self.add_server_message(format!("Moderation failed: {}", e));
                     }
                 }
                 VoiceCommandResult::Ping(result) => {
@@ -681,6 +1589,20 @@ impl View for RoomPage {
                     self.overlay_visible = !self.overlay_visible;
                 }
             }
+            Message::FileDropped(path) => {
+                // Dropped before logging in
+                if self.user_id == 0 || !self.has_permission(Permissions::CHAT) {
+                    return Task::none();
+                }
+                if self.direct_peer.is_some() {
+                    self.add_server_message("Files can only be attached in the room chat".to_string());
+                } else if self.pending_attachments.len() + self.uploads_in_progress >= MAX_ATTACHMENTS {
+                    self.add_server_message(format!("At most {} files can be attached to a message", MAX_ATTACHMENTS));
+                } else {
+                    self.uploads_in_progress += 1;
+                    return Task::done(Message::ExecuteVoiceCommand(VoiceCommand::UploadAttachment(path)));
+                }
+            }
             Message::ServerEventReceived(event) => match event {
                 ClientEvent::ParticipantsList {
                     user_id,
@@ -691,52 +1613,197 @@ impl View for RoomPage {
                         .into_iter()
                         .map(|info| (info.user_id, info))
                         .collect();
+
+                    // A new session, the server forgot our typing state and read marker
+                    self.typing_users.clear();
+                    self.typing_sent_at = None;
+                    self.read_marker = 0;
+
+                    // Files we uploaded belong to the previous session, and a restarted server reuses attachment ids
+                    self.pending_attachments.clear();
+                    self.attachment_previews.clear();
+
+                    // Sent on every login, so after a reconnect this also fetches messages missed meanwhile
+                    if !self.history_loading {
+                        self.history_loading = true;
+                        return Task::done(Message::ExecuteVoiceCommand(VoiceCommand::LoadChatHistory(None)));
+                    }
                 }
-                ClientEvent::UserJoinedServer { user_id, username } => {
-                    debug!("User {} joined server", username);
-                    self.participants.insert(
-                        user_id,
-                        ParticipantInfo::new(user_id, username, false, false),
-                    );
+                ClientEvent::UserJoinedServer { participant } => {
+                    debug!("User {} joined server", participant.username);
+                    self.participants.insert(participant.user_id, participant);
+                }
+                ClientEvent::UserRenamed { user_id, username } => {
+                    debug!("User {} renamed to {}", user_id, username);
+                    if let Some(user) = self.participants.get_mut(&user_id) {
+                        user.username = username;
+                    }
+                }
+                ClientEvent::Motd { message } => {
+                    self.add_server_message(message);
+                }
+                ClientEvent::ServerShuttingDown { reason, reconnect_after_ms } => {
+                    let message = if reconnect_after_ms > 0 {
+                        format!("{} (back in about {} s)", reason, reconnect_after_ms.div_ceil(1000))
+                    } else {
+                        reason
+                    };
+                    self.add_server_message(message);
+                }
+                ClientEvent::Reconnecting { attempt, delay_ms } => {
+                    debug!("Reconnecting in {} ms, attempt {}", delay_ms, attempt);
+                    if attempt == 1 {
+                        self.add_server_message("Connection lost, reconnecting...".to_string());
+                    }
+                }
+                ClientEvent::Reconnected { user_id, channel_id } => {
+                    self.user_id = user_id;
+                    if let Some(user) = self.participants.get_mut(&user_id) {
+                        user.channel_id = channel_id;
+                        user.is_muted = self.muted;
+                        user.is_deafened = self.deafened;
+                    }
+                    self.add_server_message("Reconnected".to_string());
+                }
+                ClientEvent::ReconnectFailed { reason } => {
+                    self.add_server_message(format!("Could not reconnect: {}", reason));
+                }
+                ClientEvent::ChannelsList { channels } => {
+                    self.channels = channels
+                        .into_iter()
+                        .map(|channel| (channel.channel_id, channel))
+                        .collect();
+                }
+                ClientEvent::ChannelCreated { channel } => {
+                    debug!("Channel {} created", channel.name);
+                    self.channels.insert(channel.channel_id, channel);
                 }
-                ClientEvent::UserJoinedVoice { user_id } => {
-                    debug!("User {} joined voice", user_id);
+                ClientEvent::UserJoinedVoice { user_id, channel_id } => {
+                    debug!("User {} joined voice channel {}", user_id, channel_id);
                     if let Some(user) = self.participants.get_mut(&user_id) {
-                        user.in_voice = true;
+                        user.channel_id = Some(channel_id);
                         user.is_muted = false;
                     }
                 }
                 ClientEvent::UserLeftVoice { user_id } => {
                     debug!("User {} left voice", user_id);
                     if let Some(user) = self.participants.get_mut(&user_id) {
-                        user.in_voice = false;
+                        user.channel_id = None;
                         user.is_muted = false;
                     }
                 }
                 ClientEvent::UserLeftServer { user_id } => {
                     debug!("User {} left server", user_id);
                     self.participants.remove(&user_id);
+                    self.server_muted.remove(&user_id);
+
+                    self.typing_users.remove(&user_id);
+                    self.read_markers.remove(&user_id);
+
+                    // Direct messages cannot reach the user anymore
+                    self.direct_messages.remove(&user_id);
+                    self.unread_direct.remove(&user_id);
+                    if self.direct_peer == Some(user_id) {
+                        self.direct_peer = None;
+                    }
                 }
                 ClientEvent::UserSentMessage {
+                    message_id,
                     user_id,
                     timestamp,
                     message,
+                    reply_to,
+                    mentions,
+                    attachments,
                 } => {
+                    self.typing_users.remove(&user_id);
                     if let Some(participant) = self.participants.get(&user_id) {
-                        let chat_msg =
-                            ChatMessage::new(participant.username.clone(), message, timestamp);
-                        self.chat_history.insert(timestamp, chat_msg);
-
-                        return iced::widget::operation::snap_to(
-                            Id::new("chat_area_scroll"),
-                            scrollable::RelativeOffset::END,
-                        );
+                        let chat_msg = ChatMessage::new(participant.username.clone(), message, timestamp)
+                            .with_reply_to(reply_to)
+                            .with_mentions_me(user_id != self.user_id && mentions.contains(&self.user_id))
+                            .with_attachments(attachments.clone());
+                        self.insert_chat_message(message_id, timestamp, chat_msg);
+
+                        return Task::batch([
+                            iced::widget::operation::snap_to(
+                                Id::new("chat_area_scroll"),
+                                scrollable::RelativeOffset::END,
+                            ),
+                            self.mark_read(),
+                            self.load_previews(&attachments),
+                        ]);
                     }
                 }
 
-                ClientEvent::UserMuteState { user_id, is_muted } => {
+                ClientEvent::MessageEdited { message_id, message } => {
+                    if let Some(chat_msg) = self.find_chat_message_mut(message_id) {
+                        chat_msg.message = message;
+                        chat_msg.edited = true;
+                    }
+                }
+                ClientEvent::MessageDeleted { message_id } => {
+                    self.remove_chat_message(message_id);
+                }
+                ClientEvent::MessageReactionChanged { message_id, user_id, emoji, reacted } => {
+                    let username = self.username_of(user_id);
+                    if let Some(chat_msg) = self.find_chat_message_mut(message_id) {
+                        chat_msg.set_reaction(&username, &emoji, reacted);
+                    }
+                }
+                ClientEvent::DirectMessageReceived { from_user_id, timestamp, message } => {
+                    if self.direct_peer != Some(from_user_id) {
+                        self.unread_direct.insert(from_user_id);
+                    }
+                    let chat_msg = ChatMessage::new(self.username_of(from_user_id), message, timestamp);
+                    return self.push_direct_message(from_user_id, chat_msg);
+                }
+                ClientEvent::TypingStarted { user_id } => {
+                    if user_id != self.user_id {
+                        self.typing_users.insert(user_id);
+                    }
+                }
+                ClientEvent::TypingStopped { user_id } => {
+                    self.typing_users.remove(&user_id);
+                }
+                ClientEvent::MessageRead { user_id, message_id } => {
+                    let marker = self.read_markers.entry(user_id).or_default();
+                    *marker = (*marker).max(message_id);
+                }
+                ClientEvent::UserMuteState { user_id, is_muted, is_deafened } => {
                     if let Some(user) = self.participants.get_mut(&user_id) {
                         user.is_muted = is_muted;
+                        user.is_deafened = is_deafened;
+                    }
+                }
+                ClientEvent::UserModerated { user_id, moderator_id, action, reason } => {
+                    let name = |id: u64| {
+                        self.participants.get(&id).map(|p| p.username.clone()).unwrap_or_else(|| format!("#{}", id))
+                    };
+                    let mut message = if user_id == self.user_id {
+                        format!("You were {} by {}", action, name(moderator_id))
+                    } else {
+                        format!("{} was {} by {}", name(user_id), action, name(moderator_id))
+                    };
+                    if !reason.is_empty() {
+                        message = format!("{}: {}", message, reason);
+                    }
+                    self.add_server_message(message);
+
+                    match action {
+                        ModerationAction::ServerMuted => {
+                            self.server_muted.insert(user_id);
+                            if let Some(user) = self.participants.get_mut(&user_id) {
+                                user.is_muted = true;
+                            }
+                            if user_id == self.user_id && !self.muted {
+                                self.muted = true;
+                                return Task::done(Message::MuteInput(true));
+                            }
+                        }
+                        ModerationAction::ServerUnmuted => {
+                            self.server_muted.remove(&user_id);
+                        }
+                        _ => {}
                     }
                 }
             },
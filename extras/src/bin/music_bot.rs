@@ -24,12 +24,17 @@
 //!
 //! # Specify custom servers
 //! music_bot --server 192.168.1.100:9001 --voice-server 192.168.1.100:9002 music.wav
+//!
+//! # Stream into a specific voice channel
+//! music_bot --channel Music music.wav
 //! ```
 //!
 //! # Options
 //!
 //! - `--server <addr>` - Management server address (default: `127.0.0.1:9001`)
 //! - `--voice-server <addr>` - Voice relay server address (default: `127.0.0.1:9002`)
+//! - `--channel <name>` - Voice channel to join (default: first channel on the server)
+//! - `--password <password>` - Server password, if the server requires one
 
 use std::time::{Duration, Instant};
 use tokio::time::sleep;
@@ -47,6 +52,8 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
 
     let mut server_addr = "127.0.0.1:9001".to_string();
     let mut voice_server_addr = "127.0.0.1:9002".to_string();
+    let mut channel_name = None;
+    let mut password = None;
     let mut wav_file = None;
 
     let mut i = 1;
@@ -70,6 +77,24 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
                     std::process::exit(1);
                 }
             }
+            "--channel" => {
+                i += 1;
+                if i < args.len() {
+                    channel_name = Some(args[i].clone());
+                } else {
+                    eprintln!("Error: --channel requires a name");
+                    std::process::exit(1);
+                }
+            }
+            "--password" => {
+                i += 1;
+                if i < args.len() {
+                    password = Some(args[i].clone());
+                } else {
+                    eprintln!("Error: --password requires a value");
+                    std::process::exit(1);
+                }
+            }
             arg if !arg.starts_with("--") => {
                 wav_file = Some(arg.to_string());
             }
@@ -89,6 +114,8 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
             eprintln!("Options:");
             eprintln!("  --server <addr>        Management server address (default: 127.0.0.1:9001)");
             eprintln!("  --voice-server <addr>  Voice relay server address (default: 127.0.0.1:9002)");
+            eprintln!("  --channel <name>       Voice channel to join (default: first channel)");
+            eprintln!("  --password <password>  Server password, if required");
             eprintln!();
             eprintln!("Example: music_bot --server 192.168.1.100:9001 music.wav");
             std::process::exit(1);
@@ -146,10 +173,21 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
     // Connect to voice server
     info!("Connecting to voice servers...");
     let client = Client::new();
-    client.connect(&server_addr, &voice_server_addr, "music_bot").await?;
-    client.join_channel().await?;
+    client.connect(&server_addr, &voice_server_addr, "music_bot", password.as_deref()).await?;
     info!("Connected!");
 
+    let channels = client.list_channels().await?;
+    let channel = match &channel_name {
+        Some(name) => channels.iter().find(|c| c.name.eq_ignore_ascii_case(name)),
+        None => channels.first(),
+    };
+    let Some(channel) = channel else {
+        error!("Voice channel not found: {}", channel_name.unwrap_or_default());
+        return Err("Voice channel not found".into());
+    };
+    client.join_channel(channel.channel_id).await?;
+    info!("Joined voice channel: {}", channel.name);
+
     let voice_input_tx = client.get_voice_input_sender(48000)?;
 
     // Stream the WAV file in 20ms frames (960 samples at 48kHz)
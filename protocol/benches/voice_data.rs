@@ -0,0 +1,82 @@
+//! Hot path of the voice relay: decoding a `VoiceData` datagram and forwarding it to a recipient.
+//!
+//! Run with `cargo bench -p voiceapp-protocol`. The owned variants are what the relay did before
+//! `PacketRef`, the borrowed ones decode and reseal the datagram in reused buffers.
+
+use std::hint::black_box;
+use criterion::{criterion_group, criterion_main, Criterion};
+use voiceapp_protocol::crypto::{VoiceCipher, VoiceDirection, VOICE_KEY_LEN, VOICE_TAG_LEN};
+use voiceapp_protocol::{Packet, PacketRef, VoiceDataRef, VOICE_DATA_HEADER_LEN};
+
+const SENDER_ID: u64 = 7;
+const OPUS_FRAME_LEN: usize = 120; // 20 ms at 48 kbit/s
+
+/// A datagram as the relay receives it, sealed with the sender's key
+fn datagram(sender: &VoiceCipher) -> Vec<u8> {
+    let data = sender.seal(VoiceDirection::ToRelay, SENDER_ID, 1, 960, &[0x5a; OPUS_FRAME_LEN]);
+    Packet::VoiceData { user_id: 0, sequence: 1, timestamp: 960, data }.encode().unwrap()
+}
+
+fn decode(c: &mut Criterion) {
+    let datagram = datagram(&VoiceCipher::new(&[1; VOICE_KEY_LEN]));
+    let mut group = c.benchmark_group("decode_voice_data");
+
+    group.bench_function("owned", |b| b.iter(|| Packet::decode(black_box(&datagram)).unwrap()));
+    group.bench_function("borrowed", |b| b.iter(|| PacketRef::decode(black_box(&datagram)).unwrap()));
+
+    group.finish();
+}
+
+/// Forwarding as the relay did before `PacketRef`: decode, open, seal and encode a new packet
+fn forward_reencode(sender: &VoiceCipher, recipient: &VoiceCipher, datagram: &[u8]) -> Vec<u8> {
+    let Ok((Packet::VoiceData { sequence, timestamp, data, .. }, _)) = Packet::decode(datagram) else {
+        unreachable!()
+    };
+    let opus_frame = sender.open(VoiceDirection::ToRelay, SENDER_ID, sequence, timestamp, &data).unwrap();
+    let data = recipient.seal(VoiceDirection::FromRelay, SENDER_ID, sequence, timestamp, &opus_frame);
+    Packet::VoiceData { user_id: SENDER_ID, sequence, timestamp, data }.encode().unwrap()
+}
+
+/// Forwarding in place: open the payload in the receive buffer, copy it to a reused buffer and seal it there
+fn forward_in_place(sender: &VoiceCipher, recipient: &VoiceCipher, received: &mut [u8], forwarded: &mut Vec<u8>) {
+    let Ok((PacketRef::VoiceData(voice_data), size)) = PacketRef::decode(received) else {
+        unreachable!()
+    };
+    let (sequence, timestamp) = (voice_data.sequence, voice_data.timestamp);
+    let payload = &mut received[VOICE_DATA_HEADER_LEN..size];
+    sender.open_in_place(VoiceDirection::ToRelay, SENDER_ID, sequence, timestamp, payload).unwrap();
+
+    forwarded.clear();
+    forwarded.extend_from_slice(&received[..size]);
+    VoiceDataRef::rewrite_header(forwarded, SENDER_ID, sequence).unwrap();
+    let payload = &mut forwarded[VOICE_DATA_HEADER_LEN..];
+    recipient.seal_in_place(VoiceDirection::FromRelay, SENDER_ID, sequence, timestamp, payload);
+}
+
+fn forward(c: &mut Criterion) {
+    let sender = VoiceCipher::new(&[1; VOICE_KEY_LEN]);
+    let recipient = VoiceCipher::new(&[2; VOICE_KEY_LEN]);
+    let datagram = datagram(&sender);
+    let mut received = datagram.clone();
+    let mut forwarded = Vec::with_capacity(datagram.len());
+
+    // Both paths produce the same bytes
+    let expected = forward_reencode(&sender, &recipient, &datagram);
+    forward_in_place(&sender, &recipient, &mut received, &mut forwarded);
+    assert_eq!(forwarded, expected);
+    assert_eq!(expected.len(), VOICE_DATA_HEADER_LEN + OPUS_FRAME_LEN + VOICE_TAG_LEN);
+
+    let mut group = c.benchmark_group("forward_voice_data");
+    group.bench_function("reencode", |b| b.iter(|| forward_reencode(&sender, &recipient, black_box(&datagram))));
+    group.bench_function("in_place", |b| {
+        b.iter(|| {
+            // Stands in for the socket writing into the receive buffer
+            received.copy_from_slice(black_box(&datagram));
+            forward_in_place(&sender, &recipient, &mut received, &mut forwarded);
+        });
+    });
+    group.finish();
+}
+
+criterion_group!(benches, decode, forward);
+criterion_main!(benches);
@@ -0,0 +1,30 @@
+//! Decodes arbitrary bytes with both framings. Decoding must never panic, must consume exactly the
+//! length its header declares, and whatever decodes must encode again to the same packet.
+
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use voiceapp_protocol::{Framing, Packet, PacketRef};
+
+fn check(data: &[u8], framing: Framing) {
+    let Ok((packet, size)) = Packet::decode_with(data, framing) else {
+        return;
+    };
+    let declared = match framing {
+        Framing::Standard => 3 + usize::from(u16::from_be_bytes([data[1], data[2]])),
+        Framing::Extended => 5 + u32::from_be_bytes([data[1], data[2], data[3], data[4]]) as usize,
+    };
+    assert_eq!(size, declared);
+    assert!(size <= data.len());
+
+    let encoded = packet.encode_with(framing).expect("decoded packet does not encode");
+    assert_eq!(Packet::decode_with(&encoded, framing), Ok((packet, encoded.len())));
+}
+
+fuzz_target!(|data: &[u8]| {
+    check(data, Framing::Standard);
+    check(data, Framing::Extended);
+
+    let borrowed = PacketRef::decode(data).map(|(packet, size)| (packet.into_owned(), size));
+    assert_eq!(borrowed, Packet::decode(data));
+});
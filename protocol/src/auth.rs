@@ -0,0 +1,126 @@
+//! Challenge-response login authentication.
+//!
+//! The secret never travels over the wire. The client derives a per-user key
+//! `Argon2id(secret, SHA-256(domain || salt || lowercase username))` from the salt the
+//! server sends in `ServerHello`, so keys differ between users and servers and are slow
+//! to guess. The server only keeps `SHA-256(key)`, the stored key, which cannot log in.
+//! It also sends a random challenge, and the client answers in `LoginRequest` with
+//! `key XOR HMAC-SHA256(stored key, challenge)`.
+
+use argon2::Argon2;
+use hmac::{Hmac, Mac};
+use sha2::{Digest, Sha256};
+
+/// Length of the server challenge in bytes.
+pub const AUTH_CHALLENGE_LEN: usize = 32;
+
+/// Length of the server salt in bytes.
+pub const AUTH_SALT_LEN: usize = 16;
+
+/// Length of derived keys, stored keys and login proofs in bytes.
+pub const AUTH_PROOF_LEN: usize = 32;
+
+const SALT_DOMAIN: &[u8] = b"voiceapp-login-salt-v2";
+
+type HmacSha256 = Hmac<Sha256>;
+
+/// Derives the per-user key from a username, a secret and the server salt.
+///
+/// Usernames differing only in ASCII case get the same key. This is deliberately slow,
+/// clients derive it once per login.
+///
+/// # Panics
+/// Does not panic in practice, the default Argon2 parameters accept the fixed salt and key lengths.
+#[must_use]
+pub fn derive_key(
+    username: &str,
+    secret: &str,
+    salt: &[u8; AUTH_SALT_LEN],
+) -> [u8; AUTH_PROOF_LEN] {
+    let mut hasher = Sha256::new();
+    hasher.update(SALT_DOMAIN);
+    hasher.update(salt);
+    hasher.update((username.len() as u64).to_be_bytes());
+    hasher.update(username.to_ascii_lowercase().as_bytes());
+    let user_salt: [u8; 32] = hasher.finalize().into();
+
+    let mut key = [0u8; AUTH_PROOF_LEN];
+    Argon2::default()
+        .hash_password_into(secret.as_bytes(), &user_salt, &mut key)
+        .expect("default Argon2 parameters accept 32 byte salts and keys");
+    key
+}
+
+/// Returns the key servers store instead of the derived key, it verifies proofs but cannot compute them.
+#[must_use]
+pub fn stored_key(key: &[u8; AUTH_PROOF_LEN]) -> [u8; AUTH_PROOF_LEN] {
+    Sha256::digest(key).into()
+}
+
+/// Computes the login proof for a server challenge.
+#[must_use]
+pub fn compute_proof(
+    key: &[u8; AUTH_PROOF_LEN],
+    challenge: &[u8; AUTH_CHALLENGE_LEN],
+) -> [u8; AUTH_PROOF_LEN] {
+    xor(key, &challenge_mac(&stored_key(key), challenge))
+}
+
+/// Checks a login proof against a stored key in constant time.
+#[must_use]
+pub fn verify_proof(
+    stored: &[u8; AUTH_PROOF_LEN],
+    challenge: &[u8; AUTH_CHALLENGE_LEN],
+    proof: &[u8; AUTH_PROOF_LEN],
+) -> bool {
+    let key = xor(proof, &challenge_mac(stored, challenge));
+    let difference = stored_key(&key)
+        .iter()
+        .zip(stored)
+        .fold(0, |difference, (a, b)| difference | (a ^ b));
+    difference == 0
+}
+
+fn challenge_mac(
+    stored: &[u8; AUTH_PROOF_LEN],
+    challenge: &[u8; AUTH_CHALLENGE_LEN],
+) -> [u8; AUTH_PROOF_LEN] {
+    let mut mac = HmacSha256::new_from_slice(stored).expect("HMAC accepts keys of any length");
+    mac.update(challenge);
+    mac.finalize().into_bytes().into()
+}
+
+fn xor(a: &[u8; AUTH_PROOF_LEN], b: &[u8; AUTH_PROOF_LEN]) -> [u8; AUTH_PROOF_LEN] {
+    std::array::from_fn(|i| a[i] ^ b[i])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SALT: [u8; AUTH_SALT_LEN] = [9; AUTH_SALT_LEN];
+    const CHALLENGE: [u8; AUTH_CHALLENGE_LEN] = [3; AUTH_CHALLENGE_LEN];
+
+    #[test]
+    fn proof_verifies_against_stored_key() {
+        let key = derive_key("alice", "s3cret", &SALT);
+        let stored = stored_key(&key);
+        let proof = compute_proof(&key, &CHALLENGE);
+        assert!(verify_proof(&stored, &CHALLENGE, &proof));
+
+        // Another challenge, password, user or salt gives a different key or proof
+        assert!(!verify_proof(&stored, &[4; AUTH_CHALLENGE_LEN], &proof));
+        let guess = compute_proof(&derive_key("alice", "guess", &SALT), &CHALLENGE);
+        assert!(!verify_proof(&stored, &CHALLENGE, &guess));
+        assert_ne!(derive_key("bob", "s3cret", &SALT), key);
+        assert_eq!(derive_key("Alice", "s3cret", &SALT), key);
+        assert_ne!(derive_key("alice", "s3cret", &[8; AUTH_SALT_LEN]), key);
+    }
+
+    #[test]
+    fn stored_key_cannot_log_in() {
+        let stored = stored_key(&derive_key("alice", "s3cret", &SALT));
+        let forged = compute_proof(&stored, &CHALLENGE);
+        assert!(!verify_proof(&stored, &CHALLENGE, &forged));
+    }
+}
@@ -0,0 +1,296 @@
+//! Authenticated encryption of `VoiceData` payloads.
+//!
+//! Every user receives a random key in `LoginResponse`. Voice is encrypted hop by hop:
+//! the client seals its Opus frames with its own key, the relay opens them, and seals
+//! them again with each recipient's key. The relay therefore needs no channel keys
+//! and users can move between channels without re-keying.
+//!
+//! The cipher is ChaCha20-Poly1305. The nonce is built from the direction, the sending
+//! user and the packet sequence number, so it never repeats for a key as long as
+//! sequence numbers are not reused within a login. Relays renumber the packets they
+//! forward so sequence numbers keep rising when a user resumes its session under the
+//! same id. The `VoiceData` header fields are authenticated as associated data.
+
+use std::fmt;
+use chacha20poly1305::aead::{Aead, AeadInPlace, KeyInit, Payload};
+use chacha20poly1305::{ChaCha20Poly1305, Key, Nonce, Tag};
+use crate::error::ProtocolError;
+
+/// Length of a voice key in bytes.
+pub const VOICE_KEY_LEN: usize = 32;
+
+/// Bytes added to each payload by the authentication tag.
+pub const VOICE_TAG_LEN: usize = 16;
+
+/// Number of sequence numbers behind the newest one that are still accepted.
+pub const REPLAY_WINDOW_LEN: u32 = 64;
+
+/// Which hop a voice packet travels on. Part of the nonce, so both hops can share a key.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum VoiceDirection {
+    /// Client to relay, sealed with the sender's key.
+    ToRelay,
+    /// Relay to client, sealed with the recipient's key.
+    FromRelay,
+}
+
+/// Seals and opens voice payloads with a single user's key.
+#[derive(Clone)]
+pub struct VoiceCipher {
+    cipher: ChaCha20Poly1305,
+}
+
+impl VoiceCipher {
+    /// Creates a cipher from a key handed out in `LoginResponse`.
+    #[must_use]
+    pub fn new(key: &[u8; VOICE_KEY_LEN]) -> Self {
+        Self {
+            cipher: ChaCha20Poly1305::new(Key::from_slice(key)),
+        }
+    }
+
+    /// Encrypts an Opus frame, `user_id` is the user who spoke it.
+    ///
+    /// # Panics
+    /// Panics if the payload exceeds the cipher's size limit (about 256 GiB).
+    #[must_use]
+    pub fn seal(
+        &self,
+        direction: VoiceDirection,
+        user_id: u64,
+        sequence: u32,
+        timestamp: u32,
+        plaintext: &[u8],
+    ) -> Vec<u8> {
+        let aad = associated_data(user_id, sequence, timestamp);
+        self.cipher
+            .encrypt(
+                &nonce(direction, user_id, sequence),
+                Payload {
+                    msg: plaintext,
+                    aad: &aad,
+                },
+            )
+            .expect("voice payload too large")
+    }
+
+    /// Decrypts and authenticates a payload sealed with [`VoiceCipher::seal`].
+    ///
+    /// # Errors
+    /// Returns [`ProtocolError::DecryptionFailed`] if the payload or header was tampered with,
+    /// or was sealed with another key or direction.
+    pub fn open(
+        &self,
+        direction: VoiceDirection,
+        user_id: u64,
+        sequence: u32,
+        timestamp: u32,
+        ciphertext: &[u8],
+    ) -> Result<Vec<u8>, ProtocolError> {
+        let aad = associated_data(user_id, sequence, timestamp);
+        self.cipher
+            .decrypt(
+                &nonce(direction, user_id, sequence),
+                Payload {
+                    msg: ciphertext,
+                    aad: &aad,
+                },
+            )
+            .map_err(|_| ProtocolError::DecryptionFailed)
+    }
+
+    /// Like [`VoiceCipher::seal`] without allocating. `payload` holds the Opus frame followed by
+    /// [`VOICE_TAG_LEN`] bytes that are overwritten with the authentication tag.
+    ///
+    /// # Panics
+    /// Panics if `payload` is shorter than [`VOICE_TAG_LEN`] or exceeds the cipher's size limit.
+    pub fn seal_in_place(
+        &self,
+        direction: VoiceDirection,
+        user_id: u64,
+        sequence: u32,
+        timestamp: u32,
+        payload: &mut [u8],
+    ) {
+        let aad = associated_data(user_id, sequence, timestamp);
+        let (plaintext, tag) = payload.split_at_mut(payload.len() - VOICE_TAG_LEN);
+        let computed = self
+            .cipher
+            .encrypt_in_place_detached(&nonce(direction, user_id, sequence), &aad, plaintext)
+            .expect("voice payload too large");
+        tag.copy_from_slice(&computed);
+    }
+
+    /// Like [`VoiceCipher::open`] without allocating, decrypts `payload` in place and returns the
+    /// length of the Opus frame at its start.
+    ///
+    /// # Errors
+    /// Returns [`ProtocolError::DecryptionFailed`] like [`VoiceCipher::open`], the contents of `payload`
+    /// are unspecified afterwards.
+    pub fn open_in_place(
+        &self,
+        direction: VoiceDirection,
+        user_id: u64,
+        sequence: u32,
+        timestamp: u32,
+        payload: &mut [u8],
+    ) -> Result<usize, ProtocolError> {
+        let len = payload.len().checked_sub(VOICE_TAG_LEN).ok_or(ProtocolError::DecryptionFailed)?;
+        let aad = associated_data(user_id, sequence, timestamp);
+        let (ciphertext, tag) = payload.split_at_mut(len);
+        self.cipher
+            .decrypt_in_place_detached(&nonce(direction, user_id, sequence), &aad, ciphertext, Tag::from_slice(tag))
+            .map_err(|_| ProtocolError::DecryptionFailed)?;
+        Ok(len)
+    }
+}
+
+impl fmt::Debug for VoiceCipher {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("VoiceCipher").finish_non_exhaustive()
+    }
+}
+
+/// Nonce layout: `[direction: u8][user_id: low 7 bytes][sequence: u32]`.
+fn nonce(direction: VoiceDirection, user_id: u64, sequence: u32) -> Nonce {
+    let mut nonce = [0u8; 12];
+    nonce[0] = match direction {
+        VoiceDirection::ToRelay => 0,
+        VoiceDirection::FromRelay => 1,
+    };
+    nonce[1..8].copy_from_slice(&user_id.to_be_bytes()[1..]);
+    nonce[8..].copy_from_slice(&sequence.to_be_bytes());
+    nonce.into()
+}
+
+fn associated_data(user_id: u64, sequence: u32, timestamp: u32) -> [u8; 16] {
+    let mut aad = [0u8; 16];
+    aad[..8].copy_from_slice(&user_id.to_be_bytes());
+    aad[8..12].copy_from_slice(&sequence.to_be_bytes());
+    aad[12..].copy_from_slice(&timestamp.to_be_bytes());
+    aad
+}
+
+/// Sliding window over recently seen sequence numbers of one sender.
+///
+/// Only feed it packets that were successfully opened, otherwise forged packets
+/// could advance the window and get genuine ones dropped.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ReplayWindow {
+    newest: Option<u32>,
+    seen: u64, // Bit `n` is set if `newest - n` was accepted
+}
+
+impl ReplayWindow {
+    /// Creates an empty window.
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Records `sequence` and returns true if it was not seen before and is not too old.
+    pub fn accept(&mut self, sequence: u32) -> bool {
+        let Some(newest) = self.newest else {
+            self.newest = Some(sequence);
+            self.seen = 1;
+            return true;
+        };
+
+        if sequence > newest {
+            let shift = sequence - newest;
+            self.seen = if shift < REPLAY_WINDOW_LEN { (self.seen << shift) | 1 } else { 1 };
+            self.newest = Some(sequence);
+            return true;
+        }
+
+        let age = newest - sequence;
+        if age >= REPLAY_WINDOW_LEN || self.seen & (1 << age) != 0 {
+            return false;
+        }
+
+        self.seen |= 1 << age;
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const KEY: [u8; VOICE_KEY_LEN] = [7; VOICE_KEY_LEN];
+
+    #[test]
+    fn seal_open_roundtrip() {
+        let cipher = VoiceCipher::new(&KEY);
+        let sealed = cipher.seal(VoiceDirection::ToRelay, 3, 10, 4800, b"opus");
+        assert_eq!(sealed.len(), 4 + VOICE_TAG_LEN);
+        assert_eq!(cipher.open(VoiceDirection::ToRelay, 3, 10, 4800, &sealed).unwrap(), b"opus");
+    }
+
+    #[test]
+    fn open_rejects_tampering() {
+        let cipher = VoiceCipher::new(&KEY);
+        let mut sealed = cipher.seal(VoiceDirection::ToRelay, 3, 10, 4800, b"opus");
+
+        // Header fields are authenticated
+        assert!(cipher.open(VoiceDirection::ToRelay, 4, 10, 4800, &sealed).is_err());
+        assert!(cipher.open(VoiceDirection::ToRelay, 3, 11, 4800, &sealed).is_err());
+        assert!(cipher.open(VoiceDirection::ToRelay, 3, 10, 4801, &sealed).is_err());
+        assert!(cipher.open(VoiceDirection::FromRelay, 3, 10, 4800, &sealed).is_err());
+        assert!(VoiceCipher::new(&[8; VOICE_KEY_LEN])
+            .open(VoiceDirection::ToRelay, 3, 10, 4800, &sealed)
+            .is_err());
+
+        sealed[0] ^= 1;
+        assert_eq!(
+            cipher.open(VoiceDirection::ToRelay, 3, 10, 4800, &sealed),
+            Err(ProtocolError::DecryptionFailed)
+        );
+    }
+
+    #[test]
+    fn in_place_matches_allocating() {
+        let cipher = VoiceCipher::new(&KEY);
+        let sealed = cipher.seal(VoiceDirection::FromRelay, 3, 10, 4800, b"opus");
+
+        let mut payload = b"opus".to_vec();
+        payload.resize(4 + VOICE_TAG_LEN, 0);
+        cipher.seal_in_place(VoiceDirection::FromRelay, 3, 10, 4800, &mut payload);
+        assert_eq!(payload, sealed);
+
+        let len = cipher.open_in_place(VoiceDirection::FromRelay, 3, 10, 4800, &mut payload).unwrap();
+        assert_eq!(&payload[..len], b"opus");
+
+        let mut tampered = sealed.clone();
+        tampered[0] ^= 1;
+        assert_eq!(
+            cipher.open_in_place(VoiceDirection::FromRelay, 3, 10, 4800, &mut tampered),
+            Err(ProtocolError::DecryptionFailed)
+        );
+        assert_eq!(
+            cipher.open_in_place(VoiceDirection::FromRelay, 3, 10, 4800, &mut [0; VOICE_TAG_LEN - 1]),
+            Err(ProtocolError::DecryptionFailed)
+        );
+    }
+
+    #[test]
+    fn replay_window_rejects_duplicates_and_old_packets() {
+        let mut window = ReplayWindow::new();
+        assert!(window.accept(100));
+        assert!(!window.accept(100));
+
+        // Reordered packets inside the window are accepted once
+        assert!(window.accept(98));
+        assert!(window.accept(101));
+        assert!(!window.accept(98));
+        assert!(window.accept(99));
+
+        // Too old
+        assert!(!window.accept(101 - REPLAY_WINDOW_LEN));
+
+        // Large jump clears the history
+        assert!(window.accept(1000));
+        assert!(window.accept(1000 - REPLAY_WINDOW_LEN + 1));
+        assert!(!window.accept(1000));
+    }
+}
@@ -1,6 +1,6 @@
 use std::fmt;
 
-/// Protocol decoding errors.
+/// Protocol encoding and decoding errors.
 #[derive(Debug, Clone, PartialEq, Eq)]
 #[non_exhaustive]
 pub enum ProtocolError {
@@ -8,6 +8,8 @@ pub enum ProtocolError {
     UnknownPacketId(u8),
     InvalidUtf8,
     IncompletePayload { expected: usize, got: usize },
+    DecryptionFailed,
+    PayloadTooLarge { size: usize },
 }
 
 impl fmt::Display for ProtocolError {
@@ -21,12 +23,16 @@ impl fmt::Display for ProtocolError {
             }
             Self::UnknownPacketId(id) => write!(f, "unknown packet id: 0x{id:02x}"),
             Self::InvalidUtf8 => write!(f, "invalid UTF-8 encoding"),
+            Self::DecryptionFailed => write!(f, "voice payload failed authentication"),
             Self::IncompletePayload { expected, got } => {
                 write!(
                     f,
                     "incomplete payload: expected {expected} bytes, got {got}"
                 )
             }
+            Self::PayloadTooLarge { size } => {
+                write!(f, "payload too large: {size} bytes")
+            }
         }
     }
 }
@@ -0,0 +1,60 @@
+//! Packet headers and length prefixes, which widen once both peers speak [`EXTENDED_FRAMING_VERSION`].
+
+use crate::version::EXTENDED_FRAMING_VERSION;
+
+/// Largest payload of a packet framed with [`Framing::Extended`]. Longer packets are rejected
+/// as soon as their header arrives, so a bogus length cannot make a peer buffer without bound.
+pub const MAX_EXTENDED_PAYLOAD_LEN: usize = 16 * 1024 * 1024;
+
+/// How the payload length is written in packet headers, and string lengths and element counts in payloads.
+///
+/// `Hello` and `ServerHello` always use [`Framing::Standard`]. Right after `ServerHello` both peers
+/// switch to the framing of the negotiated revision, see [`Framing::for_version`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub enum Framing {
+    /// `[packet_id: u8][payload_len: u16][payload...]`, payloads up to 65535 bytes and u16 lengths and counts.
+    #[default]
+    Standard,
+    /// `[packet_id: u8][payload_len: u32][payload...]`, payloads up to [`MAX_EXTENDED_PAYLOAD_LEN`] bytes
+    /// and u32 lengths and counts.
+    Extended,
+}
+
+impl Framing {
+    /// Returns the framing spoken after negotiating `version`.
+    #[must_use]
+    pub const fn for_version(version: u16) -> Self {
+        if version >= EXTENDED_FRAMING_VERSION {
+            Self::Extended
+        } else {
+            Self::Standard
+        }
+    }
+
+    /// Returns the largest payload a packet header can announce.
+    #[must_use]
+    pub const fn max_payload_len(self) -> usize {
+        match self {
+            Self::Standard => u16::MAX as usize,
+            Self::Extended => MAX_EXTENDED_PAYLOAD_LEN,
+        }
+    }
+
+    /// Returns the bytes taken by a packet header, the packet id and the payload length.
+    ///
+    /// A buffer failing to decode with [`ProtocolError::PacketTooShort`](crate::ProtocolError::PacketTooShort)
+    /// is only incomplete while it is shorter than this, past the header the payload is malformed.
+    #[must_use]
+    pub const fn header_len(self) -> usize {
+        1 + self.len_prefix_size()
+    }
+
+    /// Returns the bytes taken by a string length or element count in payloads.
+    #[must_use]
+    pub const fn len_prefix_size(self) -> usize {
+        match self {
+            Self::Standard => 2,
+            Self::Extended => 4,
+        }
+    }
+}
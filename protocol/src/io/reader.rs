@@ -1,16 +1,29 @@
 use crate::error::ProtocolError;
+use crate::framing::{Framing, MAX_EXTENDED_PAYLOAD_LEN};
 
 /// Helper for reading binary data with automatic cursor advancement.
 pub struct Reader<'a> {
     data: &'a [u8],
     pos: usize,
+    framing: Framing, // Width of lengths read with `read_len`
 }
 
 impl<'a> Reader<'a> {
     #[inline]
     #[must_use]
     pub fn new(data: &'a [u8]) -> Self {
-        Self { data, pos: 0 }
+        Self::with_framing(data, Framing::Standard)
+    }
+
+    /// Creates a reader whose string lengths and element counts are as wide as the payload length of `framing`.
+    #[inline]
+    #[must_use]
+    pub fn with_framing(data: &'a [u8], framing: Framing) -> Self {
+        Self {
+            data,
+            pos: 0,
+            framing,
+        }
     }
 
     #[inline]
@@ -76,13 +89,65 @@ impl<'a> Reader<'a> {
         Ok(self.read_u8()? != 0)
     }
 
+    /// Reads a presence-flagged u64: `[present: bool][value: u64]`.
+    #[inline]
+    pub fn read_optional_u64(&mut self) -> Result<Option<u64>, ProtocolError> {
+        if self.read_bool()? {
+            Ok(Some(self.read_u64()?))
+        } else {
+            Ok(None)
+        }
+    }
+
+    /// Reads a fixed-size byte array.
+    #[inline]
+    pub fn read_array<const N: usize>(&mut self) -> Result<[u8; N], ProtocolError> {
+        let bytes: [u8; N] = self
+            .data
+            .get(self.pos..self.pos + N)
+            .ok_or(ProtocolError::PacketTooShort {
+                expected: self.pos + N,
+                got: self.data.len(),
+            })?
+            .try_into()
+            .unwrap();
+        self.pos += N;
+        Ok(bytes)
+    }
+
+    /// Reads the payload length of a packet header.
+    ///
+    /// Extended lengths above [`MAX_EXTENDED_PAYLOAD_LEN`] fail with [`ProtocolError::PayloadTooLarge`].
+    #[inline]
+    pub fn read_payload_len(&mut self, framing: Framing) -> Result<usize, ProtocolError> {
+        match framing {
+            Framing::Standard => Ok(usize::from(self.read_u16()?)),
+            Framing::Extended => {
+                let len = usize::try_from(self.read_u32()?).unwrap_or(usize::MAX);
+                if len > MAX_EXTENDED_PAYLOAD_LEN {
+                    return Err(ProtocolError::PayloadTooLarge { size: len });
+                }
+                Ok(len)
+            }
+        }
+    }
+
+    /// Reads a length or element count written with `Writer::write_len` under the same framing.
+    #[inline]
+    pub fn read_len(&mut self) -> Result<usize, ProtocolError> {
+        match self.framing {
+            Framing::Standard => Ok(usize::from(self.read_u16()?)),
+            Framing::Extended => Ok(usize::try_from(self.read_u32()?).unwrap_or(usize::MAX)),
+        }
+    }
+
     pub fn read_string(&mut self) -> Result<String, ProtocolError> {
-        let len = self.read_u16()? as usize;
+        let len = self.read_len()?;
         let bytes =
             self.data
-                .get(self.pos..self.pos + len)
+                .get(self.pos..self.pos.saturating_add(len))
                 .ok_or(ProtocolError::PacketTooShort {
-                    expected: self.pos + len,
+                    expected: self.pos.saturating_add(len),
                     got: self.data.len(),
                 })?;
         self.pos += len;
@@ -1,13 +1,21 @@
+use crate::framing::Framing;
+
 /// Helper for building binary payloads.
 #[derive(Default)]
 pub struct Writer {
     buf: Vec<u8>,
+    framing: Framing,    // Width of lengths written with `write_len`
+    truncated_len: bool, // A length written with `write_len` did not fit in its prefix
 }
 
 impl Writer {
+    /// Creates a writer whose string lengths and element counts are as wide as the payload length of `framing`.
     #[must_use]
-    pub fn new() -> Self {
-        Self::default()
+    pub fn with_framing(framing: Framing) -> Self {
+        Self {
+            framing,
+            ..Self::default()
+        }
     }
 
     #[inline]
@@ -35,13 +43,52 @@ impl Writer {
         self.buf.push(u8::from(value));
     }
 
-    /// Writes a length-prefixed string. Panics if string exceeds 65535 bytes.
+    /// Writes a presence-flagged u64: `[present: bool][value: u64]`.
+    #[inline]
+    pub fn write_optional_u64(&mut self, value: Option<u64>) {
+        self.write_bool(value.is_some());
+        if let Some(value) = value {
+            self.write_u64(value);
+        }
+    }
+
+    /// Writes a length-prefixed string. Strings longer than their prefix allows have their
+    /// length saturated and the packet is rejected by `Packet::encode`.
     #[inline]
     pub fn write_string(&mut self, s: &str) {
-        self.write_u16(s.len().try_into().expect("string too long"));
+        self.write_len(s.len());
         self.buf.extend_from_slice(s.as_bytes());
     }
 
+    /// Writes a length or element count, a u16 with [`Framing::Standard`] and a u32 with
+    /// [`Framing::Extended`], saturated like string lengths.
+    #[inline]
+    pub fn write_len(&mut self, len: usize) {
+        match self.framing {
+            Framing::Standard => {
+                let value = u16::try_from(len).unwrap_or_else(|_| {
+                    self.truncated_len = true;
+                    u16::MAX
+                });
+                self.write_u16(value);
+            }
+            Framing::Extended => {
+                let value = u32::try_from(len).unwrap_or_else(|_| {
+                    self.truncated_len = true;
+                    u32::MAX
+                });
+                self.write_u32(value);
+            }
+        }
+    }
+
+    /// Returns true if a length written with `write_len` was saturated, the payload cannot be read back.
+    #[inline]
+    #[must_use]
+    pub fn has_truncated_len(&self) -> bool {
+        self.truncated_len
+    }
+
     #[inline]
     pub fn write_bytes(&mut self, bytes: &[u8]) {
         self.buf.extend_from_slice(bytes);
@@ -53,16 +100,30 @@ impl Writer {
         self.buf.len()
     }
 
+    /// Reserves the payload length of a packet header, filled in by `write_payload_len_at`.
     #[inline]
-    pub fn reserve_u16(&mut self) -> usize {
+    pub fn reserve_payload_len(&mut self, framing: Framing) -> usize {
         let pos = self.buf.len();
-        self.buf.extend_from_slice(&[0u8; 2]);
+        match framing {
+            Framing::Standard => self.write_u16(0),
+            Framing::Extended => self.write_u32(0),
+        }
         pos
     }
 
+    /// Writes a payload length reserved at `pos`, saturated if it exceeds what `framing` can announce.
     #[inline]
-    pub fn write_u16_at(&mut self, pos: usize, value: u16) {
-        self.buf[pos..pos + 2].copy_from_slice(&value.to_be_bytes());
+    pub fn write_payload_len_at(&mut self, pos: usize, framing: Framing, len: usize) {
+        match framing {
+            Framing::Standard => {
+                let value = u16::try_from(len).unwrap_or(u16::MAX);
+                self.buf[pos..pos + 2].copy_from_slice(&value.to_be_bytes());
+            }
+            Framing::Extended => {
+                let value = u32::try_from(len).unwrap_or(u32::MAX);
+                self.buf[pos..pos + 4].copy_from_slice(&value.to_be_bytes());
+            }
+        }
     }
 
     #[inline]
@@ -1,11 +1,33 @@
 //! Binary protocol for voice application communication.
 //!
-//! Wire format: `[packet_id: u8][payload_len: u16][payload...]`
+//! Wire format: `[packet_id: u8][payload_len: u16][payload...]`, with a u32
+//! `payload_len` once both peers speak [`EXTENDED_FRAMING_VERSION`], see [`Framing`].
+//!
+//! Connections start with a `Hello`/`ServerHello` exchange that negotiates
+//! the protocol revision and optional [`Capabilities`].
+//!
+//! `VoiceData` payloads are encrypted with per-user keys, see [`crypto`].
 
+pub mod auth;
+pub mod crypto;
 mod error;
+mod framing;
 mod io;
 mod packet;
+mod packet_ref;
 mod packet_id;
+mod role;
+mod version;
 
 pub use error::ProtocolError;
-pub use packet::{Packet, ParticipantInfo};
+pub use framing::{Framing, MAX_EXTENDED_PAYLOAD_LEN};
+pub use packet::{
+    Attachment, AttachmentRejectReason, ChannelInfo, ChatHistoryMessage, ChatRejectReason, LoginRejectReason,
+    ModerationAction, ModerationRejectReason, Packet, ParticipantInfo, Reaction,
+};
+pub use packet_ref::{PacketRef, VoiceDataRef, VOICE_DATA_HEADER_LEN};
+pub use role::{Permissions, Role};
+pub use version::{
+    negotiate_version, Capabilities, ATTACHMENT_CHUNK_LEN, EXTENDED_FRAMING_VERSION, HEARTBEAT_INTERVAL_MS,
+    MIN_PROTOCOL_VERSION, PROTOCOL_VERSION, TYPING_INTERVAL_MS,
+};
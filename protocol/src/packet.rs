@@ -1,6 +1,12 @@
+use std::fmt;
+use crate::auth::{AUTH_CHALLENGE_LEN, AUTH_PROOF_LEN, AUTH_SALT_LEN};
+use crate::crypto::VOICE_KEY_LEN;
 use crate::error::ProtocolError;
+use crate::framing::Framing;
 use crate::io::{Reader, Writer};
 use crate::packet_id::PacketId;
+use crate::role::{Permissions, Role};
+use crate::version::Capabilities;
 
 /// User information.
 #[derive(Debug, Clone, PartialEq, Eq)]
@@ -8,39 +14,542 @@ use crate::packet_id::PacketId;
 pub struct ParticipantInfo {
     pub user_id: u64,
     pub username: String,
-    pub in_voice: bool,
+    /// Voice channel the user is currently in, `None` if not in voice.
+    pub channel_id: Option<u64>,
     pub is_muted: bool,
+    /// The user does not hear the voice channel.
+    pub is_deafened: bool,
+    pub role: Role,
+    /// What the user may do, decided by the server from its role.
+    pub permissions: Permissions,
 }
 
 impl ParticipantInfo {
     /// Creates a new participant.
     #[must_use]
-    pub fn new(user_id: u64, username: String, in_voice: bool, is_muted: bool) -> Self {
+    pub fn new(
+        user_id: u64,
+        username: String,
+        channel_id: Option<u64>,
+        is_muted: bool,
+        is_deafened: bool,
+        role: Role,
+        permissions: Permissions,
+    ) -> Self {
         Self {
             user_id,
             username,
-            in_voice,
+            channel_id,
             is_muted,
+            is_deafened,
+            role,
+            permissions,
         }
     }
 
+    /// Returns true if the user is in any voice channel.
+    #[must_use]
+    pub fn in_voice(&self) -> bool {
+        self.channel_id.is_some()
+    }
+
     fn write(&self, w: &mut Writer) {
         w.write_u64(self.user_id);
         w.write_string(&self.username);
-        w.write_bool(self.in_voice);
+        w.write_optional_u64(self.channel_id);
         w.write_bool(self.is_muted);
+        w.write_bool(self.is_deafened);
+        w.write_u8(self.role.code());
+        w.write_u32(self.permissions.bits());
     }
 
     fn read(r: &mut Reader) -> Result<Self, ProtocolError> {
         Ok(Self {
             user_id: r.read_u64()?,
             username: r.read_string()?,
-            in_voice: r.read_bool()?,
+            channel_id: r.read_optional_u64()?,
             is_muted: r.read_bool()?,
+            is_deafened: r.read_bool()?,
+            role: Role::from_code(r.read_u8()?),
+            permissions: Permissions::from_bits(r.read_u32()?),
+        })
+    }
+}
+
+/// Voice channel information.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct ChannelInfo {
+    pub channel_id: u64,
+    pub name: String,
+}
+
+impl ChannelInfo {
+    /// Creates a new channel.
+    #[must_use]
+    pub fn new(channel_id: u64, name: String) -> Self {
+        Self { channel_id, name }
+    }
+
+    fn write(&self, w: &mut Writer) {
+        w.write_u64(self.channel_id);
+        w.write_string(&self.name);
+    }
+
+    fn read(r: &mut Reader) -> Result<Self, ProtocolError> {
+        Ok(Self {
+            channel_id: r.read_u64()?,
+            name: r.read_string()?,
         })
     }
 }
 
+/// Users who reacted to a chat message with the same emoji.
+///
+/// Carries usernames rather than user ids, like [`ChatHistoryMessage`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct Reaction {
+    pub emoji: String,
+    /// In the order they reacted.
+    pub usernames: Vec<String>,
+}
+
+impl Reaction {
+    /// Creates a new reaction.
+    #[must_use]
+    pub fn new(emoji: String, usernames: Vec<String>) -> Self {
+        Self { emoji, usernames }
+    }
+
+    fn write(&self, w: &mut Writer) {
+        w.write_string(&self.emoji);
+        w.write_len(self.usernames.len());
+        for username in &self.usernames {
+            w.write_string(username);
+        }
+    }
+
+    fn read(r: &mut Reader) -> Result<Self, ProtocolError> {
+        let emoji = r.read_string()?;
+        let usernames = read_list(r, Reader::read_string)?;
+        Ok(Self { emoji, usernames })
+    }
+}
+
+/// File attached to a chat message, uploaded beforehand with `UploadAttachmentRequest`.
+///
+/// Its content is fetched separately with `DownloadChunkRequest`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct Attachment {
+    /// Assigned by the server when the upload starts.
+    pub attachment_id: u64,
+    /// File name chosen by the uploader, without directories.
+    pub name: String,
+    /// MIME type given by the uploader, not checked by the server.
+    pub mime_type: String,
+    /// Size in bytes.
+    pub size: u64,
+}
+
+impl Attachment {
+    /// Creates a new attachment reference.
+    #[must_use]
+    pub fn new(attachment_id: u64, name: String, mime_type: String, size: u64) -> Self {
+        Self {
+            attachment_id,
+            name,
+            mime_type,
+            size,
+        }
+    }
+
+    /// Returns true if the MIME type names an image.
+    #[must_use]
+    pub fn is_image(&self) -> bool {
+        self.mime_type.starts_with("image/")
+    }
+
+    fn write(&self, w: &mut Writer) {
+        w.write_u64(self.attachment_id);
+        w.write_string(&self.name);
+        w.write_string(&self.mime_type);
+        w.write_u64(self.size);
+    }
+
+    fn read(r: &mut Reader) -> Result<Self, ProtocolError> {
+        Ok(Self {
+            attachment_id: r.read_u64()?,
+            name: r.read_string()?,
+            mime_type: r.read_string()?,
+            size: r.read_u64()?,
+        })
+    }
+}
+
+/// Chat message kept by the server, returned by `ChatHistoryRequest`.
+///
+/// Carries the username rather than the user id, the author may have left or logged in again since.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct ChatHistoryMessage {
+    /// Assigned by the server, increases with every message.
+    pub message_id: u64,
+    /// Unix time in milliseconds, unique per message on a server.
+    pub timestamp: u64,
+    pub username: String,
+    pub message: String,
+    /// Message this one replies to.
+    pub reply_to: Option<u64>,
+    /// True if the message was changed with `EditMessageRequest`.
+    pub edited: bool,
+    /// Emoji reactions, in the order they were first added.
+    pub reactions: Vec<Reaction>,
+    /// Files attached to the message.
+    pub attachments: Vec<Attachment>,
+}
+
+impl ChatHistoryMessage {
+    /// Creates a new history entry, not a reply and not edited.
+    #[must_use]
+    pub fn new(message_id: u64, timestamp: u64, username: String, message: String) -> Self {
+        Self {
+            message_id,
+            timestamp,
+            username,
+            message,
+            reply_to: None,
+            edited: false,
+            reactions: Vec::new(),
+            attachments: Vec::new(),
+        }
+    }
+
+    /// Marks the entry as a reply to `message_id`.
+    #[must_use]
+    pub fn with_reply_to(mut self, message_id: u64) -> Self {
+        self.reply_to = Some(message_id);
+        self
+    }
+
+    /// Marks the entry as edited.
+    #[must_use]
+    pub fn with_edited(mut self, edited: bool) -> Self {
+        self.edited = edited;
+        self
+    }
+
+    /// Sets the emoji reactions of the entry.
+    #[must_use]
+    pub fn with_reactions(mut self, reactions: Vec<Reaction>) -> Self {
+        self.reactions = reactions;
+        self
+    }
+
+    /// Sets the files attached to the entry.
+    #[must_use]
+    pub fn with_attachments(mut self, attachments: Vec<Attachment>) -> Self {
+        self.attachments = attachments;
+        self
+    }
+
+    fn write(&self, w: &mut Writer) {
+        w.write_u64(self.message_id);
+        w.write_u64(self.timestamp);
+        w.write_string(&self.username);
+        w.write_string(&self.message);
+        w.write_optional_u64(self.reply_to);
+        w.write_bool(self.edited);
+        w.write_len(self.reactions.len());
+        for reaction in &self.reactions {
+            reaction.write(w);
+        }
+        w.write_len(self.attachments.len());
+        for attachment in &self.attachments {
+            attachment.write(w);
+        }
+    }
+
+    fn read(r: &mut Reader) -> Result<Self, ProtocolError> {
+        Ok(Self {
+            message_id: r.read_u64()?,
+            timestamp: r.read_u64()?,
+            username: r.read_string()?,
+            message: r.read_string()?,
+            reply_to: r.read_optional_u64()?,
+            edited: r.read_bool()?,
+            reactions: read_list(r, Reaction::read)?,
+            attachments: read_attachments(r)?,
+        })
+    }
+}
+
+/// Reason a login attempt was rejected.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub enum LoginRejectReason {
+    /// Username is empty or too long.
+    InvalidUsername,
+    /// Password is missing or wrong.
+    InvalidCredentials,
+    /// Another user is already logged in with this name.
+    NameTaken,
+    /// Server reached its user limit.
+    ServerFull,
+    /// User or address is banned from the server.
+    Banned,
+    /// Server-muted users keep their name until the mute is lifted, only sent in `ChangeNicknameResponse`.
+    ServerMuted,
+    /// Reason code not known to this build.
+    Unknown(u8),
+}
+
+impl LoginRejectReason {
+    fn code(self) -> u8 {
+        match self {
+            Self::InvalidUsername => 1,
+            Self::InvalidCredentials => 2,
+            Self::NameTaken => 3,
+            Self::ServerFull => 4,
+            Self::Banned => 5,
+            Self::ServerMuted => 6,
+            Self::Unknown(code) => code,
+        }
+    }
+
+    fn from_code(code: u8) -> Self {
+        match code {
+            1 => Self::InvalidUsername,
+            2 => Self::InvalidCredentials,
+            3 => Self::NameTaken,
+            4 => Self::ServerFull,
+            5 => Self::Banned,
+            6 => Self::ServerMuted,
+            code => Self::Unknown(code),
+        }
+    }
+}
+
+impl fmt::Display for LoginRejectReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::InvalidUsername => write!(f, "invalid username"),
+            Self::InvalidCredentials => write!(f, "invalid credentials"),
+            Self::NameTaken => write!(f, "username is already taken"),
+            Self::ServerFull => write!(f, "server is full"),
+            Self::Banned => write!(f, "banned from this server"),
+            Self::ServerMuted => write!(f, "server-muted users cannot change their name"),
+            Self::Unknown(code) => write!(f, "unknown reason ({code})"),
+        }
+    }
+}
+
+/// Moderation action taken against a user.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub enum ModerationAction {
+    /// Disconnected from the server, may log in again.
+    Kicked,
+    /// Disconnected and refused on later logins.
+    Banned,
+    /// Voice is no longer relayed until the user is unmuted by a moderator.
+    ServerMuted,
+    /// Server mute was lifted.
+    ServerUnmuted,
+    /// Action code not known to this build.
+    Unknown(u8),
+}
+
+impl ModerationAction {
+    fn code(self) -> u8 {
+        match self {
+            Self::Kicked => 1,
+            Self::Banned => 2,
+            Self::ServerMuted => 3,
+            Self::ServerUnmuted => 4,
+            Self::Unknown(code) => code,
+        }
+    }
+
+    fn from_code(code: u8) -> Self {
+        match code {
+            1 => Self::Kicked,
+            2 => Self::Banned,
+            3 => Self::ServerMuted,
+            4 => Self::ServerUnmuted,
+            code => Self::Unknown(code),
+        }
+    }
+}
+
+impl fmt::Display for ModerationAction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Kicked => write!(f, "kicked"),
+            Self::Banned => write!(f, "banned"),
+            Self::ServerMuted => write!(f, "server muted"),
+            Self::ServerUnmuted => write!(f, "server unmuted"),
+            Self::Unknown(code) => write!(f, "unknown action ({code})"),
+        }
+    }
+}
+
+/// Reason a moderation request was rejected.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub enum ModerationRejectReason {
+    /// Caller is not allowed to moderate this user.
+    NotPermitted,
+    /// No logged in user has the given id, or the username to unban is not banned.
+    UnknownUser,
+    /// Reason code not known to this build.
+    Unknown(u8),
+}
+
+impl ModerationRejectReason {
+    fn code(self) -> u8 {
+        match self {
+            Self::NotPermitted => 1,
+            Self::UnknownUser => 2,
+            Self::Unknown(code) => code,
+        }
+    }
+
+    fn from_code(code: u8) -> Self {
+        match code {
+            1 => Self::NotPermitted,
+            2 => Self::UnknownUser,
+            code => Self::Unknown(code),
+        }
+    }
+}
+
+impl fmt::Display for ModerationRejectReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::NotPermitted => write!(f, "not permitted"),
+            Self::UnknownUser => write!(f, "unknown user"),
+            Self::Unknown(code) => write!(f, "unknown reason ({code})"),
+        }
+    }
+}
+
+/// Reason a chat message was rejected.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub enum ChatRejectReason {
+    /// Caller is not allowed to chat.
+    NotPermitted,
+    /// Message is empty or only whitespace.
+    Empty,
+    /// Message is longer than the server allows.
+    TooLong,
+    /// Message contains control characters other than line breaks and tabs.
+    InvalidCharacters,
+    /// Message replies to a message the server does not keep.
+    UnknownReply,
+    /// An attachment is unknown, not uploaded by the sender or not fully uploaded.
+    UnknownAttachment,
+    /// Message has more attachments than the server allows.
+    TooManyAttachments,
+    /// Reason code not known to this build.
+    Unknown(u8),
+}
+
+impl ChatRejectReason {
+    fn code(self) -> u8 {
+        match self {
+            Self::NotPermitted => 1,
+            Self::Empty => 2,
+            Self::TooLong => 3,
+            Self::InvalidCharacters => 4,
+            Self::UnknownReply => 5,
+            Self::UnknownAttachment => 6,
+            Self::TooManyAttachments => 7,
+            Self::Unknown(code) => code,
+        }
+    }
+
+    fn from_code(code: u8) -> Self {
+        match code {
+            1 => Self::NotPermitted,
+            2 => Self::Empty,
+            3 => Self::TooLong,
+            4 => Self::InvalidCharacters,
+            5 => Self::UnknownReply,
+            6 => Self::UnknownAttachment,
+            7 => Self::TooManyAttachments,
+            code => Self::Unknown(code),
+        }
+    }
+}
+
+impl fmt::Display for ChatRejectReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::NotPermitted => write!(f, "not permitted"),
+            Self::Empty => write!(f, "message is empty"),
+            Self::TooLong => write!(f, "message is too long"),
+            Self::InvalidCharacters => write!(f, "message contains invalid characters"),
+            Self::UnknownReply => write!(f, "replied message is unknown"),
+            Self::UnknownAttachment => write!(f, "attachment is unknown"),
+            Self::TooManyAttachments => write!(f, "too many attachments"),
+            Self::Unknown(code) => write!(f, "unknown reason ({code})"),
+        }
+    }
+}
+
+/// Reason an attachment upload was rejected.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub enum AttachmentRejectReason {
+    /// Caller is not allowed to chat, or the server does not take attachments.
+    NotPermitted,
+    /// File is larger than the server allows.
+    TooLarge,
+    /// File name or MIME type is empty, too long or contains invalid characters.
+    InvalidName,
+    /// Server has no room left for the file, or the caller has too many uploads in progress.
+    StorageFull,
+    /// Reason code not known to this build.
+    Unknown(u8),
+}
+
+impl AttachmentRejectReason {
+    fn code(self) -> u8 {
+        match self {
+            Self::NotPermitted => 1,
+            Self::TooLarge => 2,
+            Self::InvalidName => 3,
+            Self::StorageFull => 4,
+            Self::Unknown(code) => code,
+        }
+    }
+
+    fn from_code(code: u8) -> Self {
+        match code {
+            1 => Self::NotPermitted,
+            2 => Self::TooLarge,
+            3 => Self::InvalidName,
+            4 => Self::StorageFull,
+            code => Self::Unknown(code),
+        }
+    }
+}
+
+impl fmt::Display for AttachmentRejectReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::NotPermitted => write!(f, "not permitted"),
+            Self::TooLarge => write!(f, "file is too large"),
+            Self::InvalidName => write!(f, "invalid file name or type"),
+            Self::StorageFull => write!(f, "server storage is full"),
+            Self::Unknown(code) => write!(f, "unknown reason ({code})"),
+        }
+    }
+}
+
 /// Protocol packet types for client-server communication.
 #[derive(Debug, Clone, PartialEq, Eq)]
 #[non_exhaustive]
@@ -49,6 +558,8 @@ pub enum Packet {
     LoginRequest {
         request_id: u64,
         username: String,
+        /// Answer to the `ServerHello` challenge, `None` when logging in without a password.
+        auth_proof: Option<[u8; AUTH_PROOF_LEN]>,
     },
     VoiceAuthRequest {
         request_id: u64,
@@ -56,6 +567,7 @@ pub enum Packet {
     },
     JoinVoiceChannelRequest {
         request_id: u64,
+        channel_id: u64,
     },
     LeaveVoiceChannelRequest {
         request_id: u64,
@@ -63,17 +575,149 @@ pub enum Packet {
     ChatMessageRequest {
         request_id: u64,
         message: String,
+        /// Message this one replies to.
+        reply_to: Option<u64>,
+        /// Ids of fully uploaded attachments, a message with attachments may have empty text.
+        attachments: Vec<u64>,
     },
     PingRequest {
         request_id: u64,
     },
+    CreateChannelRequest {
+        request_id: u64,
+        name: String,
+    },
+    ListChannelsRequest {
+        request_id: u64,
+    },
+    /// First packet on a connection, announces the client's protocol revision.
+    Hello {
+        request_id: u64,
+        protocol_version: u16,
+        capabilities: Capabilities,
+    },
+    ChangeNicknameRequest {
+        request_id: u64,
+        username: String,
+    },
+    /// Sent between `Hello` and `LoginRequest` to get back the `user_id` of a dropped connection.
+    ResumeSessionRequest {
+        request_id: u64,
+        /// Token from the `ResumeToken` event of the previous connection.
+        token: u64,
+    },
+    /// Disconnects a user, answered with `ModerationResponse`.
+    KickUserRequest {
+        request_id: u64,
+        user_id: u64,
+        reason: String,
+    },
+    /// Disconnects a user and refuses its username on later logins, and its IP address if `ban_address` is set.
+    /// Answered with `ModerationResponse`.
+    BanUserRequest {
+        request_id: u64,
+        user_id: u64,
+        reason: String,
+        ban_address: bool,
+    },
+    /// Stops (or resumes) relaying a user's voice, answered with `ModerationResponse`.
+    ServerMuteUserRequest {
+        request_id: u64,
+        user_id: u64,
+        muted: bool,
+    },
+    /// Lifts the ban of a username and of the IP addresses banned along with it,
+    /// answered with `ModerationResponse`.
+    UnbanUserRequest {
+        request_id: u64,
+        username: String,
+    },
+    /// Sets the sender's own mute and deafen state, answered with `SetMuteStateResponse`.
+    SetMuteStateRequest {
+        request_id: u64,
+        is_muted: bool,
+        is_deafened: bool,
+    },
+    /// Fetches up to `limit` stored chat messages, answered with `ChatHistoryResponse`.
+    /// Without bounds, or with `before` set, the newest matching messages are returned,
+    /// with only `after` set the oldest ones.
+    ChatHistoryRequest {
+        request_id: u64,
+        /// Only messages older than this timestamp.
+        before: Option<u64>,
+        /// Only messages newer than this timestamp.
+        after: Option<u64>,
+        limit: u16,
+    },
+    /// Replaces the text of one of the sender's own messages, answered with `EditMessageResponse`.
+    EditMessageRequest {
+        request_id: u64,
+        message_id: u64,
+        message: String,
+    },
+    /// Deletes one of the sender's own messages, or with the moderate permission one of a lower role's,
+    /// answered with `DeleteMessageResponse`.
+    DeleteMessageRequest {
+        request_id: u64,
+        message_id: u64,
+    },
+    /// Sends a message to one user only, answered with `DirectMessageResponse`.
+    /// Direct messages are not kept by the server.
+    DirectMessageRequest {
+        request_id: u64,
+        user_id: u64,
+        message: String,
+    },
+    /// Tells the server the sender is (or stopped) typing a chat message, not answered.
+    /// Clients re-send it every `TYPING_INTERVAL_MS` while typing, the server stops the indicator a while
+    /// after the last one and when the sender's chat message arrives.
+    SetTypingRequest {
+        request_id: u64,
+        is_typing: bool,
+    },
+    /// Tells the server the sender has read the chat up to `message_id`, not answered.
+    MarkReadRequest {
+        request_id: u64,
+        message_id: u64,
+    },
+    /// Adds (or removes) the sender's reaction to a chat message, answered with `SetReactionResponse`.
+    SetReactionRequest {
+        request_id: u64,
+        message_id: u64,
+        emoji: String,
+        reacted: bool,
+    },
+    /// Starts uploading a file of `size` bytes, answered with `UploadAttachmentResponse`.
+    UploadAttachmentRequest {
+        request_id: u64,
+        name: String,
+        mime_type: String,
+        size: u64,
+    },
+    /// Uploads the next part of a file, at most [`ATTACHMENT_CHUNK_LEN`](crate::ATTACHMENT_CHUNK_LEN) bytes.
+    /// Chunks are sent in order, `offset` is the number of bytes uploaded before this one.
+    UploadChunkRequest {
+        request_id: u64,
+        attachment_id: u64,
+        offset: u64,
+        data: Vec<u8>,
+    },
+    /// Asks for up to [`ATTACHMENT_CHUNK_LEN`](crate::ATTACHMENT_CHUNK_LEN) bytes of a file starting at `offset`.
+    DownloadChunkRequest {
+        request_id: u64,
+        attachment_id: u64,
+        offset: u64,
+    },
 
     // Responses
     LoginResponse {
         request_id: u64,
         id: u64,
         voice_token: u64,
+        /// Key for sealing and opening this user's `VoiceData` payloads.
+        voice_key: [u8; VOICE_KEY_LEN],
         participants: Vec<ParticipantInfo>,
+        channels: Vec<ChannelInfo>,
     },
     VoiceAuthResponse {
         request_id: u64,
@@ -87,13 +731,105 @@ pub enum Packet {
         request_id: u64,
         success: bool,
     },
+    /// `message_id` is the id assigned to the message, 0 if it was rejected, `rejection` is `None` on success.
     ChatMessageResponse {
         request_id: u64,
-        success: bool,
+        message_id: u64,
+        rejection: Option<ChatRejectReason>,
     },
     PingResponse {
         request_id: u64,
     },
+    CreateChannelResponse {
+        request_id: u64,
+        success: bool,
+        channel_id: u64,
+    },
+    ListChannelsResponse {
+        request_id: u64,
+        channels: Vec<ChannelInfo>,
+    },
+    /// Reply to `Hello`. When `accepted` is false the server closes the connection.
+    ServerHello {
+        request_id: u64,
+        protocol_version: u16,
+        capabilities: Capabilities,
+        accepted: bool,
+        /// Random nonce the client signs in `LoginRequest::auth_proof`.
+        auth_challenge: [u8; AUTH_CHALLENGE_LEN],
+        /// Salt of the password keys on this server, see [`derive_key`](crate::auth::derive_key).
+        auth_salt: [u8; AUTH_SALT_LEN],
+    },
+    /// Negative reply to `LoginRequest`.
+    LoginRejected {
+        request_id: u64,
+        reason: LoginRejectReason,
+    },
+    /// Reply to `ChangeNicknameRequest`, `rejection` is `None` if the name was changed.
+    ChangeNicknameResponse {
+        request_id: u64,
+        rejection: Option<LoginRejectReason>,
+    },
+    /// Reply to `ResumeSessionRequest`, when `accepted` the next login with the same username
+    /// keeps the previous `user_id`.
+    ResumeSessionResponse {
+        request_id: u64,
+        accepted: bool,
+    },
+    /// Reply to `KickUserRequest`, `BanUserRequest`, `ServerMuteUserRequest` and `UnbanUserRequest`,
+    /// `rejection` is `None` on success.
+    ModerationResponse {
+        request_id: u64,
+        rejection: Option<ModerationRejectReason>,
+    },
+    /// Reply to `SetMuteStateRequest` with the state the server applied,
+    /// a user who may not speak stays muted.
+    SetMuteStateResponse {
+        request_id: u64,
+        is_muted: bool,
+        is_deafened: bool,
+    },
+    /// Reply to `ChatHistoryRequest`, messages are ordered oldest first.
+    /// The server may return fewer than `limit`, `has_more` tells whether the page was cut short.
+    ChatHistoryResponse {
+        request_id: u64,
+        messages: Vec<ChatHistoryMessage>,
+        has_more: bool,
+    },
+    EditMessageResponse {
+        request_id: u64,
+        success: bool,
+    },
+    DeleteMessageResponse {
+        request_id: u64,
+        success: bool,
+    },
+    /// `timestamp` is when the message was delivered, 0 if it was rejected.
+    DirectMessageResponse {
+        request_id: u64,
+        success: bool,
+        timestamp: u64,
+    },
+    SetReactionResponse {
+        request_id: u64,
+        success: bool,
+    },
+    /// `attachment_id` identifies the upload in `UploadChunkRequest`, 0 if it was rejected.
+    UploadAttachmentResponse {
+        request_id: u64,
+        attachment_id: u64,
+        rejection: Option<AttachmentRejectReason>,
+    },
+    UploadChunkResponse {
+        request_id: u64,
+        success: bool,
+    },
+    /// `data` is empty past the end of the file or when `success` is false.
+    DownloadChunkResponse {
+        request_id: u64,
+        success: bool,
+        data: Vec<u8>,
+    },
 
     // Events
     UserJoinedServer {
@@ -101,6 +837,7 @@ pub enum Packet {
     },
     UserJoinedVoice {
         user_id: u64,
+        channel_id: u64,
     },
     UserLeftVoice {
         user_id: u64,
@@ -109,16 +846,88 @@ pub enum Packet {
         user_id: u64,
     },
     UserSentMessage {
+        message_id: u64,
         user_id: u64,
         timestamp: u64,
         message: String,
+        /// Message this one replies to.
+        reply_to: Option<u64>,
+        /// Logged in users mentioned with `@username`, resolved by the server.
+        mentions: Vec<u64>,
+        attachments: Vec<Attachment>,
+    },
+    /// The text of a chat message was replaced by its author.
+    MessageEdited {
+        message_id: u64,
+        message: String,
+    },
+    /// A chat message was deleted by its author or a moderator.
+    MessageDeleted {
+        message_id: u64,
+    },
+    /// A direct message to the receiving user, sent to nobody else.
+    DirectMessageReceived {
+        from_user_id: u64,
+        timestamp: u64,
+        message: String,
+    },
+    /// A user started typing a chat message.
+    TypingStarted {
+        user_id: u64,
     },
+    /// A user stopped typing, sent its message or went quiet.
+    TypingStopped {
+        user_id: u64,
+    },
+    /// A user has read the chat up to `message_id`, markers only move forward.
+    MessageRead {
+        user_id: u64,
+        message_id: u64,
+    },
+    /// A user added (or removed) a reaction to a chat message.
+    MessageReactionChanged {
+        message_id: u64,
+        user_id: u64,
+        emoji: String,
+        reacted: bool,
+    },
+    /// Mute or deafen state of a user changed, sent by the server only.
     UserMuteState {
         user_id: u64,
         is_muted: bool,
+        is_deafened: bool,
+    },
+    ChannelCreated {
+        channel: ChannelInfo,
+    },
+    UserRenamed {
+        user_id: u64,
+        username: String,
+    },
+    /// Server message of the day, sent right after `LoginResponse` if configured.
+    Motd {
+        message: String,
+    },
+    /// Sent before the server closes the connection, `reconnect_after_ms` is 0 if it is not expected back.
+    ServerShuttingDown {
+        reason: String,
+        reconnect_after_ms: u32,
+    },
+    /// Sent after `LoginResponse`, lets a client that loses the connection resume its session.
+    ResumeToken {
+        token: u64,
+    },
+    /// A moderator acted against a user, sent to everyone including the affected user before it is disconnected.
+    UserModerated {
+        user_id: u64,
+        moderator_id: u64,
+        action: ModerationAction,
+        /// Reason given by the moderator, empty if none.
+        reason: String,
     },
 
     // UDP
+    /// Opus frame, `data` is sealed with [`crate::crypto::VoiceCipher`].
     VoiceData {
         user_id: u64,
         sequence: u32,
@@ -128,68 +937,153 @@ pub enum Packet {
 }
 
 impl Packet {
-    /// Encode packet to wire format.
+    /// Encode packet to wire format with [`Framing::Standard`].
     ///
     /// Format: `[packet_id: u8][payload_len: u16][payload...]`
     ///
-    /// # Panics
-    /// Panics if payload exceeds 65535 bytes or participants exceed 65535.
-    #[must_use]
+    /// # Errors
+    /// Returns [`ProtocolError::PayloadTooLarge`] if the payload exceeds 65535 bytes.
+    pub fn encode(&self) -> Result<Vec<u8>, ProtocolError> {
+        self.encode_with(Framing::Standard)
+    }
+
+    /// Encode packet to wire format with the given framing.
+    ///
+    /// # Errors
+    /// Returns [`ProtocolError::PayloadTooLarge`] if the payload exceeds [`Framing::max_payload_len`],
+    /// or a string or list is longer than its length prefix allows.
     #[allow(clippy::too_many_lines)]
-    pub fn encode(&self) -> Vec<u8> {
-        let mut w = Writer::new();
+    pub fn encode_with(&self, framing: Framing) -> Result<Vec<u8>, ProtocolError> {
+        let mut w = Writer::with_framing(framing);
         w.write_u8(self.id());
 
-        let len_pos = w.reserve_u16();
+        let len_pos = w.reserve_payload_len(framing);
         let payload_start = w.position();
 
         match self {
             Self::LoginRequest {
                 request_id,
                 username,
+                auth_proof,
+            } => {
+                w.write_u64(*request_id);
+                w.write_string(username);
+                w.write_bool(auth_proof.is_some());
+                if let Some(proof) = auth_proof {
+                    w.write_bytes(proof);
+                }
+            }
+            Self::VoiceAuthRequest {
+                request_id,
+                voice_token,
+            } => {
+                w.write_u64(*request_id);
+                w.write_u64(*voice_token);
+            }
+            Self::JoinVoiceChannelRequest {
+                request_id,
+                channel_id,
+            } => {
+                w.write_u64(*request_id);
+                w.write_u64(*channel_id);
+            }
+            Self::LeaveVoiceChannelRequest { request_id }
+            | Self::PingRequest { request_id }
+            | Self::ListChannelsRequest { request_id }
+            | Self::PingResponse { request_id } => {
+                w.write_u64(*request_id);
+            }
+            Self::ChatMessageRequest {
+                request_id,
+                message,
+                reply_to,
+                attachments,
+            } => {
+                w.write_u64(*request_id);
+                w.write_string(message);
+                w.write_optional_u64(*reply_to);
+                w.write_len(attachments.len());
+                for attachment_id in attachments {
+                    w.write_u64(*attachment_id);
+                }
+            }
+            Self::CreateChannelRequest { request_id, name } => {
+                w.write_u64(*request_id);
+                w.write_string(name);
+            }
+            Self::ChangeNicknameRequest {
+                request_id,
+                username,
+            }
+            | Self::UnbanUserRequest {
+                request_id,
+                username,
             } => {
                 w.write_u64(*request_id);
                 w.write_string(username);
             }
-            Self::VoiceAuthRequest {
+            Self::ResumeSessionRequest { request_id, token } => {
+                w.write_u64(*request_id);
+                w.write_u64(*token);
+            }
+            Self::KickUserRequest {
                 request_id,
-                voice_token,
+                user_id,
+                reason,
             } => {
                 w.write_u64(*request_id);
-                w.write_u64(*voice_token);
+                w.write_u64(*user_id);
+                w.write_string(reason);
             }
-            Self::JoinVoiceChannelRequest { request_id }
-            | Self::LeaveVoiceChannelRequest { request_id } => {
+            Self::BanUserRequest {
+                request_id,
+                user_id,
+                reason,
+                ban_address,
+            } => {
                 w.write_u64(*request_id);
+                w.write_u64(*user_id);
+                w.write_string(reason);
+                w.write_bool(*ban_address);
             }
-            Self::ChatMessageRequest {
+            Self::ServerMuteUserRequest {
                 request_id,
-                message,
+                user_id,
+                muted,
             } => {
                 w.write_u64(*request_id);
-                w.write_string(message);
+                w.write_u64(*user_id);
+                w.write_bool(*muted);
             }
-            Self::PingRequest { request_id } => {
+            Self::Hello {
+                request_id,
+                protocol_version,
+                capabilities,
+            } => {
                 w.write_u64(*request_id);
+                w.write_u16(*protocol_version);
+                w.write_u32(capabilities.bits());
             }
             Self::LoginResponse {
                 request_id,
                 id,
                 voice_token,
+                voice_key,
                 participants,
+                channels,
             } => {
                 w.write_u64(*request_id);
                 w.write_u64(*id);
                 w.write_u64(*voice_token);
-                w.write_u16(
-                    participants
-                        .len()
-                        .try_into()
-                        .expect("too many participants"),
-                );
+                w.write_bytes(voice_key);
+                w.write_len(participants.len());
                 for p in participants {
                     p.write(&mut w);
                 }
+                w.write_len(channels.len());
+                for c in channels {
+                    c.write(&mut w);
+                }
             }
             Self::VoiceAuthResponse {
                 request_id,
@@ -203,34 +1097,331 @@ impl Packet {
                 request_id,
                 success,
             }
-            | Self::ChatMessageResponse {
+            | Self::EditMessageResponse {
+                request_id,
+                success,
+            }
+            | Self::DeleteMessageResponse {
+                request_id,
+                success,
+            }
+            | Self::SetReactionResponse {
+                request_id,
+                success,
+            }
+            | Self::UploadChunkResponse {
+                request_id,
+                success,
+            } => {
+                w.write_u64(*request_id);
+                w.write_bool(*success);
+            }
+            Self::ChatMessageResponse {
+                request_id,
+                message_id,
+                rejection,
+            } => {
+                w.write_u64(*request_id);
+                w.write_u64(*message_id);
+                w.write_bool(rejection.is_some());
+                if let Some(reason) = rejection {
+                    w.write_u8(reason.code());
+                }
+            }
+            Self::CreateChannelResponse {
+                request_id,
+                success,
+                channel_id,
+            } => {
+                w.write_u64(*request_id);
+                w.write_bool(*success);
+                w.write_u64(*channel_id);
+            }
+            Self::ListChannelsResponse {
+                request_id,
+                channels,
+            } => {
+                w.write_u64(*request_id);
+                w.write_len(channels.len());
+                for c in channels {
+                    c.write(&mut w);
+                }
+            }
+            Self::ServerHello {
+                request_id,
+                protocol_version,
+                capabilities,
+                accepted,
+                auth_challenge,
+                auth_salt,
+            } => {
+                w.write_u64(*request_id);
+                w.write_u16(*protocol_version);
+                w.write_u32(capabilities.bits());
+                w.write_bool(*accepted);
+                w.write_bytes(auth_challenge);
+                w.write_bytes(auth_salt);
+            }
+            Self::LoginRejected { request_id, reason } => {
+                w.write_u64(*request_id);
+                w.write_u8(reason.code());
+            }
+            Self::ChangeNicknameResponse {
+                request_id,
+                rejection,
+            } => {
+                w.write_u64(*request_id);
+                w.write_bool(rejection.is_some());
+                if let Some(reason) = rejection {
+                    w.write_u8(reason.code());
+                }
+            }
+            Self::ResumeSessionResponse { request_id, accepted } => {
+                w.write_u64(*request_id);
+                w.write_bool(*accepted);
+            }
+            Self::ModerationResponse {
+                request_id,
+                rejection,
+            } => {
+                w.write_u64(*request_id);
+                w.write_bool(rejection.is_some());
+                if let Some(reason) = rejection {
+                    w.write_u8(reason.code());
+                }
+            }
+            Self::SetMuteStateRequest {
+                request_id,
+                is_muted,
+                is_deafened,
+            }
+            | Self::SetMuteStateResponse {
+                request_id,
+                is_muted,
+                is_deafened,
+            } => {
+                w.write_u64(*request_id);
+                w.write_bool(*is_muted);
+                w.write_bool(*is_deafened);
+            }
+            Self::ChatHistoryRequest {
+                request_id,
+                before,
+                after,
+                limit,
+            } => {
+                w.write_u64(*request_id);
+                w.write_optional_u64(*before);
+                w.write_optional_u64(*after);
+                w.write_u16(*limit);
+            }
+            Self::ChatHistoryResponse {
+                request_id,
+                messages,
+                has_more,
+            } => {
+                w.write_u64(*request_id);
+                w.write_len(messages.len());
+                for m in messages {
+                    m.write(&mut w);
+                }
+                w.write_bool(*has_more);
+            }
+            Self::EditMessageRequest {
+                request_id,
+                message_id,
+                message,
+            } => {
+                w.write_u64(*request_id);
+                w.write_u64(*message_id);
+                w.write_string(message);
+            }
+            Self::DeleteMessageRequest { request_id, message_id }
+            | Self::MarkReadRequest { request_id, message_id } => {
+                w.write_u64(*request_id);
+                w.write_u64(*message_id);
+            }
+            Self::DirectMessageRequest {
+                request_id,
+                user_id,
+                message,
+            } => {
+                w.write_u64(*request_id);
+                w.write_u64(*user_id);
+                w.write_string(message);
+            }
+            Self::SetTypingRequest { request_id, is_typing } => {
+                w.write_u64(*request_id);
+                w.write_bool(*is_typing);
+            }
+            Self::SetReactionRequest {
+                request_id,
+                message_id,
+                emoji,
+                reacted,
+            } => {
+                w.write_u64(*request_id);
+                w.write_u64(*message_id);
+                w.write_string(emoji);
+                w.write_bool(*reacted);
+            }
+            Self::UploadAttachmentRequest {
+                request_id,
+                name,
+                mime_type,
+                size,
+            } => {
+                w.write_u64(*request_id);
+                w.write_string(name);
+                w.write_string(mime_type);
+                w.write_u64(*size);
+            }
+            Self::UploadChunkRequest {
+                request_id,
+                attachment_id,
+                offset,
+                data,
+            } => {
+                w.write_u64(*request_id);
+                w.write_u64(*attachment_id);
+                w.write_u64(*offset);
+                w.write_bytes(data);
+            }
+            Self::DownloadChunkRequest {
+                request_id,
+                attachment_id,
+                offset,
+            } => {
+                w.write_u64(*request_id);
+                w.write_u64(*attachment_id);
+                w.write_u64(*offset);
+            }
+            Self::UploadAttachmentResponse {
+                request_id,
+                attachment_id,
+                rejection,
+            } => {
+                w.write_u64(*request_id);
+                w.write_u64(*attachment_id);
+                w.write_bool(rejection.is_some());
+                if let Some(reason) = rejection {
+                    w.write_u8(reason.code());
+                }
+            }
+            Self::DownloadChunkResponse {
                 request_id,
                 success,
+                data,
             } => {
                 w.write_u64(*request_id);
                 w.write_bool(*success);
+                w.write_bytes(data);
             }
-            Self::PingResponse { request_id } => {
+            Self::DirectMessageResponse {
+                request_id,
+                success,
+                timestamp,
+            } => {
                 w.write_u64(*request_id);
+                w.write_bool(*success);
+                w.write_u64(*timestamp);
             }
             Self::UserJoinedServer { participant } => participant.write(&mut w),
-            Self::UserJoinedVoice { user_id }
-            | Self::UserLeftVoice { user_id }
-            | Self::UserLeftServer { user_id } => {
+            Self::UserJoinedVoice {
+                user_id,
+                channel_id,
+            } => {
                 w.write_u64(*user_id);
+                w.write_u64(*channel_id);
             }
-            Self::UserMuteState { user_id, is_muted } => {
+            Self::UserLeftVoice { user_id }
+            | Self::UserLeftServer { user_id }
+            | Self::TypingStarted { user_id }
+            | Self::TypingStopped { user_id } => {
+                w.write_u64(*user_id);
+            }
+            Self::UserMuteState {
+                user_id,
+                is_muted,
+                is_deafened,
+            } => {
                 w.write_u64(*user_id);
                 w.write_bool(*is_muted);
+                w.write_bool(*is_deafened);
+            }
+            Self::ChannelCreated { channel } => channel.write(&mut w),
+            Self::UserRenamed { user_id, username } => {
+                w.write_u64(*user_id);
+                w.write_string(username);
+            }
+            Self::Motd { message } => w.write_string(message),
+            Self::ServerShuttingDown { reason, reconnect_after_ms } => {
+                w.write_string(reason);
+                w.write_u32(*reconnect_after_ms);
+            }
+            Self::ResumeToken { token } => w.write_u64(*token),
+            Self::UserModerated {
+                user_id,
+                moderator_id,
+                action,
+                reason,
+            } => {
+                w.write_u64(*user_id);
+                w.write_u64(*moderator_id);
+                w.write_u8(action.code());
+                w.write_string(reason);
             }
             Self::UserSentMessage {
+                message_id,
                 user_id,
                 timestamp,
                 message,
+                reply_to,
+                mentions,
+                attachments,
             } => {
+                w.write_u64(*message_id);
                 w.write_u64(*user_id);
                 w.write_u64(*timestamp);
                 w.write_string(message);
+                w.write_optional_u64(*reply_to);
+                w.write_len(mentions.len());
+                for user_id in mentions {
+                    w.write_u64(*user_id);
+                }
+                w.write_len(attachments.len());
+                for attachment in attachments {
+                    attachment.write(&mut w);
+                }
+            }
+            Self::MessageEdited { message_id, message } => {
+                w.write_u64(*message_id);
+                w.write_string(message);
+            }
+            Self::MessageDeleted { message_id } => w.write_u64(*message_id),
+            Self::DirectMessageReceived {
+                from_user_id,
+                timestamp,
+                message,
+            } => {
+                w.write_u64(*from_user_id);
+                w.write_u64(*timestamp);
+                w.write_string(message);
+            }
+            Self::MessageRead { user_id, message_id } => {
+                w.write_u64(*user_id);
+                w.write_u64(*message_id);
+            }
+            Self::MessageReactionChanged {
+                message_id,
+                user_id,
+                emoji,
+                reacted,
+            } => {
+                w.write_u64(*message_id);
+                w.write_u64(*user_id);
+                w.write_string(emoji);
+                w.write_bool(*reacted);
             }
             Self::VoiceData {
                 user_id,
@@ -245,25 +1436,36 @@ impl Packet {
             }
         }
 
-        w.write_u16_at(
-            len_pos,
-            (w.position() - payload_start)
-                .try_into()
-                .expect("payload too large"),
-        );
-        w.into_vec()
+        let size = w.position() - payload_start;
+        if size > framing.max_payload_len() || w.has_truncated_len() {
+            return Err(ProtocolError::PayloadTooLarge { size });
+        }
+        w.write_payload_len_at(len_pos, framing, size);
+        Ok(w.into_vec())
     }
 
-    /// Decode packet from wire format.
+    /// Decode packet from wire format with [`Framing::Standard`].
     ///
     /// Returns decoded packet and number of bytes consumed from the buffer.
     ///
     /// # Errors
     /// Returns error if buffer is incomplete or contains invalid data.
     pub fn decode(buf: &[u8]) -> Result<(Self, usize), ProtocolError> {
+        Self::decode_with(buf, Framing::Standard)
+    }
+
+    /// Decode packet from wire format with the given framing.
+    ///
+    /// Returns decoded packet and number of bytes consumed from the buffer.
+    ///
+    /// # Errors
+    /// Returns error if buffer is incomplete or contains invalid data, and
+    /// [`ProtocolError::PayloadTooLarge`] as soon as the header announces more than the framing allows.
+    #[allow(clippy::too_many_lines)]
+    pub fn decode_with(buf: &[u8], framing: Framing) -> Result<(Self, usize), ProtocolError> {
         let mut header = Reader::new(buf);
         let packet_id = PacketId::try_from(header.read_u8()?)?;
-        let payload_len = header.read_u16()? as usize;
+        let payload_len = header.read_payload_len(framing)?;
         let remaining = header.remaining();
 
         if remaining.len() < payload_len {
@@ -273,70 +1475,270 @@ impl Packet {
             });
         }
 
-        let mut r = Reader::new(&remaining[..payload_len]);
+        let mut r = Reader::with_framing(&remaining[..payload_len], framing);
 
         let packet = match packet_id {
             PacketId::LoginRequest => Self::LoginRequest {
                 request_id: r.read_u64()?,
-                username: r.read_string()?,
+                username: r.read_string()?,
+                auth_proof: if r.read_bool()? {
+                    Some(r.read_array()?)
+                } else {
+                    None
+                },
+            },
+            PacketId::VoiceAuthRequest => Self::VoiceAuthRequest {
+                request_id: r.read_u64()?,
+                voice_token: r.read_u64()?,
+            },
+            PacketId::JoinVoiceChannelRequest => Self::JoinVoiceChannelRequest {
+                request_id: r.read_u64()?,
+                channel_id: r.read_u64()?,
+            },
+            PacketId::LeaveVoiceChannelRequest => Self::LeaveVoiceChannelRequest {
+                request_id: r.read_u64()?,
+            },
+            PacketId::ChatMessageRequest => Self::ChatMessageRequest {
+                request_id: r.read_u64()?,
+                message: r.read_string()?,
+                reply_to: r.read_optional_u64()?,
+                attachments: read_list(&mut r, Reader::read_u64)?,
+            },
+            PacketId::PingRequest => Self::PingRequest {
+                request_id: r.read_u64()?,
+            },
+            PacketId::CreateChannelRequest => Self::CreateChannelRequest {
+                request_id: r.read_u64()?,
+                name: r.read_string()?,
+            },
+            PacketId::ListChannelsRequest => Self::ListChannelsRequest {
+                request_id: r.read_u64()?,
+            },
+            PacketId::Hello => Self::Hello {
+                request_id: r.read_u64()?,
+                protocol_version: r.read_u16()?,
+                capabilities: Capabilities::from_bits(r.read_u32()?),
+            },
+            PacketId::ChangeNicknameRequest => Self::ChangeNicknameRequest {
+                request_id: r.read_u64()?,
+                username: r.read_string()?,
+            },
+            PacketId::ResumeSessionRequest => Self::ResumeSessionRequest {
+                request_id: r.read_u64()?,
+                token: r.read_u64()?,
+            },
+            PacketId::KickUserRequest => Self::KickUserRequest {
+                request_id: r.read_u64()?,
+                user_id: r.read_u64()?,
+                reason: r.read_string()?,
+            },
+            PacketId::BanUserRequest => Self::BanUserRequest {
+                request_id: r.read_u64()?,
+                user_id: r.read_u64()?,
+                reason: r.read_string()?,
+                ban_address: r.read_bool()?,
+            },
+            PacketId::ServerMuteUserRequest => Self::ServerMuteUserRequest {
+                request_id: r.read_u64()?,
+                user_id: r.read_u64()?,
+                muted: r.read_bool()?,
+            },
+            PacketId::UnbanUserRequest => Self::UnbanUserRequest {
+                request_id: r.read_u64()?,
+                username: r.read_string()?,
+            },
+            PacketId::LoginResponse => {
+                let request_id = r.read_u64()?;
+                let id = r.read_u64()?;
+                let voice_token = r.read_u64()?;
+                let voice_key = r.read_array()?;
+                let participants = read_list(&mut r, ParticipantInfo::read)?;
+                let channels = read_channels(&mut r)?;
+                Self::LoginResponse {
+                    request_id,
+                    id,
+                    voice_token,
+                    voice_key,
+                    participants,
+                    channels,
+                }
+            }
+            PacketId::VoiceAuthResponse => Self::VoiceAuthResponse {
+                request_id: r.read_u64()?,
+                success: r.read_bool()?,
+            },
+            PacketId::JoinVoiceChannelResponse => Self::JoinVoiceChannelResponse {
+                request_id: r.read_u64()?,
+                success: r.read_bool()?,
+            },
+            PacketId::LeaveVoiceChannelResponse => Self::LeaveVoiceChannelResponse {
+                request_id: r.read_u64()?,
+                success: r.read_bool()?,
+            },
+            PacketId::ChatMessageResponse => Self::ChatMessageResponse {
+                request_id: r.read_u64()?,
+                message_id: r.read_u64()?,
+                rejection: if r.read_bool()? {
+                    Some(ChatRejectReason::from_code(r.read_u8()?))
+                } else {
+                    None
+                },
             },
-            PacketId::VoiceAuthRequest => Self::VoiceAuthRequest {
+            PacketId::PingResponse => Self::PingResponse {
                 request_id: r.read_u64()?,
-                voice_token: r.read_u64()?,
             },
-            PacketId::JoinVoiceChannelRequest => Self::JoinVoiceChannelRequest {
+            PacketId::CreateChannelResponse => Self::CreateChannelResponse {
                 request_id: r.read_u64()?,
+                success: r.read_bool()?,
+                channel_id: r.read_u64()?,
             },
-            PacketId::LeaveVoiceChannelRequest => Self::LeaveVoiceChannelRequest {
+            PacketId::ListChannelsResponse => Self::ListChannelsResponse {
                 request_id: r.read_u64()?,
+                channels: read_channels(&mut r)?,
             },
-            PacketId::ChatMessageRequest => Self::ChatMessageRequest {
+            PacketId::ServerHello => Self::ServerHello {
                 request_id: r.read_u64()?,
-                message: r.read_string()?,
+                protocol_version: r.read_u16()?,
+                capabilities: Capabilities::from_bits(r.read_u32()?),
+                accepted: r.read_bool()?,
+                auth_challenge: r.read_array()?,
+                auth_salt: r.read_array()?,
             },
-            PacketId::PingRequest => Self::PingRequest {
+            PacketId::LoginRejected => Self::LoginRejected {
                 request_id: r.read_u64()?,
+                reason: LoginRejectReason::from_code(r.read_u8()?),
             },
-            PacketId::LoginResponse => {
+            PacketId::ChangeNicknameResponse => Self::ChangeNicknameResponse {
+                request_id: r.read_u64()?,
+                rejection: if r.read_bool()? {
+                    Some(LoginRejectReason::from_code(r.read_u8()?))
+                } else {
+                    None
+                },
+            },
+            PacketId::ResumeSessionResponse => Self::ResumeSessionResponse {
+                request_id: r.read_u64()?,
+                accepted: r.read_bool()?,
+            },
+            PacketId::ModerationResponse => Self::ModerationResponse {
+                request_id: r.read_u64()?,
+                rejection: if r.read_bool()? {
+                    Some(ModerationRejectReason::from_code(r.read_u8()?))
+                } else {
+                    None
+                },
+            },
+            PacketId::SetMuteStateRequest => Self::SetMuteStateRequest {
+                request_id: r.read_u64()?,
+                is_muted: r.read_bool()?,
+                is_deafened: r.read_bool()?,
+            },
+            PacketId::SetMuteStateResponse => Self::SetMuteStateResponse {
+                request_id: r.read_u64()?,
+                is_muted: r.read_bool()?,
+                is_deafened: r.read_bool()?,
+            },
+            PacketId::ChatHistoryRequest => Self::ChatHistoryRequest {
+                request_id: r.read_u64()?,
+                before: r.read_optional_u64()?,
+                after: r.read_optional_u64()?,
+                limit: r.read_u16()?,
+            },
+            PacketId::ChatHistoryResponse => {
                 let request_id = r.read_u64()?;
-                let id = r.read_u64()?;
-                let voice_token = r.read_u64()?;
-                let count = r.read_u16()? as usize;
-                let mut participants = Vec::with_capacity(count);
-                for _ in 0..count {
-                    participants.push(ParticipantInfo::read(&mut r)?);
-                }
-                Self::LoginResponse {
+                let messages = read_list(&mut r, ChatHistoryMessage::read)?;
+                Self::ChatHistoryResponse {
                     request_id,
-                    id,
-                    voice_token,
-                    participants,
+                    messages,
+                    has_more: r.read_bool()?,
                 }
             }
-            PacketId::VoiceAuthResponse => Self::VoiceAuthResponse {
+            PacketId::EditMessageRequest => Self::EditMessageRequest {
+                request_id: r.read_u64()?,
+                message_id: r.read_u64()?,
+                message: r.read_string()?,
+            },
+            PacketId::DeleteMessageRequest => Self::DeleteMessageRequest {
+                request_id: r.read_u64()?,
+                message_id: r.read_u64()?,
+            },
+            PacketId::EditMessageResponse => Self::EditMessageResponse {
                 request_id: r.read_u64()?,
                 success: r.read_bool()?,
             },
-            PacketId::JoinVoiceChannelResponse => Self::JoinVoiceChannelResponse {
+            PacketId::DeleteMessageResponse => Self::DeleteMessageResponse {
                 request_id: r.read_u64()?,
                 success: r.read_bool()?,
             },
-            PacketId::LeaveVoiceChannelResponse => Self::LeaveVoiceChannelResponse {
+            PacketId::DirectMessageRequest => Self::DirectMessageRequest {
+                request_id: r.read_u64()?,
+                user_id: r.read_u64()?,
+                message: r.read_string()?,
+            },
+            PacketId::SetTypingRequest => Self::SetTypingRequest {
+                request_id: r.read_u64()?,
+                is_typing: r.read_bool()?,
+            },
+            PacketId::MarkReadRequest => Self::MarkReadRequest {
+                request_id: r.read_u64()?,
+                message_id: r.read_u64()?,
+            },
+            PacketId::SetReactionRequest => Self::SetReactionRequest {
+                request_id: r.read_u64()?,
+                message_id: r.read_u64()?,
+                emoji: r.read_string()?,
+                reacted: r.read_bool()?,
+            },
+            PacketId::UploadAttachmentRequest => Self::UploadAttachmentRequest {
+                request_id: r.read_u64()?,
+                name: r.read_string()?,
+                mime_type: r.read_string()?,
+                size: r.read_u64()?,
+            },
+            PacketId::UploadChunkRequest => Self::UploadChunkRequest {
+                request_id: r.read_u64()?,
+                attachment_id: r.read_u64()?,
+                offset: r.read_u64()?,
+                data: r.remaining().to_vec(),
+            },
+            PacketId::DownloadChunkRequest => Self::DownloadChunkRequest {
+                request_id: r.read_u64()?,
+                attachment_id: r.read_u64()?,
+                offset: r.read_u64()?,
+            },
+            PacketId::DirectMessageResponse => Self::DirectMessageResponse {
                 request_id: r.read_u64()?,
                 success: r.read_bool()?,
+                timestamp: r.read_u64()?,
             },
-            PacketId::ChatMessageResponse => Self::ChatMessageResponse {
+            PacketId::SetReactionResponse => Self::SetReactionResponse {
                 request_id: r.read_u64()?,
                 success: r.read_bool()?,
             },
-            PacketId::PingResponse => Self::PingResponse {
+            PacketId::UploadAttachmentResponse => Self::UploadAttachmentResponse {
+                request_id: r.read_u64()?,
+                attachment_id: r.read_u64()?,
+                rejection: if r.read_bool()? {
+                    Some(AttachmentRejectReason::from_code(r.read_u8()?))
+                } else {
+                    None
+                },
+            },
+            PacketId::UploadChunkResponse => Self::UploadChunkResponse {
+                request_id: r.read_u64()?,
+                success: r.read_bool()?,
+            },
+            PacketId::DownloadChunkResponse => Self::DownloadChunkResponse {
                 request_id: r.read_u64()?,
+                success: r.read_bool()?,
+                data: r.remaining().to_vec(),
             },
             PacketId::UserJoinedServer => Self::UserJoinedServer {
                 participant: ParticipantInfo::read(&mut r)?,
             },
             PacketId::UserJoinedVoice => Self::UserJoinedVoice {
                 user_id: r.read_u64()?,
+                channel_id: r.read_u64()?,
             },
             PacketId::UserLeftVoice => Self::UserLeftVoice {
                 user_id: r.read_u64()?,
@@ -345,13 +1747,69 @@ impl Packet {
                 user_id: r.read_u64()?,
             },
             PacketId::UserSentMessage => Self::UserSentMessage {
+                message_id: r.read_u64()?,
                 user_id: r.read_u64()?,
                 timestamp: r.read_u64()?,
                 message: r.read_string()?,
+                reply_to: r.read_optional_u64()?,
+                mentions: read_list(&mut r, Reader::read_u64)?,
+                attachments: read_attachments(&mut r)?,
+            },
+            PacketId::MessageEdited => Self::MessageEdited {
+                message_id: r.read_u64()?,
+                message: r.read_string()?,
+            },
+            PacketId::MessageDeleted => Self::MessageDeleted {
+                message_id: r.read_u64()?,
+            },
+            PacketId::DirectMessageReceived => Self::DirectMessageReceived {
+                from_user_id: r.read_u64()?,
+                timestamp: r.read_u64()?,
+                message: r.read_string()?,
+            },
+            PacketId::TypingStarted => Self::TypingStarted {
+                user_id: r.read_u64()?,
+            },
+            PacketId::TypingStopped => Self::TypingStopped {
+                user_id: r.read_u64()?,
+            },
+            PacketId::MessageRead => Self::MessageRead {
+                user_id: r.read_u64()?,
+                message_id: r.read_u64()?,
+            },
+            PacketId::MessageReactionChanged => Self::MessageReactionChanged {
+                message_id: r.read_u64()?,
+                user_id: r.read_u64()?,
+                emoji: r.read_string()?,
+                reacted: r.read_bool()?,
             },
             PacketId::UserMuteState => Self::UserMuteState {
                 user_id: r.read_u64()?,
                 is_muted: r.read_bool()?,
+                is_deafened: r.read_bool()?,
+            },
+            PacketId::ChannelCreated => Self::ChannelCreated {
+                channel: ChannelInfo::read(&mut r)?,
+            },
+            PacketId::UserRenamed => Self::UserRenamed {
+                user_id: r.read_u64()?,
+                username: r.read_string()?,
+            },
+            PacketId::Motd => Self::Motd {
+                message: r.read_string()?,
+            },
+            PacketId::ServerShuttingDown => Self::ServerShuttingDown {
+                reason: r.read_string()?,
+                reconnect_after_ms: r.read_u32()?,
+            },
+            PacketId::ResumeToken => Self::ResumeToken {
+                token: r.read_u64()?,
+            },
+            PacketId::UserModerated => Self::UserModerated {
+                user_id: r.read_u64()?,
+                moderator_id: r.read_u64()?,
+                action: ModerationAction::from_code(r.read_u8()?),
+                reason: r.read_string()?,
             },
             PacketId::VoiceData => Self::VoiceData {
                 user_id: r.read_u64()?,
@@ -374,18 +1832,67 @@ impl Packet {
             Self::LeaveVoiceChannelRequest { .. } => PacketId::LeaveVoiceChannelRequest,
             Self::ChatMessageRequest { .. } => PacketId::ChatMessageRequest,
             Self::PingRequest { .. } => PacketId::PingRequest,
+            Self::CreateChannelRequest { .. } => PacketId::CreateChannelRequest,
+            Self::ListChannelsRequest { .. } => PacketId::ListChannelsRequest,
+            Self::Hello { .. } => PacketId::Hello,
+            Self::ChangeNicknameRequest { .. } => PacketId::ChangeNicknameRequest,
+            Self::ResumeSessionRequest { .. } => PacketId::ResumeSessionRequest,
+            Self::KickUserRequest { .. } => PacketId::KickUserRequest,
+            Self::BanUserRequest { .. } => PacketId::BanUserRequest,
+            Self::ServerMuteUserRequest { .. } => PacketId::ServerMuteUserRequest,
+            Self::UnbanUserRequest { .. } => PacketId::UnbanUserRequest,
             Self::LoginResponse { .. } => PacketId::LoginResponse,
             Self::VoiceAuthResponse { .. } => PacketId::VoiceAuthResponse,
             Self::JoinVoiceChannelResponse { .. } => PacketId::JoinVoiceChannelResponse,
             Self::LeaveVoiceChannelResponse { .. } => PacketId::LeaveVoiceChannelResponse,
             Self::ChatMessageResponse { .. } => PacketId::ChatMessageResponse,
             Self::PingResponse { .. } => PacketId::PingResponse,
+            Self::CreateChannelResponse { .. } => PacketId::CreateChannelResponse,
+            Self::ListChannelsResponse { .. } => PacketId::ListChannelsResponse,
+            Self::ServerHello { .. } => PacketId::ServerHello,
+            Self::LoginRejected { .. } => PacketId::LoginRejected,
+            Self::ChangeNicknameResponse { .. } => PacketId::ChangeNicknameResponse,
+            Self::ResumeSessionResponse { .. } => PacketId::ResumeSessionResponse,
+            Self::ModerationResponse { .. } => PacketId::ModerationResponse,
+            Self::SetMuteStateRequest { .. } => PacketId::SetMuteStateRequest,
+            Self::SetMuteStateResponse { .. } => PacketId::SetMuteStateResponse,
+            Self::ChatHistoryRequest { .. } => PacketId::ChatHistoryRequest,
+            Self::ChatHistoryResponse { .. } => PacketId::ChatHistoryResponse,
+            Self::EditMessageRequest { .. } => PacketId::EditMessageRequest,
+            Self::DeleteMessageRequest { .. } => PacketId::DeleteMessageRequest,
+            Self::EditMessageResponse { .. } => PacketId::EditMessageResponse,
+            Self::DeleteMessageResponse { .. } => PacketId::DeleteMessageResponse,
+            Self::DirectMessageRequest { .. } => PacketId::DirectMessageRequest,
+            Self::DirectMessageResponse { .. } => PacketId::DirectMessageResponse,
+            Self::SetTypingRequest { .. } => PacketId::SetTypingRequest,
+            Self::MarkReadRequest { .. } => PacketId::MarkReadRequest,
+            Self::SetReactionRequest { .. } => PacketId::SetReactionRequest,
+            Self::SetReactionResponse { .. } => PacketId::SetReactionResponse,
+            Self::UploadAttachmentRequest { .. } => PacketId::UploadAttachmentRequest,
+            Self::UploadChunkRequest { .. } => PacketId::UploadChunkRequest,
+            Self::DownloadChunkRequest { .. } => PacketId::DownloadChunkRequest,
+            Self::UploadAttachmentResponse { .. } => PacketId::UploadAttachmentResponse,
+            Self::UploadChunkResponse { .. } => PacketId::UploadChunkResponse,
+            Self::DownloadChunkResponse { .. } => PacketId::DownloadChunkResponse,
             Self::UserJoinedServer { .. } => PacketId::UserJoinedServer,
             Self::UserJoinedVoice { .. } => PacketId::UserJoinedVoice,
             Self::UserLeftVoice { .. } => PacketId::UserLeftVoice,
             Self::UserLeftServer { .. } => PacketId::UserLeftServer,
             Self::UserSentMessage { .. } => PacketId::UserSentMessage,
+            Self::MessageEdited { .. } => PacketId::MessageEdited,
+            Self::MessageDeleted { .. } => PacketId::MessageDeleted,
+            Self::DirectMessageReceived { .. } => PacketId::DirectMessageReceived,
+            Self::TypingStarted { .. } => PacketId::TypingStarted,
+            Self::TypingStopped { .. } => PacketId::TypingStopped,
+            Self::MessageRead { .. } => PacketId::MessageRead,
+            Self::MessageReactionChanged { .. } => PacketId::MessageReactionChanged,
             Self::UserMuteState { .. } => PacketId::UserMuteState,
+            Self::ChannelCreated { .. } => PacketId::ChannelCreated,
+            Self::UserRenamed { .. } => PacketId::UserRenamed,
+            Self::Motd { .. } => PacketId::Motd,
+            Self::ServerShuttingDown { .. } => PacketId::ServerShuttingDown,
+            Self::ResumeToken { .. } => PacketId::ResumeToken,
+            Self::UserModerated { .. } => PacketId::UserModerated,
             Self::VoiceData { .. } => PacketId::VoiceData,
         }
         .as_u8()
@@ -399,28 +1906,95 @@ impl Packet {
         match self {
             Self::LoginRequest { request_id, .. }
             | Self::VoiceAuthRequest { request_id, .. }
-            | Self::JoinVoiceChannelRequest { request_id }
+            | Self::JoinVoiceChannelRequest { request_id, .. }
             | Self::LeaveVoiceChannelRequest { request_id }
             | Self::ChatMessageRequest { request_id, .. }
             | Self::PingRequest { request_id }
+            | Self::CreateChannelRequest { request_id, .. }
+            | Self::ListChannelsRequest { request_id }
+            | Self::Hello { request_id, .. }
+            | Self::ChangeNicknameRequest { request_id, .. }
+            | Self::ResumeSessionRequest { request_id, .. }
+            | Self::KickUserRequest { request_id, .. }
+            | Self::BanUserRequest { request_id, .. }
+            | Self::ServerMuteUserRequest { request_id, .. }
+            | Self::UnbanUserRequest { request_id, .. }
+            | Self::SetMuteStateRequest { request_id, .. }
+            | Self::ChatHistoryRequest { request_id, .. }
+            | Self::EditMessageRequest { request_id, .. }
+            | Self::DeleteMessageRequest { request_id, .. }
+            | Self::DirectMessageRequest { request_id, .. }
+            | Self::SetTypingRequest { request_id, .. }
+            | Self::MarkReadRequest { request_id, .. }
+            | Self::SetReactionRequest { request_id, .. }
+            | Self::UploadAttachmentRequest { request_id, .. }
+            | Self::UploadChunkRequest { request_id, .. }
+            | Self::DownloadChunkRequest { request_id, .. }
             | Self::LoginResponse { request_id, .. }
             | Self::VoiceAuthResponse { request_id, .. }
             | Self::JoinVoiceChannelResponse { request_id, .. }
             | Self::LeaveVoiceChannelResponse { request_id, .. }
             | Self::ChatMessageResponse { request_id, .. }
-            | Self::PingResponse { request_id } => Some(*request_id),
+            | Self::PingResponse { request_id }
+            | Self::CreateChannelResponse { request_id, .. }
+            | Self::ListChannelsResponse { request_id, .. }
+            | Self::ServerHello { request_id, .. }
+            | Self::LoginRejected { request_id, .. }
+            | Self::ChangeNicknameResponse { request_id, .. }
+            | Self::ResumeSessionResponse { request_id, .. }
+            | Self::ModerationResponse { request_id, .. }
+            | Self::SetMuteStateResponse { request_id, .. }
+            | Self::ChatHistoryResponse { request_id, .. }
+            | Self::EditMessageResponse { request_id, .. }
+            | Self::DeleteMessageResponse { request_id, .. }
+            | Self::DirectMessageResponse { request_id, .. }
+            | Self::SetReactionResponse { request_id, .. }
+            | Self::UploadAttachmentResponse { request_id, .. }
+            | Self::UploadChunkResponse { request_id, .. }
+            | Self::DownloadChunkResponse { request_id, .. } => Some(*request_id),
             _ => None,
         }
     }
 }
 
+fn read_attachments(r: &mut Reader) -> Result<Vec<Attachment>, ProtocolError> {
+    read_list(r, Attachment::read)
+}
+
+fn read_channels(r: &mut Reader) -> Result<Vec<ChannelInfo>, ProtocolError> {
+    read_list(r, ChannelInfo::read)
+}
+
+/// Reads a list prefixed with its element count.
+///
+/// Every element takes at least one byte, so a count past the end of the payload fails before anything
+/// is allocated, and no more is reserved up front than a u16 count could ask for.
+fn read_list<'a, T>(
+    r: &mut Reader<'a>,
+    mut read: impl FnMut(&mut Reader<'a>) -> Result<T, ProtocolError>,
+) -> Result<Vec<T>, ProtocolError> {
+    let count = r.read_len()?;
+    if count > r.remaining().len() {
+        return Err(ProtocolError::PacketTooShort {
+            expected: r.position().saturating_add(count),
+            got: r.position() + r.remaining().len(),
+        });
+    }
+
+    let mut items = Vec::with_capacity(count.min(usize::from(u16::MAX)));
+    for _ in 0..count {
+        items.push(read(r)?);
+    }
+    Ok(items)
+}
+
 #[cfg(test)]
 #[allow(clippy::unreadable_literal, clippy::needless_pass_by_value)]
 mod tests {
     use super::*;
 
     fn roundtrip(packet: Packet) {
-        let encoded = packet.encode();
+        let encoded = packet.encode().expect("encode failed");
         let (decoded, size) = Packet::decode(&encoded).expect("decode failed");
         assert_eq!(packet, decoded);
         assert_eq!(encoded.len(), size);
@@ -431,6 +2005,7 @@ mod tests {
         roundtrip(Packet::LoginRequest {
             request_id: 1,
             username: "alice".to_string(),
+            auth_proof: None,
         });
     }
 
@@ -444,7 +2019,7 @@ mod tests {
 
     #[test]
     fn roundtrip_empty_payload() {
-        roundtrip(Packet::JoinVoiceChannelRequest { request_id: 3 });
+        roundtrip(Packet::LeaveVoiceChannelRequest { request_id: 3 });
     }
 
     #[test]
@@ -465,29 +2040,44 @@ mod tests {
             request_id: 6,
             id: 42,
             voice_token: 0xDEADBEEF,
+            voice_key: [0x5A; VOICE_KEY_LEN],
             participants: vec![
                 ParticipantInfo {
                     user_id: 1,
                     username: "alice".to_string(),
-                    in_voice: true,
+                    channel_id: Some(1),
                     is_muted: false,
+                    is_deafened: true,
+                    role: Role::Admin,
+                    permissions: Permissions::ALL,
                 },
                 ParticipantInfo {
                     user_id: 2,
                     username: "bob".to_string(),
-                    in_voice: false,
+                    channel_id: None,
                     is_muted: true,
+                    is_deafened: false,
+                    role: Role::Unknown(9),
+                    permissions: Permissions::from_bits(0x8000_0001),
                 },
             ],
+            channels: vec![ChannelInfo {
+                channel_id: 1,
+                name: "General".to_string(),
+            }],
         });
     }
 
     #[test]
     fn roundtrip_multiple_strings() {
         roundtrip(Packet::UserSentMessage {
+            message_id: 1,
             user_id: 111,
             timestamp: 0xDEADBEEF,
             message: "Test message".to_string(),
+            reply_to: None,
+            mentions: Vec::new(),
+            attachments: Vec::new(),
         });
     }
 
@@ -506,15 +2096,20 @@ mod tests {
         roundtrip(Packet::LoginRequest {
             request_id: 7,
             username: String::new(),
+            auth_proof: None,
         });
     }
 
     #[test]
     fn roundtrip_unicode_string() {
         roundtrip(Packet::UserSentMessage {
+            message_id: 2,
             user_id: 1,
             timestamp: 0xDEADBEEF,
             message: "用户🎉 Привет мир! 🌍".to_string(),
+            reply_to: Some(1),
+            mentions: vec![1],
+            attachments: Vec::new(),
         });
     }
 
@@ -524,7 +2119,9 @@ mod tests {
             request_id: 8,
             id: 1,
             voice_token: 123,
+            voice_key: [0; VOICE_KEY_LEN],
             participants: vec![],
+            channels: vec![],
         });
     }
 
@@ -537,4 +2134,498 @@ mod tests {
             data: vec![0xFF; 1024],
         });
     }
+
+    #[test]
+    fn encode_rejects_oversized_payload() {
+        let packet = Packet::ChatMessageRequest {
+            request_id: 1,
+            message: "a".repeat(usize::from(u16::MAX)),
+            reply_to: None,
+            attachments: Vec::new(),
+        };
+        assert!(matches!(
+            packet.encode(),
+            Err(ProtocolError::PayloadTooLarge { size }) if size > usize::from(u16::MAX)
+        ));
+    }
+
+    #[test]
+    fn roundtrip_channel_list() {
+        roundtrip(Packet::ListChannelsResponse {
+            request_id: 9,
+            channels: vec![
+                ChannelInfo::new(1, "standup".to_string()),
+                ChannelInfo::new(2, "pairing-1".to_string()),
+            ],
+        });
+        roundtrip(Packet::ChannelCreated {
+            channel: ChannelInfo::new(3, "afk".to_string()),
+        });
+    }
+
+    #[test]
+    fn roundtrip_join_channel_by_id() {
+        roundtrip(Packet::JoinVoiceChannelRequest {
+            request_id: 10,
+            channel_id: 0xFFFF_FFFF_FFFF_FFFF,
+        });
+        roundtrip(Packet::UserJoinedVoice {
+            user_id: 5,
+            channel_id: 2,
+        });
+    }
+
+    #[test]
+    fn roundtrip_handshake_preserves_unknown_capabilities() {
+        roundtrip(Packet::Hello {
+            request_id: 11,
+            protocol_version: 7,
+            capabilities: Capabilities::from_bits(0x8000_0001),
+        });
+        roundtrip(Packet::ServerHello {
+            request_id: 11,
+            protocol_version: 1,
+            capabilities: Capabilities::CHANNELS,
+            accepted: true,
+            auth_challenge: [0xAB; AUTH_CHALLENGE_LEN],
+            auth_salt: [0xCD; AUTH_SALT_LEN],
+        });
+    }
+
+    #[test]
+    fn roundtrip_login_with_proof() {
+        roundtrip(Packet::LoginRequest {
+            request_id: 12,
+            username: "alice".to_string(),
+            auth_proof: Some([0x5A; AUTH_PROOF_LEN]),
+        });
+        roundtrip(Packet::LoginRejected {
+            request_id: 12,
+            reason: LoginRejectReason::InvalidCredentials,
+        });
+        roundtrip(Packet::LoginRejected {
+            request_id: 13,
+            reason: LoginRejectReason::Unknown(0xEE),
+        });
+    }
+
+    #[test]
+    fn roundtrip_login_reject_reasons() {
+        for reason in [
+            LoginRejectReason::InvalidUsername,
+            LoginRejectReason::InvalidCredentials,
+            LoginRejectReason::NameTaken,
+            LoginRejectReason::ServerFull,
+            LoginRejectReason::Banned,
+            LoginRejectReason::ServerMuted,
+        ] {
+            roundtrip(Packet::LoginRejected {
+                request_id: 14,
+                reason,
+            });
+        }
+    }
+
+    #[test]
+    fn roundtrip_nickname_change() {
+        roundtrip(Packet::ChangeNicknameRequest {
+            request_id: 15,
+            username: "Alice".to_string(),
+        });
+        roundtrip(Packet::ChangeNicknameResponse {
+            request_id: 15,
+            rejection: None,
+        });
+        roundtrip(Packet::ChangeNicknameResponse {
+            request_id: 16,
+            rejection: Some(LoginRejectReason::NameTaken),
+        });
+        roundtrip(Packet::UserRenamed {
+            user_id: 7,
+            username: "Alice".to_string(),
+        });
+    }
+
+    #[test]
+    fn roundtrip_motd() {
+        roundtrip(Packet::Motd {
+            message: "Welcome! Voice is recorded in #standup".to_string(),
+        });
+    }
+
+    #[test]
+    fn roundtrip_server_shutting_down() {
+        roundtrip(Packet::ServerShuttingDown {
+            reason: "Server is restarting".to_string(),
+            reconnect_after_ms: 5000,
+        });
+        roundtrip(Packet::ServerShuttingDown {
+            reason: String::new(),
+            reconnect_after_ms: 0,
+        });
+    }
+
+    #[test]
+    fn roundtrip_session_resume() {
+        roundtrip(Packet::ResumeToken {
+            token: 0x0123_4567_89ab_cdef,
+        });
+        roundtrip(Packet::ResumeSessionRequest {
+            request_id: 17,
+            token: 0x0123_4567_89ab_cdef,
+        });
+        roundtrip(Packet::ResumeSessionResponse {
+            request_id: 17,
+            accepted: true,
+        });
+        roundtrip(Packet::ResumeSessionResponse {
+            request_id: 18,
+            accepted: false,
+        });
+    }
+
+    #[test]
+    fn roundtrip_moderation() {
+        roundtrip(Packet::KickUserRequest {
+            request_id: 19,
+            user_id: 2,
+            reason: "spamming".to_string(),
+        });
+        roundtrip(Packet::BanUserRequest {
+            request_id: 20,
+            user_id: 3,
+            reason: String::new(),
+            ban_address: true,
+        });
+        roundtrip(Packet::ServerMuteUserRequest {
+            request_id: 21,
+            user_id: 4,
+            muted: true,
+        });
+        roundtrip(Packet::UnbanUserRequest {
+            request_id: 22,
+            username: "bob".to_string(),
+        });
+        roundtrip(Packet::ModerationResponse {
+            request_id: 19,
+            rejection: None,
+        });
+        roundtrip(Packet::ModerationResponse {
+            request_id: 20,
+            rejection: Some(ModerationRejectReason::NotPermitted),
+        });
+
+        for action in [
+            ModerationAction::Kicked,
+            ModerationAction::Banned,
+            ModerationAction::ServerMuted,
+            ModerationAction::ServerUnmuted,
+            ModerationAction::Unknown(0xEE),
+        ] {
+            roundtrip(Packet::UserModerated {
+                user_id: 2,
+                moderator_id: 1,
+                action,
+                reason: "spamming".to_string(),
+            });
+        }
+    }
+
+    #[test]
+    fn roundtrip_mute_state() {
+        roundtrip(Packet::SetMuteStateRequest {
+            request_id: 22,
+            is_muted: false,
+            is_deafened: true,
+        });
+        roundtrip(Packet::SetMuteStateResponse {
+            request_id: 22,
+            is_muted: true,
+            is_deafened: true,
+        });
+        roundtrip(Packet::UserMuteState {
+            user_id: 5,
+            is_muted: true,
+            is_deafened: false,
+        });
+    }
+
+    #[test]
+    fn roundtrip_chat_history() {
+        roundtrip(Packet::ChatHistoryRequest {
+            request_id: 23,
+            before: Some(1_700_000_000_000),
+            after: None,
+            limit: 50,
+        });
+        roundtrip(Packet::ChatHistoryResponse {
+            request_id: 23,
+            messages: vec![
+                ChatHistoryMessage::new(7, 1_699_999_999_000, "alice".to_string(), "hi".to_string()),
+                ChatHistoryMessage::new(8, 1_699_999_999_001, "bob".to_string(), String::new())
+                    .with_reply_to(7)
+                    .with_edited(true)
+                    .with_reactions(vec![Reaction::new(
+                        "👍".to_string(),
+                        vec!["alice".to_string(), "carol".to_string()],
+                    )]),
+            ],
+            has_more: true,
+        });
+        roundtrip(Packet::ChatHistoryResponse {
+            request_id: 24,
+            messages: Vec::new(),
+            has_more: false,
+        });
+    }
+
+    #[test]
+    fn roundtrip_message_edits() {
+        roundtrip(Packet::ChatMessageRequest {
+            request_id: 25,
+            message: "me too".to_string(),
+            reply_to: Some(7),
+            attachments: Vec::new(),
+        });
+        roundtrip(Packet::ChatMessageResponse {
+            request_id: 25,
+            message_id: 9,
+            rejection: None,
+        });
+        roundtrip(Packet::ChatMessageResponse {
+            request_id: 25,
+            message_id: 0,
+            rejection: Some(ChatRejectReason::TooLong),
+        });
+        roundtrip(Packet::EditMessageRequest {
+            request_id: 26,
+            message_id: 9,
+            message: "me three".to_string(),
+        });
+        roundtrip(Packet::EditMessageResponse {
+            request_id: 26,
+            success: true,
+        });
+        roundtrip(Packet::DeleteMessageRequest {
+            request_id: 27,
+            message_id: 9,
+        });
+        roundtrip(Packet::DeleteMessageResponse {
+            request_id: 27,
+            success: false,
+        });
+        roundtrip(Packet::MessageEdited {
+            message_id: 9,
+            message: "me three".to_string(),
+        });
+        roundtrip(Packet::MessageDeleted { message_id: 9 });
+    }
+
+    #[test]
+    fn roundtrip_direct_messages() {
+        let request = Packet::DirectMessageRequest {
+            request_id: 28,
+            user_id: 4,
+            message: "psst".to_string(),
+        };
+        assert_eq!(request.request_id(), Some(28));
+        roundtrip(request);
+        roundtrip(Packet::DirectMessageResponse {
+            request_id: 28,
+            success: true,
+            timestamp: 1_700_000_000_000,
+        });
+        let event = Packet::DirectMessageReceived {
+            from_user_id: 3,
+            timestamp: 1_700_000_000_000,
+            message: "psst".to_string(),
+        };
+        assert_eq!(event.request_id(), None);
+        roundtrip(event);
+    }
+
+    #[test]
+    fn roundtrip_typing_and_read_markers() {
+        roundtrip(Packet::SetTypingRequest {
+            request_id: 29,
+            is_typing: true,
+        });
+        roundtrip(Packet::MarkReadRequest {
+            request_id: 30,
+            message_id: 12,
+        });
+        roundtrip(Packet::TypingStarted { user_id: 3 });
+        roundtrip(Packet::TypingStopped { user_id: 3 });
+        roundtrip(Packet::MessageRead {
+            user_id: 3,
+            message_id: 12,
+        });
+    }
+
+    #[test]
+    fn roundtrip_mentions_and_reactions() {
+        roundtrip(Packet::UserSentMessage {
+            message_id: 13,
+            user_id: 3,
+            timestamp: 1_700_000_000_000,
+            message: "@alice @bob look".to_string(),
+            reply_to: None,
+            mentions: vec![1, 2],
+            attachments: Vec::new(),
+        });
+        roundtrip(Packet::SetReactionRequest {
+            request_id: 31,
+            message_id: 13,
+            emoji: "🎉".to_string(),
+            reacted: true,
+        });
+        roundtrip(Packet::SetReactionResponse {
+            request_id: 31,
+            success: false,
+        });
+        roundtrip(Packet::MessageReactionChanged {
+            message_id: 13,
+            user_id: 1,
+            emoji: "🎉".to_string(),
+            reacted: false,
+        });
+    }
+
+    #[test]
+    fn roundtrip_attachments() {
+        let screenshot = Attachment::new(5, "screenshot.png".to_string(), "image/png".to_string(), 40_000);
+        assert!(screenshot.is_image());
+        roundtrip(Packet::UploadAttachmentRequest {
+            request_id: 32,
+            name: "screenshot.png".to_string(),
+            mime_type: "image/png".to_string(),
+            size: 40_000,
+        });
+        roundtrip(Packet::UploadAttachmentResponse {
+            request_id: 32,
+            attachment_id: 5,
+            rejection: None,
+        });
+        roundtrip(Packet::UploadAttachmentResponse {
+            request_id: 32,
+            attachment_id: 0,
+            rejection: Some(AttachmentRejectReason::TooLarge),
+        });
+        roundtrip(Packet::UploadChunkRequest {
+            request_id: 33,
+            attachment_id: 5,
+            offset: 32_768,
+            data: vec![0x89; 7_232],
+        });
+        roundtrip(Packet::UploadChunkResponse {
+            request_id: 33,
+            success: true,
+        });
+        roundtrip(Packet::DownloadChunkRequest {
+            request_id: 34,
+            attachment_id: 5,
+            offset: 0,
+        });
+        roundtrip(Packet::DownloadChunkResponse {
+            request_id: 34,
+            success: true,
+            data: vec![0x89; crate::ATTACHMENT_CHUNK_LEN],
+        });
+        roundtrip(Packet::ChatMessageRequest {
+            request_id: 35,
+            message: String::new(),
+            reply_to: None,
+            attachments: vec![5],
+        });
+        roundtrip(Packet::UserSentMessage {
+            message_id: 14,
+            user_id: 3,
+            timestamp: 1_700_000_000_000,
+            message: String::new(),
+            reply_to: None,
+            mentions: Vec::new(),
+            attachments: vec![screenshot.clone()],
+        });
+        roundtrip(Packet::ChatHistoryResponse {
+            request_id: 36,
+            messages: vec![ChatHistoryMessage::new(14, 1_700_000_000_000, "alice".to_string(), String::new())
+                .with_attachments(vec![screenshot])],
+            has_more: false,
+        });
+    }
+
+    #[test]
+    fn roundtrip_extended_framing() {
+        let messages: Vec<_> = (1..=40)
+            .map(|id| ChatHistoryMessage::new(id, 1_700_000_000_000 + id, "alice".to_string(), "a".repeat(2000)))
+            .collect();
+        let packet = Packet::ChatHistoryResponse { request_id: 37, messages, has_more: false };
+
+        // Too large for a u16 length, but fine once both peers speak the extended framing
+        assert!(matches!(packet.encode(), Err(ProtocolError::PayloadTooLarge { .. })));
+        let encoded = packet.encode_with(Framing::Extended).expect("encode failed");
+        assert!(encoded.len() > usize::from(u16::MAX));
+        assert_eq!(Packet::decode_with(&encoded, Framing::Extended), Ok((packet, encoded.len())));
+
+        let ping = Packet::PingRequest { request_id: 38 };
+        let encoded = ping.encode_with(Framing::Extended).expect("encode failed");
+        assert_eq!(encoded[..5], [PacketId::PingRequest as u8, 0, 0, 0, 8]);
+        assert_eq!(Packet::decode_with(&encoded, Framing::Extended), Ok((ping, encoded.len())));
+    }
+
+    #[test]
+    fn extended_framing_widens_lengths_and_counts() {
+        let participants: Vec<_> = (1..=u64::from(u16::MAX) + 1)
+            .map(|id| ParticipantInfo::new(id, String::new(), None, false, false, Role::Member, Permissions::NONE))
+            .collect();
+        let packet = Packet::LoginResponse {
+            request_id: 40,
+            id: 1,
+            voice_token: 2,
+            voice_key: [7; VOICE_KEY_LEN],
+            participants,
+            channels: Vec::new(),
+        };
+        assert!(matches!(packet.encode(), Err(ProtocolError::PayloadTooLarge { .. })));
+        let encoded = packet.encode_with(Framing::Extended).expect("encode failed");
+        assert_eq!(Packet::decode_with(&encoded, Framing::Extended), Ok((packet, encoded.len())));
+
+        let packet = Packet::ChatMessageRequest {
+            request_id: 41,
+            message: "a".repeat(usize::from(u16::MAX) + 1),
+            reply_to: None,
+            attachments: Vec::new(),
+        };
+        assert!(matches!(packet.encode(), Err(ProtocolError::PayloadTooLarge { .. })));
+        let encoded = packet.encode_with(Framing::Extended).expect("encode failed");
+        assert_eq!(encoded[13..17], (u32::from(u16::MAX) + 1).to_be_bytes());
+        assert_eq!(Packet::decode_with(&encoded, Framing::Extended), Ok((packet, encoded.len())));
+    }
+
+    #[test]
+    fn extended_framing_rejects_oversized_lengths() {
+        // Announced lengths past the limit fail before the payload arrives
+        let header = [PacketId::PingRequest as u8, 0xFF, 0xFF, 0xFF, 0xFF];
+        assert!(matches!(
+            Packet::decode_with(&header, Framing::Extended),
+            Err(ProtocolError::PayloadTooLarge { size }) if size > crate::MAX_EXTENDED_PAYLOAD_LEN
+        ));
+        assert!(matches!(
+            Packet::decode_with(&header[..3], Framing::Extended),
+            Err(ProtocolError::PacketTooShort { .. })
+        ));
+
+        // Counts past the end of the payload fail before allocating
+        let mut encoded = Packet::ChatHistoryResponse { request_id: 39, messages: Vec::new(), has_more: false }
+            .encode_with(Framing::Extended)
+            .expect("encode failed");
+        encoded[13..17].copy_from_slice(&u32::MAX.to_be_bytes());
+        assert!(matches!(
+            Packet::decode_with(&encoded, Framing::Extended),
+            Err(ProtocolError::PacketTooShort { .. })
+        ));
+
+        assert_eq!(Framing::for_version(crate::MIN_PROTOCOL_VERSION), Framing::Standard);
+        assert_eq!(Framing::for_version(crate::EXTENDED_FRAMING_VERSION), Framing::Extended);
+    }
 }
@@ -30,6 +30,26 @@ packet_ids! {
     LeaveVoiceChannelRequest = 0x04,
     ChatMessageRequest = 0x05,
     PingRequest = 0x06,
+    CreateChannelRequest = 0x07,
+    ListChannelsRequest = 0x08,
+    Hello = 0x09,
+    ChangeNicknameRequest = 0x0A,
+    ResumeSessionRequest = 0x0B,
+    KickUserRequest = 0x0C,
+    BanUserRequest = 0x0D,
+    ServerMuteUserRequest = 0x0E,
+    SetMuteStateRequest = 0x0F,
+    ChatHistoryRequest = 0x10,
+    EditMessageRequest = 0x11,
+    DeleteMessageRequest = 0x12,
+    DirectMessageRequest = 0x13,
+    SetTypingRequest = 0x14,
+    MarkReadRequest = 0x15,
+    SetReactionRequest = 0x16,
+    UploadAttachmentRequest = 0x17,
+    UploadChunkRequest = 0x18,
+    DownloadChunkRequest = 0x19,
+    UnbanUserRequest = 0x1A,
 
     // Responses (0x20-0x3F)
     LoginResponse = 0x21,
@@ -38,6 +58,22 @@ packet_ids! {
     LeaveVoiceChannelResponse = 0x24,
     ChatMessageResponse = 0x25,
     PingResponse = 0x26,
+    CreateChannelResponse = 0x27,
+    ListChannelsResponse = 0x28,
+    ServerHello = 0x29,
+    LoginRejected = 0x2A,
+    ChangeNicknameResponse = 0x2B,
+    ResumeSessionResponse = 0x2C,
+    ModerationResponse = 0x2D,
+    SetMuteStateResponse = 0x2E,
+    ChatHistoryResponse = 0x2F,
+    EditMessageResponse = 0x30,
+    DeleteMessageResponse = 0x31,
+    DirectMessageResponse = 0x32,
+    SetReactionResponse = 0x33,
+    UploadAttachmentResponse = 0x34,
+    UploadChunkResponse = 0x35,
+    DownloadChunkResponse = 0x36,
 
     // Events (0x40-0x5F)
     UserJoinedServer = 0x41,
@@ -46,6 +82,19 @@ packet_ids! {
     UserLeftServer = 0x44,
     UserSentMessage = 0x45,
     UserMuteState = 0x46,
+    ChannelCreated = 0x47,
+    UserRenamed = 0x48,
+    Motd = 0x49,
+    ServerShuttingDown = 0x4A,
+    ResumeToken = 0x4B,
+    UserModerated = 0x4C,
+    MessageEdited = 0x4D,
+    MessageDeleted = 0x4E,
+    DirectMessageReceived = 0x4F,
+    TypingStarted = 0x50,
+    TypingStopped = 0x51,
+    MessageRead = 0x52,
+    MessageReactionChanged = 0x53,
 
     // UDP (0x60+)
     VoiceData = 0x61,
@@ -0,0 +1,175 @@
+//! Borrowed views of received packets, so the voice path can read and forward `VoiceData` without allocating.
+
+use crate::error::ProtocolError;
+use crate::framing::Framing;
+use crate::io::Reader;
+use crate::packet::Packet;
+use crate::packet_id::PacketId;
+
+/// Bytes of an encoded `VoiceData` before its payload: packet id, payload length, user id, sequence and timestamp.
+pub const VOICE_DATA_HEADER_LEN: usize = 19;
+
+/// Offset of the user id in an encoded `VoiceData`, followed by the sequence number.
+const VOICE_DATA_USER_ID_OFFSET: usize = 3;
+
+/// A received packet, borrowing from the receive buffer where decoding would otherwise allocate.
+///
+/// Datagrams always use [`Framing::Standard`].
+#[derive(Debug, Clone, PartialEq)]
+pub enum PacketRef<'a> {
+    /// `VoiceData` borrowing its payload.
+    VoiceData(VoiceDataRef<'a>),
+    /// Any other packet, decoded like [`Packet::decode`].
+    Other(Packet),
+}
+
+/// `VoiceData` whose payload stays in the receive buffer.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct VoiceDataRef<'a> {
+    pub user_id: u64,
+    pub sequence: u32,
+    pub timestamp: u32,
+    pub data: &'a [u8],
+}
+
+impl<'a> PacketRef<'a> {
+    /// Decode a packet, `VoiceData` borrows its payload from `buf`.
+    ///
+    /// Returns decoded packet and number of bytes consumed from the buffer.
+    ///
+    /// # Errors
+    /// Returns error if buffer is incomplete or contains invalid data.
+    pub fn decode(buf: &'a [u8]) -> Result<(Self, usize), ProtocolError> {
+        if buf.first() != Some(&PacketId::VoiceData.as_u8()) {
+            return Packet::decode(buf).map(|(packet, size)| (Self::Other(packet), size));
+        }
+
+        let mut header = Reader::new(buf);
+        header.read_u8()?;
+        let payload_len = header.read_payload_len(Framing::Standard)?;
+        let remaining = header.remaining();
+
+        if remaining.len() < payload_len {
+            return Err(ProtocolError::IncompletePayload {
+                expected: payload_len,
+                got: remaining.len(),
+            });
+        }
+
+        let mut r = Reader::new(&remaining[..payload_len]);
+        let voice_data = VoiceDataRef {
+            user_id: r.read_u64()?,
+            sequence: r.read_u32()?,
+            timestamp: r.read_u32()?,
+            data: r.remaining(),
+        };
+
+        Ok((Self::VoiceData(voice_data), header.position() + payload_len))
+    }
+
+    /// Copies borrowed data into an owned [`Packet`].
+    #[must_use]
+    pub fn into_owned(self) -> Packet {
+        match self {
+            Self::VoiceData(voice_data) => voice_data.into_owned(),
+            Self::Other(packet) => packet,
+        }
+    }
+}
+
+impl VoiceDataRef<'_> {
+    /// Copies the payload into an owned [`Packet::VoiceData`].
+    #[must_use]
+    pub fn into_owned(self) -> Packet {
+        Packet::VoiceData {
+            user_id: self.user_id,
+            sequence: self.sequence,
+            timestamp: self.timestamp,
+            data: self.data.to_vec(),
+        }
+    }
+
+    /// Replaces the user id and sequence number of an encoded `VoiceData` in place, so a relay can forward it
+    /// without re-encoding. Its payload starts at [`VOICE_DATA_HEADER_LEN`].
+    ///
+    /// # Errors
+    /// Returns [`ProtocolError::UnknownPacketId`] if `packet` holds another packet, or
+    /// [`ProtocolError::PacketTooShort`] if it ends before the payload.
+    pub fn rewrite_header(packet: &mut [u8], user_id: u64, sequence: u32) -> Result<(), ProtocolError> {
+        if packet.len() < VOICE_DATA_HEADER_LEN {
+            return Err(ProtocolError::PacketTooShort {
+                expected: VOICE_DATA_HEADER_LEN,
+                got: packet.len(),
+            });
+        }
+        if packet[0] != PacketId::VoiceData.as_u8() {
+            return Err(ProtocolError::UnknownPacketId(packet[0]));
+        }
+
+        packet[VOICE_DATA_USER_ID_OFFSET..VOICE_DATA_USER_ID_OFFSET + 8].copy_from_slice(&user_id.to_be_bytes());
+        packet[VOICE_DATA_USER_ID_OFFSET + 8..VOICE_DATA_USER_ID_OFFSET + 12].copy_from_slice(&sequence.to_be_bytes());
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn voice_data() -> Packet {
+        Packet::VoiceData {
+            user_id: 42,
+            sequence: 7,
+            timestamp: 960,
+            data: vec![1, 2, 3, 4, 5],
+        }
+    }
+
+    #[test]
+    fn decode_borrows_voice_data() {
+        let encoded = voice_data().encode().unwrap();
+        let (packet, size) = PacketRef::decode(&encoded).unwrap();
+
+        assert_eq!(size, encoded.len());
+        let PacketRef::VoiceData(voice_data_ref) = packet else {
+            panic!("expected VoiceData, got {packet:?}");
+        };
+        assert_eq!(voice_data_ref.data, &encoded[VOICE_DATA_HEADER_LEN..]);
+        assert_eq!(voice_data_ref.into_owned(), voice_data());
+        assert_eq!(Packet::decode(&encoded), Ok((packet.into_owned(), size)));
+    }
+
+    #[test]
+    fn decode_falls_back_to_owned_packets() {
+        let auth = Packet::VoiceAuthRequest { request_id: 1, voice_token: 99 };
+        let encoded = auth.encode().unwrap();
+        assert_eq!(PacketRef::decode(&encoded), Ok((PacketRef::Other(auth), encoded.len())));
+
+        // Truncated voice packets fail like they do for Packet::decode
+        let encoded = voice_data().encode().unwrap();
+        for len in [1, 3, VOICE_DATA_HEADER_LEN - 1, encoded.len() - 1] {
+            assert_eq!(PacketRef::decode(&encoded[..len]).map(|_| ()), Packet::decode(&encoded[..len]).map(|_| ()));
+        }
+    }
+
+    #[test]
+    fn rewrite_header_in_place() {
+        let mut encoded = voice_data().encode().unwrap();
+        VoiceDataRef::rewrite_header(&mut encoded, 1234, 70).unwrap();
+
+        let Ok((Packet::VoiceData { user_id, sequence, data, .. }, _)) = Packet::decode(&encoded) else {
+            panic!("rewritten packet does not decode");
+        };
+        assert_eq!((user_id, sequence, data), (1234, 70, vec![1, 2, 3, 4, 5]));
+
+        let mut auth = Packet::VoiceAuthRequest { request_id: 1, voice_token: 99 }.encode().unwrap();
+        assert!(matches!(
+            VoiceDataRef::rewrite_header(&mut auth, 1234, 70),
+            Err(ProtocolError::UnknownPacketId(_))
+        ));
+        assert!(matches!(
+            VoiceDataRef::rewrite_header(&mut encoded[..VOICE_DATA_HEADER_LEN - 1], 1234, 70),
+            Err(ProtocolError::PacketTooShort { .. })
+        ));
+    }
+}
@@ -0,0 +1,105 @@
+//! User roles and the permissions the server grants them, sent in [`ParticipantInfo`](crate::ParticipantInfo).
+
+use std::fmt;
+use std::ops::BitOr;
+
+/// Role assigned to a user by the server, from least to most privileged.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub enum Role {
+    /// Limited access, by default chat and listening only.
+    Guest,
+    /// Regular user.
+    Member,
+    /// May moderate guests and members.
+    Moderator,
+    /// May moderate everyone except other admins.
+    Admin,
+    /// Role code not known to this build.
+    Unknown(u8),
+}
+
+impl Role {
+    pub(crate) fn code(self) -> u8 {
+        match self {
+            Self::Guest => 0,
+            Self::Member => 1,
+            Self::Moderator => 2,
+            Self::Admin => 3,
+            Self::Unknown(code) => code,
+        }
+    }
+
+    pub(crate) fn from_code(code: u8) -> Self {
+        match code {
+            0 => Self::Guest,
+            1 => Self::Member,
+            2 => Self::Moderator,
+            3 => Self::Admin,
+            code => Self::Unknown(code),
+        }
+    }
+}
+
+impl fmt::Display for Role {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Guest => write!(f, "guest"),
+            Self::Member => write!(f, "member"),
+            Self::Moderator => write!(f, "moderator"),
+            Self::Admin => write!(f, "admin"),
+            Self::Unknown(code) => write!(f, "unknown role ({code})"),
+        }
+    }
+}
+
+/// Bitmask of actions a user may perform.
+///
+/// Unknown bits are preserved, like in [`Capabilities`](crate::Capabilities).
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub struct Permissions(u32);
+
+impl Permissions {
+    /// Nothing allowed.
+    pub const NONE: Self = Self(0);
+    /// Sending chat messages.
+    pub const CHAT: Self = Self(1 << 0);
+    /// Joining voice channels.
+    pub const JOIN_VOICE: Self = Self(1 << 1);
+    /// Having voice relayed to the channel, without it users only listen.
+    pub const SPEAK: Self = Self(1 << 2);
+    /// Kicking, banning and server-muting users of a lower role.
+    pub const MODERATE: Self = Self(1 << 3);
+    /// Creating voice channels.
+    pub const CREATE_CHANNELS: Self = Self(1 << 4);
+
+    /// Every permission known to this build.
+    pub const ALL: Self =
+        Self(Self::CHAT.0 | Self::JOIN_VOICE.0 | Self::SPEAK.0 | Self::MODERATE.0 | Self::CREATE_CHANNELS.0);
+
+    /// Creates a permission set from raw bits.
+    #[must_use]
+    pub const fn from_bits(bits: u32) -> Self {
+        Self(bits)
+    }
+
+    /// Returns the raw bits.
+    #[must_use]
+    pub const fn bits(self) -> u32 {
+        self.0
+    }
+
+    /// Returns true if every permission in `other` is present.
+    #[must_use]
+    pub const fn contains(self, other: Self) -> bool {
+        self.0 & other.0 == other.0
+    }
+}
+
+impl BitOr for Permissions {
+    type Output = Self;
+
+    fn bitor(self, rhs: Self) -> Self {
+        Self(self.0 | rhs.0)
+    }
+}
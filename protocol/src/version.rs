@@ -0,0 +1,119 @@
+//! Protocol revision and optional capabilities exchanged in the `Hello`/`ServerHello` handshake.
+
+use std::ops::BitOr;
+
+/// Protocol revision spoken by this build. Bumped on incompatible wire format changes.
+pub const PROTOCOL_VERSION: u16 = 10;
+
+/// Oldest protocol revision this build can still talk to.
+pub const MIN_PROTOCOL_VERSION: u16 = 9;
+
+/// First protocol revision whose packets carry a u32 payload length, see [`crate::Framing`].
+pub const EXTENDED_FRAMING_VERSION: u16 = 10;
+
+/// Longest a client with [`Capabilities::HEARTBEAT`] stays silent on the management connection
+/// and on the voice socket.
+pub const HEARTBEAT_INTERVAL_MS: u32 = 15_000;
+
+/// How often a client with [`Capabilities::TYPING`] re-sends `SetTypingRequest` while the user keeps typing.
+/// Servers let the indicator expire a while after the last one.
+pub const TYPING_INTERVAL_MS: u32 = 3_000;
+
+/// Most file bytes carried by one `UploadChunkRequest` or `DownloadChunkResponse`.
+pub const ATTACHMENT_CHUNK_LEN: usize = 32 * 1024;
+
+/// Returns the revision to speak with a peer announcing `peer_version`,
+/// or `None` if the peer is too old for this build.
+#[must_use]
+pub fn negotiate_version(peer_version: u16) -> Option<u16> {
+    let version = peer_version.min(PROTOCOL_VERSION);
+    (version >= MIN_PROTOCOL_VERSION).then_some(version)
+}
+
+/// Bitmask of optional protocol features.
+///
+/// Unknown bits are preserved, so a peer can always compute the intersection
+/// with whatever a newer peer announces.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub struct Capabilities(u32);
+
+impl Capabilities {
+    /// No optional features.
+    pub const NONE: Self = Self(0);
+    /// Runtime voice channel creation and listing.
+    pub const CHANNELS: Self = Self(1 << 0);
+    /// Changing the username after login.
+    pub const NICKNAMES: Self = Self(1 << 1);
+    /// Message of the day sent after login.
+    pub const MOTD: Self = Self(1 << 2);
+    /// Notice before the server shuts down.
+    pub const SHUTDOWN_NOTICE: Self = Self(1 << 3);
+    /// Keeping the same user id when reconnecting shortly after a dropped connection.
+    pub const SESSION_RESUME: Self = Self(1 << 4);
+    /// Client sends `PingRequest` and re-sends `VoiceAuthRequest` every [`HEARTBEAT_INTERVAL_MS`],
+    /// the server drops it once it goes quiet.
+    pub const HEARTBEAT: Self = Self(1 << 5);
+    /// Kicking, banning and server-muting users.
+    pub const MODERATION: Self = Self(1 << 6);
+    /// Chat messages kept by the server and paged through with `ChatHistoryRequest`.
+    pub const CHAT_HISTORY: Self = Self(1 << 7);
+    /// Receiving `DirectMessageReceived`, users without it cannot be sent direct messages.
+    pub const DIRECT_MESSAGES: Self = Self(1 << 8);
+    /// `SetTypingRequest` and the `TypingStarted`/`TypingStopped` events.
+    pub const TYPING: Self = Self(1 << 9);
+    /// `MarkReadRequest` and the `MessageRead` event.
+    pub const READ_MARKERS: Self = Self(1 << 10);
+    /// `SetReactionRequest` and the `MessageReactionChanged` event.
+    pub const REACTIONS: Self = Self(1 << 11);
+    /// Files attached to chat messages, transferred in chunks with `UploadChunkRequest` and `DownloadChunkRequest`.
+    pub const ATTACHMENTS: Self = Self(1 << 12);
+
+    /// Everything supported by this build.
+    pub const SUPPORTED: Self = Self(
+        Self::CHANNELS.0
+            | Self::NICKNAMES.0
+            | Self::MOTD.0
+            | Self::SHUTDOWN_NOTICE.0
+            | Self::SESSION_RESUME.0
+            | Self::HEARTBEAT.0
+            | Self::MODERATION.0
+            | Self::CHAT_HISTORY.0
+            | Self::DIRECT_MESSAGES.0
+            | Self::TYPING.0
+            | Self::READ_MARKERS.0
+            | Self::REACTIONS.0
+            | Self::ATTACHMENTS.0,
+    );
+
+    /// Creates a capability set from raw bits.
+    #[must_use]
+    pub const fn from_bits(bits: u32) -> Self {
+        Self(bits)
+    }
+
+    /// Returns the raw bits.
+    #[must_use]
+    pub const fn bits(self) -> u32 {
+        self.0
+    }
+
+    /// Returns true if every capability in `other` is present.
+    #[must_use]
+    pub const fn contains(self, other: Self) -> bool {
+        self.0 & other.0 == other.0
+    }
+
+    /// Returns the capabilities present in both sets.
+    #[must_use]
+    pub const fn intersection(self, other: Self) -> Self {
+        Self(self.0 & other.0)
+    }
+}
+
+impl BitOr for Capabilities {
+    type Output = Self;
+
+    fn bitor(self, rhs: Self) -> Self {
+        Self(self.0 | rhs.0)
+    }
+}
@@ -0,0 +1,446 @@
+//! Property tests for encoding and decoding: every packet roundtrips, and `decode` never panics on
+//! untrusted bytes and always consumes exactly the payload length its header declares.
+
+use proptest::collection::vec;
+use proptest::prelude::*;
+use voiceapp_protocol::auth::{AUTH_CHALLENGE_LEN, AUTH_PROOF_LEN, AUTH_SALT_LEN};
+use voiceapp_protocol::crypto::VOICE_KEY_LEN;
+use voiceapp_protocol::{
+    Attachment, AttachmentRejectReason, Capabilities, ChannelInfo, ChatHistoryMessage, ChatRejectReason, Framing,
+    LoginRejectReason, ModerationAction, ModerationRejectReason, Packet, PacketRef, ParticipantInfo, Permissions,
+    ProtocolError, Reaction, Role,
+};
+
+/// Codes from here on are not assigned to any known variant, so `Unknown` roundtrips
+const FIRST_UNASSIGNED_CODE: u8 = 64;
+
+fn text() -> impl Strategy<Value = String> {
+    ".{0,24}"
+}
+
+fn bytes() -> impl Strategy<Value = Vec<u8>> {
+    vec(any::<u8>(), 0..64)
+}
+
+fn ids() -> impl Strategy<Value = Vec<u64>> {
+    vec(any::<u64>(), 0..8)
+}
+
+fn unknown_code() -> impl Strategy<Value = u8> {
+    FIRST_UNASSIGNED_CODE..=u8::MAX
+}
+
+fn capabilities() -> impl Strategy<Value = Capabilities> {
+    any::<u32>().prop_map(Capabilities::from_bits)
+}
+
+fn role() -> impl Strategy<Value = Role> {
+    prop_oneof![
+        Just(Role::Guest),
+        Just(Role::Member),
+        Just(Role::Moderator),
+        Just(Role::Admin),
+        unknown_code().prop_map(Role::Unknown),
+    ]
+}
+
+fn login_reject_reason() -> impl Strategy<Value = LoginRejectReason> {
+    prop_oneof![
+        Just(LoginRejectReason::InvalidUsername),
+        Just(LoginRejectReason::InvalidCredentials),
+        Just(LoginRejectReason::NameTaken),
+        Just(LoginRejectReason::ServerFull),
+        Just(LoginRejectReason::Banned),
+        Just(LoginRejectReason::ServerMuted),
+        unknown_code().prop_map(LoginRejectReason::Unknown),
+    ]
+}
+
+fn moderation_action() -> impl Strategy<Value = ModerationAction> {
+    prop_oneof![
+        Just(ModerationAction::Kicked),
+        Just(ModerationAction::Banned),
+        Just(ModerationAction::ServerMuted),
+        Just(ModerationAction::ServerUnmuted),
+        unknown_code().prop_map(ModerationAction::Unknown),
+    ]
+}
+
+fn moderation_reject_reason() -> impl Strategy<Value = ModerationRejectReason> {
+    prop_oneof![
+        Just(ModerationRejectReason::NotPermitted),
+        Just(ModerationRejectReason::UnknownUser),
+        unknown_code().prop_map(ModerationRejectReason::Unknown),
+    ]
+}
+
+fn chat_reject_reason() -> impl Strategy<Value = ChatRejectReason> {
+    prop_oneof![
+        Just(ChatRejectReason::NotPermitted),
+        Just(ChatRejectReason::Empty),
+        Just(ChatRejectReason::TooLong),
+        Just(ChatRejectReason::InvalidCharacters),
+        Just(ChatRejectReason::UnknownReply),
+        Just(ChatRejectReason::UnknownAttachment),
+        Just(ChatRejectReason::TooManyAttachments),
+        unknown_code().prop_map(ChatRejectReason::Unknown),
+    ]
+}
+
+fn attachment_reject_reason() -> impl Strategy<Value = AttachmentRejectReason> {
+    prop_oneof![
+        Just(AttachmentRejectReason::NotPermitted),
+        Just(AttachmentRejectReason::TooLarge),
+        Just(AttachmentRejectReason::InvalidName),
+        Just(AttachmentRejectReason::StorageFull),
+        unknown_code().prop_map(AttachmentRejectReason::Unknown),
+    ]
+}
+
+fn participant() -> impl Strategy<Value = ParticipantInfo> {
+    (any::<u64>(), text(), any::<Option<u64>>(), any::<bool>(), any::<bool>(), role(), any::<u32>()).prop_map(
+        |(user_id, username, channel_id, is_muted, is_deafened, role, permissions)| {
+            let permissions = Permissions::from_bits(permissions);
+            ParticipantInfo::new(user_id, username, channel_id, is_muted, is_deafened, role, permissions)
+        },
+    )
+}
+
+fn channel() -> impl Strategy<Value = ChannelInfo> {
+    (any::<u64>(), text()).prop_map(|(channel_id, name)| ChannelInfo::new(channel_id, name))
+}
+
+fn attachment() -> impl Strategy<Value = Attachment> {
+    (any::<u64>(), text(), text(), any::<u64>())
+        .prop_map(|(attachment_id, name, mime_type, size)| Attachment::new(attachment_id, name, mime_type, size))
+}
+
+fn history_message() -> impl Strategy<Value = ChatHistoryMessage> {
+    let reaction = (text(), vec(text(), 0..4)).prop_map(|(emoji, usernames)| Reaction::new(emoji, usernames));
+    (
+        (any::<u64>(), any::<u64>(), text(), text()),
+        any::<Option<u64>>(),
+        any::<bool>(),
+        vec(reaction, 0..3),
+        vec(attachment(), 0..3),
+    )
+        .prop_map(|((message_id, timestamp, username, message), reply_to, edited, reactions, attachments)| {
+            let entry = ChatHistoryMessage::new(message_id, timestamp, username, message)
+                .with_edited(edited)
+                .with_reactions(reactions)
+                .with_attachments(attachments);
+            match reply_to {
+                Some(message_id) => entry.with_reply_to(message_id),
+                None => entry,
+            }
+        })
+}
+
+/// Requests sent by clients
+fn request() -> impl Strategy<Value = Packet> {
+    prop_oneof![
+        (any::<u64>(), text(), any::<Option<[u8; AUTH_PROOF_LEN]>>()).prop_map(
+            |(request_id, username, auth_proof)| Packet::LoginRequest { request_id, username, auth_proof }
+        ),
+        (any::<u64>(), any::<u64>())
+            .prop_map(|(request_id, voice_token)| Packet::VoiceAuthRequest { request_id, voice_token }),
+        (any::<u64>(), any::<u64>())
+            .prop_map(|(request_id, channel_id)| Packet::JoinVoiceChannelRequest { request_id, channel_id }),
+        any::<u64>().prop_map(|request_id| Packet::LeaveVoiceChannelRequest { request_id }),
+        (any::<u64>(), text(), any::<Option<u64>>(), ids()).prop_map(
+            |(request_id, message, reply_to, attachments)| Packet::ChatMessageRequest {
+                request_id,
+                message,
+                reply_to,
+                attachments,
+            }
+        ),
+        any::<u64>().prop_map(|request_id| Packet::PingRequest { request_id }),
+        (any::<u64>(), text()).prop_map(|(request_id, name)| Packet::CreateChannelRequest { request_id, name }),
+        any::<u64>().prop_map(|request_id| Packet::ListChannelsRequest { request_id }),
+        (any::<u64>(), any::<u16>(), capabilities()).prop_map(|(request_id, protocol_version, capabilities)| {
+            Packet::Hello { request_id, protocol_version, capabilities }
+        }),
+        (any::<u64>(), text())
+            .prop_map(|(request_id, username)| Packet::ChangeNicknameRequest { request_id, username }),
+        (any::<u64>(), any::<u64>()).prop_map(|(request_id, token)| Packet::ResumeSessionRequest { request_id, token }),
+        (any::<u64>(), any::<u64>(), text())
+            .prop_map(|(request_id, user_id, reason)| Packet::KickUserRequest { request_id, user_id, reason }),
+        (any::<u64>(), any::<u64>(), text(), any::<bool>()).prop_map(
+            |(request_id, user_id, reason, ban_address)| Packet::BanUserRequest {
+                request_id,
+                user_id,
+                reason,
+                ban_address,
+            }
+        ),
+        (any::<u64>(), any::<u64>(), any::<bool>())
+            .prop_map(|(request_id, user_id, muted)| Packet::ServerMuteUserRequest { request_id, user_id, muted }),
+        (any::<u64>(), text()).prop_map(|(request_id, username)| Packet::UnbanUserRequest { request_id, username }),
+        (any::<u64>(), any::<bool>(), any::<bool>()).prop_map(|(request_id, is_muted, is_deafened)| {
+            Packet::SetMuteStateRequest { request_id, is_muted, is_deafened }
+        }),
+        (any::<u64>(), any::<Option<u64>>(), any::<Option<u64>>(), any::<u16>()).prop_map(
+            |(request_id, before, after, limit)| Packet::ChatHistoryRequest { request_id, before, after, limit }
+        ),
+        (any::<u64>(), any::<u64>(), text()).prop_map(|(request_id, message_id, message)| {
+            Packet::EditMessageRequest { request_id, message_id, message }
+        }),
+        (any::<u64>(), any::<u64>())
+            .prop_map(|(request_id, message_id)| Packet::DeleteMessageRequest { request_id, message_id }),
+        (any::<u64>(), any::<u64>(), text()).prop_map(|(request_id, user_id, message)| {
+            Packet::DirectMessageRequest { request_id, user_id, message }
+        }),
+        (any::<u64>(), any::<bool>())
+            .prop_map(|(request_id, is_typing)| Packet::SetTypingRequest { request_id, is_typing }),
+        (any::<u64>(), any::<u64>())
+            .prop_map(|(request_id, message_id)| Packet::MarkReadRequest { request_id, message_id }),
+        (any::<u64>(), any::<u64>(), text(), any::<bool>()).prop_map(
+            |(request_id, message_id, emoji, reacted)| Packet::SetReactionRequest {
+                request_id,
+                message_id,
+                emoji,
+                reacted,
+            }
+        ),
+        (any::<u64>(), text(), text(), any::<u64>()).prop_map(|(request_id, name, mime_type, size)| {
+            Packet::UploadAttachmentRequest { request_id, name, mime_type, size }
+        }),
+        (any::<u64>(), any::<u64>(), any::<u64>(), bytes()).prop_map(
+            |(request_id, attachment_id, offset, data)| Packet::UploadChunkRequest {
+                request_id,
+                attachment_id,
+                offset,
+                data,
+            }
+        ),
+        (any::<u64>(), any::<u64>(), any::<u64>()).prop_map(|(request_id, attachment_id, offset)| {
+            Packet::DownloadChunkRequest { request_id, attachment_id, offset }
+        }),
+    ]
+}
+
+/// Responses sent by the server
+fn response() -> impl Strategy<Value = Packet> {
+    prop_oneof![
+        (
+            (any::<u64>(), any::<u64>(), any::<u64>(), any::<[u8; VOICE_KEY_LEN]>()),
+            vec(participant(), 0..4),
+            vec(channel(), 0..4),
+        )
+            .prop_map(|((request_id, id, voice_token, voice_key), participants, channels)| {
+                Packet::LoginResponse { request_id, id, voice_token, voice_key, participants, channels }
+            }),
+        (any::<u64>(), any::<bool>())
+            .prop_map(|(request_id, success)| Packet::VoiceAuthResponse { request_id, success }),
+        (any::<u64>(), any::<bool>())
+            .prop_map(|(request_id, success)| Packet::JoinVoiceChannelResponse { request_id, success }),
+        (any::<u64>(), any::<bool>())
+            .prop_map(|(request_id, success)| Packet::LeaveVoiceChannelResponse { request_id, success }),
+        (any::<u64>(), any::<u64>(), proptest::option::of(chat_reject_reason())).prop_map(
+            |(request_id, message_id, rejection)| Packet::ChatMessageResponse { request_id, message_id, rejection }
+        ),
+        any::<u64>().prop_map(|request_id| Packet::PingResponse { request_id }),
+        (any::<u64>(), any::<bool>(), any::<u64>()).prop_map(|(request_id, success, channel_id)| {
+            Packet::CreateChannelResponse { request_id, success, channel_id }
+        }),
+        (any::<u64>(), vec(channel(), 0..4))
+            .prop_map(|(request_id, channels)| Packet::ListChannelsResponse { request_id, channels }),
+        (
+            any::<u64>(),
+            any::<u16>(),
+            capabilities(),
+            any::<bool>(),
+            any::<[u8; AUTH_CHALLENGE_LEN]>(),
+            any::<[u8; AUTH_SALT_LEN]>(),
+        )
+            .prop_map(|(request_id, protocol_version, capabilities, accepted, auth_challenge, auth_salt)| {
+                Packet::ServerHello { request_id, protocol_version, capabilities, accepted, auth_challenge, auth_salt }
+            }),
+        (any::<u64>(), login_reject_reason())
+            .prop_map(|(request_id, reason)| Packet::LoginRejected { request_id, reason }),
+        (any::<u64>(), proptest::option::of(login_reject_reason()))
+            .prop_map(|(request_id, rejection)| Packet::ChangeNicknameResponse { request_id, rejection }),
+        (any::<u64>(), any::<bool>())
+            .prop_map(|(request_id, accepted)| Packet::ResumeSessionResponse { request_id, accepted }),
+        (any::<u64>(), proptest::option::of(moderation_reject_reason()))
+            .prop_map(|(request_id, rejection)| Packet::ModerationResponse { request_id, rejection }),
+        (any::<u64>(), any::<bool>(), any::<bool>()).prop_map(|(request_id, is_muted, is_deafened)| {
+            Packet::SetMuteStateResponse { request_id, is_muted, is_deafened }
+        }),
+        (any::<u64>(), vec(history_message(), 0..4), any::<bool>()).prop_map(
+            |(request_id, messages, has_more)| Packet::ChatHistoryResponse { request_id, messages, has_more }
+        ),
+        (any::<u64>(), any::<bool>())
+            .prop_map(|(request_id, success)| Packet::EditMessageResponse { request_id, success }),
+        (any::<u64>(), any::<bool>())
+            .prop_map(|(request_id, success)| Packet::DeleteMessageResponse { request_id, success }),
+        (any::<u64>(), any::<bool>(), any::<u64>()).prop_map(|(request_id, success, timestamp)| {
+            Packet::DirectMessageResponse { request_id, success, timestamp }
+        }),
+        (any::<u64>(), any::<bool>())
+            .prop_map(|(request_id, success)| Packet::SetReactionResponse { request_id, success }),
+        (any::<u64>(), any::<u64>(), proptest::option::of(attachment_reject_reason())).prop_map(
+            |(request_id, attachment_id, rejection)| Packet::UploadAttachmentResponse {
+                request_id,
+                attachment_id,
+                rejection,
+            }
+        ),
+        (any::<u64>(), any::<bool>())
+            .prop_map(|(request_id, success)| Packet::UploadChunkResponse { request_id, success }),
+        (any::<u64>(), any::<bool>(), bytes()).prop_map(|(request_id, success, data)| {
+            Packet::DownloadChunkResponse { request_id, success, data }
+        }),
+    ]
+}
+
+/// Events pushed by the server and voice datagrams
+fn event() -> impl Strategy<Value = Packet> {
+    prop_oneof![
+        participant().prop_map(|participant| Packet::UserJoinedServer { participant }),
+        (any::<u64>(), any::<u64>())
+            .prop_map(|(user_id, channel_id)| Packet::UserJoinedVoice { user_id, channel_id }),
+        any::<u64>().prop_map(|user_id| Packet::UserLeftVoice { user_id }),
+        any::<u64>().prop_map(|user_id| Packet::UserLeftServer { user_id }),
+        (
+            (any::<u64>(), any::<u64>(), any::<u64>(), text()),
+            any::<Option<u64>>(),
+            ids(),
+            vec(attachment(), 0..3),
+        )
+            .prop_map(|((message_id, user_id, timestamp, message), reply_to, mentions, attachments)| {
+                Packet::UserSentMessage { message_id, user_id, timestamp, message, reply_to, mentions, attachments }
+            }),
+        (any::<u64>(), text()).prop_map(|(message_id, message)| Packet::MessageEdited { message_id, message }),
+        any::<u64>().prop_map(|message_id| Packet::MessageDeleted { message_id }),
+        (any::<u64>(), any::<u64>(), text()).prop_map(|(from_user_id, timestamp, message)| {
+            Packet::DirectMessageReceived { from_user_id, timestamp, message }
+        }),
+        any::<u64>().prop_map(|user_id| Packet::TypingStarted { user_id }),
+        any::<u64>().prop_map(|user_id| Packet::TypingStopped { user_id }),
+        (any::<u64>(), any::<u64>()).prop_map(|(user_id, message_id)| Packet::MessageRead { user_id, message_id }),
+        (any::<u64>(), any::<u64>(), text(), any::<bool>()).prop_map(|(message_id, user_id, emoji, reacted)| {
+            Packet::MessageReactionChanged { message_id, user_id, emoji, reacted }
+        }),
+        (any::<u64>(), any::<bool>(), any::<bool>()).prop_map(|(user_id, is_muted, is_deafened)| {
+            Packet::UserMuteState { user_id, is_muted, is_deafened }
+        }),
+        channel().prop_map(|channel| Packet::ChannelCreated { channel }),
+        (any::<u64>(), text()).prop_map(|(user_id, username)| Packet::UserRenamed { user_id, username }),
+        text().prop_map(|message| Packet::Motd { message }),
+        (text(), any::<u32>())
+            .prop_map(|(reason, reconnect_after_ms)| Packet::ServerShuttingDown { reason, reconnect_after_ms }),
+        any::<u64>().prop_map(|token| Packet::ResumeToken { token }),
+        (any::<u64>(), any::<u64>(), moderation_action(), text()).prop_map(
+            |(user_id, moderator_id, action, reason)| Packet::UserModerated { user_id, moderator_id, action, reason }
+        ),
+        (any::<u64>(), any::<u32>(), any::<u32>(), bytes()).prop_map(|(user_id, sequence, timestamp, data)| {
+            Packet::VoiceData { user_id, sequence, timestamp, data }
+        }),
+    ]
+}
+
+/// Every packet variant
+fn packet() -> impl Strategy<Value = Packet> {
+    prop_oneof![request(), response(), event()]
+}
+
+/// A header for any packet id declaring exactly the length of an arbitrary payload
+fn framed_payload() -> impl Strategy<Value = (Vec<u8>, Framing)> {
+    (any::<u8>(), vec(any::<u8>(), 0..256), prop_oneof![Just(Framing::Standard), Just(Framing::Extended)]).prop_map(
+        |(packet_id, payload, framing)| {
+            let mut buf = vec![packet_id];
+            match framing {
+                Framing::Standard => buf.extend_from_slice(&u16::try_from(payload.len()).unwrap().to_be_bytes()),
+                Framing::Extended => buf.extend_from_slice(&u32::try_from(payload.len()).unwrap().to_be_bytes()),
+            }
+            buf.extend_from_slice(&payload);
+            (buf, framing)
+        },
+    )
+}
+
+/// Bytes before the payload
+fn header_len(framing: Framing) -> usize {
+    match framing {
+        Framing::Standard => 3,
+        Framing::Extended => 5,
+    }
+}
+
+/// Payload length declared in the header of `buf`
+fn declared_len(buf: &[u8], framing: Framing) -> usize {
+    match framing {
+        Framing::Standard => usize::from(u16::from_be_bytes([buf[1], buf[2]])),
+        Framing::Extended => usize::try_from(u32::from_be_bytes([buf[1], buf[2], buf[3], buf[4]])).unwrap(),
+    }
+}
+
+/// Decodes untrusted bytes and checks what holds for any input: no panic, exactly the declared length
+/// is consumed, and whatever decodes encodes again to the same packet
+fn check_decode(buf: &[u8], framing: Framing) -> Result<(), TestCaseError> {
+    let Ok((packet, size)) = Packet::decode_with(buf, framing) else {
+        return Ok(());
+    };
+    prop_assert_eq!(size, header_len(framing) + declared_len(buf, framing));
+    prop_assert!(size <= buf.len());
+
+    let encoded = packet.encode_with(framing).expect("decoded packet does not encode");
+    prop_assert!(encoded.len() <= size, "payload grew from {} to {} bytes", size, encoded.len());
+    prop_assert_eq!(Packet::decode_with(&encoded, framing), Ok((packet, encoded.len())));
+
+    if framing == Framing::Standard {
+        let borrowed = PacketRef::decode(buf).map(|(packet, size)| (packet.into_owned(), size));
+        prop_assert_eq!(borrowed, Packet::decode(buf));
+    }
+    Ok(())
+}
+
+proptest! {
+    #[test]
+    fn roundtrip(packet in packet()) {
+        for framing in [Framing::Standard, Framing::Extended] {
+            let encoded = packet.encode_with(framing).expect("encode failed");
+            prop_assert_eq!(declared_len(&encoded, framing), encoded.len() - header_len(framing));
+            prop_assert_eq!(Packet::decode_with(&encoded, framing), Ok((packet.clone(), encoded.len())));
+        }
+
+        let encoded = packet.encode().expect("encode failed");
+        let borrowed = PacketRef::decode(&encoded).map(|(packet, size)| (packet.into_owned(), size));
+        prop_assert_eq!(borrowed, Ok((packet, encoded.len())));
+    }
+
+    #[test]
+    fn truncated_packets_are_incomplete(packet in packet(), cut in any::<prop::sample::Index>()) {
+        let encoded = packet.encode().expect("encode failed");
+        let len = cut.index(encoded.len());
+        let result = Packet::decode(&encoded[..len]);
+        let incomplete =
+            matches!(result, Err(ProtocolError::PacketTooShort { .. } | ProtocolError::IncompletePayload { .. }));
+        prop_assert!(incomplete, "{} of {} bytes decoded to {:?}", len, encoded.len(), result);
+    }
+
+    #[test]
+    fn decode_never_panics(buf in vec(any::<u8>(), 0..512)) {
+        check_decode(&buf, Framing::Standard)?;
+        check_decode(&buf, Framing::Extended)?;
+    }
+
+    #[test]
+    fn decode_never_panics_on_arbitrary_payloads((buf, framing) in framed_payload()) {
+        check_decode(&buf, framing)?;
+    }
+
+    #[test]
+    fn decode_never_panics_on_corrupted_packets(
+        packet in packet(),
+        position in any::<prop::sample::Index>(),
+        byte in any::<u8>(),
+    ) {
+        let mut encoded = packet.encode().expect("encode failed");
+        let position = position.index(encoded.len());
+        encoded[position] = byte;
+        check_decode(&encoded, Framing::Standard)?;
+    }
+}
@@ -1,35 +1,50 @@
 use async_channel::{Receiver, Sender};
 use std::sync::{Arc, Mutex};
-use std::sync::atomic::{AtomicU64, Ordering};
+use tokio::task::JoinHandle;
 use tracing::info;
+use voiceapp_protocol::{Attachment, Capabilities, ChannelInfo, ChatHistoryMessage};
 
 use crate::error::SdkError;
-use crate::network::{TcpClient, UdpClient, EventHandler, ClientEvent, ApiClient};
+use crate::network::{TcpClient, UdpClient, EventHandler, ClientEvent, ApiClient, TlsConfig};
+use crate::reconnect::{Reconnector, Session};
 use crate::voice;
 use crate::voice::decoder::Decoder;
 
 /// Voice communication client
+///
+/// Once connected, a dropped management connection is re-established in the background,
+/// see `ClientEvent::Reconnecting` and `ClientEvent::Reconnected`.
 pub struct Client {
     tcp_client: TcpClient,
     udp_client: UdpClient,
-    api_client: ApiClient,
+    api_client: Arc<ApiClient>,
     event_handler: EventHandler,
-    voice_io_manager: Mutex<voice::io_manager::InputOutputManager>,
-    user_id: AtomicU64,
+    voice_io_manager: Arc<Mutex<voice::io_manager::InputOutputManager>>,
+    session: Arc<Mutex<Option<Session>>>, // Set once connected, used to reconnect
+    reconnect_task: Mutex<Option<JoinHandle<()>>>,
 }
 
 impl Client {
     pub fn new() -> Self {
-        let tcp_client = TcpClient::new();
+        Self::build(None)
+    }
+
+    /// Client whose management connection is wrapped in TLS
+    pub fn with_tls(tls: TlsConfig) -> Self {
+        Self::build(Some(tls))
+    }
+
+    fn build(tls: Option<TlsConfig>) -> Self {
+        let tcp_client = TcpClient::new(tls);
         let udp_client = UdpClient::new();
-        let api_client = ApiClient::new(tcp_client.clone(), udp_client.clone());
+        let api_client = Arc::new(ApiClient::new(tcp_client.clone(), udp_client.clone()));
         let event_handler = EventHandler::new();
-        let voice_io_manager = Mutex::new(
+        let voice_io_manager = Arc::new(Mutex::new(
             voice::io_manager::InputOutputManager::new(
                 udp_client.packet_sender(),
                 udp_client.packet_receiver()
             )
-        );
+        ));
 
         Client {
             tcp_client,
@@ -37,7 +52,8 @@ impl Client {
             api_client,
             event_handler,
             voice_io_manager,
-            user_id: AtomicU64::new(0),
+            session: Arc::new(Mutex::new(None)),
+            reconnect_task: Mutex::new(None),
         }
     }
 
@@ -78,12 +94,23 @@ impl Client {
     }
 
     /// Connects to the management and voice servers, returns user_id.
+    ///
+    /// `password` is required when the server has a password or credentials file configured,
+    /// a wrong or missing one fails with `SdkError::AuthenticationFailed`.
+    /// Other refusals (name taken, server full, ...) fail with `SdkError::LoginRejected`.
+    ///
+    /// If the connection drops later on, the client logs in again with exponential backoff,
+    /// rejoins the voice channel and restores the mute and deafen state.
     pub async fn connect(
         &self,
         management_server_addr: &str,
         voice_server_addr: &str,
         username: &str,
+        password: Option<&str>,
     ) -> Result<u64, SdkError> {
+        self.stop_reconnecting();
+        *self.session.lock().map_err(|_| SdkError::LockError)? = None;
+
         // Connect TCP socket
         self.tcp_client.connect(management_server_addr).await?;
         self.event_handler.listen_to_packets(self.tcp_client.packet_stream());
@@ -93,27 +120,190 @@ impl Client {
         self.udp_client.connect(voice_server_addr).await?;
         info!("[Voice server] Connected to {}", voice_server_addr);
 
-        let (user_id, voice_token) = self.api_client.authenticate_management(username).await?;
-        self.user_id.store(user_id, Ordering::Relaxed);
-        self.api_client.authenticate_voice(voice_token).await?;
+        let reconnector = self.reconnector();
+        let user_id = reconnector.login(username, password, None).await?;
+
+        *self.session.lock().map_err(|_| SdkError::LockError)? = Some(Session {
+            management_addr: management_server_addr.to_string(),
+            username: username.to_string(),
+            password: password.map(str::to_string),
+            channel_id: None,
+            is_muted: false,
+            is_deafened: false,
+        });
+        *self.reconnect_task.lock().map_err(|_| SdkError::LockError)? = Some(reconnector.spawn());
 
         Ok(user_id)
     }
 
-    pub async fn join_channel(&self) -> Result<(), SdkError> {
-        self.api_client.join_channel().await
+    /// Joins the given voice channel, moving out of the current one if needed.
+    pub async fn join_channel(&self, channel_id: u64) -> Result<(), SdkError> {
+        self.api_client.join_channel(channel_id).await?;
+        self.update_session(|session| session.channel_id = Some(channel_id))
     }
 
     pub async fn leave_channel(&self) -> Result<(), SdkError> {
+        // Not rejoined after a reconnect even if the request itself fails
+        self.update_session(|session| session.channel_id = None)?;
         self.api_client.leave_channel().await
     }
 
-    pub async fn send_message(&self, message: &str) -> Result<(), SdkError> {
-        self.api_client.send_message(message).await
+    /// Creates a new voice channel and returns its id.
+    pub async fn create_channel(&self, name: &str) -> Result<u64, SdkError> {
+        self.api_client.create_channel(name).await
+    }
+
+    /// Changes the username, other users receive `ClientEvent::UserRenamed`.
+    pub async fn change_nickname(&self, username: &str) -> Result<(), SdkError> {
+        self.api_client.change_nickname(username).await?;
+        // Reconnects log in under the new name, the server only resumes sessions of the same name
+        self.update_session(|session| session.username = username.to_string())
+    }
+
+    /// Fetches the current list of voice channels.
+    pub async fn list_channels(&self) -> Result<Vec<ChannelInfo>, SdkError> {
+        self.api_client.list_channels().await
+    }
+
+    /// Disconnects a user, requires the caller to be a moderator on the server.
+    /// Everyone receives `ClientEvent::UserModerated`.
+    pub async fn kick_user(&self, user_id: u64, reason: &str) -> Result<(), SdkError> {
+        self.api_client.kick_user(user_id, reason).await
+    }
+
+    /// Disconnects a user and refuses its username on later logins, and its IP address if `ban_address` is set.
+    /// Requires the caller to be a moderator on the server.
+    pub async fn ban_user(&self, user_id: u64, reason: &str, ban_address: bool) -> Result<(), SdkError> {
+        self.api_client.ban_user(user_id, reason, ban_address).await
+    }
+
+    /// Stops (or resumes) relaying a user's voice, requires the caller to be a moderator on the server.
+    /// A server-muted user cannot unmute itself.
+    pub async fn server_mute_user(&self, user_id: u64, muted: bool) -> Result<(), SdkError> {
+        self.api_client.server_mute_user(user_id, muted).await
     }
 
+    /// Lifts the ban of a username and of the IP addresses banned along with it, requires the caller
+    /// to be a moderator on the server who outranks the role of that username.
+    pub async fn unban_user(&self, username: &str) -> Result<(), SdkError> {
+        self.api_client.unban_user(username).await
+    }
+
+    /// Sends a chat message, as a reply if `reply_to` is set, and returns its id. `attachments` are ids of files
+    /// uploaded with [`upload_attachment`](Self::upload_attachment), the text may be empty when there are any.
+    /// Messages the server rejects, such as empty or too long ones or replies to messages it no longer keeps,
+    /// fail with `SdkError::RequestRejected` carrying the reason.
+    pub async fn send_message(
+        &self,
+        message: &str,
+        reply_to: Option<u64>,
+        attachments: &[u64],
+    ) -> Result<u64, SdkError> {
+        self.api_client.send_message(message, reply_to, attachments).await
+    }
+
+    /// Uploads a file to attach to a chat message and returns its reference, see `Attachment::attachment_id`.
+    /// Files the server refuses, such as too large ones, fail with `SdkError::RequestRejected` carrying the reason.
+    pub async fn upload_attachment(&self, name: &str, mime_type: &str, data: &[u8]) -> Result<Attachment, SdkError> {
+        self.api_client.upload_attachment(name, mime_type, data).await
+    }
+
+    /// Downloads the content of a file attached to a chat message.
+    /// Fails with `SdkError::RequestRejected` if the server no longer keeps it.
+    pub async fn download_attachment(&self, attachment: &Attachment) -> Result<Vec<u8>, SdkError> {
+        self.api_client.download_attachment(attachment).await
+    }
+
+    /// Replaces the text of one of our own messages, others see `ClientEvent::MessageEdited`.
+    pub async fn edit_message(&self, message_id: u64, message: &str) -> Result<(), SdkError> {
+        self.api_client.edit_message(message_id, message).await
+    }
+
+    /// Deletes one of our own messages, or as a moderator one of a user with a lower role,
+    /// others see `ClientEvent::MessageDeleted`.
+    pub async fn delete_message(&self, message_id: u64) -> Result<(), SdkError> {
+        self.api_client.delete_message(message_id).await
+    }
+
+    /// Sends a message to `user_id` only and returns the timestamp it was delivered at, the receiver sees
+    /// `ClientEvent::DirectMessageReceived`. Direct messages are not kept by the server, sending fails with
+    /// `SdkError::RequestRejected` if the user is gone or its client does not support them.
+    pub async fn send_direct_message(&self, user_id: u64, message: &str) -> Result<u64, SdkError> {
+        self.api_client.send_direct_message(user_id, message).await
+    }
+
+    /// Tells others whether we are typing a chat message, they see `ClientEvent::TypingStarted` and
+    /// `ClientEvent::TypingStopped`. Call it again every `TYPING_INTERVAL_MS` while typing, the server stops
+    /// the indicator a while after the last call and when our message is sent. Does nothing if the server
+    /// does not support typing indicators.
+    pub async fn set_typing(&self, is_typing: bool) -> Result<(), SdkError> {
+        self.api_client.set_typing(is_typing).await
+    }
+
+    /// Tells others we have read the chat up to `message_id`, they see `ClientEvent::MessageRead`.
+    /// The server ignores markers that do not move forward. Does nothing if the server does not support
+    /// read markers.
+    pub async fn mark_read(&self, message_id: u64) -> Result<(), SdkError> {
+        self.api_client.mark_read(message_id).await
+    }
+
+    /// Adds (or removes) our reaction to a chat message, everyone sees `ClientEvent::MessageReactionChanged`.
+    /// Fails with `SdkError::RequestRejected` if the message is no longer kept, the reaction is already there
+    /// (or not there) or the message has too many different reactions.
+    pub async fn set_reaction(&self, message_id: u64, emoji: &str, reacted: bool) -> Result<(), SdkError> {
+        self.api_client.set_reaction(message_id, emoji, reacted).await
+    }
+
+    /// Fetches up to `limit` chat messages sent after `after` and before `before`, oldest first,
+    /// and whether more messages match. Without bounds the newest messages are returned,
+    /// pass the oldest timestamp received as `before` to page further back.
+    pub async fn chat_history(
+        &self,
+        before: Option<u64>,
+        after: Option<u64>,
+        limit: u16,
+    ) -> Result<(Vec<ChatHistoryMessage>, bool), SdkError> {
+        self.api_client.chat_history(before, after, limit).await
+    }
+
+    /// Mutes or unmutes the microphone for other users. Users the server does not let speak stay muted,
+    /// unmuting them fails with `SdkError::RequestRejected`.
     pub async fn send_mute_state(&self, is_muted: bool) -> Result<(), SdkError> {
-        self.api_client.send_mute_state(self.user_id.load(Ordering::Relaxed), is_muted).await
+        let (_, is_deafened) = self.mute_state()?;
+        self.set_mute_state(is_muted, is_deafened).await
+    }
+
+    /// Stops (or resumes) hearing the voice channel: the relay no longer forwards voice to us
+    /// and other users see us deafened. Local playback is left to the caller.
+    pub async fn send_deafen_state(&self, is_deafened: bool) -> Result<(), SdkError> {
+        let (is_muted, _) = self.mute_state()?;
+        self.set_mute_state(is_muted, is_deafened).await
+    }
+
+    async fn set_mute_state(&self, is_muted: bool, is_deafened: bool) -> Result<(), SdkError> {
+        // Remembered first so a reconnect restores it even if sending fails
+        self.update_session(|session| {
+            session.is_muted = is_muted;
+            session.is_deafened = is_deafened;
+        })?;
+
+        let (applied_muted, applied_deafened) = self.api_client.set_mute_state(is_muted, is_deafened).await?;
+        self.update_session(|session| {
+            session.is_muted = applied_muted;
+            session.is_deafened = applied_deafened;
+        })?;
+
+        if applied_muted != is_muted {
+            return Err(SdkError::RequestRejected("not allowed to speak".to_string()));
+        }
+
+        Ok(())
+    }
+
+    /// Returns the last mute and deafen state sent, `(false, false)` before connecting
+    fn mute_state(&self) -> Result<(bool, bool), SdkError> {
+        let session = self.session.lock().map_err(|_| SdkError::LockError)?;
+        Ok(session.as_ref().map_or((false, false), |session| (session.is_muted, session.is_deafened)))
     }
 
     /// Ping the management server and return round-trip time in milliseconds
@@ -121,8 +311,44 @@ impl Client {
         self.api_client.ping().await
     }
 
+    /// Optional protocol features supported by both this client and the server.
+    /// Empty until `connect()` has completed the handshake.
+    pub fn capabilities(&self) -> Capabilities {
+        self.api_client.capabilities()
+    }
+
     /// Get voice stats (bytes_sent, bytes_received)
     pub fn get_voice_stats(&self) -> (u64, u64) {
         self.udp_client.get_stats()
     }
+
+    fn reconnector(&self) -> Reconnector {
+        Reconnector {
+            tcp_client: self.tcp_client.clone(),
+            api_client: self.api_client.clone(),
+            event_handler: self.event_handler.clone(),
+            voice_io_manager: self.voice_io_manager.clone(),
+            session: self.session.clone(),
+        }
+    }
+
+    /// Applies `update` to the session if connected
+    fn update_session(&self, update: impl FnOnce(&mut Session)) -> Result<(), SdkError> {
+        if let Some(session) = self.session.lock().map_err(|_| SdkError::LockError)?.as_mut() {
+            update(session);
+        }
+        Ok(())
+    }
+
+    fn stop_reconnecting(&self) {
+        if let Some(task) = self.reconnect_task.lock().ok().and_then(|mut task| task.take()) {
+            task.abort();
+        }
+    }
+}
+
+impl Drop for Client {
+    fn drop(&mut self) {
+        self.stop_reconnecting();
+    }
 }
@@ -1,10 +1,14 @@
 use thiserror::Error;
+use voiceapp_protocol::LoginRejectReason;
 
 /// SDK error type
 #[derive(Debug, Clone, Error)]
 pub enum SdkError {
     #[error("connection failed: {0}")]
     ConnectionFailed(String),
+    /// TLS setup or handshake with the management server failed
+    #[error("TLS error: {0}")]
+    TlsError(String),
     #[error("disconnected from server")]
     Disconnected,
     #[error("timeout: {0}")]
@@ -21,4 +25,14 @@ pub enum SdkError {
     ChannelClosed,
     #[error("invalid input: {0}")]
     InvalidInput(String),
+    #[error("authentication failed")]
+    AuthenticationFailed,
+    /// Server refused the login for a reason other than bad credentials
+    #[error("login rejected: {0}")]
+    LoginRejected(LoginRejectReason),
+    #[error("request rejected: {0}")]
+    RequestRejected(String),
+    /// Server protocol revision is incompatible, `server` is 0 if it predates version negotiation
+    #[error("protocol version mismatch: client {client}, server {server}")]
+    ProtocolVersionMismatch { client: u16, server: u16 },
 }
\ No newline at end of file
@@ -6,8 +6,9 @@
 //!
 //! async fn example() -> Result<(), SdkError> {
 //!     let client = Client::new();
-//!     client.connect("mgmt:8080", "voice:9090", "user").await?;
-//!     client.join_channel().await?;
+//!     client.connect("mgmt:8080", "voice:9090", "user", None).await?;
+//!     let channels = client.list_channels().await?;
+//!     client.join_channel(channels[0].channel_id).await?;
 //!     Ok(())
 //! }
 //! ```
@@ -15,10 +16,14 @@
 mod network;
 mod voice;
 mod client;
+mod reconnect;
 mod error;
 
 pub use client::Client;
 pub use error::SdkError;
-pub use network::ClientEvent;
+pub use network::{ClientEvent, TlsConfig};
 pub use voice::decoder::Decoder;
-pub use voiceapp_protocol::ParticipantInfo;
+pub use voiceapp_protocol::{
+    Attachment, Capabilities, ChannelInfo, ChatHistoryMessage, LoginRejectReason, ModerationAction, ParticipantInfo,
+    Permissions, Reaction, Role, PROTOCOL_VERSION, TYPING_INTERVAL_MS,
+};
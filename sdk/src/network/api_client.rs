@@ -1,7 +1,12 @@
-use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::atomic::{AtomicU32, AtomicU64, Ordering};
 use std::time::Instant;
 use tracing::info;
-use voiceapp_protocol::Packet;
+use voiceapp_protocol::auth::{compute_proof, derive_key, AUTH_CHALLENGE_LEN, AUTH_SALT_LEN};
+use voiceapp_protocol::crypto::VOICE_KEY_LEN;
+use voiceapp_protocol::{
+    negotiate_version, Attachment, Capabilities, ChannelInfo, ChatHistoryMessage, LoginRejectReason,
+    ModerationRejectReason, Packet, ATTACHMENT_CHUNK_LEN, PROTOCOL_VERSION,
+};
 
 use crate::error::SdkError;
 use super::tcp_client::TcpClient;
@@ -12,6 +17,8 @@ pub(crate) struct ApiClient {
     tcp_client: TcpClient,
     udp_client: UdpClient,
     request_id_counter: AtomicU64,
+    capabilities: AtomicU32, // Negotiated with the server during handshake
+    voice_token: AtomicU64, // Re-sent by heartbeats to keep the voice socket alive
 }
 
 impl ApiClient {
@@ -21,6 +28,22 @@ impl ApiClient {
             tcp_client,
             udp_client,
             request_id_counter: AtomicU64::new(1),
+            capabilities: AtomicU32::new(Capabilities::NONE.bits()),
+            voice_token: AtomicU64::new(0),
+        }
+    }
+
+    /// Capabilities supported by both the client and the server
+    pub fn capabilities(&self) -> Capabilities {
+        Capabilities::from_bits(self.capabilities.load(Ordering::Relaxed))
+    }
+
+    /// Fails with `RequestRejected` if the server did not announce the capability
+    fn require_capability(&self, capability: Capabilities, feature: &str) -> Result<(), SdkError> {
+        if self.capabilities().contains(capability) {
+            Ok(())
+        } else {
+            Err(SdkError::RequestRejected(format!("server does not support {}", feature)))
         }
     }
 
@@ -30,33 +53,137 @@ impl ApiClient {
     }
 
     /// Authenticate with management server via TCP
-    /// Returns the user_id and voice_token needed for UDP voice authentication
-    pub async fn authenticate_management(&self, username: &str) -> Result<(u64, u64), SdkError> {
-        let request_id = self.next_request_id();
-        let request = Packet::LoginRequest {
-            request_id,
-            username: username.to_string(),
+    /// The password, if any, is proven via challenge-response and never sent.
+    /// With a `resume_token` from a dropped connection the server may give back the previous user_id.
+    /// Returns the user_id, the voice_token needed for UDP voice authentication
+    /// and the voice_key for encrypting voice packets
+    pub async fn authenticate_management(
+        &self,
+        username: &str,
+        password: Option<&str>,
+        resume_token: Option<u64>,
+    ) -> Result<(u64, u64, [u8; VOICE_KEY_LEN]), SdkError> {
+        let (auth_challenge, auth_salt) = self.negotiate_protocol().await?;
+
+        if let Some(token) = resume_token.filter(|_| self.capabilities().contains(Capabilities::SESSION_RESUME)) {
+            self.resume_session(token).await?;
+        }
+
+        let auth_proof = match password {
+            Some(password) => {
+                // Deriving the key is slow on purpose, keep it off the runtime threads
+                let (name, password) = (username.to_string(), password.to_string());
+                let key = tokio::task::spawn_blocking(move || derive_key(&name, &password, &auth_salt))
+                    .await
+                    .map_err(|_| SdkError::AuthenticationFailed)?;
+                Some(compute_proof(&key, &auth_challenge))
+            }
+            None => None,
         };
 
+        let request_id = self.next_request_id();
+        let request = Packet::LoginRequest { request_id, username: username.to_string(), auth_proof };
+
         let response = self
             .tcp_client
             .send_request_with_response(
                 request,
                 |packet| {
-                    if let Packet::LoginResponse { request_id: _, id, voice_token, participants: _ } = packet {
-                        Ok((id, voice_token))
-                    } else {
-                        Err("Expected LoginResponse packet".to_string())
+                    match packet {
+                        Packet::LoginResponse { id, voice_token, voice_key, .. } => {
+                            Ok(Ok((id, voice_token, voice_key)))
+                        }
+                        Packet::LoginRejected { reason, .. } => Ok(Err(reason)),
+                        _ => Err("Expected LoginResponse packet".to_string()),
                     }
                 },
             )
             .await?;
 
+        let response = match response {
+            Ok(response) => response,
+            Err(LoginRejectReason::InvalidCredentials) => return Err(SdkError::AuthenticationFailed),
+            Err(reason) => return Err(SdkError::LoginRejected(reason)),
+        };
+
         info!("[Management server] Authenticated, user_id={}", response.0);
 
         Ok(response)
     }
 
+    /// Exchange Hello/ServerHello, fails if the server speaks an incompatible protocol revision
+    /// Returns the login challenge and the salt of password keys sent by the server
+    async fn negotiate_protocol(&self) -> Result<([u8; AUTH_CHALLENGE_LEN], [u8; AUTH_SALT_LEN]), SdkError> {
+        let request_id = self.next_request_id();
+        let request = Packet::Hello {
+            request_id,
+            protocol_version: PROTOCOL_VERSION,
+            capabilities: Capabilities::SUPPORTED,
+        };
+
+        let result = self
+            .tcp_client
+            .send_request_with_response(request, |packet| {
+                if let Packet::ServerHello {
+                    protocol_version, capabilities, accepted, auth_challenge, auth_salt, ..
+                } = packet
+                {
+                    Ok((protocol_version, capabilities, accepted, (auth_challenge, auth_salt)))
+                } else {
+                    Err("Expected ServerHello packet".to_string())
+                }
+            })
+            .await;
+
+        let (server_version, server_capabilities, accepted, auth) = match result {
+            Ok(hello) => hello,
+            // Servers without version negotiation drop the unknown Hello packet and never answer
+            Err(SdkError::Timeout(_)) => {
+                return Err(SdkError::ProtocolVersionMismatch { client: PROTOCOL_VERSION, server: 0 });
+            }
+            Err(e) => return Err(e),
+        };
+
+        let Some(version) = negotiate_version(server_version).filter(|_| accepted) else {
+            return Err(SdkError::ProtocolVersionMismatch {
+                client: PROTOCOL_VERSION,
+                server: server_version,
+            });
+        };
+
+        let capabilities = server_capabilities.intersection(Capabilities::SUPPORTED);
+        self.capabilities.store(capabilities.bits(), Ordering::Relaxed);
+
+        info!(
+            "[Management server] Protocol negotiated: version={}, capabilities={:#x}",
+            version,
+            capabilities.bits()
+        );
+
+        Ok(auth)
+    }
+
+    /// Ask the server to keep the user_id of a dropped connection for the following login
+    async fn resume_session(&self, token: u64) -> Result<(), SdkError> {
+        let request_id = self.next_request_id();
+        let request = Packet::ResumeSessionRequest { request_id, token };
+
+        let accepted = self
+            .tcp_client
+            .send_request_with_response(request, |packet| {
+                if let Packet::ResumeSessionResponse { accepted, .. } = packet {
+                    Ok(accepted)
+                } else {
+                    Err("Expected ResumeSessionResponse packet".to_string())
+                }
+            })
+            .await?;
+
+        info!("[Management server] Session resume {}", if accepted { "accepted" } else { "rejected" });
+
+        Ok(())
+    }
+
     /// Authenticate with voice server via UDP
     pub async fn authenticate_voice(&self, voice_token: u64) -> Result<(), SdkError> {
         let request_id = self.next_request_id();
@@ -82,17 +209,31 @@ impl ApiClient {
             ));
         }
 
+        self.voice_token.store(voice_token, Ordering::Relaxed);
         info!("[Voice server] Authenticated successfully");
 
         Ok(())
     }
 
-    /// Join voice channel
-    pub async fn join_channel(&self) -> Result<(), SdkError> {
+    /// Join voice channel (moves to it when already in another channel)
+    pub async fn join_channel(&self, channel_id: u64) -> Result<(), SdkError> {
         let request_id = self.next_request_id();
-        let request = Packet::JoinVoiceChannelRequest { request_id };
+        let request = Packet::JoinVoiceChannelRequest { request_id, channel_id };
 
-        self.tcp_client.send_request(request).await?;
+        let success = self
+            .tcp_client
+            .send_request_with_response(request, |packet| {
+                if let Packet::JoinVoiceChannelResponse { success, .. } = packet {
+                    Ok(success)
+                } else {
+                    Err("Expected JoinVoiceChannelResponse packet".to_string())
+                }
+            })
+            .await?;
+
+        if !success {
+            return Err(SdkError::RequestRejected(format!("unknown channel {}", channel_id)));
+        }
 
         Ok(())
     }
@@ -107,25 +248,476 @@ impl ApiClient {
         Ok(())
     }
 
-    /// Send chat message
-    pub async fn send_message(&self, message: &str) -> Result<(), SdkError> {
+    /// Create a new voice channel, returns its channel_id
+    pub async fn create_channel(&self, name: &str) -> Result<u64, SdkError> {
+        self.require_capability(Capabilities::CHANNELS, "channel management")?;
+
         let request_id = self.next_request_id();
-        let request = Packet::ChatMessageRequest {
+        let request = Packet::CreateChannelRequest {
             request_id,
+            name: name.to_string(),
+        };
+
+        let (success, channel_id) = self
+            .tcp_client
+            .send_request_with_response(request, |packet| {
+                if let Packet::CreateChannelResponse { success, channel_id, .. } = packet {
+                    Ok((success, channel_id))
+                } else {
+                    Err("Expected CreateChannelResponse packet".to_string())
+                }
+            })
+            .await?;
+
+        if !success {
+            return Err(SdkError::RequestRejected(format!("cannot create channel '{}'", name)));
+        }
+
+        Ok(channel_id)
+    }
+
+    /// Fetch the list of voice channels
+    pub async fn list_channels(&self) -> Result<Vec<ChannelInfo>, SdkError> {
+        self.require_capability(Capabilities::CHANNELS, "channel management")?;
+
+        let request_id = self.next_request_id();
+        let request = Packet::ListChannelsRequest { request_id };
+
+        self.tcp_client
+            .send_request_with_response(request, |packet| {
+                if let Packet::ListChannelsResponse { channels, .. } = packet {
+                    Ok(channels)
+                } else {
+                    Err("Expected ListChannelsResponse packet".to_string())
+                }
+            })
+            .await
+    }
+
+    /// Change the username of the logged in user
+    pub async fn change_nickname(&self, username: &str) -> Result<(), SdkError> {
+        self.require_capability(Capabilities::NICKNAMES, "nickname changes")?;
+
+        let request_id = self.next_request_id();
+        let request = Packet::ChangeNicknameRequest {
+            request_id,
+            username: username.to_string(),
+        };
+
+        let rejection = self
+            .tcp_client
+            .send_request_with_response(request, |packet| {
+                if let Packet::ChangeNicknameResponse { rejection, .. } = packet {
+                    Ok(rejection)
+                } else {
+                    Err("Expected ChangeNicknameResponse packet".to_string())
+                }
+            })
+            .await?;
+
+        if let Some(reason) = rejection {
+            return Err(SdkError::RequestRejected(reason.to_string()));
+        }
+
+        Ok(())
+    }
+
+    /// Disconnect a user, fails with `RequestRejected` unless the caller is a moderator
+    pub async fn kick_user(&self, user_id: u64, reason: &str) -> Result<(), SdkError> {
+        let request = Packet::KickUserRequest {
+            request_id: self.next_request_id(),
+            user_id,
+            reason: reason.to_string(),
+        };
+        self.moderate(request).await
+    }
+
+    /// Disconnect a user and refuse its username, and its IP address if `ban_address` is set, on later logins
+    pub async fn ban_user(&self, user_id: u64, reason: &str, ban_address: bool) -> Result<(), SdkError> {
+        let request = Packet::BanUserRequest {
+            request_id: self.next_request_id(),
+            user_id,
+            reason: reason.to_string(),
+            ban_address,
+        };
+        self.moderate(request).await
+    }
+
+    /// Stop or resume relaying a user's voice
+    pub async fn server_mute_user(&self, user_id: u64, muted: bool) -> Result<(), SdkError> {
+        let request = Packet::ServerMuteUserRequest {
+            request_id: self.next_request_id(),
+            user_id,
+            muted,
+        };
+        self.moderate(request).await
+    }
+
+    /// Lift the ban of a username and of the IP addresses banned along with it
+    pub async fn unban_user(&self, username: &str) -> Result<(), SdkError> {
+        let request = Packet::UnbanUserRequest {
+            request_id: self.next_request_id(),
+            username: username.to_string(),
+        };
+        self.moderate(request).await
+    }
+
+    /// Send a moderation request and turn a rejection into `RequestRejected`
+    async fn moderate(&self, request: Packet) -> Result<(), SdkError> {
+        self.require_capability(Capabilities::MODERATION, "moderation")?;
+
+        let rejection: Option<ModerationRejectReason> = self
+            .tcp_client
+            .send_request_with_response(request, |packet| {
+                if let Packet::ModerationResponse { rejection, .. } = packet {
+                    Ok(rejection)
+                } else {
+                    Err("Expected ModerationResponse packet".to_string())
+                }
+            })
+            .await?;
+
+        if let Some(reason) = rejection {
+            return Err(SdkError::RequestRejected(reason.to_string()));
+        }
+
+        Ok(())
+    }
+
+    /// Send a chat message, optionally as a reply to another message and with uploaded attachments
+    /// Returns the message id assigned by the server
+    pub async fn send_message(
+        &self,
+        message: &str,
+        reply_to: Option<u64>,
+        attachments: &[u64],
+    ) -> Result<u64, SdkError> {
+        if !attachments.is_empty() {
+            self.require_capability(Capabilities::ATTACHMENTS, "attachments")?;
+        }
+
+        let request = Packet::ChatMessageRequest {
+            request_id: self.next_request_id(),
             message: message.to_string(),
+            reply_to,
+            attachments: attachments.to_vec(),
         };
 
-        self.tcp_client.send_request(request).await?;
+        let (message_id, rejection) = self
+            .tcp_client
+            .send_request_with_response(request, |packet| {
+                if let Packet::ChatMessageResponse { message_id, rejection, .. } = packet {
+                    Ok((message_id, rejection))
+                } else {
+                    Err("Expected ChatMessageResponse packet".to_string())
+                }
+            })
+            .await?;
+
+        if let Some(reason) = rejection {
+            return Err(SdkError::RequestRejected(reason.to_string()));
+        }
+
+        Ok(message_id)
+    }
+
+    /// Replace the text of one of our own messages
+    pub async fn edit_message(&self, message_id: u64, message: &str) -> Result<(), SdkError> {
+        let request = Packet::EditMessageRequest {
+            request_id: self.next_request_id(),
+            message_id,
+            message: message.to_string(),
+        };
+
+        let success = self
+            .tcp_client
+            .send_request_with_response(request, |packet| {
+                if let Packet::EditMessageResponse { success, .. } = packet {
+                    Ok(success)
+                } else {
+                    Err("Expected EditMessageResponse packet".to_string())
+                }
+            })
+            .await?;
+
+        if !success {
+            return Err(SdkError::RequestRejected(format!("cannot edit message {}", message_id)));
+        }
+
+        Ok(())
+    }
+
+    /// Delete one of our own messages, or as a moderator one of a lower role's
+    pub async fn delete_message(&self, message_id: u64) -> Result<(), SdkError> {
+        let request = Packet::DeleteMessageRequest {
+            request_id: self.next_request_id(),
+            message_id,
+        };
+
+        let success = self
+            .tcp_client
+            .send_request_with_response(request, |packet| {
+                if let Packet::DeleteMessageResponse { success, .. } = packet {
+                    Ok(success)
+                } else {
+                    Err("Expected DeleteMessageResponse packet".to_string())
+                }
+            })
+            .await?;
+
+        if !success {
+            return Err(SdkError::RequestRejected(format!("cannot delete message {}", message_id)));
+        }
+
+        Ok(())
+    }
+
+    /// Send a message to one user only
+    /// Returns the timestamp the server delivered it at
+    pub async fn send_direct_message(&self, user_id: u64, message: &str) -> Result<u64, SdkError> {
+        self.require_capability(Capabilities::DIRECT_MESSAGES, "direct messages")?;
+
+        let request = Packet::DirectMessageRequest {
+            request_id: self.next_request_id(),
+            user_id,
+            message: message.to_string(),
+        };
+
+        let (success, timestamp) = self
+            .tcp_client
+            .send_request_with_response(request, |packet| {
+                if let Packet::DirectMessageResponse { success, timestamp, .. } = packet {
+                    Ok((success, timestamp))
+                } else {
+                    Err("Expected DirectMessageResponse packet".to_string())
+                }
+            })
+            .await?;
+
+        if !success {
+            return Err(SdkError::RequestRejected(format!("cannot send direct message to user {}", user_id)));
+        }
+
+        Ok(timestamp)
+    }
+
+    /// Add (or remove) our reaction to a chat message
+    pub async fn set_reaction(&self, message_id: u64, emoji: &str, reacted: bool) -> Result<(), SdkError> {
+        self.require_capability(Capabilities::REACTIONS, "reactions")?;
+
+        let request = Packet::SetReactionRequest {
+            request_id: self.next_request_id(),
+            message_id,
+            emoji: emoji.to_string(),
+            reacted,
+        };
+
+        let success = self
+            .tcp_client
+            .send_request_with_response(request, |packet| {
+                if let Packet::SetReactionResponse { success, .. } = packet {
+                    Ok(success)
+                } else {
+                    Err("Expected SetReactionResponse packet".to_string())
+                }
+            })
+            .await?;
+
+        if !success {
+            return Err(SdkError::RequestRejected(format!("cannot change reaction to message {}", message_id)));
+        }
 
         Ok(())
     }
 
-    /// Send mute state (event, no response expected)
-    pub async fn send_mute_state(&self, user_id: u64, is_muted: bool) -> Result<(), SdkError> {
-        // TODO: this is bad from security perspective and we need to get rid of event as request
-        let packet = Packet::UserMuteState { user_id, is_muted };
+    /// Upload a file in chunks, one request at a time
+    /// Returns the attachment to pass to `send_message`
+    pub async fn upload_attachment(&self, name: &str, mime_type: &str, data: &[u8]) -> Result<Attachment, SdkError> {
+        self.require_capability(Capabilities::ATTACHMENTS, "attachments")?;
+
+        let request = Packet::UploadAttachmentRequest {
+            request_id: self.next_request_id(),
+            name: name.to_string(),
+            mime_type: mime_type.to_string(),
+            size: data.len() as u64,
+        };
+
+        let (attachment_id, rejection) = self
+            .tcp_client
+            .send_request_with_response(request, |packet| {
+                if let Packet::UploadAttachmentResponse { attachment_id, rejection, .. } = packet {
+                    Ok((attachment_id, rejection))
+                } else {
+                    Err("Expected UploadAttachmentResponse packet".to_string())
+                }
+            })
+            .await?;
+
+        if let Some(reason) = rejection {
+            return Err(SdkError::RequestRejected(reason.to_string()));
+        }
+
+        let mut offset = 0;
+        for chunk in data.chunks(ATTACHMENT_CHUNK_LEN) {
+            let request = Packet::UploadChunkRequest {
+                request_id: self.next_request_id(),
+                attachment_id,
+                offset,
+                data: chunk.to_vec(),
+            };
+
+            let success = self
+                .tcp_client
+                .send_request_with_response(request, |packet| {
+                    if let Packet::UploadChunkResponse { success, .. } = packet {
+                        Ok(success)
+                    } else {
+                        Err("Expected UploadChunkResponse packet".to_string())
+                    }
+                })
+                .await?;
+
+            if !success {
+                return Err(SdkError::RequestRejected(format!("cannot upload attachment {}", attachment_id)));
+            }
+            offset += chunk.len() as u64;
+        }
+
+        Ok(Attachment::new(attachment_id, name.to_string(), mime_type.to_string(), offset))
+    }
+
+    /// Download a file in chunks, one request at a time
+    pub async fn download_attachment(&self, attachment: &Attachment) -> Result<Vec<u8>, SdkError> {
+        self.require_capability(Capabilities::ATTACHMENTS, "attachments")?;
+
+        let mut data = Vec::new();
+        while (data.len() as u64) < attachment.size {
+            let request = Packet::DownloadChunkRequest {
+                request_id: self.next_request_id(),
+                attachment_id: attachment.attachment_id,
+                offset: data.len() as u64,
+            };
+
+            let (success, chunk) = self
+                .tcp_client
+                .send_request_with_response(request, |packet| {
+                    if let Packet::DownloadChunkResponse { success, data, .. } = packet {
+                        Ok((success, data))
+                    } else {
+                        Err("Expected DownloadChunkResponse packet".to_string())
+                    }
+                })
+                .await?;
+
+            // An empty chunk before the end means the file changed size, which the server never does
+            if !success || chunk.is_empty() {
+                return Err(SdkError::RequestRejected(format!(
+                    "cannot download attachment {}",
+                    attachment.attachment_id
+                )));
+            }
+            data.extend_from_slice(&chunk);
+        }
+
+        Ok(data)
+    }
+
+    /// Tell others we are (or stopped) typing a chat message, without waiting for a response.
+    /// Does nothing on servers without typing indicators
+    pub async fn set_typing(&self, is_typing: bool) -> Result<(), SdkError> {
+        if !self.capabilities().contains(Capabilities::TYPING) {
+            return Ok(());
+        }
 
-        self.tcp_client.send_event(packet).await?;
+        let request = Packet::SetTypingRequest {
+            request_id: self.next_request_id(),
+            is_typing,
+        };
+        self.tcp_client.send_event(request).await
+    }
+
+    /// Tell others we have read the chat up to `message_id`, without waiting for a response.
+    /// Does nothing on servers without read markers
+    pub async fn mark_read(&self, message_id: u64) -> Result<(), SdkError> {
+        if !self.capabilities().contains(Capabilities::READ_MARKERS) {
+            return Ok(());
+        }
+
+        let request = Packet::MarkReadRequest {
+            request_id: self.next_request_id(),
+            message_id,
+        };
+        self.tcp_client.send_event(request).await
+    }
+
+    /// Fetch up to `limit` chat messages sent after `after` and before `before`, oldest first.
+    /// Returns the messages and whether more messages match
+    pub async fn chat_history(
+        &self,
+        before: Option<u64>,
+        after: Option<u64>,
+        limit: u16,
+    ) -> Result<(Vec<ChatHistoryMessage>, bool), SdkError> {
+        self.require_capability(Capabilities::CHAT_HISTORY, "chat history")?;
+
+        let request = Packet::ChatHistoryRequest {
+            request_id: self.next_request_id(),
+            before,
+            after,
+            limit,
+        };
+
+        let page = self
+            .tcp_client
+            .send_request_with_response(request, |packet| {
+                if let Packet::ChatHistoryResponse { messages, has_more, .. } = packet {
+                    Ok((messages, has_more))
+                } else {
+                    Err("Expected ChatHistoryResponse packet".to_string())
+                }
+            })
+            .await?;
+
+        Ok(page)
+    }
+
+    /// Set own mute and deafen state, returns the state the server applied
+    pub async fn set_mute_state(&self, is_muted: bool, is_deafened: bool) -> Result<(bool, bool), SdkError> {
+        let request = Packet::SetMuteStateRequest {
+            request_id: self.next_request_id(),
+            is_muted,
+            is_deafened,
+        };
+
+        let applied = self
+            .tcp_client
+            .send_request_with_response(request, |packet| {
+                if let Packet::SetMuteStateResponse { is_muted, is_deafened, .. } = packet {
+                    Ok((is_muted, is_deafened))
+                } else {
+                    Err("Expected SetMuteStateResponse packet".to_string())
+                }
+            })
+            .await?;
+
+        Ok(applied)
+    }
+
+    /// Send a heartbeat on both connections without waiting for the responses:
+    /// a ping to the management server and the voice authentication again to the voice server
+    pub async fn send_heartbeat(&self) -> Result<(), SdkError> {
+        let ping = Packet::PingRequest { request_id: self.next_request_id() };
+        self.tcp_client.send_event(ping).await?;
+
+        let voice_auth = Packet::VoiceAuthRequest {
+            request_id: self.next_request_id(),
+            voice_token: self.voice_token.load(Ordering::Relaxed),
+        };
+        self.udp_client
+            .packet_sender()
+            .send(voice_auth.encode().map_err(|e| SdkError::InvalidInput(e.to_string()))?)
+            .await
+            .map_err(|_| SdkError::Disconnected)?;
 
         Ok(())
     }
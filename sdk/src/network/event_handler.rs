@@ -1,6 +1,9 @@
 use async_channel::{unbounded, Receiver, Sender};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
 use tracing::{debug, error};
-use voiceapp_protocol::{Packet, ParticipantInfo};
+use voiceapp_protocol::{Attachment, ChannelInfo, ModerationAction, Packet, ParticipantInfo};
 
 /// Events from the voice server
 #[derive(Debug, Clone)]
@@ -10,31 +13,99 @@ pub enum ClientEvent {
         user_id: u64,
         participants: Vec<ParticipantInfo>,
     },
-    /// A user joined the server
-    UserJoinedServer { user_id: u64, username: String },
-    /// A user joined a voice channel
-    UserJoinedVoice { user_id: u64 },
+    /// Current voice channel list, sent after connection and on list refresh
+    ChannelsList { channels: Vec<ChannelInfo> },
+    /// A new voice channel was created
+    ChannelCreated { channel: ChannelInfo },
+    /// A user joined the server, with its role and permissions
+    UserJoinedServer { participant: ParticipantInfo },
+    /// A user changed their username
+    UserRenamed { user_id: u64, username: String },
+    /// Server message of the day, sent after login
+    Motd { message: String },
+    /// Server is about to close the connection, `reconnect_after_ms` is 0 if it is not expected back
+    ServerShuttingDown { reason: String, reconnect_after_ms: u32 },
+    /// Connection to the management server was lost, attempt number `attempt` starts in `delay_ms`
+    Reconnecting { attempt: u32, delay_ms: u64 },
+    /// Logged in again after a lost connection, `user_id` is unchanged if the server resumed the session.
+    /// `channel_id` is the voice channel that was rejoined, if any
+    Reconnected { user_id: u64, channel_id: Option<u64> },
+    /// Gave up reconnecting because the server refused the login
+    ReconnectFailed { reason: String },
+    /// A user joined (or moved to) a voice channel
+    UserJoinedVoice { user_id: u64, channel_id: u64 },
     /// A user left a voice channel
     UserLeftVoice { user_id: u64 },
     /// A user left the server
     UserLeftServer { user_id: u64 },
-    /// A user sent a chat message
+    /// A user sent a chat message, `reply_to` is the id of the message it replies to,
+    /// `mentions` the users it mentions with `@username` and `attachments` the files attached to it
     UserSentMessage {
+        message_id: u64,
         user_id: u64,
         timestamp: u64,
         message: String,
+        reply_to: Option<u64>,
+        mentions: Vec<u64>,
+        attachments: Vec<Attachment>,
     },
-    /// A user's mute state changed
+    /// The author replaced the text of a chat message
+    MessageEdited { message_id: u64, message: String },
+    /// A chat message was deleted by its author or a moderator
+    MessageDeleted { message_id: u64 },
+    /// Another user sent a message to us only
+    DirectMessageReceived {
+        from_user_id: u64,
+        timestamp: u64,
+        message: String,
+    },
+    /// A user started typing a chat message
+    TypingStarted { user_id: u64 },
+    /// A user stopped typing, sent its message or went quiet
+    TypingStopped { user_id: u64 },
+    /// A user has read the chat up to `message_id`
+    MessageRead { user_id: u64, message_id: u64 },
+    /// A user added (or removed) a reaction to a chat message
+    MessageReactionChanged {
+        message_id: u64,
+        user_id: u64,
+        emoji: String,
+        reacted: bool,
+    },
+    /// A user's mute or deafen state changed
     UserMuteState {
         user_id: u64,
         is_muted: bool,
+        is_deafened: bool,
     },
+    /// A moderator kicked, banned or server-muted a user. A kicked or banned client does not reconnect
+    UserModerated {
+        user_id: u64,
+        moderator_id: u64,
+        action: ModerationAction,
+        reason: String,
+    },
+}
+
+/// Hints from the server for reconnecting after the connection drops
+#[derive(Debug, Default)]
+pub(crate) struct ReconnectHints {
+    /// Token from the last login, lets the server give back the same user_id
+    pub resume_token: Option<u64>,
+    /// Delay the server asked for before it shut down
+    pub reconnect_after: Option<Duration>,
+    /// A moderator kicked or banned this user
+    pub removed: bool,
 }
 
 /// Handles TCP event processing and emits client events
+#[derive(Clone)]
 pub struct EventHandler {
     event_tx: Sender<ClientEvent>,
     event_rx: Receiver<ClientEvent>,
+    listening: Arc<AtomicBool>,
+    user_id: Arc<AtomicU64>, // From the last LoginResponse
+    reconnect_hints: Arc<Mutex<ReconnectHints>>,
 }
 
 impl EventHandler {
@@ -45,6 +116,9 @@ impl EventHandler {
         Self {
             event_tx,
             event_rx,
+            listening: Arc::new(AtomicBool::new(false)),
+            user_id: Arc::new(AtomicU64::new(0)),
+            reconnect_hints: Arc::new(Mutex::new(ReconnectHints::default())),
         }
     }
 
@@ -53,15 +127,34 @@ impl EventHandler {
         self.event_rx.clone()
     }
 
-    /// Listen to incoming packets and process events
+    /// Emit an event that does not come from a server packet
+    pub fn emit(&self, event: ClientEvent) {
+        if self.event_tx.try_send(event).is_err() {
+            tracing::warn!("channel closed");
+        }
+    }
+
+    /// Returns the hints received since the last call and clears them
+    pub(crate) fn take_reconnect_hints(&self) -> ReconnectHints {
+        self.reconnect_hints.lock().map(|mut hints| std::mem::take(&mut *hints)).unwrap_or_default()
+    }
+
+    /// Listen to incoming packets and process events.
+    /// The packet stream outlives reconnects, so only the first call starts a listener
     pub fn listen_to_packets(&self, packet_rx: Receiver<Packet>) {
+        if self.listening.swap(true, Ordering::Relaxed) {
+            return;
+        }
+
         let event_tx = self.event_tx.clone();
+        let user_id = self.user_id.clone();
+        let reconnect_hints = self.reconnect_hints.clone();
 
         tokio::spawn(async move {
             loop {
                 match packet_rx.recv().await {
                     Ok(packet) => {
-                        if let Err(e) = Self::handle_packet(packet, &event_tx).await {
+                        if let Err(e) = Self::handle_packet(packet, &event_tx, &user_id, &reconnect_hints).await {
                             error!("Event handling error: {}", e);
                         }
                     }
@@ -75,10 +168,22 @@ impl EventHandler {
     }
 
     /// Handle individual packet based on type
-    async fn handle_packet(packet: Packet, event_tx: &Sender<ClientEvent>) -> Result<(), String> {
+    async fn handle_packet(
+        packet: Packet,
+        event_tx: &Sender<ClientEvent>,
+        user_id: &AtomicU64,
+        reconnect_hints: &Mutex<ReconnectHints>,
+    ) -> Result<(), String> {
         match packet {
-            Packet::LoginResponse { request_id: _, id, voice_token: _, participants } => {
-                Self::handle_login_response(id, participants, event_tx).await
+            Packet::LoginResponse { id, participants, channels, .. } => {
+                user_id.store(id, Ordering::Relaxed);
+                Self::handle_login_response(id, participants, channels, event_tx).await
+            }
+            Packet::ListChannelsResponse { request_id: _, channels } => {
+                Self::handle_channels_list(channels, event_tx).await
+            }
+            Packet::ChannelCreated { channel } => {
+                Self::handle_channel_created(channel, event_tx).await
             }
             Packet::UserJoinedServer { participant } => {
                 Self::handle_user_joined_server(participant, event_tx).await
@@ -86,17 +191,69 @@ impl EventHandler {
             Packet::UserLeftServer { user_id } => {
                 Self::handle_user_left_server(user_id, event_tx).await
             }
-            Packet::UserJoinedVoice { user_id } => {
-                Self::handle_user_joined_voice(user_id, event_tx).await
+            Packet::UserRenamed { user_id, username } => {
+                Self::handle_user_renamed(user_id, username, event_tx).await
+            }
+            Packet::Motd { message } => {
+                Self::handle_motd(message, event_tx).await
+            }
+            Packet::ServerShuttingDown { reason, reconnect_after_ms } => {
+                Self::handle_server_shutting_down(reason, reconnect_after_ms, event_tx, reconnect_hints).await
+            }
+            Packet::ResumeToken { token } => {
+                Self::handle_resume_token(token, reconnect_hints)
+            }
+            Packet::UserJoinedVoice { user_id, channel_id } => {
+                Self::handle_user_joined_voice(user_id, channel_id, event_tx).await
             }
             Packet::UserLeftVoice { user_id } => {
                 Self::handle_user_left_voice(user_id, event_tx).await
             }
-            Packet::UserSentMessage { user_id, timestamp, message } => {
-                Self::handle_user_sent_message(user_id, timestamp, message, event_tx).await
+            Packet::UserSentMessage { message_id, user_id, timestamp, message, reply_to, mentions, attachments } => {
+                let event = ClientEvent::UserSentMessage {
+                    message_id,
+                    user_id,
+                    timestamp,
+                    message,
+                    reply_to,
+                    mentions,
+                    attachments,
+                };
+                Self::handle_user_sent_message(event, event_tx).await
+            }
+            Packet::MessageEdited { message_id, message } => {
+                Self::handle_message_edited(message_id, message, event_tx).await
+            }
+            Packet::MessageDeleted { message_id } => {
+                Self::handle_message_deleted(message_id, event_tx).await
             }
-            Packet::UserMuteState { user_id, is_muted } => {
-                Self::handle_user_mute_state(user_id, is_muted, event_tx).await
+            Packet::DirectMessageReceived { from_user_id, timestamp, message } => {
+                Self::handle_direct_message_received(from_user_id, timestamp, message, event_tx).await
+            }
+            Packet::TypingStarted { user_id } => {
+                Self::handle_typing(ClientEvent::TypingStarted { user_id }, event_tx).await
+            }
+            Packet::TypingStopped { user_id } => {
+                Self::handle_typing(ClientEvent::TypingStopped { user_id }, event_tx).await
+            }
+            Packet::MessageRead { user_id, message_id } => {
+                Self::handle_message_read(user_id, message_id, event_tx).await
+            }
+            Packet::MessageReactionChanged { message_id, user_id, emoji, reacted } => {
+                Self::handle_message_reaction_changed(message_id, user_id, emoji, reacted, event_tx).await
+            }
+            Packet::UserMuteState { user_id, is_muted, is_deafened } => {
+                Self::handle_user_mute_state(user_id, is_muted, is_deafened, event_tx).await
+            }
+            Packet::UserModerated { user_id: target_id, moderator_id, action, reason } => {
+                let removed = matches!(action, ModerationAction::Kicked | ModerationAction::Banned);
+                if removed && target_id == user_id.load(Ordering::Relaxed) {
+                    reconnect_hints
+                        .lock()
+                        .map_err(|_| "reconnect hints lock poisoned".to_string())?
+                        .removed = true;
+                }
+                Self::handle_user_moderated(target_id, moderator_id, action, reason, event_tx).await
             }
             _ => { Ok(()) }
         }
@@ -105,24 +262,53 @@ impl EventHandler {
     async fn handle_login_response(
         id: u64,
         participants: Vec<ParticipantInfo>,
+        channels: Vec<ChannelInfo>,
         event_tx: &Sender<ClientEvent>,
     ) -> Result<(), String> {
         if event_tx.send(ClientEvent::ParticipantsList { user_id: id, participants }).await.is_err() {
             tracing::warn!("channel closed");
         }
 
+        if event_tx.send(ClientEvent::ChannelsList { channels }).await.is_err() {
+            tracing::warn!("channel closed");
+        }
+
         debug!("Login successful: user_id={}", id);
         Ok(())
     }
 
+    async fn handle_channels_list(
+        channels: Vec<ChannelInfo>,
+        event_tx: &Sender<ClientEvent>,
+    ) -> Result<(), String> {
+        if event_tx.send(ClientEvent::ChannelsList { channels }).await.is_err() {
+            tracing::warn!("channel closed");
+        }
+
+        Ok(())
+    }
+
+    async fn handle_channel_created(
+        channel: ChannelInfo,
+        event_tx: &Sender<ClientEvent>,
+    ) -> Result<(), String> {
+        let channel_id = channel.channel_id;
+
+        if event_tx.send(ClientEvent::ChannelCreated { channel }).await.is_err() {
+            tracing::warn!("channel closed");
+        }
+
+        debug!("Channel created: id={}", channel_id);
+        Ok(())
+    }
+
     async fn handle_user_joined_server(
         participant: ParticipantInfo,
         event_tx: &Sender<ClientEvent>,
     ) -> Result<(), String> {
         let user_id = participant.user_id;
-        let username = participant.username.clone();
 
-        if event_tx.send(ClientEvent::UserJoinedServer { user_id, username }).await.is_err() {
+        if event_tx.send(ClientEvent::UserJoinedServer { participant }).await.is_err() {
             tracing::warn!("channel closed");
         }
 
@@ -142,15 +328,75 @@ impl EventHandler {
         Ok(())
     }
 
+    async fn handle_user_renamed(
+        user_id: u64,
+        username: String,
+        event_tx: &Sender<ClientEvent>,
+    ) -> Result<(), String> {
+        debug!("User renamed: id={}, username={}", user_id, username);
+
+        if event_tx.send(ClientEvent::UserRenamed { user_id, username }).await.is_err() {
+            tracing::warn!("channel closed");
+        }
+
+        Ok(())
+    }
+
+    async fn handle_motd(
+        message: String,
+        event_tx: &Sender<ClientEvent>,
+    ) -> Result<(), String> {
+        debug!("Message of the day: {}", message);
+
+        if event_tx.send(ClientEvent::Motd { message }).await.is_err() {
+            tracing::warn!("channel closed");
+        }
+
+        Ok(())
+    }
+
+    async fn handle_server_shutting_down(
+        reason: String,
+        reconnect_after_ms: u32,
+        event_tx: &Sender<ClientEvent>,
+        reconnect_hints: &Mutex<ReconnectHints>,
+    ) -> Result<(), String> {
+        debug!("Server shutting down: reason={}, reconnect_after_ms={}", reason, reconnect_after_ms);
+
+        if reconnect_after_ms > 0 {
+            reconnect_hints
+                .lock()
+                .map_err(|_| "reconnect hints lock poisoned".to_string())?
+                .reconnect_after = Some(Duration::from_millis(u64::from(reconnect_after_ms)));
+        }
+
+        if event_tx.send(ClientEvent::ServerShuttingDown { reason, reconnect_after_ms }).await.is_err() {
+            tracing::warn!("channel closed");
+        }
+
+        Ok(())
+    }
+
+    fn handle_resume_token(token: u64, reconnect_hints: &Mutex<ReconnectHints>) -> Result<(), String> {
+        reconnect_hints
+            .lock()
+            .map_err(|_| "reconnect hints lock poisoned".to_string())?
+            .resume_token = Some(token);
+
+        debug!("Resume token received");
+        Ok(())
+    }
+
     async fn handle_user_joined_voice(
         user_id: u64,
+        channel_id: u64,
         event_tx: &Sender<ClientEvent>,
     ) -> Result<(), String> {
-        if event_tx.send(ClientEvent::UserJoinedVoice { user_id }).await.is_err() {
+        if event_tx.send(ClientEvent::UserJoinedVoice { user_id, channel_id }).await.is_err() {
             tracing::warn!("channel closed");
         }
 
-        debug!("User joined voice: id={}", user_id);
+        debug!("User joined voice: id={}, channel_id={}", user_id, channel_id);
         Ok(())
     }
 
@@ -166,29 +412,111 @@ impl EventHandler {
         Ok(())
     }
 
-    async fn handle_user_sent_message(
-        user_id: u64,
+    async fn handle_user_sent_message(event: ClientEvent, event_tx: &Sender<ClientEvent>) -> Result<(), String> {
+        if event_tx.send(event).await.is_err() {
+            tracing::warn!("channel closed");
+        }
+
+        Ok(())
+    }
+
+    async fn handle_message_edited(
+        message_id: u64,
+        message: String,
+        event_tx: &Sender<ClientEvent>,
+    ) -> Result<(), String> {
+        if event_tx.send(ClientEvent::MessageEdited { message_id, message }).await.is_err() {
+            tracing::warn!("channel closed");
+        }
+
+        debug!("Message edited: id={}", message_id);
+        Ok(())
+    }
+
+    async fn handle_message_deleted(message_id: u64, event_tx: &Sender<ClientEvent>) -> Result<(), String> {
+        if event_tx.send(ClientEvent::MessageDeleted { message_id }).await.is_err() {
+            tracing::warn!("channel closed");
+        }
+
+        debug!("Message deleted: id={}", message_id);
+        Ok(())
+    }
+
+    async fn handle_direct_message_received(
+        from_user_id: u64,
         timestamp: u64,
         message: String,
         event_tx: &Sender<ClientEvent>,
     ) -> Result<(), String> {
-        if event_tx.send(ClientEvent::UserSentMessage { user_id, timestamp, message }).await.is_err() {
+        let event = ClientEvent::DirectMessageReceived { from_user_id, timestamp, message };
+        if event_tx.send(event).await.is_err() {
+            tracing::warn!("channel closed");
+        }
+
+        debug!("Direct message received: from={}", from_user_id);
+        Ok(())
+    }
+
+    async fn handle_typing(event: ClientEvent, event_tx: &Sender<ClientEvent>) -> Result<(), String> {
+        if event_tx.send(event).await.is_err() {
             tracing::warn!("channel closed");
         }
 
         Ok(())
     }
 
+    async fn handle_message_read(user_id: u64, message_id: u64, event_tx: &Sender<ClientEvent>) -> Result<(), String> {
+        if event_tx.send(ClientEvent::MessageRead { user_id, message_id }).await.is_err() {
+            tracing::warn!("channel closed");
+        }
+
+        debug!("Message read: user={}, id={}", user_id, message_id);
+        Ok(())
+    }
+
+    async fn handle_message_reaction_changed(
+        message_id: u64,
+        user_id: u64,
+        emoji: String,
+        reacted: bool,
+        event_tx: &Sender<ClientEvent>,
+    ) -> Result<(), String> {
+        let event = ClientEvent::MessageReactionChanged { message_id, user_id, emoji, reacted };
+        if event_tx.send(event).await.is_err() {
+            tracing::warn!("channel closed");
+        }
+
+        debug!("Message reaction changed: user={}, id={}, reacted={}", user_id, message_id, reacted);
+        Ok(())
+    }
+
     async fn handle_user_mute_state(
         user_id: u64,
         is_muted: bool,
+        is_deafened: bool,
         event_tx: &Sender<ClientEvent>,
     ) -> Result<(), String> {
-        if event_tx.send(ClientEvent::UserMuteState { user_id, is_muted }).await.is_err() {
+        if event_tx.send(ClientEvent::UserMuteState { user_id, is_muted, is_deafened }).await.is_err() {
+            tracing::warn!("channel closed");
+        }
+
+        debug!("User mute state changed: id={}, is_muted={}, is_deafened={}", user_id, is_muted, is_deafened);
+        Ok(())
+    }
+
+    async fn handle_user_moderated(
+        user_id: u64,
+        moderator_id: u64,
+        action: ModerationAction,
+        reason: String,
+        event_tx: &Sender<ClientEvent>,
+    ) -> Result<(), String> {
+        debug!("User moderated: id={}, moderator_id={}, action={}", user_id, moderator_id, action);
+
+        if event_tx.send(ClientEvent::UserModerated { user_id, moderator_id, action, reason }).await.is_err() {
             tracing::warn!("channel closed");
         }
 
-        debug!("User mute state changed: id={}, is_muted={}", user_id, is_muted);
         Ok(())
     }
 }
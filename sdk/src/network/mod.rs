@@ -2,8 +2,10 @@ pub(crate) mod tcp_client;
 pub(crate) mod udp_client;
 pub(crate) mod event_handler;
 pub(crate) mod api_client;
+pub(crate) mod tls;
 
 pub use event_handler::ClientEvent;
+pub use tls::TlsConfig;
 pub(crate) use tcp_client::TcpClient;
 pub(crate) use udp_client::UdpClient;
 pub(crate) use event_handler::EventHandler;
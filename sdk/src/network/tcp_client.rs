@@ -1,13 +1,15 @@
 use async_channel::{unbounded, Receiver, Sender};
 use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
 use std::time::Duration;
-use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
 use tokio::net::TcpStream;
 use tokio::sync::oneshot;
 use tracing::{debug, error};
-use voiceapp_protocol::Packet;
+use voiceapp_protocol::{negotiate_version, Framing, Packet};
 
 use crate::error::SdkError;
+use crate::network::tls::TlsConfig;
 
 /// Default timeout for request/response operations
 const REQUEST_TIMEOUT_SECS: u64 = 5;
@@ -21,19 +23,30 @@ pub struct TcpClient {
     send_rx: Receiver<(Vec<u8>, Option<RequestCallback>)>,
     packet_tx: Sender<Packet>,
     packet_rx: Receiver<Packet>,
+    disconnected_tx: Sender<()>,
+    disconnected_rx: Receiver<()>,
+    close_tx: Arc<Mutex<Option<oneshot::Sender<()>>>>, // Dropping it closes the current connection
+    framing: Arc<Mutex<Framing>>, // Packet headers of the current connection, switched right after ServerHello
+    tls: Option<TlsConfig>,
 }
 
 impl TcpClient {
-    /// Create a new TcpClient
-    pub fn new() -> Self {
+    /// Create a new TcpClient, wrapping the connection in TLS if `tls` is set
+    pub fn new(tls: Option<TlsConfig>) -> Self {
         let (send_tx, send_rx) = unbounded();
         let (packet_tx, packet_rx) = unbounded();
+        let (disconnected_tx, disconnected_rx) = unbounded();
 
         Self {
             send_tx,
             send_rx,
             packet_tx,
             packet_rx,
+            disconnected_tx,
+            disconnected_rx,
+            close_tx: Arc::new(Mutex::new(None)),
+            framing: Arc::new(Mutex::new(Framing::Standard)),
+            tls,
         }
     }
 
@@ -42,6 +55,12 @@ impl TcpClient {
         self.packet_rx.clone()
     }
 
+    /// Get a receiver that is notified when the connection drops.
+    /// Connections replaced by a later `connect()` close without a notification
+    pub fn disconnected(&self) -> Receiver<()> {
+        self.disconnected_rx.clone()
+    }
+
     /// Connect to TCP server and spawn handler
     pub async fn connect(&self, addr: &str) -> Result<(), SdkError> {
         debug!("TCP connect to {}", addr);
@@ -52,7 +71,21 @@ impl TcpClient {
 
         debug!("TCP connected to {}", addr);
 
-        self.spawn_handler(socket);
+        let Some(tls) = &self.tls else {
+            self.spawn_handler(socket);
+            return Ok(());
+        };
+
+        let (connector, server_name) = tls.connector(addr)?;
+        let handshake = connector.connect(server_name, socket);
+        let stream = tokio::time::timeout(Duration::from_secs(REQUEST_TIMEOUT_SECS), handshake)
+            .await
+            .map_err(|_| SdkError::TlsError("handshake timed out".to_string()))?
+            .map_err(|e| SdkError::TlsError(e.to_string()))?;
+
+        debug!("TLS established with {}", addr);
+
+        self.spawn_handler(stream);
         Ok(())
     }
 
@@ -62,7 +95,7 @@ impl TcpClient {
         packet: Packet,
     ) -> Result<(), SdkError> {
         // Encode packet
-        let encoded = packet.encode();
+        let encoded = packet.encode_with(self.framing()).map_err(|e| SdkError::InvalidInput(e.to_string()))?;
 
         // Send packet without callback (no response expected)
         self.send_tx
@@ -85,7 +118,7 @@ impl TcpClient {
         let (response_tx, response_rx) = oneshot::channel();
 
         // Encode packet
-        let encoded = request.encode();
+        let encoded = request.encode_with(self.framing()).map_err(|e| SdkError::InvalidInput(e.to_string()))?;
 
         // Send request with callback registered for expected response
         self.send_tx
@@ -111,10 +144,34 @@ impl TcpClient {
         decoder(packet).map_err(SdkError::ConnectionFailed)
     }
 
-    /// Spawn TCP handler task
-    fn spawn_handler(&self, mut socket: TcpStream) {
+    /// Framing negotiated on the current connection
+    fn framing(&self) -> Framing {
+        self.framing.lock().map(|framing| *framing).unwrap_or_default()
+    }
+
+    /// Spawn TCP handler task, closing the previous connection if any
+    fn spawn_handler<S>(&self, mut socket: S)
+    where
+        S: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+    {
         let send_rx = self.send_rx.clone();
         let packet_tx = self.packet_tx.clone();
+        let disconnected_tx = self.disconnected_tx.clone();
+        let framing = self.framing.clone();
+        let (close_tx, mut close_rx) = oneshot::channel();
+
+        if let Ok(mut current) = self.close_tx.lock() {
+            *current = Some(close_tx);
+        }
+
+        // Every connection starts with the Hello/ServerHello exchange
+        if let Ok(mut framing) = self.framing.lock() {
+            *framing = Framing::Standard;
+        }
+
+        // Requests queued while disconnected belong to the previous session, their callers get Disconnected
+        while self.send_rx.try_recv().is_ok() {}
+        while self.disconnected_rx.try_recv().is_ok() {}
 
         tokio::spawn(async move {
             let mut read_buf = [0u8; 4096];
@@ -125,6 +182,12 @@ impl TcpClient {
 
             loop {
                 tokio::select! {
+                    // Connection was replaced by a new one
+                    _ = &mut close_rx => {
+                        debug!("TCP connection closed");
+                        return;
+                    }
+
                     // Handle outgoing packets
                     result = send_rx.recv() => {
                         if let Err(e) = Self::handle_outgoing(
@@ -144,7 +207,8 @@ impl TcpClient {
                             &read_buf,
                             &mut pending_responses,
                             &packet_tx,
-                            &mut accumulator
+                            &mut accumulator,
+                            &framing
                         ).await {
                             Ok(should_continue) => {
                                 if !should_continue {
@@ -161,12 +225,13 @@ impl TcpClient {
             }
 
             debug!("TCP handler stopped");
+            let _ = disconnected_tx.try_send(());
         });
     }
 
     /// Handle outgoing packet
-    async fn handle_outgoing(
-        socket: &mut TcpStream,
+    async fn handle_outgoing<S: AsyncWrite + Unpin>(
+        socket: &mut S,
         recv_result: Result<(Vec<u8>, Option<RequestCallback>), async_channel::RecvError>,
         pending_responses: &mut HashMap<u64, oneshot::Sender<Packet>>,
     ) -> Result<(), String> {
@@ -196,6 +261,7 @@ impl TcpClient {
         pending_responses: &mut HashMap<u64, oneshot::Sender<Packet>>,
         packet_tx: &Sender<Packet>,
         accumulator: &mut Vec<u8>,
+        framing: &Mutex<Framing>,
     ) -> Result<bool, String> {
         match read_result {
             Ok(0) => { Ok(false) }
@@ -205,12 +271,23 @@ impl TcpClient {
 
                 // Parse all complete packets from the accumulator
                 loop {
-                    match Packet::decode(accumulator) {
+                    let current_framing = framing.lock().map(|framing| *framing).unwrap_or_default();
+                    match Packet::decode_with(accumulator, current_framing) {
                         Ok((packet, size)) => {
                             let packet_id = packet.id();
 
                             debug!("Received TCP packet: ID 0x{:02x}", packet_id);
 
+                            // Packets after ServerHello use the framing of the negotiated revision, switch before
+                            // the caller waiting for it sends anything else
+                            if let Packet::ServerHello { protocol_version, accepted: true, .. } = &packet {
+                                if let (Some(version), Ok(mut framing)) =
+                                    (negotiate_version(*protocol_version), framing.lock())
+                                {
+                                    *framing = Framing::for_version(version);
+                                }
+                            }
+
                             if let Some(req_id) = packet.request_id() {
                                 if let Some(tx) = pending_responses.remove(&req_id) {
                                     let _ = tx.send(packet.clone());
@@ -222,16 +299,19 @@ impl TcpClient {
                             // Remove the processed packet from the accumulator
                             accumulator.drain(..size);
                         }
-                        Err(voiceapp_protocol::ProtocolError::IncompletePayload { .. }) |
-                        Err(voiceapp_protocol::ProtocolError::PacketTooShort { .. }) => {
+                        Err(voiceapp_protocol::ProtocolError::IncompletePayload { .. }) => {
                             // Wait for more data
                             break;
                         }
-                        Err(e) => {
-                            error!("Parse error: {}, clearing buffer", e);
-                            accumulator.clear();
+                        Err(voiceapp_protocol::ProtocolError::PacketTooShort { .. })
+                            if accumulator.len() < current_framing.header_len() =>
+                        {
                             break;
                         }
+                        Err(e) => {
+                            // Skipping the packet would read the rest of the stream out of step
+                            return Err(format!("Parse error: {e}"));
+                        }
                     }
                 }
 
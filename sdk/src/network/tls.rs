@@ -0,0 +1,142 @@
+use std::sync::Arc;
+use tokio_rustls::rustls::client::danger::{HandshakeSignatureValid, ServerCertVerified, ServerCertVerifier};
+use tokio_rustls::rustls::crypto::{ring, verify_tls12_signature, verify_tls13_signature, CryptoProvider};
+use tokio_rustls::rustls::pki_types::pem::PemObject;
+use tokio_rustls::rustls::pki_types::{CertificateDer, ServerName, UnixTime};
+use tokio_rustls::rustls::{
+    CertificateError, ClientConfig, DigitallySignedStruct, Error, RootCertStore, SignatureScheme,
+};
+use tokio_rustls::TlsConnector;
+
+use crate::error::SdkError;
+
+/// TLS settings for the management connection
+#[derive(Clone, Debug)]
+pub struct TlsConfig {
+    trust: Trust,
+    server_name: Option<String>,
+}
+
+#[derive(Clone, Debug)]
+enum Trust {
+    /// Certificates chaining up to one of these roots, with hostname verification
+    Roots(Arc<RootCertStore>),
+    /// Exactly this end-entity certificate
+    Pinned(CertificateDer<'static>),
+}
+
+impl TlsConfig {
+    /// Trust server certificates issued by the given PEM encoded CA certificates
+    pub fn with_ca_pem(pem: &[u8]) -> Result<Self, SdkError> {
+        let mut roots = RootCertStore::empty();
+        for cert in parse_certificates(pem)? {
+            roots.add(cert).map_err(|e| SdkError::TlsError(e.to_string()))?;
+        }
+
+        Ok(Self { trust: Trust::Roots(Arc::new(roots)), server_name: None })
+    }
+
+    /// Trust only this exact PEM encoded server certificate (self-signed setups).
+    /// Hostname and expiry are not checked.
+    pub fn with_pinned_cert_pem(pem: &[u8]) -> Result<Self, SdkError> {
+        let cert = parse_certificates(pem)?.into_iter().next().expect("parse_certificates never returns empty");
+        Ok(Self { trust: Trust::Pinned(cert), server_name: None })
+    }
+
+    /// Name the certificate is verified against, defaults to the host of the management address
+    #[must_use]
+    pub fn server_name(mut self, name: &str) -> Self {
+        self.server_name = Some(name.to_string());
+        self
+    }
+
+    /// Build a connector and the server name to present for `addr` (`host:port`)
+    pub(crate) fn connector(&self, addr: &str) -> Result<(TlsConnector, ServerName<'static>), SdkError> {
+        let provider = Arc::new(ring::default_provider());
+        let builder = ClientConfig::builder_with_provider(provider.clone())
+            .with_safe_default_protocol_versions()
+            .map_err(|e| SdkError::TlsError(e.to_string()))?;
+
+        let config = match &self.trust {
+            Trust::Roots(roots) => builder.with_root_certificates(roots.clone()).with_no_client_auth(),
+            Trust::Pinned(cert) => builder
+                .dangerous()
+                .with_custom_certificate_verifier(Arc::new(PinnedCertVerifier { cert: cert.clone(), provider }))
+                .with_no_client_auth(),
+        };
+
+        let host = self.server_name.as_deref().unwrap_or_else(|| host_of(addr));
+        let server_name = ServerName::try_from(host.to_string())
+            .map_err(|_| SdkError::TlsError(format!("invalid server name '{}'", host)))?;
+
+        Ok((TlsConnector::from(Arc::new(config)), server_name))
+    }
+}
+
+/// Parse PEM certificates, fails if there are none
+fn parse_certificates(pem: &[u8]) -> Result<Vec<CertificateDer<'static>>, SdkError> {
+    let certs = CertificateDer::pem_slice_iter(pem)
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|e| SdkError::TlsError(format!("invalid certificate: {}", e)))?;
+
+    if certs.is_empty() {
+        return Err(SdkError::TlsError("no certificate found in PEM data".to_string()));
+    }
+
+    Ok(certs)
+}
+
+/// Host part of `host:port` or `[ipv6]:port`
+fn host_of(addr: &str) -> &str {
+    if let Some(rest) = addr.strip_prefix('[') {
+        return rest.split(']').next().unwrap_or(rest);
+    }
+
+    addr.rsplit_once(':').map_or(addr, |(host, _)| host)
+}
+
+/// Accepts the pinned certificate only, handshake signatures are still verified against it
+#[derive(Debug)]
+struct PinnedCertVerifier {
+    cert: CertificateDer<'static>,
+    provider: Arc<CryptoProvider>,
+}
+
+impl ServerCertVerifier for PinnedCertVerifier {
+    fn verify_server_cert(
+        &self,
+        end_entity: &CertificateDer<'_>,
+        _intermediates: &[CertificateDer<'_>],
+        _server_name: &ServerName<'_>,
+        _ocsp_response: &[u8],
+        _now: UnixTime,
+    ) -> Result<ServerCertVerified, Error> {
+        if end_entity.as_ref() == self.cert.as_ref() {
+            Ok(ServerCertVerified::assertion())
+        } else {
+            Err(Error::InvalidCertificate(CertificateError::ApplicationVerificationFailure))
+        }
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, Error> {
+        verify_tls12_signature(message, cert, dss, &self.provider.signature_verification_algorithms)
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, Error> {
+        verify_tls13_signature(message, cert, dss, &self.provider.signature_verification_algorithms)
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        self.provider.signature_verification_algorithms.supported_schemes()
+    }
+}
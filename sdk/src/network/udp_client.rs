@@ -3,7 +3,7 @@ use dashmap::DashMap;
 use std::sync::Arc;
 use std::sync::atomic::{AtomicU64, Ordering};
 use std::time::Duration;
-use tokio::net::UdpSocket;
+use tokio::net::{lookup_host, UdpSocket};
 use tokio::sync::oneshot;
 use tracing::{debug, error};
 use voiceapp_protocol::Packet;
@@ -65,14 +65,21 @@ impl UdpClient {
 
     /// Connect UDP socket to server and spawn handler
     pub async fn connect(&self, addr: &str) -> Result<(), SdkError> {
-        // Create UDP socket (bind to any available port)
-        let socket = UdpSocket::bind("0.0.0.0:0")
+        let server_addr = lookup_host(addr)
+            .await
+            .map_err(|e| SdkError::ConnectionFailed(format!("UDP resolve failed: {}", e)))?
+            .next()
+            .ok_or_else(|| SdkError::ConnectionFailed(format!("UDP resolve failed: no address for {}", addr)))?;
+
+        // Create UDP socket of the server's address family (bind to any available port)
+        let local_addr = if server_addr.is_ipv6() { "[::]:0" } else { "0.0.0.0:0" };
+        let socket = UdpSocket::bind(local_addr)
             .await
             .map_err(|e| SdkError::ConnectionFailed(format!("UDP bind failed: {}", e)))?;
 
         // Connect socket to server address
         socket
-            .connect(addr)
+            .connect(server_addr)
             .await
             .map_err(|e| SdkError::ConnectionFailed(format!("UDP connect failed: {}", e)))?;
 
@@ -94,6 +101,7 @@ impl UdpClient {
         // Extract request_id from the packet
         let request_id = request.request_id()
             .ok_or_else(|| SdkError::ConnectionFailed("Packet does not have request_id".to_string()))?;
+        let encoded = request.encode().map_err(|e| SdkError::InvalidInput(e.to_string()))?;
 
         for attempt in 1..=MAX_RETRY_ATTEMPTS {
             debug!(
@@ -109,7 +117,7 @@ impl UdpClient {
 
             // Send request
             self.send_tx
-                .send(request.encode())
+                .send(encoded.clone())
                 .await
                 .map_err(|_| SdkError::Disconnected)?;
 
@@ -0,0 +1,181 @@
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::task::JoinHandle;
+use tracing::{debug, info, warn};
+use voiceapp_protocol::{Capabilities, LoginRejectReason, HEARTBEAT_INTERVAL_MS};
+
+use crate::error::SdkError;
+use crate::network::{ApiClient, ClientEvent, EventHandler, TcpClient};
+use crate::voice::io_manager::InputOutputManager;
+
+/// Delay before the first reconnect attempt, doubled after every failed attempt
+const INITIAL_BACKOFF: Duration = Duration::from_millis(500);
+
+/// Longest delay between two reconnect attempts
+const MAX_BACKOFF: Duration = Duration::from_secs(30);
+
+/// Login details and voice state of a connected client, restored after a reconnect
+#[derive(Clone, Debug)]
+pub(crate) struct Session {
+    pub management_addr: String,
+    pub username: String,
+    pub password: Option<String>,
+    pub channel_id: Option<u64>, // Voice channel to rejoin
+    pub is_muted: bool,
+    pub is_deafened: bool,
+}
+
+/// Logs the client in and does it again with exponential backoff whenever the management connection drops,
+/// sends heartbeats in between if the server expects them
+#[derive(Clone)]
+pub(crate) struct Reconnector {
+    pub tcp_client: TcpClient,
+    pub api_client: Arc<ApiClient>,
+    pub event_handler: EventHandler,
+    pub voice_io_manager: Arc<Mutex<InputOutputManager>>,
+    pub session: Arc<Mutex<Option<Session>>>,
+}
+
+impl Reconnector {
+    /// Authenticates on the connected management and voice sockets and starts the voice session, returns `user_id`
+    pub async fn login(
+        &self,
+        username: &str,
+        password: Option<&str>,
+        resume_token: Option<u64>,
+    ) -> Result<u64, SdkError> {
+        let (user_id, voice_token, voice_key) = self
+            .api_client
+            .authenticate_management(username, password, resume_token)
+            .await?;
+        self.voice_io_manager
+            .lock()
+            .map_err(|_| SdkError::LockError)?
+            .start_voice_session(user_id, &voice_key);
+        self.api_client.authenticate_voice(voice_token).await?;
+
+        Ok(user_id)
+    }
+
+    /// Spawn the task sending heartbeats and reconnecting after every dropped connection,
+    /// it stops when the server refuses the login or the task is aborted
+    pub fn spawn(self) -> JoinHandle<()> {
+        tokio::spawn(async move {
+            let disconnected = self.tcp_client.disconnected();
+            let mut heartbeat = tokio::time::interval(Duration::from_millis(u64::from(HEARTBEAT_INTERVAL_MS)));
+
+            loop {
+                tokio::select! {
+                    result = disconnected.recv() => {
+                        if result.is_err() {
+                            break;
+                        }
+                        warn!("[Management server] Connection lost");
+
+                        if !self.reconnect().await {
+                            break;
+                        }
+                        heartbeat.reset();
+                    }
+
+                    _ = heartbeat.tick() => {
+                        if self.api_client.capabilities().contains(Capabilities::HEARTBEAT) {
+                            if let Err(e) = self.api_client.send_heartbeat().await {
+                                debug!("Failed to send heartbeat: {}", e);
+                            }
+                        }
+                    }
+                }
+            }
+        })
+    }
+
+    /// Retry until logged in again, returns false if the server refused the login
+    async fn reconnect(&self) -> bool {
+        let hints = self.event_handler.take_reconnect_hints();
+        if hints.removed {
+            info!("[Management server] Removed by a moderator, not reconnecting");
+            return false;
+        }
+
+        let mut delay = hints.reconnect_after.unwrap_or(INITIAL_BACKOFF);
+        let mut attempt: u32 = 0;
+
+        loop {
+            attempt = attempt.saturating_add(1);
+            self.event_handler.emit(ClientEvent::Reconnecting {
+                attempt,
+                delay_ms: u64::try_from(delay.as_millis()).unwrap_or(u64::MAX),
+            });
+            tokio::time::sleep(delay).await;
+
+            let Some(session) = self.session() else {
+                return false;
+            };
+
+            match self.try_reconnect(&session, hints.resume_token).await {
+                Ok(user_id) => {
+                    let channel_id = self.restore().await;
+                    info!("[Management server] Reconnected, user_id={}", user_id);
+                    self.event_handler.emit(ClientEvent::Reconnected { user_id, channel_id });
+                    return true;
+                }
+                Err(e) if is_permanent(&e) => {
+                    warn!("[Management server] Reconnect refused: {}", e);
+                    self.event_handler.emit(ClientEvent::ReconnectFailed { reason: e.to_string() });
+                    return false;
+                }
+                Err(e) => {
+                    warn!("[Management server] Reconnect attempt {} failed: {}", attempt, e);
+                    delay = (delay * 2).min(MAX_BACKOFF);
+                }
+            }
+        }
+    }
+
+    async fn try_reconnect(&self, session: &Session, resume_token: Option<u64>) -> Result<u64, SdkError> {
+        self.tcp_client.connect(&session.management_addr).await?;
+        self.login(&session.username, session.password.as_deref(), resume_token).await
+    }
+
+    /// Rejoin the previous voice channel and restore the mute and deafen state, returns the rejoined channel
+    async fn restore(&self) -> Option<u64> {
+        let session = self.session()?;
+
+        let channel_id = match session.channel_id {
+            Some(channel_id) => match self.api_client.join_channel(channel_id).await {
+                Ok(()) => Some(channel_id),
+                Err(e) => {
+                    warn!("Failed to rejoin voice channel {}: {}", channel_id, e);
+                    if let Ok(Some(session)) = self.session.lock().as_deref_mut() {
+                        session.channel_id = None;
+                    }
+                    None
+                }
+            },
+            None => None,
+        };
+
+        if session.is_muted || session.is_deafened {
+            if let Err(e) = self.api_client.set_mute_state(session.is_muted, session.is_deafened).await {
+                warn!("Failed to restore mute state: {}", e);
+            }
+        }
+
+        channel_id
+    }
+
+    fn session(&self) -> Option<Session> {
+        self.session.lock().ok().and_then(|session| session.clone())
+    }
+}
+
+/// Errors a retry cannot fix
+fn is_permanent(error: &SdkError) -> bool {
+    matches!(
+        error,
+        SdkError::AuthenticationFailed
+            | SdkError::ProtocolVersionMismatch { .. }
+            | SdkError::LoginRejected(LoginRejectReason::InvalidUsername | LoginRejectReason::Banned)
+    )
+}
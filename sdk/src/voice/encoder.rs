@@ -49,7 +49,6 @@ impl Encoder {
 
         let packet = VoiceData {
             sequence: self.sequence,
-            user_id: 0,
             timestamp: self.timestamp,
             opus_frame,
         };
@@ -1,10 +1,10 @@
 use async_channel::{Receiver, Sender};
 use tracing::{error, info};
-use voiceapp_protocol::Packet;
 use crate::error::SdkError;
 use crate::voice::encoder::Encoder;
 use crate::voice::opus_consts::{OPUS_FRAME_SIZE, OPUS_SAMPLE_RATE};
 use crate::voice::resampler::AudioResampler;
+use crate::voice::session::VoiceSessionSlot;
 
 /// Voice input pipeline: resamples, buffers, and encodes audio to Opus
 pub(crate) struct InputPipeline;
@@ -17,6 +17,7 @@ impl InputPipeline {
         target_sample_rate: u32,
         voice_input_rx: Receiver<Vec<f32>>,
         udp_send_tx: Sender<Vec<u8>>,
+        voice_session: VoiceSessionSlot,
     ) -> Result<Self, SdkError> {
         let encoder = Encoder::new()?;
 
@@ -31,7 +32,7 @@ impl InputPipeline {
         };
 
         // Spawn the pipeline processing task
-        tokio::spawn(Self::pipeline_task(encoder, resampler, voice_input_rx, udp_send_tx));
+        tokio::spawn(Self::pipeline_task(encoder, resampler, voice_input_rx, udp_send_tx, voice_session));
 
         Ok(InputPipeline {})
     }
@@ -61,26 +62,36 @@ impl InputPipeline {
         }
     }
 
-    /// Encode frames from buffer and send to UDP
+    /// Encode frames from buffer, encrypt and send to UDP
     async fn encode_and_send(
         encoder: &mut Encoder,
         encode_buffer: &mut Vec<f32>,
         udp_send_tx: &Sender<Vec<u8>>,
+        voice_session: &VoiceSessionSlot,
     ) -> bool {
         while encode_buffer.len() >= OPUS_FRAME_SIZE as usize {
             let frame: Vec<f32> = encode_buffer.drain(0..OPUS_FRAME_SIZE as usize).collect();
 
             match encoder.encode(&frame) {
                 Ok(voice_data) => {
-                    // Encode VoiceData to Packet and send to UDP
-                    let packet = Packet::VoiceData {
-                        user_id: voice_data.user_id,
-                        sequence: voice_data.sequence,
-                        timestamp: voice_data.timestamp,
-                        data: voice_data.opus_frame,
+                    // Not logged in yet, there is no key to seal the frame with
+                    let Some(session) = voice_session.current() else {
+                        continue;
                     };
 
-                    if udp_send_tx.send(packet.encode()).await.is_err() {
+                    // Seal VoiceData into Packet and send to UDP. The session assigns the
+                    // sequence number, as it must stay unique across pipeline restarts
+                    let packet = session.seal(voice_data.timestamp, &voice_data.opus_frame);
+
+                    let encoded = match packet.encode() {
+                        Ok(encoded) => encoded,
+                        Err(e) => {
+                            error!("Dropping voice frame: {}", e);
+                            continue;
+                        }
+                    };
+
+                    if udp_send_tx.send(encoded).await.is_err() {
                         error!("UDP send channel closed, stopping pipeline");
                         return false;
                     }
@@ -100,6 +111,7 @@ impl InputPipeline {
         mut resampler: Option<AudioResampler>,
         input_rx: Receiver<Vec<f32>>,
         udp_send_tx: Sender<Vec<u8>>,
+        voice_session: VoiceSessionSlot,
     ) {
         const RESAMPLER_CHUNK_SIZE: usize = 480;
 
@@ -108,7 +120,7 @@ impl InputPipeline {
 
         while let Ok(frame) = input_rx.recv().await {
             Self::resample(&frame, &mut resampler, &mut resample_buffer, &mut encode_buffer);
-            if !Self::encode_and_send(&mut encoder, &mut encode_buffer, &udp_send_tx).await {
+            if !Self::encode_and_send(&mut encoder, &mut encode_buffer, &udp_send_tx, &voice_session).await {
                 return;
             }
         }
@@ -1,34 +1,48 @@
 use std::sync::Arc;
 use async_channel::{unbounded, Receiver, Sender};
 use dashmap::DashMap;
-use tracing::{error, info};
+use tracing::{debug, error, info};
+use voiceapp_protocol::crypto::VOICE_KEY_LEN;
 use voiceapp_protocol::Packet;
 use crate::error::SdkError;
 use crate::voice::input_pipeline::InputPipeline;
 use crate::voice::decoder::VoiceData;
 use crate::voice::decoder::Decoder;
+use crate::voice::session::{VoiceSession, VoiceSessionSlot};
 
 /// Manages voice input and output with dynamic sample rate configuration
 pub(crate) struct InputOutputManager {
     send_tx: Sender<Vec<u8>>,
     input_pipeline: Option<InputPipeline>,
     output_decoders: Arc<DashMap<u64, (u32, Arc<Decoder>)>>,
+    voice_session: VoiceSessionSlot,
 }
 
 impl InputOutputManager {
     pub fn new(send_tx: Sender<Vec<u8>>, receive_tx: Receiver<Packet>) -> Self {
         let output_decoders = Arc::new(DashMap::new());
+        let voice_session = VoiceSessionSlot::default();
 
         // Spawn async task to process incoming voice packets
-        tokio::spawn(Self::process_incoming_packets(receive_tx, Arc::clone(&output_decoders)));
+        tokio::spawn(Self::process_incoming_packets(
+            receive_tx,
+            Arc::clone(&output_decoders),
+            voice_session.clone(),
+        ));
 
         InputOutputManager {
             send_tx,
             input_pipeline: None,
-            output_decoders
+            output_decoders,
+            voice_session,
         }
     }
 
+    /// Start encrypting voice with the key received at login, replaces the previous session
+    pub fn start_voice_session(&mut self, user_id: u64, voice_key: &[u8; VOICE_KEY_LEN]) {
+        self.voice_session.replace(VoiceSession::new(user_id, voice_key));
+    }
+
     /// Get the voice input sender for external audio sources
     /// External sources can change, but they all write to the same stream
     pub fn get_voice_input_sender(&mut self, input_sample_rate: u32) -> Result<Sender<Vec<f32>>, SdkError> {
@@ -42,6 +56,7 @@ impl InputOutputManager {
             input_sample_rate,
             new_rx,
             self.send_tx.clone(),
+            self.voice_session.clone(),
         )?;
 
         self.input_pipeline = Some(pipeline);
@@ -91,6 +106,7 @@ impl InputOutputManager {
     async fn process_incoming_packets(
         receive_rx: Receiver<Packet>,
         output_decoders: Arc<DashMap<u64, (u32, Arc<Decoder>)>>,
+        voice_session: VoiceSessionSlot,
     ) {
         info!("Voice packet processor started");
 
@@ -99,11 +115,19 @@ impl InputOutputManager {
                 Ok(packet) => {
                     // Only process VoiceData packets
                     if let Packet::VoiceData { user_id, sequence, timestamp, data } = packet {
+                        // Decrypt and drop forged or replayed packets
+                        let Some(session) = voice_session.current() else {
+                            continue;
+                        };
+                        let Some(data) = session.open(user_id, sequence, timestamp, data) else {
+                            debug!("Dropping voice packet from user {}: sequence={}", user_id, sequence);
+                            continue;
+                        };
+
                         // Create VoiceData struct for decoder
                         let voice_data = VoiceData {
                             sequence,
                             timestamp,
-                            user_id,
                             opus_frame: data,
                         };
 
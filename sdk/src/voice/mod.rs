@@ -5,4 +5,5 @@ pub(crate) mod resampler;
 pub(crate) mod neteq;
 pub(crate) mod models;
 pub(crate) mod opus_consts;
-pub(crate) mod io_manager;
\ No newline at end of file
+pub(crate) mod io_manager;
+pub(crate) mod session;
\ No newline at end of file
@@ -0,0 +1,61 @@
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::{Arc, RwLock};
+use dashmap::DashMap;
+use voiceapp_protocol::crypto::{ReplayWindow, VoiceCipher, VoiceDirection, VOICE_KEY_LEN};
+use voiceapp_protocol::Packet;
+
+/// Voice encryption state of one login, shared by the input pipeline and the packet processor
+pub(crate) struct VoiceSession {
+    user_id: u64,
+    cipher: VoiceCipher,
+    next_sequence: AtomicU32, // Outlives input pipelines, so no nonce is reused with this key
+    replay_windows: DashMap<u64, ReplayWindow>, // Sequence numbers already received per sender
+}
+
+impl VoiceSession {
+    pub fn new(user_id: u64, voice_key: &[u8; VOICE_KEY_LEN]) -> Self {
+        Self {
+            user_id,
+            cipher: VoiceCipher::new(voice_key),
+            next_sequence: AtomicU32::new(0),
+            replay_windows: DashMap::new(),
+        }
+    }
+
+    /// Seal an encoded Opus frame into a VoiceData packet for the relay
+    pub fn seal(&self, timestamp: u32, opus_frame: &[u8]) -> Packet {
+        let sequence = self.next_sequence.fetch_add(1, Ordering::Relaxed);
+        let data = self.cipher.seal(VoiceDirection::ToRelay, self.user_id, sequence, timestamp, opus_frame);
+
+        Packet::VoiceData { user_id: self.user_id, sequence, timestamp, data }
+    }
+
+    /// Open a VoiceData payload relayed from `user_id` in place, returns the Opus frame in the same buffer
+    /// or None if it is forged or replayed
+    pub fn open(&self, user_id: u64, sequence: u32, timestamp: u32, mut data: Vec<u8>) -> Option<Vec<u8>> {
+        let len = self.cipher.open_in_place(VoiceDirection::FromRelay, user_id, sequence, timestamp, &mut data).ok()?;
+
+        if !self.replay_windows.entry(user_id).or_default().accept(sequence) {
+            return None;
+        }
+
+        data.truncate(len);
+        Some(data)
+    }
+}
+
+/// Holds the voice session of the current login, empty until connected
+#[derive(Clone, Default)]
+pub(crate) struct VoiceSessionSlot(Arc<RwLock<Option<Arc<VoiceSession>>>>);
+
+impl VoiceSessionSlot {
+    pub fn current(&self) -> Option<Arc<VoiceSession>> {
+        self.0.read().ok()?.clone()
+    }
+
+    pub fn replace(&self, session: VoiceSession) {
+        if let Ok(mut current) = self.0.write() {
+            *current = Some(Arc::new(session));
+        }
+    }
+}
@@ -0,0 +1,136 @@
+//! Client reconnecting after its management connection drops.
+
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::Duration;
+use async_channel::Receiver;
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::Notify;
+use voiceapp_sdk::{Client, ClientEvent};
+use voiceapp_server::{Authenticator, Limits, ManagementServer, UsernamePolicy, VoiceRelayServer};
+
+async fn start_servers(management_port: u16, voice_port: u16) {
+    let (management_server, events_rx) = ManagementServer::new(
+        vec!["General".to_string()],
+        Authenticator::default(),
+        Limits::default(),
+        UsernamePolicy::default(),
+    );
+    tokio::spawn(async move { management_server.run(SocketAddr::from(([127, 0, 0, 1], management_port))).await });
+
+    let mut voice_server = VoiceRelayServer::new(events_rx);
+    tokio::spawn(async move { voice_server.run(SocketAddr::from(([127, 0, 0, 1], voice_port))).await });
+
+    for _ in 0..50 {
+        if TcpStream::connect(("127.0.0.1", management_port)).await.is_ok() {
+            return;
+        }
+        tokio::time::sleep(Duration::from_millis(20)).await;
+    }
+    panic!("server did not start");
+}
+
+/// Forwards connections from `port` to `target_port`, notifying the returned handle drops all open ones
+async fn start_proxy(port: u16, target_port: u16) -> Arc<Notify> {
+    let listener = TcpListener::bind(("127.0.0.1", port)).await.unwrap();
+    let drop_connections = Arc::new(Notify::new());
+    let notify = drop_connections.clone();
+
+    tokio::spawn(async move {
+        while let Ok((mut client, _)) = listener.accept().await {
+            let notify = notify.clone();
+            tokio::spawn(async move {
+                let mut server = TcpStream::connect(("127.0.0.1", target_port)).await.unwrap();
+                tokio::select! {
+                    _ = tokio::io::copy_bidirectional(&mut client, &mut server) => {}
+                    () = notify.notified() => {}
+                }
+            });
+        }
+    });
+
+    drop_connections
+}
+
+/// Waits for the first event matching `predicate`
+async fn next_event(events: &Receiver<ClientEvent>, predicate: impl Fn(&ClientEvent) -> bool) -> ClientEvent {
+    tokio::time::timeout(Duration::from_secs(5), async {
+        loop {
+            let event = events.recv().await.unwrap();
+            if predicate(&event) {
+                return event;
+            }
+        }
+    })
+    .await
+    .expect("event not received")
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn resumes_session_after_dropped_connection() {
+    start_servers(39401, 39411).await;
+    let drop_connections = start_proxy(39421, 39401).await;
+
+    let alice = Client::new();
+    let events = alice.event_stream();
+    let user_id = alice.connect("127.0.0.1:39421", "127.0.0.1:39411", "alice", None).await.unwrap();
+    let channel_id = alice.list_channels().await.unwrap()[0].channel_id;
+    alice.join_channel(channel_id).await.unwrap();
+    alice.send_mute_state(true).await.unwrap();
+    alice.send_deafen_state(true).await.unwrap();
+
+    drop_connections.notify_waiters();
+
+    let reconnecting = next_event(&events, |e| matches!(e, ClientEvent::Reconnecting { .. })).await;
+    assert!(matches!(reconnecting, ClientEvent::Reconnecting { attempt: 1, .. }));
+
+    let reconnected = next_event(&events, |e| matches!(e, ClientEvent::Reconnected { .. })).await;
+    assert!(matches!(
+        reconnected,
+        ClientEvent::Reconnected { user_id: id, channel_id: Some(channel) } if id == user_id && channel == channel_id
+    ));
+
+    // Other users see the same user back in the channel with the mute and deafen state restored
+    let bob = Client::new();
+    let bob_events = bob.event_stream();
+    bob.connect("127.0.0.1:39401", "127.0.0.1:39411", "bob", None).await.unwrap();
+
+    let ClientEvent::ParticipantsList { participants, .. } =
+        next_event(&bob_events, |e| matches!(e, ClientEvent::ParticipantsList { .. })).await
+    else {
+        unreachable!()
+    };
+    let alice_info = participants.iter().find(|p| p.username == "alice").expect("alice not logged in");
+    assert_eq!(alice_info.user_id, user_id);
+    assert_eq!(alice_info.channel_id, Some(channel_id));
+    assert!(alice_info.is_muted);
+    assert!(alice_info.is_deafened);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn reconnects_under_the_changed_nickname() {
+    start_servers(39402, 39412).await;
+    let drop_connections = start_proxy(39422, 39402).await;
+
+    let alice = Client::new();
+    let events = alice.event_stream();
+    let user_id = alice.connect("127.0.0.1:39422", "127.0.0.1:39412", "alice", None).await.unwrap();
+    alice.change_nickname("alicia").await.unwrap();
+
+    drop_connections.notify_waiters();
+
+    let reconnected = next_event(&events, |e| matches!(e, ClientEvent::Reconnected { .. })).await;
+    assert!(matches!(reconnected, ClientEvent::Reconnected { user_id: id, .. } if id == user_id));
+
+    let bob = Client::new();
+    let bob_events = bob.event_stream();
+    bob.connect("127.0.0.1:39402", "127.0.0.1:39412", "bob", None).await.unwrap();
+
+    let ClientEvent::ParticipantsList { participants, .. } =
+        next_event(&bob_events, |e| matches!(e, ClientEvent::ParticipantsList { .. })).await
+    else {
+        unreachable!()
+    };
+    let alice_info = participants.iter().find(|p| p.user_id == user_id).expect("alice not logged in");
+    assert_eq!(alice_info.username, "alicia");
+}
@@ -0,0 +1,84 @@
+//! Client connecting to a TLS management server with a self-signed certificate generated at test time.
+
+use std::net::SocketAddr;
+use std::time::Duration;
+use tokio::net::TcpStream;
+use voiceapp_sdk::{Client, SdkError, TlsConfig};
+use voiceapp_server::{load_tls_acceptor, Authenticator, Limits, ManagementServer, UsernamePolicy, VoiceRelayServer};
+
+/// Fresh self-signed certificate for localhost, returns (cert PEM, key PEM)
+fn self_signed_cert() -> (String, String) {
+    let certified = rcgen::generate_simple_self_signed(vec!["localhost".to_string()]).unwrap();
+    (certified.cert.pem(), certified.key_pair.serialize_pem())
+}
+
+/// Starts TLS management and voice servers, returns the certificate PEM the server presents
+async fn start_servers(name: &str, management_port: u16, voice_port: u16) -> String {
+    let (cert_pem, key_pem) = self_signed_cert();
+    let dir = std::env::temp_dir().join(format!("voiceapp-sdk-tls-{}-{}", name, std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    std::fs::write(dir.join("cert.pem"), &cert_pem).unwrap();
+    std::fs::write(dir.join("key.pem"), &key_pem).unwrap();
+
+    let acceptor = load_tls_acceptor(&dir.join("cert.pem"), &dir.join("key.pem")).unwrap();
+    let (management_server, events_rx) = ManagementServer::new(
+        vec!["General".to_string()],
+        Authenticator::default(),
+        Limits::default(),
+        UsernamePolicy::default(),
+    );
+    let management_server = management_server.with_tls(acceptor);
+    tokio::spawn(async move { management_server.run(SocketAddr::from(([127, 0, 0, 1], management_port))).await });
+
+    let mut voice_server = VoiceRelayServer::new(events_rx);
+    tokio::spawn(async move { voice_server.run(SocketAddr::from(([127, 0, 0, 1], voice_port))).await });
+
+    for _ in 0..50 {
+        if TcpStream::connect(("127.0.0.1", management_port)).await.is_ok() {
+            return cert_pem;
+        }
+        tokio::time::sleep(Duration::from_millis(20)).await;
+    }
+    panic!("server did not start");
+}
+
+async fn connect(tls: TlsConfig, management_port: u16, voice_port: u16) -> Result<u64, SdkError> {
+    let client = Client::with_tls(tls);
+    client
+        .connect(&format!("127.0.0.1:{}", management_port), &format!("127.0.0.1:{}", voice_port), "alice", None)
+        .await
+}
+
+#[tokio::test]
+async fn connects_with_pinned_certificate() {
+    let cert_pem = start_servers("pinned", 39201, 39211).await;
+
+    let tls = TlsConfig::with_pinned_cert_pem(cert_pem.as_bytes()).unwrap();
+    assert!(connect(tls, 39201, 39211).await.is_ok());
+}
+
+#[tokio::test]
+async fn connects_with_custom_ca() {
+    let cert_pem = start_servers("ca", 39202, 39212).await;
+
+    // The certificate is issued for localhost, not the IP we dial
+    let tls = TlsConfig::with_ca_pem(cert_pem.as_bytes()).unwrap().server_name("localhost");
+    assert!(connect(tls, 39202, 39212).await.is_ok());
+}
+
+#[tokio::test]
+async fn rejects_unexpected_certificate() {
+    start_servers("unexpected", 39203, 39213).await;
+
+    let (other_cert_pem, _) = self_signed_cert();
+    let tls = TlsConfig::with_pinned_cert_pem(other_cert_pem.as_bytes()).unwrap();
+    assert!(matches!(connect(tls, 39203, 39213).await, Err(SdkError::TlsError(_))));
+
+    let tls = TlsConfig::with_ca_pem(other_cert_pem.as_bytes()).unwrap().server_name("localhost");
+    assert!(matches!(connect(tls, 39203, 39213).await, Err(SdkError::TlsError(_))));
+}
+
+#[test]
+fn rejects_pem_without_certificate() {
+    assert!(matches!(TlsConfig::with_pinned_cert_pem(b"not a certificate"), Err(SdkError::TlsError(_))));
+}
@@ -0,0 +1,121 @@
+//! Command-line flags of the server binary.
+//!
+//! Every flag can also be given as an environment variable, flags win over the environment
+//! and both win over the config file. The shared password is only read from `SERVER_PASSWORD`,
+//! command-line arguments show up in `ps` and shell history.
+
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use clap::{Parser, Subcommand};
+use crate::config::Config;
+use crate::management::user::UsernamePolicy;
+
+#[derive(Debug, Parser)]
+#[command(name = "voiceapp-server", version, about = "Voice application server")]
+pub struct Cli {
+    #[command(subcommand)]
+    pub command: Option<Command>,
+
+    /// TOML config file
+    #[arg(short, long, env = "CONFIG_FILE", value_name = "FILE")]
+    pub config: Option<PathBuf>,
+
+    /// Management (TCP) listen address, e.g. 0.0.0.0:9001 or [::]:9001
+    #[arg(long, env = "MANAGEMENT_BIND", value_name = "ADDR")]
+    management_bind: Option<SocketAddr>,
+
+    /// Port of the management listen address
+    #[arg(long, env = "MANAGEMENT_PORT", value_name = "PORT")]
+    management_port: Option<u16>,
+
+    /// Voice relay (UDP) listen address, e.g. 0.0.0.0:9002 or [::]:9002
+    #[arg(long, env = "VOICE_BIND", value_name = "ADDR")]
+    voice_bind: Option<SocketAddr>,
+
+    /// Port of the voice relay listen address
+    #[arg(long, env = "VOICE_RELAY_PORT", value_name = "PORT")]
+    voice_port: Option<u16>,
+
+    /// Comma-separated voice channels created on startup
+    #[arg(long, env = "VOICE_CHANNELS", value_name = "NAMES", value_delimiter = ',')]
+    voice_channels: Option<Vec<String>>,
+
+    /// Duplicate username handling: case-insensitive, case-sensitive or allow-duplicates
+    #[arg(long, env = "USERNAME_POLICY", value_name = "POLICY")]
+    username_policy: Option<UsernamePolicy>,
+
+    /// Maximum number of logged in users, 0 for unlimited
+    #[arg(long, env = "MAX_USERS", value_name = "COUNT")]
+    max_users: Option<usize>,
+
+    /// Message of the day sent after login
+    #[arg(long, env = "MOTD", value_name = "TEXT")]
+    motd: Option<String>,
+
+    /// Per-user credentials file, see the hash-password command
+    #[arg(long, env = "CREDENTIALS_FILE", value_name = "FILE")]
+    credentials_file: Option<PathBuf>,
+
+    /// PEM certificate chain, enables TLS on the management port together with --tls-key-file
+    #[arg(long, env = "TLS_CERT_FILE", value_name = "FILE")]
+    tls_cert_file: Option<PathBuf>,
+
+    /// PEM private key for --tls-cert-file
+    #[arg(long, env = "TLS_KEY_FILE", value_name = "FILE")]
+    tls_key_file: Option<PathBuf>,
+
+    /// Log verbosity: trace, debug, info, warn or error
+    #[arg(long, env = "LOG_LEVEL", value_name = "LEVEL")]
+    log_level: Option<String>,
+}
+
+#[derive(Debug, Subcommand)]
+pub enum Command {
+    /// Add a user with its own password, read from stdin, to a credentials file, creating the file if needed
+    HashPassword { file: PathBuf, username: String },
+}
+
+impl Cli {
+    /// Overrides the config file settings with the given flags, an empty MOTD or password counts as unset.
+    pub fn apply(self, config: &mut Config) {
+        if let Some(addr) = self.management_bind {
+            config.management_bind = addr;
+        }
+        if let Some(port) = self.management_port {
+            config.management_bind.set_port(port);
+        }
+        if let Some(addr) = self.voice_bind {
+            config.voice_bind = addr;
+        }
+        if let Some(port) = self.voice_port {
+            config.voice_bind.set_port(port);
+        }
+        if let Some(channels) = self.voice_channels {
+            config.voice_channels = channels.into_iter().map(|name| name.trim().to_string()).collect();
+        }
+        if let Some(policy) = self.username_policy {
+            config.username_policy = policy;
+        }
+        if let Some(max_users) = self.max_users {
+            config.limits.max_users = Some(max_users).filter(|&max| max > 0);
+        }
+        if let Some(motd) = self.motd.filter(|v| !v.is_empty()) {
+            config.motd = Some(motd);
+        }
+        if let Some(password) = std::env::var("SERVER_PASSWORD").ok().filter(|v| !v.is_empty()) {
+            config.auth.password = Some(password);
+        }
+        if let Some(path) = self.credentials_file {
+            config.auth.credentials_file = Some(path);
+        }
+        if let Some(path) = self.tls_cert_file {
+            config.tls.cert_file = Some(path);
+        }
+        if let Some(path) = self.tls_key_file {
+            config.tls.key_file = Some(path);
+        }
+        if let Some(level) = self.log_level {
+            config.log_level = level;
+        }
+    }
+}
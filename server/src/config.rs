@@ -1,6 +1,18 @@
-//! Configuration constants for the voiceapp server.
+//! Configuration for the voiceapp server.
+//!
+//! Settings come from an optional TOML file, see [`Config::load`]. The binary then applies
+//! environment variables and command-line flags on top and calls [`Config::validate`]
+//! before starting anything.
 
-use std::env;
+use std::collections::HashSet;
+use std::net::{Ipv4Addr, SocketAddr};
+use std::path::{Path, PathBuf};
+use serde::Deserialize;
+use tracing::Level;
+use voiceapp_protocol::{Permissions, Role, HEARTBEAT_INTERVAL_MS, TYPING_INTERVAL_MS};
+use crate::error::ServerError;
+use crate::management::role::{PermissionName, RoleName, Roles};
+use crate::management::user::UsernamePolicy;
 
 /// Default port for the management (TCP) server.
 pub const DEFAULT_MANAGEMENT_PORT: u16 = 9001;
@@ -8,29 +20,459 @@ pub const DEFAULT_MANAGEMENT_PORT: u16 = 9001;
 /// Default port for the voice relay (UDP) server.
 pub const DEFAULT_VOICE_PORT: u16 = 9002;
 
-/// Buffer size for reading packets.
-pub const PACKET_BUFFER_SIZE: usize = 4096;
+/// Default buffer size for reading packets.
+pub const DEFAULT_PACKET_BUFFER_SIZE: usize = 4096;
 
-/// Capacity of the broadcast channel for client messages.
-pub const BROADCAST_CHANNEL_CAPACITY: usize = 1000;
+/// Smallest packet buffer that still fits a full Opus frame in a `VoiceData` datagram.
+pub const MIN_PACKET_BUFFER_SIZE: usize = 1500;
 
-/// Maximum allowed username length.
-pub const MAX_USERNAME_LEN: usize = 32;
+/// Default capacity of the broadcast channel for client messages.
+pub const DEFAULT_BROADCAST_CHANNEL_CAPACITY: usize = 1000;
 
-/// Returns the management server port from `MANAGEMENT_PORT` env var or default.
-#[must_use]
-pub fn management_port() -> u16 {
-    env::var("MANAGEMENT_PORT")
-        .ok()
-        .and_then(|v| v.parse().ok())
-        .unwrap_or(DEFAULT_MANAGEMENT_PORT)
+/// Default maximum username length.
+pub const DEFAULT_MAX_USERNAME_LEN: usize = 32;
+
+/// Default maximum voice channel name length.
+pub const DEFAULT_MAX_CHANNEL_NAME_LEN: usize = 32;
+
+/// Default maximum chat message length in bytes, matches the desktop client's input limit.
+pub const DEFAULT_MAX_CHAT_MESSAGE_LEN: usize = 2000;
+
+/// Largest allowed `limits.max_chat_message_len`, chat packets and history pages need room for their other fields.
+pub const MAX_CHAT_MESSAGE_LEN: usize = 60_000;
+
+/// Default time a disconnected user's id is kept for a resuming client.
+pub const DEFAULT_RESUME_WINDOW_MS: u64 = 30_000;
+
+/// Default time without heartbeats after which a client is disconnected, three missed heartbeats.
+pub const DEFAULT_IDLE_TIMEOUT_MS: u64 = 45_000;
+
+/// Default time after the last `SetTypingRequest` until a user stops showing as typing, over three typing intervals.
+pub const DEFAULT_TYPING_TIMEOUT_MS: u64 = 10_000;
+
+/// Default number of chat messages kept for `ChatHistoryRequest`.
+pub const DEFAULT_CHAT_HISTORY_LEN: usize = 10_000;
+
+/// Most chat messages returned by one `ChatHistoryRequest`.
+pub const MAX_CHAT_HISTORY_PAGE_LEN: usize = 100;
+
+/// Most different emoji reacted to one chat message.
+pub const MAX_REACTIONS_PER_MESSAGE: usize = 20;
+
+/// Maximum reaction emoji length in bytes, enough for emoji sequences like flags and skin tones.
+pub const MAX_REACTION_LEN: usize = 32;
+
+/// Most files attached to one chat message.
+pub const MAX_ATTACHMENTS_PER_MESSAGE: usize = 10;
+
+/// Default largest attachment in bytes.
+pub const DEFAULT_MAX_ATTACHMENT_SIZE: u64 = 8 * 1024 * 1024;
+
+/// Default total size of kept attachments in bytes.
+pub const DEFAULT_ATTACHMENTS_CAPACITY: u64 = 256 * 1024 * 1024;
+
+/// Maximum attachment file name length in bytes.
+pub const MAX_ATTACHMENT_NAME_LEN: usize = 128;
+
+/// Maximum attachment MIME type length in bytes.
+pub const MAX_ATTACHMENT_MIME_TYPE_LEN: usize = 64;
+
+/// Most uploads one user may have in progress, enough for a message with the most attachments.
+pub const MAX_UNFINISHED_UPLOADS: usize = MAX_ATTACHMENTS_PER_MESSAGE;
+
+/// Maximum message of the day length in bytes.
+pub const MAX_MOTD_LEN: usize = 1024;
+
+/// Maximum shutdown message length in bytes.
+pub const MAX_SHUTDOWN_MESSAGE_LEN: usize = 256;
+
+/// Voice channel created on startup when none are configured.
+pub const DEFAULT_VOICE_CHANNEL: &str = "General";
+
+/// Server configuration, every field has a default so a config file only lists what it changes.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    /// Management (TCP) listen address, IPv4 or IPv6.
+    pub management_bind: SocketAddr,
+    /// Voice relay (UDP) listen address, IPv4 or IPv6.
+    pub voice_bind: SocketAddr,
+    /// Voice channels created on startup.
+    pub voice_channels: Vec<String>,
+    /// Duplicate username handling.
+    pub username_policy: UsernamePolicy,
+    /// Message of the day sent to users after login.
+    pub motd: Option<String>,
+    /// Log verbosity: trace, debug, info, warn or error.
+    pub log_level: String,
+    pub auth: AuthConfig,
+    pub tls: TlsConfig,
+    pub roles: RolesConfig,
+    pub moderation: ModerationConfig,
+    pub chat: ChatConfig,
+    pub attachments: AttachmentsConfig,
+    pub limits: Limits,
+    pub shutdown: ShutdownConfig,
 }
 
-/// Returns the voice relay server port from `VOICE_RELAY_PORT` env var or default.
-#[must_use]
-pub fn voice_port() -> u16 {
-    env::var("VOICE_RELAY_PORT")
-        .ok()
-        .and_then(|v| v.parse().ok())
-        .unwrap_or(DEFAULT_VOICE_PORT)
+/// Password authentication, disabled when both fields are unset.
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct AuthConfig {
+    /// Shared password required to log in.
+    pub password: Option<String>,
+    /// Per-user credentials file, see [`Credentials::load`](crate::management::auth::Credentials::load).
+    pub credentials_file: Option<PathBuf>,
+}
+
+/// TLS for the management port, enabled when both files are set.
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct TlsConfig {
+    /// PEM certificate chain.
+    pub cert_file: Option<PathBuf>,
+    /// PEM private key for `cert_file`.
+    pub key_file: Option<PathBuf>,
+}
+
+/// Who gets which role, usernames are compared ignoring case. Protect privileged names with a credentials file.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RolesConfig {
+    /// Role of users not listed below.
+    pub default: RoleName,
+    pub admins: Vec<String>,
+    pub moderators: Vec<String>,
+    pub members: Vec<String>,
+    pub guests: Vec<String>,
+    pub permissions: RolePermissionsConfig,
+}
+
+/// Permissions per role, unset roles keep the built-in defaults.
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RolePermissionsConfig {
+    pub admin: Option<Vec<PermissionName>>,
+    pub moderator: Option<Vec<PermissionName>>,
+    pub member: Option<Vec<PermissionName>>,
+    pub guest: Option<Vec<PermissionName>>,
+}
+
+/// Bans and server mutes.
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ModerationConfig {
+    /// File bans and server mutes are loaded from and saved to (`user:<username>`, `ip:<address>` and
+    /// `mute:<username>` lines).
+    pub bans_file: Option<PathBuf>,
+}
+
+/// Chat history served to users who log in later.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ChatConfig {
+    /// File messages are loaded from and appended to, history is lost on restart if unset.
+    pub history_file: Option<PathBuf>,
+    /// Number of newest messages kept in memory and served, 0 disables the history.
+    pub history_len: usize,
+}
+
+/// Files attached to chat messages.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct AttachmentsConfig {
+    /// Directory files are kept in, they are kept in memory and lost on restart if unset.
+    pub dir: Option<PathBuf>,
+    /// Largest file in bytes.
+    pub max_size: u64,
+    /// Total size of kept files in bytes, the oldest are removed to make room. 0 disables attachments.
+    pub capacity: u64,
+}
+
+/// Resource limits of the management server and voice relay.
+#[derive(Clone, Copy, Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Limits {
+    /// Maximum number of logged in users, unlimited if `None`.
+    pub max_users: Option<usize>,
+    pub max_username_len: usize,
+    pub max_channel_name_len: usize,
+    /// Longest chat message, direct message or edit in bytes.
+    pub max_chat_message_len: usize,
+    /// Read buffer size for TCP connections and UDP datagrams.
+    pub packet_buffer_size: usize,
+    /// Broadcast messages buffered per client before it starts lagging.
+    pub broadcast_channel_capacity: usize,
+    /// How long a disconnected user's id is kept for a client resuming its session, 0 disables resume.
+    pub resume_window_ms: u64,
+    /// How long a heartbeat sending client may stay silent on the management connection or the voice socket
+    /// before it is disconnected, 0 disables the timeout.
+    pub idle_timeout_ms: u64,
+    /// How long a user shows as typing after its last `SetTypingRequest`.
+    pub typing_timeout_ms: u64,
+}
+
+/// Graceful shutdown on SIGINT or SIGTERM.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ShutdownConfig {
+    /// Reason shown to connected users.
+    pub message: String,
+    /// Suggested delay before clients reconnect, 0 if the server is not coming back.
+    pub reconnect_after_ms: u32,
+    /// How long to wait for connections to close before exiting anyway.
+    pub drain_timeout_ms: u64,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            management_bind: SocketAddr::from((Ipv4Addr::UNSPECIFIED, DEFAULT_MANAGEMENT_PORT)),
+            voice_bind: SocketAddr::from((Ipv4Addr::UNSPECIFIED, DEFAULT_VOICE_PORT)),
+            voice_channels: vec![DEFAULT_VOICE_CHANNEL.to_string()],
+            username_policy: UsernamePolicy::default(),
+            motd: None,
+            log_level: if cfg!(debug_assertions) { "debug" } else { "info" }.to_string(),
+            auth: AuthConfig::default(),
+            tls: TlsConfig::default(),
+            roles: RolesConfig::default(),
+            moderation: ModerationConfig::default(),
+            chat: ChatConfig::default(),
+            attachments: AttachmentsConfig::default(),
+            limits: Limits::default(),
+            shutdown: ShutdownConfig::default(),
+        }
+    }
+}
+
+impl Default for ChatConfig {
+    fn default() -> Self {
+        Self {
+            history_file: None,
+            history_len: DEFAULT_CHAT_HISTORY_LEN,
+        }
+    }
+}
+
+impl Default for AttachmentsConfig {
+    fn default() -> Self {
+        Self {
+            dir: None,
+            max_size: DEFAULT_MAX_ATTACHMENT_SIZE,
+            capacity: DEFAULT_ATTACHMENTS_CAPACITY,
+        }
+    }
+}
+
+impl Default for RolesConfig {
+    fn default() -> Self {
+        Self {
+            default: RoleName::Member,
+            admins: Vec::new(),
+            moderators: Vec::new(),
+            members: Vec::new(),
+            guests: Vec::new(),
+            permissions: RolePermissionsConfig::default(),
+        }
+    }
+}
+
+impl RolesConfig {
+    /// Builds the role registry, later lists win for names listed twice (validation rejects that).
+    #[must_use]
+    pub fn roles(&self) -> Roles {
+        let mut roles = Roles::new(self.default.into());
+
+        for (role, names) in self.assignments() {
+            for name in names {
+                roles = roles.with_user(name, role);
+            }
+        }
+
+        let overrides = [
+            (Role::Admin, &self.permissions.admin),
+            (Role::Moderator, &self.permissions.moderator),
+            (Role::Member, &self.permissions.member),
+            (Role::Guest, &self.permissions.guest),
+        ];
+        for (role, names) in overrides {
+            if let Some(names) = names {
+                let permissions = names.iter().fold(Permissions::NONE, |acc, name| acc | (*name).into());
+                roles = roles.with_permissions(role, permissions);
+            }
+        }
+
+        roles
+    }
+
+    fn assignments(&self) -> [(Role, &Vec<String>); 4] {
+        [
+            (Role::Admin, &self.admins),
+            (Role::Moderator, &self.moderators),
+            (Role::Member, &self.members),
+            (Role::Guest, &self.guests),
+        ]
+    }
+}
+
+impl Default for ShutdownConfig {
+    fn default() -> Self {
+        Self {
+            message: "Server is shutting down".to_string(),
+            reconnect_after_ms: 5000,
+            drain_timeout_ms: 5000,
+        }
+    }
+}
+
+impl Default for Limits {
+    fn default() -> Self {
+        Self {
+            max_users: None,
+            max_username_len: DEFAULT_MAX_USERNAME_LEN,
+            max_channel_name_len: DEFAULT_MAX_CHANNEL_NAME_LEN,
+            max_chat_message_len: DEFAULT_MAX_CHAT_MESSAGE_LEN,
+            packet_buffer_size: DEFAULT_PACKET_BUFFER_SIZE,
+            broadcast_channel_capacity: DEFAULT_BROADCAST_CHANNEL_CAPACITY,
+            resume_window_ms: DEFAULT_RESUME_WINDOW_MS,
+            idle_timeout_ms: DEFAULT_IDLE_TIMEOUT_MS,
+            typing_timeout_ms: DEFAULT_TYPING_TIMEOUT_MS,
+        }
+    }
+}
+
+impl Config {
+    /// Reads a TOML config file, unset fields keep their defaults.
+    ///
+    /// # Errors
+    ///
+    /// Fails if the file cannot be read or is not a valid config.
+    pub fn load(path: &Path) -> Result<Self, ServerError> {
+        let contents = std::fs::read_to_string(path)
+            .map_err(|e| ServerError::InvalidConfig(format!("{}: {}", path.display(), e)))?;
+
+        toml::from_str(&contents).map_err(|e| ServerError::InvalidConfig(format!("{}: {}", path.display(), e)))
+    }
+
+    /// Checks the settings for values the servers cannot run with.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`ServerError::InvalidConfig`] describing the first invalid setting.
+    pub fn validate(&self) -> Result<(), ServerError> {
+        let invalid = |message: String| Err(ServerError::InvalidConfig(message));
+        let limits = &self.limits;
+
+        if limits.max_users == Some(0) {
+            return invalid("limits.max_users must be at least 1, leave it unset for no limit".to_string());
+        }
+        if limits.max_username_len == 0 {
+            return invalid("limits.max_username_len must be at least 1".to_string());
+        }
+        if limits.max_channel_name_len == 0 {
+            return invalid("limits.max_channel_name_len must be at least 1".to_string());
+        }
+        if limits.max_chat_message_len == 0 || limits.max_chat_message_len > MAX_CHAT_MESSAGE_LEN {
+            return invalid(format!(
+                "limits.max_chat_message_len must be between 1 and {}, got {}",
+                MAX_CHAT_MESSAGE_LEN, limits.max_chat_message_len
+            ));
+        }
+        if limits.packet_buffer_size < MIN_PACKET_BUFFER_SIZE {
+            return invalid(format!(
+                "limits.packet_buffer_size must be at least {}, got {}",
+                MIN_PACKET_BUFFER_SIZE, limits.packet_buffer_size
+            ));
+        }
+        if limits.broadcast_channel_capacity == 0 {
+            return invalid("limits.broadcast_channel_capacity must be at least 1".to_string());
+        }
+        if limits.idle_timeout_ms != 0 && limits.idle_timeout_ms <= u64::from(HEARTBEAT_INTERVAL_MS) {
+            return invalid(format!(
+                "limits.idle_timeout_ms must be longer than the heartbeat interval ({} ms) or 0 to disable it, got {}",
+                HEARTBEAT_INTERVAL_MS, limits.idle_timeout_ms
+            ));
+        }
+        if limits.typing_timeout_ms <= u64::from(TYPING_INTERVAL_MS) {
+            return invalid(format!(
+                "limits.typing_timeout_ms must be longer than the typing interval ({} ms), got {}",
+                TYPING_INTERVAL_MS, limits.typing_timeout_ms
+            ));
+        }
+
+        if self.attachments.capacity != 0 && self.attachments.max_size > self.attachments.capacity {
+            return invalid(format!(
+                "attachments.max_size must not be larger than attachments.capacity ({}), got {}",
+                self.attachments.capacity, self.attachments.max_size
+            ));
+        }
+
+        if self.voice_channels.is_empty() {
+            return invalid("voice_channels must list at least one channel".to_string());
+        }
+        let mut channel_names = HashSet::new();
+        for name in &self.voice_channels {
+            if name.trim().is_empty() {
+                return invalid("voice_channels must not contain blank names".to_string());
+            }
+            if name.len() > limits.max_channel_name_len {
+                return invalid(format!(
+                    "voice channel '{}' is longer than limits.max_channel_name_len ({})",
+                    name, limits.max_channel_name_len
+                ));
+            }
+            if !channel_names.insert(name.to_lowercase()) {
+                return invalid(format!("voice channel '{name}' is listed twice"));
+            }
+        }
+
+        if let Some(motd) = &self.motd {
+            if motd.len() > MAX_MOTD_LEN {
+                return invalid(format!("motd is {} bytes, at most {} are allowed", motd.len(), MAX_MOTD_LEN));
+            }
+        }
+
+        if self.shutdown.message.len() > MAX_SHUTDOWN_MESSAGE_LEN {
+            return invalid(format!(
+                "shutdown.message is {} bytes, at most {} are allowed",
+                self.shutdown.message.len(),
+                MAX_SHUTDOWN_MESSAGE_LEN
+            ));
+        }
+
+        if self.log_level.parse::<Level>().is_err() {
+            return invalid(format!(
+                "unknown log_level '{}', expected trace, debug, info, warn or error",
+                self.log_level
+            ));
+        }
+
+        if self.auth.password.as_deref() == Some("") {
+            return invalid("auth.password must not be empty, leave it unset to disable it".to_string());
+        }
+
+        let mut assigned = HashSet::new();
+        for (role, names) in self.roles.assignments() {
+            for name in names {
+                if name.trim().is_empty() {
+                    return invalid(format!("roles.{role}s must not contain blank names"));
+                }
+                if !assigned.insert(name.to_ascii_lowercase()) {
+                    return invalid(format!("user '{name}' is given more than one role in [roles]"));
+                }
+            }
+        }
+
+        if self.tls.cert_file.is_some() != self.tls.key_file.is_some() {
+            return invalid("tls.cert_file and tls.key_file must be set together".to_string());
+        }
+
+        Ok(())
+    }
+
+    /// Log verbosity, falls back to info if `log_level` did not pass validation.
+    #[must_use]
+    pub fn log_level(&self) -> Level {
+        self.log_level.parse().unwrap_or(Level::INFO)
+    }
 }
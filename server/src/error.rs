@@ -14,4 +14,22 @@ pub enum ServerError {
 
     #[error("User not found: {0}")]
     UserNotFound(SocketAddr),
+
+    #[error("Handshake failed: {0}")]
+    Handshake(String),
+
+    #[error("Invalid credentials file: {0}")]
+    InvalidCredentialsFile(String),
+
+    #[error("Invalid bans file: {0}")]
+    InvalidBansFile(String),
+
+    #[error("Invalid chat history file: {0}")]
+    InvalidHistoryFile(String),
+
+    #[error("TLS error: {0}")]
+    Tls(String),
+
+    #[error("Invalid configuration: {0}")]
+    InvalidConfig(String),
 }
@@ -1,12 +1,24 @@
-/// Events emitted by ManagementServer for VoiceRelayServer synchronization.
+use voiceapp_protocol::crypto::VOICE_KEY_LEN;
+
+/// Events emitted by `ManagementServer` for `VoiceRelayServer` synchronization.
 #[derive(Debug, Clone)]
 pub enum Event {
-    /// User connected and received authentication token.
-    UserConnected { id: u64, token: u64 },
-    /// User joined voice channel.
-    VoiceJoined { id: u64 },
+    /// User connected and received authentication token and voice key.
+    UserConnected { id: u64, token: u64, voice_key: [u8; VOICE_KEY_LEN] },
+    /// User sends heartbeats, its voice session expires once the socket goes quiet.
+    HeartbeatStarted { id: u64 },
+    /// User joined (or moved to) a voice channel.
+    VoiceJoined { id: u64, channel_id: u64 },
+    /// User may no longer or may again speak, because a moderator server-muted it or its role lacks
+    /// the speak permission. Voice of a muted user is dropped.
+    ServerMuted { id: u64, muted: bool },
+    /// User stopped or resumed listening, voice is not forwarded to a deafened user.
+    Deafened { id: u64, deafened: bool },
     /// User left voice channel.
     VoiceLeft { id: u64 },
     /// User disconnected from server.
     UserDisconnected { id: u64 },
+    /// User disconnected but may resume its session, and log in again with the same id until
+    /// `UserDisconnected` tells otherwise.
+    UserSuspended { id: u64 },
 }
\ No newline at end of file
@@ -8,15 +8,23 @@
 //!
 //! The server is split into two main components:
 //!
-//! 1. **ManagementServer** - Handles TCP connections for:
-//!    - User authentication and login
-//!    - Presence management (join/leave voice channels)
-//!    - Chat messaging
+//! 1. **`ManagementServer`** - Handles TCP connections (optionally TLS) for:
+//!    - Protocol version negotiation
+//!    - User authentication and login (optional password, challenge-response)
+//!    - Presence management (join/leave voice channels, unique usernames, nickname changes)
+//!    - Voice channel creation and listing
+//!    - Chat messaging and chat history, optionally kept in a file
+//!    - File attachments uploaded and downloaded in chunks, optionally kept in a directory
 //!    - Mute state synchronization
+//!    - Roles (admin, moderator, member, guest) and the permissions they grant
+//!    - Moderation: kicking, banning, unbanning and server-muting users
+//!    - Idle timeouts for clients that stop sending heartbeats
 //!
-//! 2. **VoiceRelayServer** - Handles UDP packets for:
+//! 2. **`VoiceRelayServer`** - Handles UDP packets for:
 //!    - Voice authentication (token-based)
-//!    - Voice packet forwarding between participants
+//!    - Voice packet decryption, replay protection and re-encrypted forwarding
+//!      between participants of the same channel, except those not allowed to speak
+//!    - Expiring voice sessions whose socket went quiet
 //!
 //! The two servers communicate via an event channel to synchronize user state.
 
@@ -29,5 +37,12 @@ pub mod voice;
 pub use config::*;
 pub use error::ServerError;
 pub use event::Event;
-pub use management::server::ManagementServer;
+pub use management::attachments::AttachmentStore;
+pub use management::auth::{Authenticator, Credentials};
+pub use management::chat_history::ChatHistory;
+pub use management::moderation::Moderation;
+pub use management::role::{PermissionName, RoleName, Roles};
+pub use management::server::{DisconnectHandle, ManagementServer, ShutdownHandle, ShutdownNotice};
+pub use management::tls::load_tls_acceptor;
+pub use management::user::UsernamePolicy;
 pub use voice::server::VoiceRelayServer;
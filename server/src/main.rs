@@ -1,46 +1,206 @@
+mod cli;
 mod config;
 mod error;
 mod event;
 mod management;
 mod voice;
 
-use tracing::error;
-use crate::config::{management_port, voice_port};
-use crate::management::server::ManagementServer;
+use std::io::IsTerminal;
+use std::path::Path;
+use std::time::Duration;
+use clap::Parser;
+use tracing::{error, info, warn};
+use crate::cli::{Cli, Command};
+use crate::config::Config;
+use crate::management::attachments::AttachmentStore;
+use crate::management::auth::{Authenticator, Credentials};
+use crate::management::chat_history::ChatHistory;
+use crate::management::moderation::Moderation;
+use crate::management::server::{ManagementServer, ShutdownNotice};
+use crate::management::tls::load_tls_acceptor;
 use crate::voice::server::VoiceRelayServer;
 
 #[tokio::main]
 async fn main() {
-    #[cfg(debug_assertions)]
-    {
-        use tracing::Level;
-        tracing_subscriber::fmt()
-            .with_max_level(Level::DEBUG)
-            .init();
+    let cli = Cli::parse();
+
+    // `hash-password <file> <username>` adds a credentials file entry and exits
+    if let Some(Command::HashPassword { file, username }) = &cli.command {
+        hash_password(file, username);
+        return;
+    }
+
+    // Logging is configured by the config itself, so config errors go to stderr
+    let mut config = match &cli.config {
+        Some(path) => match Config::load(path) {
+            Ok(config) => config,
+            Err(e) => {
+                eprintln!("{e}");
+                std::process::exit(1);
+            }
+        },
+        None => Config::default(),
+    };
+    cli.apply(&mut config);
+
+    if let Err(e) = config.validate() {
+        eprintln!("{e}");
+        std::process::exit(1);
+    }
+
+    tracing_subscriber::fmt()
+        .with_max_level(config.log_level())
+        .init();
+
+    let credentials = match &config.auth.credentials_file {
+        Some(path) => or_exit(Credentials::load(path), "credentials"),
+        None => Credentials::default(),
+    };
+    let authenticator = Authenticator::new(config.auth.password.clone(), credentials);
+    if authenticator.is_required() {
+        info!("Password authentication enabled");
+    }
+
+    let mut moderation = Moderation::default();
+    if let Some(path) = &config.moderation.bans_file {
+        moderation = or_exit(moderation.with_bans_file(path), "bans");
     }
 
-    #[cfg(not(debug_assertions))]
-    {
-        tracing_subscriber::fmt::init();
+    let mut chat_history = ChatHistory::new(config.chat.history_len);
+    if let Some(path) = &config.chat.history_file {
+        chat_history = or_exit(chat_history.with_history_file(path), "chat history");
     }
 
-    let mgmt_port = management_port();
-    let vc_port = voice_port();
+    let mut attachments = AttachmentStore::new(config.attachments.max_size, config.attachments.capacity);
+    if let Some(dir) = &config.attachments.dir {
+        attachments = or_exit(attachments.with_dir(dir), "attachments");
+    }
+
+    let tls_acceptor = match (&config.tls.cert_file, &config.tls.key_file) {
+        (Some(cert_path), Some(key_path)) => Some(or_exit(load_tls_acceptor(cert_path, key_path), "TLS certificate")),
+        _ => None, // Validation guarantees both or neither
+    };
 
-    let (management_server, events_rx) = ManagementServer::new();
+    let (mut management_server, events_rx) = ManagementServer::new(
+        config.voice_channels.clone(),
+        authenticator,
+        config.limits,
+        config.username_policy,
+    );
+    if let Some(tls_acceptor) = tls_acceptor {
+        management_server = management_server.with_tls(tls_acceptor);
+    }
+    if let Some(motd) = config.motd.clone() {
+        management_server = management_server.with_motd(motd);
+    }
+    management_server = management_server
+        .with_roles(config.roles.roles())
+        .with_moderation(moderation)
+        .with_chat_history(chat_history)
+        .with_attachments(attachments);
+    let shutdown = management_server.shutdown_handle();
+    let disconnect = management_server.disconnect_handle();
+    let management_bind = config.management_bind;
     let management_thread = tokio::spawn(async move {
-        if let Err(e) = management_server.run(mgmt_port).await {
+        if let Err(e) = management_server.run(management_bind).await {
             error!("ManagementServer error: {}", e);
+            std::process::exit(1);
         }
     });
 
-    let mut voice_relay_server = VoiceRelayServer::new(events_rx);
-    let voice_relay_thread = tokio::spawn(async move {
-        if let Err(e) = voice_relay_server.run(vc_port).await {
+    let mut voice_relay_server = VoiceRelayServer::new(events_rx)
+        .with_packet_buffer_size(config.limits.packet_buffer_size)
+        .with_idle_timeout(Duration::from_millis(config.limits.idle_timeout_ms), disconnect);
+    let voice_bind = config.voice_bind;
+    tokio::spawn(async move {
+        if let Err(e) = voice_relay_server.run(voice_bind).await {
             error!("VoiceRelayServer error: {}", e);
+            std::process::exit(1);
         }
     });
 
-    // Wait for both servers
-    let _ = tokio::join!(management_thread, voice_relay_thread);
+    // Both servers run until a signal arrives, errors exit the process
+    shutdown_signal().await;
+    info!("Shutting down");
+
+    shutdown.shutdown(ShutdownNotice {
+        reason: config.shutdown.message.clone(),
+        reconnect_after: Duration::from_millis(config.shutdown.reconnect_after_ms.into()),
+    });
+
+    let drain_timeout = Duration::from_millis(config.shutdown.drain_timeout_ms);
+    if tokio::time::timeout(drain_timeout, management_thread).await.is_err() {
+        warn!("Connections still open after {:?}, exiting anyway", drain_timeout);
+    }
+}
+
+/// Returns the loaded value, or logs the error and exits if `what` could not be loaded
+fn or_exit<T, E: std::fmt::Display>(result: Result<T, E>, what: &str) -> T {
+    result.unwrap_or_else(|e| {
+        error!("Failed to load {}: {}", what, e);
+        std::process::exit(1);
+    })
+}
+
+/// Adds `username` with a password read from stdin to the credentials `file`, exits on failure
+fn hash_password(file: &Path, username: &str) {
+    let password = match read_password() {
+        Ok(password) => password,
+        Err(e) => {
+            eprintln!("Failed to read the password: {e}");
+            std::process::exit(1);
+        }
+    };
+    if let Err(e) = Credentials::add_to_file(file, username, &password) {
+        eprintln!("{e}");
+        std::process::exit(1);
+    }
+    println!("Added {username} to {}", file.display());
+}
+
+/// Reads a password from the first line of stdin, prompting for it on a terminal
+fn read_password() -> std::io::Result<String> {
+    let stdin = std::io::stdin();
+    if stdin.is_terminal() {
+        eprint!("Password: ");
+    }
+
+    let mut line = String::new();
+    stdin.read_line(&mut line)?;
+    let password = line.trim_end_matches(['\r', '\n']);
+    if password.is_empty() {
+        return Err(std::io::Error::new(std::io::ErrorKind::InvalidInput, "the password is empty"));
+    }
+    Ok(password.to_string())
+}
+
+/// Resolves on Ctrl-C (SIGINT) or SIGTERM
+async fn shutdown_signal() {
+    let interrupt = async {
+        if let Err(e) = tokio::signal::ctrl_c().await {
+            error!("Failed to listen for Ctrl-C: {}", e);
+            std::future::pending::<()>().await;
+        }
+    };
+
+    #[cfg(unix)]
+    let terminate = async {
+        match tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate()) {
+            Ok(mut signal) => {
+                signal.recv().await;
+            }
+            Err(e) => {
+                error!("Failed to listen for SIGTERM: {}", e);
+                std::future::pending::<()>().await;
+            }
+        }
+    };
+
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+
+    tokio::select! {
+        () = interrupt => {}
+        () = terminate => {}
+    }
 }
@@ -0,0 +1,388 @@
+use std::collections::BTreeMap;
+use std::fs::{File, OpenOptions};
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, PoisonError};
+use tracing::error;
+use voiceapp_protocol::{Attachment, AttachmentRejectReason, ATTACHMENT_CHUNK_LEN};
+use crate::config::{
+    DEFAULT_ATTACHMENTS_CAPACITY, DEFAULT_MAX_ATTACHMENT_SIZE, MAX_ATTACHMENT_MIME_TYPE_LEN, MAX_ATTACHMENT_NAME_LEN,
+    MAX_UNFINISHED_UPLOADS,
+};
+use crate::error::ServerError;
+
+/// Files attached to chat messages, shared between all user handlers.
+///
+/// Files are uploaded in chunks and can be attached to a message once complete. Only the uploader may download
+/// a file until then, and everyone while a message that was not deleted refers to it. When the store is full
+/// the oldest files make room for new ones, messages referring to them stay but the files can no longer
+/// be downloaded. Files are kept in memory unless a directory is set, which is read and written on blocking
+/// threads without holding the lock.
+pub struct AttachmentStore {
+    state: Mutex<StoreState>,
+    max_size: u64,
+    capacity: u64,
+    dir: Option<PathBuf>, // One file per attachment named by its id, `<id>.part` while uploading
+}
+
+struct StoreState {
+    files: BTreeMap<u64, StoredFile>,
+    last_attachment_id: u64,
+    total_size: u64, // Unfinished uploads count with their full size
+}
+
+struct StoredFile {
+    uploader: u64, // User id, 0 for files loaded from the directory
+    attachment: Attachment,
+    received: u64,
+    messages: usize, // Messages referring to the file, which everyone may download while there are any
+    data: Vec<u8>, // Empty when the store has a directory
+}
+
+impl StoredFile {
+    fn is_complete(&self) -> bool {
+        self.received == self.attachment.size
+    }
+}
+
+impl Default for AttachmentStore {
+    fn default() -> Self {
+        Self::new(DEFAULT_MAX_ATTACHMENT_SIZE, DEFAULT_ATTACHMENTS_CAPACITY)
+    }
+}
+
+impl AttachmentStore {
+    /// Accepts files of up to `max_size` bytes and keeps up to `capacity` bytes in total,
+    /// a capacity of 0 disables attachments.
+    #[must_use]
+    pub fn new(max_size: u64, capacity: u64) -> Self {
+        Self {
+            state: Mutex::new(StoreState { files: BTreeMap::new(), last_attachment_id: 0, total_size: 0 }),
+            max_size,
+            capacity,
+            dir: None,
+        }
+    }
+
+    /// Keeps files in `dir` instead of memory, creating it if missing. Files already in it are served again,
+    /// uploads left unfinished by a previous run are removed.
+    ///
+    /// # Errors
+    /// Returns [`ServerError::Io`] if the directory cannot be created or read.
+    pub fn with_dir(mut self, dir: &Path) -> Result<Self, ServerError> {
+        std::fs::create_dir_all(dir)?;
+
+        let state = self.state.get_mut().unwrap_or_else(PoisonError::into_inner);
+        for entry in std::fs::read_dir(dir)? {
+            let entry = entry?;
+            let file_name = entry.file_name();
+            let Some(file_name) = file_name.to_str() else {
+                continue;
+            };
+
+            if Path::new(file_name).extension().is_some_and(|extension| extension == "part") {
+                std::fs::remove_file(entry.path())?;
+            } else if let Ok(attachment_id) = file_name.parse::<u64>() {
+                // Names and types are kept with the messages in the chat history
+                let size = entry.metadata()?.len();
+                let attachment = Attachment::new(attachment_id, String::new(), String::new(), size);
+                let file = StoredFile { uploader: 0, attachment, received: size, messages: 0, data: Vec::new() };
+                state.files.insert(attachment_id, file);
+                state.last_attachment_id = state.last_attachment_id.max(attachment_id);
+                state.total_size += size;
+            }
+        }
+
+        let removed = Self::make_room(state, self.capacity, Some(dir), 0).unwrap_or_default();
+        Self::delete_files(&removed);
+        self.dir = Some(dir.to_path_buf());
+        Ok(self)
+    }
+
+    /// Starts an upload of `size` bytes by `uploader` and returns the id of the attachment.
+    /// Makes room by removing the oldest files if needed.
+    ///
+    /// # Errors
+    /// Returns the [`AttachmentRejectReason`] if uploads are disabled, the name or type is invalid, the file is
+    /// too large or there is no room left for it.
+    pub async fn start(
+        self: &Arc<Self>,
+        uploader: u64,
+        name: &str,
+        mime_type: &str,
+        size: u64,
+    ) -> Result<u64, AttachmentRejectReason> {
+        let valid_text = |text: &str, max_len: usize| {
+            !text.trim().is_empty() && text.len() <= max_len && !text.chars().any(char::is_control)
+        };
+        if self.capacity == 0 {
+            return Err(AttachmentRejectReason::NotPermitted);
+        }
+        if !valid_text(name, MAX_ATTACHMENT_NAME_LEN)
+            || name.contains(['/', '\\'])
+            || !valid_text(mime_type, MAX_ATTACHMENT_MIME_TYPE_LEN)
+        {
+            return Err(AttachmentRejectReason::InvalidName);
+        }
+        if size > self.max_size {
+            return Err(AttachmentRejectReason::TooLarge);
+        }
+
+        let (attachment_id, removed) = {
+            let mut state = self.state.lock().unwrap_or_else(PoisonError::into_inner);
+            let unfinished =
+                state.files.values().filter(|file| file.uploader == uploader && !file.is_complete()).count();
+            if unfinished >= MAX_UNFINISHED_UPLOADS {
+                return Err(AttachmentRejectReason::StorageFull);
+            }
+            let Some(removed) = Self::make_room(&mut state, self.capacity, self.dir.as_deref(), size) else {
+                return Err(AttachmentRejectReason::StorageFull);
+            };
+
+            let attachment_id = state.last_attachment_id + 1;
+            state.last_attachment_id = attachment_id;
+            state.total_size += size;
+            let attachment = Attachment::new(attachment_id, name.to_string(), mime_type.to_string(), size);
+            let file = StoredFile { uploader, attachment, received: 0, messages: 0, data: Vec::new() };
+            state.files.insert(attachment_id, file);
+            (attachment_id, removed)
+        };
+
+        let Some(dir) = self.dir.clone() else {
+            return Ok(attachment_id);
+        };
+
+        // Empty files are complete right away and skip the part file
+        let path = if size == 0 { Self::file_path(&dir, attachment_id) } else { Self::part_path(&dir, attachment_id) };
+        Self::delete_later(removed);
+        let created = Self::blocking(move || File::create(path).map(drop)).await;
+        if let Err(e) = created {
+            error!("Failed to create attachment {} in {}: {}", attachment_id, dir.display(), e);
+            self.forget(attachment_id);
+            return Err(AttachmentRejectReason::StorageFull);
+        }
+
+        Ok(attachment_id)
+    }
+
+    /// Appends a chunk to an unfinished upload of `uploader`, returns true if it was written.
+    /// Chunks must follow each other, `offset` is the number of bytes received so far.
+    pub async fn write_chunk(self: &Arc<Self>, uploader: u64, attachment_id: u64, offset: u64, data: Vec<u8>) -> bool {
+        let len = data.len() as u64;
+        let completes = {
+            let mut state = self.state.lock().unwrap_or_else(PoisonError::into_inner);
+            let Some(file) = state.files.get_mut(&attachment_id).filter(|file| file.uploader == uploader) else {
+                return false;
+            };
+
+            if data.is_empty() || data.len() > ATTACHMENT_CHUNK_LEN || offset != file.received {
+                return false;
+            }
+            if file.attachment.size - file.received < len {
+                return false;
+            }
+
+            if self.dir.is_none() {
+                file.data.extend_from_slice(&data);
+                file.received += len;
+                return true;
+            }
+            file.received + len == file.attachment.size
+        };
+
+        // Written without holding the lock, the chunks of an upload come one after another from its uploader
+        let Some(dir) = self.dir.clone() else {
+            return false;
+        };
+        let (part_path, file_path) = (Self::part_path(&dir, attachment_id), Self::file_path(&dir, attachment_id));
+        let written_path = if completes { file_path.clone() } else { part_path.clone() };
+        let written = Self::blocking(move || {
+            OpenOptions::new().append(true).open(&part_path).and_then(|mut part| part.write_all(&data))?;
+            if completes {
+                std::fs::rename(&part_path, &file_path)?;
+            }
+            Ok(())
+        })
+        .await;
+        if let Err(e) = written {
+            error!("Failed to write attachment {} in {}: {}", attachment_id, dir.display(), e);
+            let removed = {
+                let mut state = self.state.lock().unwrap_or_else(PoisonError::into_inner);
+                Self::remove(&mut state, Some(&dir), attachment_id)
+            };
+            Self::delete_later(removed.into_iter().collect());
+            return false;
+        }
+
+        let mut state = self.state.lock().unwrap_or_else(PoisonError::into_inner);
+        let Some(file) = state.files.get_mut(&attachment_id) else {
+            // Discarded while it was written
+            Self::delete_later(vec![written_path]);
+            return false;
+        };
+        file.received += len;
+        true
+    }
+
+    /// Returns the attachment if `uploader` uploaded all of it.
+    pub fn complete(&self, uploader: u64, attachment_id: u64) -> Option<Attachment> {
+        let state = self.state.lock().unwrap_or_else(PoisonError::into_inner);
+        state
+            .files
+            .get(&attachment_id)
+            .filter(|file| file.uploader == uploader && file.is_complete())
+            .map(|file| file.attachment.clone())
+    }
+
+    /// Counts a sent message referring to the files, which makes them downloadable for everyone.
+    pub fn attach(&self, attachment_ids: &[u64]) {
+        let mut state = self.state.lock().unwrap_or_else(PoisonError::into_inner);
+        for attachment_id in attachment_ids {
+            if let Some(file) = state.files.get_mut(attachment_id) {
+                file.messages += 1;
+            }
+        }
+    }
+
+    /// Counts the messages referring to every file again, `attachment_ids` holding an id once for each of them.
+    pub fn recount(&self, attachment_ids: &[u64]) {
+        let mut state = self.state.lock().unwrap_or_else(PoisonError::into_inner);
+        for file in state.files.values_mut() {
+            file.messages = 0;
+        }
+        for attachment_id in attachment_ids {
+            if let Some(file) = state.files.get_mut(attachment_id) {
+                file.messages += 1;
+            }
+        }
+    }
+
+    /// Forgets a deleted message referring to the files, once none is left only their uploader may download them.
+    pub fn detach(&self, attachment_ids: &[u64]) {
+        let mut state = self.state.lock().unwrap_or_else(PoisonError::into_inner);
+        for attachment_id in attachment_ids {
+            if let Some(file) = state.files.get_mut(attachment_id) {
+                file.messages = file.messages.saturating_sub(1);
+            }
+        }
+    }
+
+    /// Returns up to [`ATTACHMENT_CHUNK_LEN`] bytes of a complete file starting at `offset`,
+    /// empty at the end of the file. Returns `None` if the file is unknown, `user_id` may not download it
+    /// or `offset` is past its end.
+    pub async fn read_chunk(self: &Arc<Self>, user_id: u64, attachment_id: u64, offset: u64) -> Option<Vec<u8>> {
+        let len = {
+            let state = self.state.lock().unwrap_or_else(PoisonError::into_inner);
+            let file = state
+                .files
+                .get(&attachment_id)
+                .filter(|file| file.is_complete() && (file.uploader == user_id || file.messages > 0))?;
+            let remaining = file.attachment.size.checked_sub(offset)?;
+            let len = usize::try_from(remaining).map_or(ATTACHMENT_CHUNK_LEN, |len| len.min(ATTACHMENT_CHUNK_LEN));
+
+            if self.dir.is_none() {
+                let start = usize::try_from(offset).ok()?;
+                return Some(file.data[start..start + len].to_vec());
+            }
+            len
+        };
+
+        // Read without holding the lock, a file removed meanwhile is no longer found
+        let dir = self.dir.clone()?;
+        let path = Self::file_path(&dir, attachment_id);
+        let result = Self::blocking(move || {
+            let mut data = vec![0; len];
+            let mut stored = File::open(path)?;
+            stored.seek(SeekFrom::Start(offset))?;
+            stored.read_exact(&mut data)?;
+            Ok(data)
+        })
+        .await;
+        match result {
+            Ok(data) => Some(data),
+            Err(e) => {
+                error!("Failed to read attachment {} in {}: {}", attachment_id, dir.display(), e);
+                None
+            }
+        }
+    }
+
+    /// Removes the unfinished uploads of `uploader`, called when its connection closes.
+    /// Their files are deleted on a blocking thread, so this must be called within a Tokio runtime.
+    pub fn discard_unfinished(&self, uploader: u64) {
+        let removed: Vec<PathBuf> = {
+            let mut state = self.state.lock().unwrap_or_else(PoisonError::into_inner);
+            let unfinished: Vec<u64> = state
+                .files
+                .iter()
+                .filter(|(_, file)| file.uploader == uploader && !file.is_complete())
+                .map(|(attachment_id, _)| *attachment_id)
+                .collect();
+            unfinished
+                .into_iter()
+                .filter_map(|attachment_id| Self::remove(&mut state, self.dir.as_deref(), attachment_id))
+                .collect()
+        };
+        Self::delete_later(removed);
+    }
+
+    /// Forgets an upload whose file could not be created
+    fn forget(&self, attachment_id: u64) {
+        let mut state = self.state.lock().unwrap_or_else(PoisonError::into_inner);
+        Self::remove(&mut state, None, attachment_id);
+    }
+
+    /// Removes the oldest complete files until `size` more bytes fit and returns the paths to delete,
+    /// `None` without removing anything if they never will
+    fn make_room(state: &mut StoreState, capacity: u64, dir: Option<&Path>, size: u64) -> Option<Vec<PathBuf>> {
+        let complete_size: u64 =
+            state.files.values().filter(|file| file.is_complete()).map(|file| file.attachment.size).sum();
+        if state.total_size - complete_size + size > capacity {
+            return None;
+        }
+
+        let mut removed = Vec::new();
+        while state.total_size + size > capacity {
+            let oldest = state.files.iter().find(|(_, file)| file.is_complete()).map(|(id, _)| *id)?;
+            removed.extend(Self::remove(state, dir, oldest));
+        }
+        Some(removed)
+    }
+
+    /// Removes a file from the store, returns the path to delete if it is kept in `dir`
+    fn remove(state: &mut StoreState, dir: Option<&Path>, attachment_id: u64) -> Option<PathBuf> {
+        let file = state.files.remove(&attachment_id)?;
+        state.total_size -= file.attachment.size;
+
+        let dir = dir?;
+        Some(if file.is_complete() { Self::file_path(dir, attachment_id) } else { Self::part_path(dir, attachment_id) })
+    }
+
+    fn delete_files(paths: &[PathBuf]) {
+        for path in paths {
+            if let Err(e) = std::fs::remove_file(path) {
+                error!("Failed to remove attachment {}: {}", path.display(), e);
+            }
+        }
+    }
+
+    /// Deletes files on a blocking thread without waiting for it
+    fn delete_later(paths: Vec<PathBuf>) {
+        if !paths.is_empty() {
+            tokio::task::spawn_blocking(move || Self::delete_files(&paths));
+        }
+    }
+
+    /// Runs file operations on a blocking thread, a panic there counts as a failed operation
+    async fn blocking<T: Send + 'static>(operation: impl FnOnce() -> io::Result<T> + Send + 'static) -> io::Result<T> {
+        tokio::task::spawn_blocking(operation).await.unwrap_or_else(|e| Err(io::Error::other(e)))
+    }
+
+    fn file_path(dir: &Path, attachment_id: u64) -> PathBuf {
+        dir.join(attachment_id.to_string())
+    }
+
+    fn part_path(dir: &Path, attachment_id: u64) -> PathBuf {
+        dir.join(format!("{attachment_id}.part"))
+    }
+}
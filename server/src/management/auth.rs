@@ -0,0 +1,230 @@
+use dashmap::DashMap;
+use std::collections::HashMap;
+use std::fmt::Write as _;
+use std::fs::OpenOptions;
+use std::io::{ErrorKind, Write};
+use std::path::Path;
+use std::sync::Arc;
+use tokio::sync::Semaphore;
+use voiceapp_protocol::auth::{derive_key, stored_key, verify_proof, AUTH_CHALLENGE_LEN, AUTH_PROOF_LEN, AUTH_SALT_LEN};
+use crate::error::ServerError;
+
+/// Verifies login proofs against an optional shared server password and per-user credentials.
+///
+/// Users listed in the credentials file must use their own password, everyone else
+/// the shared one. With neither configured the server is open. Usernames are compared
+/// ignoring ASCII case, like roles and bans, so the shared password never works for a
+/// listed user whatever the case of the name.
+pub struct Authenticator {
+    password: Option<String>,
+    credentials: Credentials,
+    shared_keys: DashMap<String, [u8; AUTH_PROOF_LEN]>, // Lowercase username -> stored key of the shared password
+    derivations: Semaphore, // Each derivation takes a blocking thread and tens of MiB
+}
+
+/// Most keys derived at the same time, logins waiting for a derivation queue up.
+const MAX_CONCURRENT_DERIVATIONS: usize = 4;
+
+/// Stored keys of users with their own password, and the salt all keys are derived with,
+/// sent to clients in `ServerHello`.
+///
+/// Stored keys verify login proofs but cannot compute them, a leaked credentials file does not let anyone log in.
+pub struct Credentials {
+    salt: [u8; AUTH_SALT_LEN],
+    stored_keys: HashMap<String, [u8; AUTH_PROOF_LEN]>, // Lowercase username -> stored key
+}
+
+impl Default for Credentials {
+    /// Nobody with a password of its own, and a new random salt.
+    fn default() -> Self {
+        Self { salt: rand::random(), stored_keys: HashMap::new() }
+    }
+}
+
+impl Credentials {
+    /// Loads a credentials file with a `salt <hex>` line and one `username:hex_stored_key` entry per line,
+    /// `#` starts a comment and later entries replace earlier ones. Entries are added with
+    /// `voiceapp-server hash-password <file> <username>`, see [`Credentials::add_to_file`].
+    ///
+    /// # Errors
+    ///
+    /// Fails if the file cannot be read, has a malformed line, or has entries but no salt.
+    pub fn load(path: &Path) -> Result<Self, ServerError> {
+        let contents = std::fs::read_to_string(path)?;
+        let invalid = |index: usize, expected: &str| {
+            ServerError::InvalidCredentialsFile(format!("{}: line {} is not `{expected}`", path.display(), index + 1))
+        };
+
+        let mut salt = None;
+        let mut stored_keys = HashMap::new();
+        for (index, line) in contents.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            if let Some(hex) = line.strip_prefix("salt ") {
+                salt = Some(decode_hex(hex.trim()).ok_or_else(|| invalid(index, "salt <hex>"))?);
+                continue;
+            }
+
+            let (username, key) = line
+                .rsplit_once(':')
+                .and_then(|(username, hex)| Some((username.trim(), decode_hex(hex.trim())?)))
+                .filter(|(username, _)| !username.is_empty())
+                .ok_or_else(|| invalid(index, "username:hex_stored_key"))?;
+            stored_keys.insert(username.to_ascii_lowercase(), key);
+        }
+
+        match salt {
+            Some(salt) => Ok(Self { salt, stored_keys }),
+            None if stored_keys.is_empty() => Ok(Self::default()),
+            None => Err(ServerError::InvalidCredentialsFile(format!("{}: missing `salt <hex>` line", path.display()))),
+        }
+    }
+
+    /// Gives `username` its own `password`.
+    #[must_use]
+    pub fn with_user(mut self, username: &str, password: &str) -> Self {
+        self.stored_keys.insert(username.to_ascii_lowercase(), self.stored_key(username, password));
+        self
+    }
+
+    /// Appends an entry for `username` to the credentials file at `path`, creating it with a new salt if missing.
+    ///
+    /// # Errors
+    ///
+    /// Fails if the existing file is invalid or the file cannot be written.
+    pub fn add_to_file(path: &Path, username: &str, password: &str) -> Result<(), ServerError> {
+        let (credentials, mut lines) = match Self::load(path) {
+            Ok(credentials) => (credentials, String::new()),
+            Err(ServerError::Io(e)) if e.kind() == ErrorKind::NotFound => {
+                let credentials = Self::default();
+                let salt_line = format!("salt {}\n", encode_hex(&credentials.salt));
+                (credentials, salt_line)
+            }
+            Err(e) => return Err(e),
+        };
+
+        let credentials = credentials.with_user(username, password);
+        let stored = credentials.stored_keys[&username.to_ascii_lowercase()];
+        let _ = writeln!(lines, "{username}:{}", encode_hex(&stored));
+        let mut file = OpenOptions::new().create(true).append(true).open(path)?;
+        file.write_all(lines.as_bytes())?;
+        Ok(())
+    }
+
+    fn stored_key(&self, username: &str, password: &str) -> [u8; AUTH_PROOF_LEN] {
+        stored_key(&derive_key(username, password, &self.salt))
+    }
+}
+
+impl Default for Authenticator {
+    /// Anyone can log in.
+    fn default() -> Self {
+        Self::new(None, Credentials::default())
+    }
+}
+
+impl Authenticator {
+    #[must_use]
+    pub fn new(password: Option<String>, credentials: Credentials) -> Self {
+        Self {
+            password,
+            credentials,
+            shared_keys: DashMap::new(),
+            derivations: Semaphore::new(MAX_CONCURRENT_DERIVATIONS),
+        }
+    }
+
+    /// Returns the salt clients derive their keys with.
+    #[must_use]
+    pub fn salt(&self) -> [u8; AUTH_SALT_LEN] {
+        self.credentials.salt
+    }
+
+    /// Returns true if `username` has its own password in the credentials file.
+    #[must_use]
+    pub fn has_credentials(&self, username: &str) -> bool {
+        self.credentials.stored_keys.contains_key(&username.to_ascii_lowercase())
+    }
+
+    /// Returns true if logins must carry a valid proof.
+    #[must_use]
+    pub fn is_required(&self) -> bool {
+        self.password.is_some() || !self.credentials.stored_keys.is_empty()
+    }
+
+    /// Checks the login proof sent in answer to `challenge`.
+    ///
+    /// The first check of a user against the shared password derives its key, which is slow on purpose.
+    /// That runs on a blocking thread, a few at a time, and the stored key is kept for later logins.
+    pub async fn verify(
+        self: &Arc<Self>,
+        username: &str,
+        challenge: &[u8; AUTH_CHALLENGE_LEN],
+        proof: Option<&[u8; AUTH_PROOF_LEN]>,
+    ) -> bool {
+        if !self.is_required() {
+            return true;
+        }
+
+        let Some(proof) = proof else {
+            return false;
+        };
+
+        match self.stored_key_of(username).await {
+            Some(stored) => verify_proof(&stored, challenge, proof),
+            None => false,
+        }
+    }
+
+    /// Returns the stored key `username` logs in with, `None` if it cannot log in
+    async fn stored_key_of(self: &Arc<Self>, username: &str) -> Option<[u8; AUTH_PROOF_LEN]> {
+        let key = username.to_ascii_lowercase();
+        if let Some(stored) = self.credentials.stored_keys.get(&key) {
+            return Some(*stored);
+        }
+        self.password.as_ref()?;
+        if let Some(stored) = self.shared_keys.get(&key) {
+            return Some(*stored);
+        }
+
+        let _permit = self.derivations.acquire().await.ok()?;
+        // Another login of the same user may have derived it while this one waited
+        if let Some(stored) = self.shared_keys.get(&key) {
+            return Some(*stored);
+        }
+
+        let authenticator = Arc::clone(self);
+        let username = username.to_string();
+        let stored = tokio::task::spawn_blocking(move || {
+            let password = authenticator.password.as_deref().unwrap_or_default();
+            authenticator.credentials.stored_key(&username, password)
+        })
+        .await
+        .ok()?;
+        self.shared_keys.insert(key, stored);
+        Some(stored)
+    }
+}
+
+fn encode_hex(bytes: &[u8]) -> String {
+    let mut hex = String::with_capacity(bytes.len() * 2);
+    for byte in bytes {
+        let _ = write!(hex, "{byte:02x}");
+    }
+    hex
+}
+
+fn decode_hex<const N: usize>(hex: &str) -> Option<[u8; N]> {
+    if hex.len() != N * 2 || !hex.is_ascii() {
+        return None;
+    }
+
+    let mut bytes = [0u8; N];
+    for (i, byte) in bytes.iter_mut().enumerate() {
+        *byte = u8::from_str_radix(&hex[i * 2..i * 2 + 2], 16).ok()?;
+    }
+    Some(bytes)
+}
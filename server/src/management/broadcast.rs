@@ -1,37 +1,90 @@
 use std::net::SocketAddr;
-use voiceapp_protocol::Packet;
+use tracing::error;
+use voiceapp_protocol::{Capabilities, Framing, Packet};
 
 /// Broadcast message sent to all connected clients.
 #[derive(Clone, Debug)]
 pub struct BroadcastMessage {
     exclude: Option<SocketAddr>,
-    data: Vec<u8>,
+    disconnect: Option<u64>, // User whose connection is closed, nothing is sent
+    required: Capabilities, // Clients without these do not understand the packet
+    data: Vec<u8>, // Encoded with standard framing, empty if it does not fit
+    extended_data: Vec<u8>, // Encoded with extended framing
 }
 
 impl BroadcastMessage {
     /// Create a broadcast message that will be sent to all clients.
+    #[must_use]
     pub fn for_all(packet: &Packet) -> Self {
         Self {
             exclude: None,
-            data: packet.encode(),
+            disconnect: None,
+            required: Capabilities::NONE,
+            data: Self::encode(packet, Framing::Standard),
+            extended_data: Self::encode(packet, Framing::Extended),
         }
     }
 
     /// Create a broadcast message that excludes the sender.
+    #[must_use]
     pub fn excluding(sender: SocketAddr, packet: &Packet) -> Self {
         Self {
             exclude: Some(sender),
-            data: packet.encode(),
+            disconnect: None,
+            required: Capabilities::NONE,
+            data: Self::encode(packet, Framing::Standard),
+            extended_data: Self::encode(packet, Framing::Extended),
         }
     }
 
-    /// Check if this message should be sent to the given address.
-    pub fn should_send_to(&self, addr: SocketAddr) -> bool {
-        self.exclude != Some(addr)
+    /// Create a message that makes the connection of the given user disconnect.
+    #[must_use]
+    pub fn disconnecting(user_id: u64) -> Self {
+        Self {
+            exclude: None,
+            disconnect: Some(user_id),
+            required: Capabilities::NONE,
+            data: Vec::new(),
+            extended_data: Vec::new(),
+        }
+    }
+
+    /// Only send the message to clients that negotiated `capability`.
+    #[must_use]
+    pub fn requiring(mut self, capability: Capabilities) -> Self {
+        self.required = capability;
+        self
+    }
+
+    /// Check if this message should be sent to the given address with the given negotiated capabilities and framing.
+    #[must_use]
+    pub fn should_send_to(&self, addr: SocketAddr, capabilities: Capabilities, framing: Framing) -> bool {
+        self.disconnect.is_none()
+            && !self.data(framing).is_empty()
+            && self.exclude != Some(addr)
+            && capabilities.contains(self.required)
     }
 
-    /// Get the encoded packet data.
-    pub fn data(&self) -> &[u8] {
-        &self.data
+    /// Check if the connection of the given user should be closed.
+    #[must_use]
+    pub fn disconnects(&self, user_id: u64) -> bool {
+        self.disconnect == Some(user_id)
     }
-}
\ No newline at end of file
+
+    /// Packets that cannot be encoded are logged and sent to nobody using that framing.
+    fn encode(packet: &Packet, framing: Framing) -> Vec<u8> {
+        packet.encode_with(framing).unwrap_or_else(|e| {
+            error!("Dropping broadcast that cannot be encoded with {:?} framing: {}", framing, e);
+            Vec::new()
+        })
+    }
+
+    /// Get the packet data encoded with the given framing.
+    #[must_use]
+    pub fn data(&self, framing: Framing) -> &[u8] {
+        match framing {
+            Framing::Standard => &self.data,
+            Framing::Extended => &self.extended_data,
+        }
+    }
+}
@@ -0,0 +1,68 @@
+use dashmap::mapref::entry::Entry;
+use dashmap::DashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use voiceapp_protocol::ChannelInfo;
+
+/// Represents a named voice channel
+#[derive(Clone, Debug)]
+pub struct Channel {
+    pub id: u64,
+    pub name: String,
+}
+
+impl Channel {
+    #[must_use]
+    pub fn info(&self) -> ChannelInfo {
+        ChannelInfo::new(self.id, self.name.clone())
+    }
+}
+
+/// Registry of voice channels shared between all user handlers
+pub struct Channels {
+    by_id: DashMap<u64, Channel>,
+    by_name: DashMap<String, u64>, // Lowercase name -> channel id
+    next_channel_id: AtomicU64,
+}
+
+impl Channels {
+    /// Creates a registry pre-populated with the given channel names.
+    pub fn new<I, S>(names: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        let channels = Self { by_id: DashMap::new(), by_name: DashMap::new(), next_channel_id: AtomicU64::new(1) };
+
+        for name in names {
+            channels.create(name.into());
+        }
+
+        channels
+    }
+
+    /// Creates a new channel, returns `None` if a channel with the same name (case-insensitive) exists.
+    pub fn create(&self, name: String) -> Option<Channel> {
+        // Claiming the name through its entry keeps concurrent creations of the same name from both succeeding
+        let Entry::Vacant(entry) = self.by_name.entry(name.to_ascii_lowercase()) else {
+            return None;
+        };
+
+        let id = self.next_channel_id.fetch_add(1, Ordering::Relaxed);
+        let channel = Channel { id, name };
+        self.by_id.insert(id, channel.clone());
+        entry.insert(id);
+
+        Some(channel)
+    }
+
+    pub fn contains(&self, id: u64) -> bool {
+        self.by_id.contains_key(&id)
+    }
+
+    /// Returns all channels ordered by id.
+    pub fn list(&self) -> Vec<ChannelInfo> {
+        let mut channels: Vec<ChannelInfo> = self.by_id.iter().map(|e| e.value().info()).collect();
+        channels.sort_by_key(|c| c.channel_id);
+        channels
+    }
+}
@@ -0,0 +1,491 @@
+use std::collections::VecDeque;
+use std::fs::OpenOptions;
+use std::io::{ErrorKind, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Mutex, MutexGuard, PoisonError};
+use std::time::{SystemTime, UNIX_EPOCH};
+use tracing::error;
+use voiceapp_protocol::{Attachment, ChatHistoryMessage, Framing, Reaction, Role};
+use crate::config::{DEFAULT_CHAT_HISTORY_LEN, MAX_REACTIONS_PER_MESSAGE};
+use crate::error::ServerError;
+
+/// Bytes of a `ChatHistoryResponse` payload taken by its other fields, besides the message count.
+const PAGE_OVERHEAD: usize = 9;
+
+/// Most bytes of messages in one `ChatHistoryResponse` with standard framing, every kept message fits in one.
+const MAX_PAGE_BYTES: usize = u16::MAX as usize - PAGE_OVERHEAD - Framing::Standard.len_prefix_size();
+
+/// Chat messages sent on this server, oldest first, shared between all user handlers.
+///
+/// Only the newest messages are kept in memory and served, the history file keeps all of them.
+/// Every message gets a unique id and timestamp, both increasing, so clients can page by timestamp
+/// without skipping any and refer to messages by id. Replies, edits, deletes and reactions only work on kept messages.
+/// Changes block until they are appended to the history file, reading the history never waits for that.
+pub struct ChatHistory {
+    state: Mutex<HistoryState>,
+    capacity: usize,
+    history_file: Option<PathBuf>, // New messages, edits and deletes are appended here
+    appending: Mutex<()>, // Taken before the state lock is released, so changes are appended in order
+}
+
+/// A kept message and who sent it, edits and deletes are checked against the author and not its name,
+/// which another user may take once the author left.
+#[derive(Clone, Debug)]
+pub struct StoredMessage {
+    pub message: ChatHistoryMessage,
+    pub author_id: Option<u64>, // `None` if loaded from the history file, user ids are not kept across restarts
+    pub author_role: Role, // Role of the author when it sent the message
+}
+
+struct HistoryState {
+    messages: VecDeque<StoredMessage>,
+    last_message_id: u64,
+    last_timestamp: u64,
+}
+
+impl HistoryState {
+    fn find(&self, message_id: u64) -> Option<usize> {
+        self.messages.binary_search_by_key(&message_id, |m| m.message.message_id).ok()
+    }
+
+    fn push(&mut self, message: StoredMessage, capacity: usize) {
+        if self.messages.len() == capacity {
+            self.messages.pop_front();
+        }
+        self.messages.push_back(message);
+    }
+}
+
+/// One line of the history file
+enum Record {
+    Message(StoredMessage),
+    Attachment { message_id: u64, attachment: Attachment },
+    Edit { message_id: u64, message: String },
+    Delete { message_id: u64 },
+    Reaction { message_id: u64, username: String, emoji: String, reacted: bool },
+}
+
+impl Default for ChatHistory {
+    fn default() -> Self {
+        Self::new(DEFAULT_CHAT_HISTORY_LEN)
+    }
+}
+
+impl ChatHistory {
+    /// Keeps the newest `capacity` messages, 0 disables the history.
+    #[must_use]
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            state: Mutex::new(HistoryState { messages: VecDeque::new(), last_message_id: 0, last_timestamp: 0 }),
+            capacity,
+            history_file: None,
+            appending: Mutex::new(()),
+        }
+    }
+
+    /// Loads messages from `path` and appends new ones, edits, deletes and reactions to it, a missing file counts
+    /// as empty. The file has one entry per line:
+    /// `message\t<id>\t<timestamp>\t<reply to id or ->\t<author role>\t<username>\t<message>`, followed by
+    /// `attachment\t<message id>\t<attachment id>\t<size>\t<mime type>\t<name>` for each attached file,
+    /// `edit\t<id>\t<message>`, `delete\t<id>` or `react\t<id>\t<+ or ->\t<username>\t<emoji>`.
+    /// Backslashes, tabs and line breaks in usernames, messages and file names are escaped with a backslash.
+    ///
+    /// # Errors
+    ///
+    /// Fails if the file cannot be read or has a malformed line.
+    pub fn with_history_file(mut self, path: &Path) -> Result<Self, ServerError> {
+        let contents = match std::fs::read_to_string(path) {
+            Ok(contents) => contents,
+            Err(e) if e.kind() == ErrorKind::NotFound => String::new(),
+            Err(e) => return Err(e.into()),
+        };
+
+        {
+            let state = self.state.get_mut().unwrap_or_else(PoisonError::into_inner);
+            for (index, line) in contents.lines().enumerate() {
+                if line.is_empty() {
+                    continue;
+                }
+
+                let Some(record) = Self::parse_line(line) else {
+                    return Err(ServerError::InvalidHistoryFile(format!(
+                        "{}: line {} is not a `message`, `attachment`, `edit`, `delete` or `react` entry",
+                        path.display(),
+                        index + 1
+                    )));
+                };
+
+                match record {
+                    Record::Message(message) => {
+                        state.last_message_id = state.last_message_id.max(message.message.message_id);
+                        state.last_timestamp = state.last_timestamp.max(message.message.timestamp);
+                        if self.capacity > 0 {
+                            state.push(message, self.capacity);
+                        }
+                    }
+                    Record::Attachment { message_id, attachment } => {
+                        if let Some(index) = state.find(message_id) {
+                            state.messages[index].message.attachments.push(attachment);
+                        }
+                    }
+                    Record::Edit { message_id, message } => {
+                        if let Some(index) = state.find(message_id) {
+                            state.messages[index].message.message = message;
+                            state.messages[index].message.edited = true;
+                        }
+                    }
+                    Record::Delete { message_id } => {
+                        if let Some(index) = state.find(message_id) {
+                            state.messages.remove(index);
+                        }
+                    }
+                    Record::Reaction { message_id, username, emoji, reacted } => {
+                        if let Some(index) = state.find(message_id) {
+                            Self::set_reaction(&mut state.messages[index].message, &username, &emoji, reacted);
+                        }
+                    }
+                }
+            }
+        }
+
+        self.history_file = Some(path.to_path_buf());
+        Ok(self)
+    }
+
+    /// Stores a message sent by the user with `author_id` and `author_role`, and returns it with its id and timestamp,
+    /// later than those of any message stored before. Returns `None` if `reply_to` is not a kept message.
+    /// Messages too large to fit in a `ChatHistoryResponse` are not stored.
+    pub fn push(
+        &self,
+        author_id: u64,
+        author_role: Role,
+        username: &str,
+        message: &str,
+        reply_to: Option<u64>,
+        attachments: Vec<Attachment>,
+    ) -> Option<ChatHistoryMessage> {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |d| u64::try_from(d.as_millis()).unwrap_or(u64::MAX));
+
+        let mut state = self.state.lock().unwrap_or_else(PoisonError::into_inner);
+        if reply_to.is_some_and(|id| state.find(id).is_none()) {
+            return None;
+        }
+
+        let timestamp = now.max(state.last_timestamp + 1);
+        state.last_timestamp = timestamp;
+        state.last_message_id += 1;
+
+        let mut message =
+            ChatHistoryMessage::new(state.last_message_id, timestamp, username.to_string(), message.to_string());
+        message.reply_to = reply_to;
+        message.attachments = attachments;
+        if self.capacity == 0 || Self::encoded_len(&message, Framing::Standard) > MAX_PAGE_BYTES {
+            return Some(message);
+        }
+
+        let stored = StoredMessage { message, author_id: Some(author_id), author_role };
+        state.push(stored.clone(), self.capacity);
+        let message = stored.message.clone();
+        self.append_unlocked(state, &Record::Message(stored));
+
+        Some(message)
+    }
+
+    /// Replaces the text of a kept message if `allowed` accepts it, returns true if it was replaced.
+    /// Edits that would no longer fit in a `ChatHistoryResponse` are refused.
+    pub fn edit(&self, message_id: u64, message: &str, allowed: impl FnOnce(&StoredMessage) -> bool) -> bool {
+        let mut state = self.state.lock().unwrap_or_else(PoisonError::into_inner);
+        let Some(index) = state.find(message_id) else {
+            return false;
+        };
+
+        let mut edited = state.messages[index].message.clone().with_edited(true);
+        edited.message = message.to_string();
+        if !allowed(&state.messages[index]) || Self::encoded_len(&edited, Framing::Standard) > MAX_PAGE_BYTES {
+            return false;
+        }
+
+        let record = Record::Edit { message_id, message: edited.message.clone() };
+        state.messages[index].message = edited;
+        self.append_unlocked(state, &record);
+        true
+    }
+
+    /// Removes a kept message if `allowed` accepts it, returns the removed message.
+    pub fn delete(&self, message_id: u64, allowed: impl FnOnce(&StoredMessage) -> bool) -> Option<StoredMessage> {
+        let mut state = self.state.lock().unwrap_or_else(PoisonError::into_inner);
+        let index = state.find(message_id).filter(|&index| allowed(&state.messages[index]))?;
+
+        let deleted = state.messages.remove(index);
+        self.append_unlocked(state, &Record::Delete { message_id });
+        deleted
+    }
+
+    /// Adds (or removes) the reaction of `username` to a kept message, returns true if it changed.
+    /// Reactions that would no longer fit in a `ChatHistoryResponse` are refused.
+    pub fn react(&self, message_id: u64, username: &str, emoji: &str, reacted: bool) -> bool {
+        let mut state = self.state.lock().unwrap_or_else(PoisonError::into_inner);
+        let Some(index) = state.find(message_id) else {
+            return false;
+        };
+
+        let mut changed = state.messages[index].message.clone();
+        if !Self::set_reaction(&mut changed, username, emoji, reacted)
+            || Self::encoded_len(&changed, Framing::Standard) > MAX_PAGE_BYTES
+        {
+            return false;
+        }
+
+        state.messages[index].message = changed;
+        let record = Record::Reaction { message_id, username: username.to_string(), emoji: emoji.to_string(), reacted };
+        self.append_unlocked(state, &record);
+        true
+    }
+
+    /// Returns the ids of the files attached to kept messages, once for every message referring to them.
+    pub fn attachment_ids(&self) -> Vec<u64> {
+        let state = self.state.lock().unwrap_or_else(PoisonError::into_inner);
+        state.messages.iter().flat_map(|m| &m.message.attachments).map(|attachment| attachment.attachment_id).collect()
+    }
+
+    /// Returns true if the message with `message_id` is kept.
+    pub fn contains(&self, message_id: u64) -> bool {
+        self.state.lock().unwrap_or_else(PoisonError::into_inner).find(message_id).is_some()
+    }
+
+    /// Returns up to `limit` messages sent after `after` and before `before`, oldest first,
+    /// and whether more messages match. Without bounds or with `before` set the newest messages are
+    /// returned, with only `after` set the oldest ones. The page fits in a packet with the given framing.
+    pub fn page(
+        &self,
+        before: Option<u64>,
+        after: Option<u64>,
+        limit: usize,
+        framing: Framing,
+    ) -> (Vec<ChatHistoryMessage>, bool) {
+        let state = self.state.lock().unwrap_or_else(PoisonError::into_inner);
+        let messages = &state.messages;
+        let start = after.map_or(0, |after| messages.partition_point(|m| m.message.timestamp <= after));
+        let end = before.map_or(messages.len(), |before| messages.partition_point(|m| m.message.timestamp < before));
+        if start >= end {
+            return (Vec::new(), false);
+        }
+
+        let matching = messages.range(start..end).map(|m| &m.message);
+        let oldest_first = after.is_some() && before.is_none();
+        let mut page = Vec::new();
+        let mut page_bytes = 0;
+        let max_page_bytes = framing.max_payload_len() - PAGE_OVERHEAD - framing.len_prefix_size();
+
+        let candidates: Box<dyn Iterator<Item = &ChatHistoryMessage>> =
+            if oldest_first { Box::new(matching) } else { Box::new(matching.rev()) };
+        for message in candidates.take(limit) {
+            page_bytes += Self::encoded_len(message, framing);
+            if page_bytes > max_page_bytes {
+                break;
+            }
+            page.push(message.clone());
+        }
+
+        let has_more = page.len() < end - start;
+        if !oldest_first {
+            page.reverse();
+        }
+        (page, has_more)
+    }
+
+    fn encoded_len(message: &ChatHistoryMessage, framing: Framing) -> usize {
+        let len = framing.len_prefix_size();
+        let reactions: usize = message
+            .reactions
+            .iter()
+            .map(|reaction| {
+                let usernames: usize = reaction.usernames.iter().map(|username| len + username.len()).sum();
+                len + reaction.emoji.len() + len + usernames
+            })
+            .sum();
+        let attachments: usize = message
+            .attachments
+            .iter()
+            .map(|attachment| 8 + len + attachment.name.len() + len + attachment.mime_type.len() + 8)
+            .sum();
+        8 + 8 + len + message.username.len() + len + message.message.len() + 9 + 1 + len + reactions + len + attachments
+    }
+
+    /// Adds (or removes) the reaction of `username`, usernames compared ignoring ASCII case like authors.
+    /// Returns false if there is nothing to change or the message already has too many different emoji.
+    fn set_reaction(message: &mut ChatHistoryMessage, username: &str, emoji: &str, reacted: bool) -> bool {
+        let index = message.reactions.iter().position(|reaction| reaction.emoji == emoji);
+        let has_reacted = index.is_some_and(|index| {
+            message.reactions[index].usernames.iter().any(|name| name.eq_ignore_ascii_case(username))
+        });
+        if reacted == has_reacted {
+            return false;
+        }
+
+        match index {
+            Some(index) if reacted => message.reactions[index].usernames.push(username.to_string()),
+            Some(index) => {
+                message.reactions[index].usernames.retain(|name| !name.eq_ignore_ascii_case(username));
+                if message.reactions[index].usernames.is_empty() {
+                    message.reactions.remove(index);
+                }
+            }
+            None if message.reactions.len() < MAX_REACTIONS_PER_MESSAGE => {
+                message.reactions.push(Reaction::new(emoji.to_string(), vec![username.to_string()]));
+            }
+            None => return false,
+        }
+        true
+    }
+
+    fn parse_line(line: &str) -> Option<Record> {
+        let (kind, rest) = line.split_once('\t')?;
+        match kind {
+            "message" => {
+                let mut fields = rest.splitn(6, '\t');
+                let message_id = fields.next()?.parse().ok()?;
+                let timestamp = fields.next()?.parse().ok()?;
+                let reply_to = match fields.next()? {
+                    "-" => None,
+                    id => Some(id.parse().ok()?),
+                };
+                let author_role = match fields.next()? {
+                    "guest" => Role::Guest,
+                    "member" => Role::Member,
+                    "moderator" => Role::Moderator,
+                    "admin" => Role::Admin,
+                    _ => return None,
+                };
+                let username = Self::unescape(fields.next()?)?;
+                let mut message =
+                    ChatHistoryMessage::new(message_id, timestamp, username, Self::unescape(fields.next()?)?);
+                message.reply_to = reply_to;
+                Some(Record::Message(StoredMessage { message, author_id: None, author_role }))
+            }
+            "attachment" => {
+                let mut fields = rest.splitn(5, '\t');
+                let message_id = fields.next()?.parse().ok()?;
+                let attachment_id = fields.next()?.parse().ok()?;
+                let size = fields.next()?.parse().ok()?;
+                let mime_type = Self::unescape(fields.next()?)?;
+                let attachment = Attachment::new(attachment_id, Self::unescape(fields.next()?)?, mime_type, size);
+                Some(Record::Attachment { message_id, attachment })
+            }
+            "edit" => {
+                let (message_id, message) = rest.split_once('\t')?;
+                Some(Record::Edit { message_id: message_id.parse().ok()?, message: Self::unescape(message)? })
+            }
+            "delete" => Some(Record::Delete { message_id: rest.parse().ok()? }),
+            "react" => {
+                let mut fields = rest.splitn(4, '\t');
+                let message_id = fields.next()?.parse().ok()?;
+                let reacted = match fields.next()? {
+                    "+" => true,
+                    "-" => false,
+                    _ => return None,
+                };
+                let username = Self::unescape(fields.next()?)?;
+                Some(Record::Reaction { message_id, username, emoji: Self::unescape(fields.next()?)?, reacted })
+            }
+            _ => None,
+        }
+    }
+
+    /// Releases the state lock and appends a record, after the records of changes made before it
+    fn append_unlocked(&self, state: MutexGuard<'_, HistoryState>, record: &Record) {
+        let _appending = self.appending.lock().unwrap_or_else(PoisonError::into_inner);
+        drop(state);
+        self.append(record);
+    }
+
+    /// Appends a record to the history file, if any. Failures are logged, the history in memory stays usable.
+    fn append(&self, record: &Record) {
+        let Some(path) = &self.history_file else {
+            return;
+        };
+
+        let line = match record {
+            Record::Message(StoredMessage { message, author_role, .. }) => {
+                let mut lines = format!(
+                    "message\t{}\t{}\t{}\t{}\t{}\t{}",
+                    message.message_id,
+                    message.timestamp,
+                    message.reply_to.map_or_else(|| "-".to_string(), |id| id.to_string()),
+                    author_role,
+                    Self::escape(&message.username),
+                    Self::escape(&message.message)
+                );
+                // Written together so a message is never saved without its attachments
+                for attachment in &message.attachments {
+                    lines.push('\n');
+                    lines.push_str(&Self::attachment_line(message.message_id, attachment));
+                }
+                lines
+            }
+            Record::Attachment { message_id, attachment } => Self::attachment_line(*message_id, attachment),
+            Record::Edit { message_id, message } => format!("edit\t{message_id}\t{}", Self::escape(message)),
+            Record::Delete { message_id } => format!("delete\t{message_id}"),
+            Record::Reaction { message_id, username, emoji, reacted } => format!(
+                "react\t{message_id}\t{}\t{}\t{}",
+                if *reacted { "+" } else { "-" },
+                Self::escape(username),
+                Self::escape(emoji)
+            ),
+        };
+
+        let result = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .and_then(|mut file| writeln!(file, "{line}"));
+        if let Err(e) = result {
+            error!("Failed to save chat history to {}: {}", path.display(), e);
+        }
+    }
+
+    fn attachment_line(message_id: u64, attachment: &Attachment) -> String {
+        format!(
+            "attachment\t{message_id}\t{}\t{}\t{}\t{}",
+            attachment.attachment_id,
+            attachment.size,
+            Self::escape(&attachment.mime_type),
+            Self::escape(&attachment.name)
+        )
+    }
+
+    fn escape(text: &str) -> String {
+        let mut escaped = String::with_capacity(text.len());
+        for c in text.chars() {
+            match c {
+                '\\' => escaped.push_str("\\\\"),
+                '\t' => escaped.push_str("\\t"),
+                '\n' => escaped.push_str("\\n"),
+                '\r' => escaped.push_str("\\r"),
+                c => escaped.push(c),
+            }
+        }
+        escaped
+    }
+
+    fn unescape(text: &str) -> Option<String> {
+        let mut unescaped = String::with_capacity(text.len());
+        let mut chars = text.chars();
+        while let Some(c) = chars.next() {
+            if c != '\\' {
+                unescaped.push(c);
+                continue;
+            }
+            match chars.next()? {
+                '\\' => unescaped.push('\\'),
+                't' => unescaped.push('\t'),
+                'n' => unescaped.push('\n'),
+                'r' => unescaped.push('\r'),
+                _ => return None,
+            }
+        }
+        Some(unescaped)
+    }
+}
@@ -1,42 +1,101 @@
 use std::net::SocketAddr;
 use std::sync::Arc;
-use dashmap::DashMap;
-use tokio::io::{AsyncReadExt, AsyncWriteExt};
-use tokio::net::TcpStream;
-use tokio::sync::broadcast;
-use tokio::sync::mpsc::UnboundedSender;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::sync::{broadcast, watch};
+use tokio::sync::mpsc::{self, UnboundedReceiver, UnboundedSender};
+use tokio::time::Instant;
 use tracing::{debug, error, warn};
-use voiceapp_protocol::{Packet, ParticipantInfo, ProtocolError};
-use crate::config::{MAX_USERNAME_LEN, PACKET_BUFFER_SIZE};
+use voiceapp_protocol::auth::{AUTH_CHALLENGE_LEN, AUTH_PROOF_LEN};
+use voiceapp_protocol::crypto::VOICE_KEY_LEN;
+use voiceapp_protocol::{
+    negotiate_version, Attachment, AttachmentRejectReason, Capabilities, ChatRejectReason, Framing,
+    LoginRejectReason, ModerationAction, ModerationRejectReason, Packet, ParticipantInfo, Permissions, ProtocolError,
+    Role, PROTOCOL_VERSION, TYPING_INTERVAL_MS,
+};
+use crate::config::{MAX_ATTACHMENTS_PER_MESSAGE, MAX_CHAT_HISTORY_PAGE_LEN, MAX_REACTION_LEN};
 use crate::error::ServerError;
 use crate::management::broadcast::BroadcastMessage;
 use crate::event::Event;
 use crate::event::Event::{VoiceJoined, VoiceLeft};
-use crate::management::user::User;
+use crate::management::server::{shutdown_notice, SharedState, ShutdownNotice};
+use crate::management::role::Roles;
+use crate::management::chat_history::ChatHistory;
+
+/// Logged in user a moderation request acts on
+struct ModerationTarget {
+    address: SocketAddr,
+    username: String,
+    role: Role,
+}
+
+/// User who just logged in, as announced to the others, and its voice credentials
+struct LoggedIn {
+    participant: ParticipantInfo,
+    voice_token: u64,
+    voice_key: [u8; VOICE_KEY_LEN],
+}
+
+/// Byte stream of a management connection, plain TCP or TLS
+pub trait ManagementStream: AsyncRead + AsyncWrite + Unpin + Send {}
+
+impl<T: AsyncRead + AsyncWrite + Unpin + Send> ManagementStream for T {}
 
 pub struct UserHandler {
-    server_users: Arc<DashMap<SocketAddr, User>>,
-    socket: TcpStream,
+    shared: SharedState,
+    socket: Box<dyn ManagementStream>,
     address: SocketAddr,
-    broadcast_channel: broadcast::Sender<BroadcastMessage>,
-    events_channel: UnboundedSender<Event>,
+    direct_channel: (UnboundedSender<Packet>, UnboundedReceiver<Packet>), // Direct messages from other users' handlers
+    shutdown: watch::Receiver<Option<ShutdownNotice>>, // Set once the server shuts down
+    protocol_version: Option<u16>, // Negotiated in Hello, required before login
+    capabilities: Capabilities,     // Capabilities supported by both sides
+    framing: Framing, // Packet headers after ServerHello, standard until then
+    auth_challenge: [u8; AUTH_CHALLENGE_LEN], // Nonce sent in ServerHello, signed by the login proof
+    resume_token: Option<u64>, // Sent after login, reserves the user id when the connection drops
+    resume_request: Option<u64>, // Token accepted by ResumeSessionRequest, its session is taken over on login
+    typing_until: Option<Instant>, // Set while others are told this user is typing
+    typing_started_at: Option<Instant>, // Last TypingStarted broadcast, at most one per typing interval
+    read_marker: u64, // Newest message this user has read
 }
 
 impl UserHandler {
+    #[must_use]
     pub fn new(
-        users: Arc<DashMap<SocketAddr, User>>,
-        socket: TcpStream,
+        shared: SharedState,
+        socket: Box<dyn ManagementStream>,
         address: SocketAddr,
-        broadcast_channel: broadcast::Sender<BroadcastMessage>,
-        events_channel: UnboundedSender<Event>,
+        shutdown: watch::Receiver<Option<ShutdownNotice>>,
     ) -> Self {
-        Self { server_users: users, socket, address, broadcast_channel, events_channel }
+        Self {
+            shared,
+            socket,
+            address,
+            direct_channel: mpsc::unbounded_channel(),
+            shutdown,
+            protocol_version: None,
+            capabilities: Capabilities::NONE,
+            framing: Framing::Standard,
+            auth_challenge: rand::random(),
+            resume_token: None,
+            resume_request: None,
+            typing_until: None,
+            typing_started_at: None,
+            read_marker: 0,
+        }
     }
 
+    /// Serves the connection until the client leaves, is disconnected or the server shuts down.
+    ///
+    /// # Errors
+    ///
+    /// Never fails at the moment, connection errors are logged and disconnect the client.
     pub async fn handle(&mut self) -> Result<(), ServerError> {
-        let mut read_buf = vec![0u8; PACKET_BUFFER_SIZE];
+        let mut read_buf = vec![0u8; self.shared.limits.packet_buffer_size];
         let mut packet_buffer = Vec::new(); // Accumulates partial packets
-        let mut broadcast_rx = self.broadcast_channel.subscribe();
+        let mut broadcast_rx = self.shared.broadcast_tx.subscribe();
+        let mut shutdown_rx = self.shutdown.clone();
+        let idle_timeout = Duration::from_millis(self.shared.limits.idle_timeout_ms);
+        let mut last_received = Instant::now();
 
         loop {
             tokio::select! {
@@ -46,40 +105,24 @@ impl UserHandler {
                         Ok(n) => {
                             if n == 0 {
                                 // User disconnected, clean up and exit
-                                self.handle_disconnect().await;
+                                self.handle_disconnect();
                                 return Ok(());
                             }
 
+                            last_received = Instant::now();
+
                             // Append new data to packet buffer
                             packet_buffer.extend_from_slice(&read_buf[..n]);
 
                             // Process all complete packets in the buffer
-                            loop {
-                                match Packet::decode(&packet_buffer) {
-                                    Ok((packet, size)) => {
-                                        // Handle the packet
-                                        if let Err(e) = self.handle_packet(packet).await {
-                                            error!("[{}] Error handling packet: {}", self.address, e);
-                                        }
-
-                                        // Remove consumed bytes from buffer
-                                        packet_buffer.drain(..size);
-                                    }
-                                    Err(ProtocolError::IncompletePayload { .. }) | Err(ProtocolError::PacketTooShort { .. }) => {
-                                        // Not enough data yet, wait for more
-                                        break;
-                                    }
-                                    Err(e) => {
-                                        warn!("[{}] Protocol error: {}", self.address, e);
-                                        packet_buffer.clear();
-                                        break;
-                                    }
-                                }
+                            if !self.handle_buffered_packets(&mut packet_buffer).await {
+                                self.handle_disconnect();
+                                return Ok(());
                             }
                         }
                         Err(e) => {
                             error!("[{}] TCP receive error: {}", self.address, e);
-                            self.handle_disconnect().await;
+                            self.handle_disconnect();
                             return Ok(());
                         }
                     }
@@ -88,9 +131,15 @@ impl UserHandler {
                 // Handle broadcast messages
                 broadcast_result = broadcast_rx.recv() => {
                     match broadcast_result {
+                        Ok(message) if self.is_disconnected_by(&message) => {
+                            debug!("[{}] Disconnected by the server", self.address);
+                            self.handle_disconnect();
+                            return Ok(());
+                        }
                         Ok(message) => {
                             if let Err(e) = self.handle_broadcast_message(message).await {
                                 error!("[{}] Failed to send broadcast message: {}", self.address, e);
+                                self.handle_disconnect();
                                 return Ok(());
                             }
                         }
@@ -99,34 +148,173 @@ impl UserHandler {
                         }
                         Err(broadcast::error::RecvError::Closed) => {
                             error!("[{}] Broadcast channel closed, skipping messages", self.address);
+                            self.handle_disconnect();
                             return Ok(());
                         }
                     }
                 }
+
+                // Handle packets sent to this user only
+                Some(packet) = self.direct_channel.1.recv() => {
+                    if let Err(e) = self.handle_direct_delivery(&packet).await {
+                        error!("[{}] Failed to send direct message: {}", self.address, e);
+                        self.handle_disconnect();
+                        return Ok(());
+                    }
+                }
+
+                // User stopped sending typing requests without saying so
+                () = tokio::time::sleep_until(self.typing_until.unwrap_or_else(Instant::now)),
+                    if self.typing_until.is_some() => {
+                    self.stop_typing();
+                }
+
+                // Client stopped sending heartbeats, the peer is most likely gone without closing the connection
+                () = tokio::time::sleep_until(last_received + idle_timeout), if self.expects_heartbeats() => {
+                    warn!("[{}] Nothing received for {:?}, disconnecting", self.address, idle_timeout);
+                    self.handle_disconnect();
+                    return Ok(());
+                }
+
+                // Server is shutting down, tell the client why and close the connection
+                notice = shutdown_notice(&mut shutdown_rx) => {
+                    if let Some(notice) = notice {
+                        if let Err(e) = self.handle_shutdown(notice, &mut broadcast_rx).await {
+                            warn!("[{}] Failed to send shutdown notice: {}", self.address, e);
+                        }
+                    }
+
+                    self.handle_disconnect();
+                    return Ok(());
+                }
             }
         }
     }
 
+    /// Handles every complete packet in the buffer, returns false if the client has to be disconnected.
+    ///
+    /// Undecodable packets disconnect the client, skipping them would read the rest of the stream out of step.
+    async fn handle_buffered_packets(&mut self, packet_buffer: &mut Vec<u8>) -> bool {
+        loop {
+            match Packet::decode_with(packet_buffer, self.framing) {
+                Ok((packet, size)) => {
+                    // Handle the packet
+                    if let Err(e) = self.handle_packet(packet).await {
+                        error!("[{}] Error handling packet: {}", self.address, e);
+
+                        if let ServerError::Handshake(_) = e {
+                            return false;
+                        }
+                    }
+
+                    // Remove consumed bytes from buffer
+                    packet_buffer.drain(..size);
+                }
+                Err(ProtocolError::IncompletePayload { .. }) => {
+                    // Not enough data yet, wait for more
+                    return true;
+                }
+                Err(ProtocolError::PacketTooShort { .. }) if packet_buffer.len() < self.framing.header_len() => {
+                    return true;
+                }
+                Err(e) => {
+                    warn!("[{}] Protocol error, disconnecting: {}", self.address, e);
+                    return false;
+                }
+            }
+        }
+    }
+
+    /// The idle timeout applies until the handshake, and afterwards only to clients that announced heartbeats
+    fn expects_heartbeats(&self) -> bool {
+        self.shared.limits.idle_timeout_ms > 0
+            && (self.protocol_version.is_none() || self.capabilities.contains(Capabilities::HEARTBEAT))
+    }
+
+    /// Check if the message closes the connection of this user
+    fn is_disconnected_by(&self, message: &BroadcastMessage) -> bool {
+        self.shared.users.get(&self.address).is_some_and(|user| message.disconnects(user.id))
+    }
+
     /// Handle a decoded packet from client
     async fn handle_packet(&mut self, packet: Packet) -> Result<(), ServerError> {
         match packet {
-            Packet::LoginRequest { request_id, username } => {
-                self.handle_login_request(request_id, username).await
+            Packet::Hello { request_id, protocol_version, capabilities } => {
+                self.handle_hello(request_id, protocol_version, capabilities).await
+            }
+            Packet::ResumeSessionRequest { request_id, token } => {
+                self.handle_resume_session_request(request_id, token).await
+            }
+            Packet::LoginRequest { request_id, username, auth_proof } => {
+                self.handle_login_request(request_id, username, auth_proof).await
             }
-            Packet::JoinVoiceChannelRequest { request_id } => {
-                self.handle_join_voice_channel_request(request_id).await
+            Packet::JoinVoiceChannelRequest { request_id, channel_id } => {
+                self.handle_join_voice_channel_request(request_id, channel_id).await
             }
             Packet::LeaveVoiceChannelRequest { request_id } => {
                 self.handle_leave_voice_channel_request(request_id).await
             }
-            Packet::ChatMessageRequest { request_id, message } => {
-                self.handle_chat_message_request(request_id, message).await
+            Packet::ChatMessageRequest { request_id, message, reply_to, attachments } => {
+                self.handle_chat_message_request(request_id, message, reply_to, attachments).await
+            }
+            Packet::EditMessageRequest { request_id, message_id, message } => {
+                self.handle_edit_message_request(request_id, message_id, message).await
+            }
+            Packet::DeleteMessageRequest { request_id, message_id } => {
+                self.handle_delete_message_request(request_id, message_id).await
+            }
+            Packet::DirectMessageRequest { request_id, user_id, message } => {
+                self.handle_direct_message_request(request_id, user_id, message).await
+            }
+            Packet::SetTypingRequest { is_typing, .. } => {
+                self.handle_set_typing_request(is_typing);
+                Ok(())
+            }
+            Packet::MarkReadRequest { message_id, .. } => {
+                self.handle_mark_read_request(message_id);
+                Ok(())
+            }
+            Packet::SetReactionRequest { request_id, message_id, emoji, reacted } => {
+                self.handle_set_reaction_request(request_id, message_id, emoji, reacted).await
+            }
+            Packet::UploadAttachmentRequest { request_id, name, mime_type, size } => {
+                self.handle_upload_attachment_request(request_id, name, mime_type, size).await
+            }
+            Packet::UploadChunkRequest { request_id, attachment_id, offset, data } => {
+                self.handle_upload_chunk_request(request_id, attachment_id, offset, data).await
+            }
+            Packet::DownloadChunkRequest { request_id, attachment_id, offset } => {
+                self.handle_download_chunk_request(request_id, attachment_id, offset).await
+            }
+            Packet::ChatHistoryRequest { request_id, before, after, limit } => {
+                self.handle_chat_history_request(request_id, before, after, limit).await
             }
             Packet::PingRequest { request_id } => {
                 self.handle_ping_request(request_id).await
             }
-            Packet::UserMuteState { user_id, is_muted } => {
-                self.handle_user_mute_state(user_id, is_muted).await
+            Packet::CreateChannelRequest { request_id, name } => {
+                self.handle_create_channel_request(request_id, name).await
+            }
+            Packet::ListChannelsRequest { request_id } => {
+                self.handle_list_channels_request(request_id).await
+            }
+            Packet::ChangeNicknameRequest { request_id, username } => {
+                self.handle_change_nickname_request(request_id, username).await
+            }
+            Packet::SetMuteStateRequest { request_id, is_muted, is_deafened } => {
+                self.handle_set_mute_state_request(request_id, is_muted, is_deafened).await
+            }
+            Packet::KickUserRequest { request_id, user_id, reason } => {
+                self.handle_kick_user_request(request_id, user_id, reason).await
+            }
+            Packet::BanUserRequest { request_id, user_id, reason, ban_address } => {
+                self.handle_ban_user_request(request_id, user_id, reason, ban_address).await
+            }
+            Packet::ServerMuteUserRequest { request_id, user_id, muted } => {
+                self.handle_server_mute_user_request(request_id, user_id, muted).await
+            }
+            Packet::UnbanUserRequest { request_id, username } => {
+                self.handle_unban_user_request(request_id, username).await
             }
             _ => {
                 warn!("[{}] Unexpected packet: {:?}", self.address, packet);
@@ -140,88 +328,323 @@ impl UserHandler {
         &mut self,
         message: BroadcastMessage,
     ) -> Result<(), ServerError> {
-        if message.should_send_to(self.address) {
-            self.socket.write_all(message.data()).await?;
+        if message.should_send_to(self.address, self.capabilities, self.framing) {
+            self.socket.write_all(message.data(self.framing)).await?;
             self.socket.flush().await?;
         }
 
         Ok(())
     }
 
+    /// Handle a packet another handler queued for this user only
+    async fn handle_direct_delivery(&mut self, packet: &Packet) -> Result<(), ServerError> {
+        self.socket.write_all(&packet.encode_with(self.framing)?).await?;
+        self.socket.flush().await?;
+        Ok(())
+    }
+
+    /// Deliver broadcasts queued before the shutdown, send the notice and close the stream
+    async fn handle_shutdown(
+        &mut self,
+        notice: ShutdownNotice,
+        broadcast_rx: &mut broadcast::Receiver<BroadcastMessage>,
+    ) -> Result<(), ServerError> {
+        loop {
+            match broadcast_rx.try_recv() {
+                Ok(message) => self.handle_broadcast_message(message).await?,
+                Err(broadcast::error::TryRecvError::Lagged(_)) => {}
+                Err(_) => break,
+            }
+        }
+
+        if self.capabilities.contains(Capabilities::SHUTDOWN_NOTICE) {
+            let packet = Packet::ServerShuttingDown {
+                reason: notice.reason,
+                reconnect_after_ms: u32::try_from(notice.reconnect_after.as_millis()).unwrap_or(u32::MAX),
+            };
+            self.socket.write_all(&packet.encode_with(self.framing)?).await?;
+        }
+
+        self.socket.flush().await?;
+        self.socket.shutdown().await?;
+
+        debug!("[{}] Connection closed for shutdown", self.address);
+        Ok(())
+    }
+
+    /// Handle hello: negotiate protocol version and capabilities, reject clients we can't talk to
+    async fn handle_hello(
+        &mut self,
+        request_id: u64,
+        protocol_version: u16,
+        capabilities: Capabilities,
+    ) -> Result<(), ServerError> {
+        // Renegotiating would switch the framing and capabilities under a logged in session
+        if self.protocol_version.is_some() {
+            return Err(ServerError::Handshake("hello after protocol negotiation".to_string()));
+        }
+
+        let negotiated = negotiate_version(protocol_version);
+        let response = Packet::ServerHello {
+            request_id,
+            protocol_version: PROTOCOL_VERSION,
+            capabilities: Capabilities::SUPPORTED,
+            accepted: negotiated.is_some(),
+            auth_challenge: self.auth_challenge,
+            auth_salt: self.shared.authenticator.salt(),
+        };
+        // Always standard framing, the client only switches once it has read this
+        self.socket.write_all(&response.encode()?).await?;
+        self.socket.flush().await?;
+
+        let Some(version) = negotiated else {
+            return Err(ServerError::Handshake(format!("unsupported protocol version {protocol_version}")));
+        };
+
+        self.protocol_version = Some(version);
+        self.capabilities = capabilities.intersection(Capabilities::SUPPORTED);
+        self.framing = Framing::for_version(version);
+
+        debug!(
+            "[{}] Protocol negotiated: version={}, capabilities={:#x}",
+            self.address, version, self.capabilities.bits()
+        );
+
+        Ok(())
+    }
+
+    /// Handle resume session request: look up the reservation of a dropped connection,
+    /// the next login keeps its user id if it succeeds with a matching username
+    async fn handle_resume_session_request(&mut self, request_id: u64, token: u64) -> Result<(), ServerError> {
+        if self.protocol_version.is_none() {
+            return Err(ServerError::Handshake("resume before protocol negotiation".to_string()));
+        }
+
+        let logged_in = self.shared.users.get(&self.address).is_some_and(|user| user.username.is_some());
+        // The reservation stays until the login proves the client owns it
+        self.resume_request = Some(token).filter(|&token| !logged_in && self.shared.resume_tokens.is_reserved(token));
+
+        let accepted = self.resume_request.is_some();
+        let response = Packet::ResumeSessionResponse { request_id, accepted };
+        self.socket.write_all(&response.encode_with(self.framing)?).await?;
+        self.socket.flush().await?;
+
+        debug!("[{}] Session resume requested: accepted={}", self.address, accepted);
+        Ok(())
+    }
+
     /// Handle login request: create user, store in users map, send response
     async fn handle_login_request(
         &mut self,
         request_id: u64,
         username: String,
+        auth_proof: Option<[u8; AUTH_PROOF_LEN]>,
     ) -> Result<(), ServerError> {
-        // Validate username
-        if username.is_empty() || username.len() > MAX_USERNAME_LEN {
-            let response = Packet::LoginResponse {
-                request_id,
-                id: 0,
-                voice_token: 0,
-                participants: vec![],
-            };
-            self.socket.write_all(&response.encode()).await?;
-            self.socket.flush().await?;
-            return Ok(());
+        if self.protocol_version.is_none() {
+            return Err(ServerError::Handshake("login before protocol negotiation".to_string()));
         }
 
-        let (user_id, voice_token) = if let Some(mut user) = self.server_users.get_mut(&self.address) {
-            user.username = Some(username.clone());
-            (user.id, user.token)
-        } else {
-            return Err(ServerError::UserNotFound(self.address));
+        if let Some(reason) = self.check_login(&username, auth_proof).await {
+            return self.reject_login(request_id, reason).await;
+        }
+
+        // Reserve the name according to the duplicate username policy,
+        // a repeated login on the same connection gives up the previous name
+        let previous_username = self.shared.users.get(&self.address).and_then(|user| user.username.clone());
+        let claimed = match &previous_username {
+            Some(previous) => self.shared.usernames.rename(previous, &username, self.address),
+            None => self.shared.usernames.claim(&username, self.address),
         };
 
-        // Collect current participants for login response
-        let participants = self
-            .server_users
-            .iter()
-            .filter(|entry| entry.value().username.is_some())
-            .map(|entry| {
-                let u = entry.value();
-                ParticipantInfo::new(u.id, u.username.clone().unwrap(), u.in_voice, u.is_muted)
-            })
-            .collect::<Vec<_>>();
+        if !claimed {
+            return self.reject_login(request_id, LoginRejectReason::NameTaken).await;
+        }
 
-        // Send login response with participant list
+        let logged_in = self.log_in(&username)?;
+
+        // Send login response with participant and channel lists
         let response = Packet::LoginResponse {
             request_id,
-            id: user_id,
-            voice_token,
-            participants,
+            id: logged_in.participant.user_id,
+            voice_token: logged_in.voice_token,
+            voice_key: logged_in.voice_key,
+            participants: self.participants(),
+            channels: self.shared.channels.list(),
         };
 
-        self.socket.write_all(&response.encode()).await?;
+        self.socket.write_all(&response.encode_with(self.framing)?).await?;
+
+        if let Some(motd) = &self.shared.motd {
+            if self.capabilities.contains(Capabilities::MOTD) {
+                let motd = Packet::Motd { message: motd.to_string() };
+                self.socket.write_all(&motd.encode_with(self.framing)?).await?;
+            }
+        }
+
+        if self.shared.resume_tokens.is_enabled() && self.capabilities.contains(Capabilities::SESSION_RESUME) {
+            let token = rand::random();
+            self.resume_token = Some(token);
+            self.socket.write_all(&Packet::ResumeToken { token }.encode_with(self.framing)?).await?;
+        }
+
         self.socket.flush().await?;
 
         // Broadcast user joined server event to all other clients
-        let joined_event = Packet::UserJoinedServer {
-            participant: ParticipantInfo::new(user_id, username.clone(), false, false),
-        };
-        let _ = self.broadcast_channel.send(BroadcastMessage::excluding(self.address, &joined_event));
-
+        let participant = logged_in.participant;
         debug!(
-            "[{}] User logged in: id={}, username={}",
-            self.address, user_id, username.clone()
+            "[{}] User logged in: id={}, username={}, role={}",
+            self.address, participant.user_id, participant.username, participant.role
         );
+        let joined_event = Packet::UserJoinedServer { participant };
+        let _ = self.shared.broadcast_tx.send(BroadcastMessage::excluding(self.address, &joined_event));
+
+        Ok(())
+    }
+
+    /// Checks everything about a login but the name reservation, returns why it is refused if it is
+    async fn check_login(
+        &mut self,
+        username: &str,
+        auth_proof: Option<[u8; AUTH_PROOF_LEN]>,
+    ) -> Option<LoginRejectReason> {
+        // Validate username
+        if username.is_empty() || username.len() > self.shared.limits.max_username_len {
+            return Some(LoginRejectReason::InvalidUsername);
+        }
+
+        // Check password proof, deriving the key for the shared password takes a while the first time
+        if !self.shared.authenticator.verify(username, &self.auth_challenge, auth_proof.as_ref()).await {
+            warn!("[{}] Authentication failed for username={}", self.address, username);
+            return Some(LoginRejectReason::InvalidCredentials);
+        }
+
+        if self.shared.moderation.is_banned(username, self.address.ip()) {
+            warn!("[{}] Banned user tried to log in: username={}", self.address, username);
+            return Some(LoginRejectReason::Banned);
+        }
+
+        // Enforce user limit
+        if let Some(max_users) = self.shared.limits.max_users {
+            let logged_in = self
+                .shared
+                .users
+                .iter()
+                .filter(|entry| entry.value().username.is_some())
+                .count();
+
+            if logged_in >= max_users {
+                return Some(LoginRejectReason::ServerFull);
+            }
+        }
+
+        None
+    }
+
+    /// Marks the user of this connection as logged in as `username`, taking over a resumed session,
+    /// and tells the voice relay what changed
+    fn log_in(&mut self, username: &str) -> Result<LoggedIn, ServerError> {
+        let Some(mut user) = self.shared.users.get_mut(&self.address) else {
+            return Err(ServerError::UserNotFound(self.address));
+        };
+        user.username = Some(username.to_string());
+
+        // Take over the id of the resumed session, the voice relay knows this connection by its new id
+        let usernames = &self.shared.usernames;
+        let resumed = self.resume_request.take().and_then(|token| {
+            self.shared.resume_tokens.take_if(token, |reservation| usernames.same(&reservation.username, username))
+        });
+        if let Some(reservation) = resumed {
+            let _ = self.shared.events_tx.send(Event::UserDisconnected { id: user.id });
+            self.shared.attachments.discard_unfinished(user.id);
+            user.id = reservation.user_id;
+            let _ = self.shared.events_tx.send(Event::UserConnected {
+                id: user.id,
+                token: user.token,
+                voice_key: user.voice_key,
+            });
+            debug!("[{}] Session resumed: id={}", self.address, user.id);
+        }
+
+        if self.capabilities.contains(Capabilities::HEARTBEAT) {
+            let _ = self.shared.events_tx.send(Event::HeartbeatStarted { id: user.id });
+        }
 
+        // Other handlers reach this one directly only once it is logged in
+        user.direct_channel =
+            self.capabilities.contains(Capabilities::DIRECT_MESSAGES).then(|| self.direct_channel.0.clone());
+
+        user.role = self.shared.roles.role_of(username);
+        user.permissions = self.shared.roles.permissions(user.role);
+
+        // Logging in again does not lift a server mute
+        user.server_muted = self.shared.moderation.is_server_muted(username);
+        if !user.can_speak() {
+            user.is_muted = true;
+            let _ = self.shared.events_tx.send(Event::ServerMuted { id: user.id, muted: true });
+        }
+
+        Ok(LoggedIn {
+            participant: ParticipantInfo::new(
+                user.id,
+                username.to_string(),
+                None,
+                user.is_muted,
+                user.is_deafened,
+                user.role,
+                user.permissions,
+            ),
+            voice_token: user.token,
+            voice_key: user.voice_key,
+        })
+    }
+
+    /// Every logged in user, as listed in the login response
+    fn participants(&self) -> Vec<ParticipantInfo> {
+        self.shared.users
+            .iter()
+            .filter_map(|entry| {
+                let u = entry.value();
+                Some(ParticipantInfo::new(
+                    u.id,
+                    u.username.clone()?,
+                    u.channel_id,
+                    u.is_muted,
+                    u.is_deafened,
+                    u.role,
+                    u.permissions,
+                ))
+            })
+            .collect()
+    }
+
+    /// Reply to a login request with `LoginRejected`
+    async fn reject_login(&mut self, request_id: u64, reason: LoginRejectReason) -> Result<(), ServerError> {
+        let response = Packet::LoginRejected { request_id, reason };
+        self.socket.write_all(&response.encode_with(self.framing)?).await?;
+        self.socket.flush().await?;
         Ok(())
     }
 
-    /// Handle join voice channel request: send response to caller and broadcast event excluding caller
+    /// Handle join voice channel request: send response to caller and broadcast event excluding caller.
+    /// Joining while already in a voice channel moves the user to the requested one.
     async fn handle_join_voice_channel_request(
         &mut self,
         request_id: u64,
+        channel_id: u64,
     ) -> Result<(), ServerError> {
-        // Get user ID and update in_voice state
-        let user_id = {
-            if let Some(mut user) = self.server_users.get_mut(&self.address) {
-                let user_id = user.id;
-                user.in_voice = true;
-                user.is_muted = false; // by default user is not muted
-                user_id
+        if !self.shared.channels.contains(channel_id) || !self.has_permission(Permissions::JOIN_VOICE) {
+            let response = Packet::JoinVoiceChannelResponse { request_id, success: false };
+            self.socket.write_all(&response.encode_with(self.framing)?).await?;
+            self.socket.flush().await?;
+            return Ok(());
+        }
+
+        // Get user ID and update channel membership
+        let (user_id, can_speak) = {
+            if let Some(mut user) = self.shared.users.get_mut(&self.address) {
+                user.channel_id = Some(channel_id);
+                user.is_muted = !user.can_speak(); // by default user is not muted
+                (user.id, user.can_speak())
             } else {
                 return Err(ServerError::UserNotFound(self.address));
             }
@@ -229,16 +652,22 @@ impl UserHandler {
 
         // Send response to caller
         let response = Packet::JoinVoiceChannelResponse { request_id, success: true };
-        self.socket.write_all(&response.encode()).await?;
+        self.socket.write_all(&response.encode_with(self.framing)?).await?;
         self.socket.flush().await?;
 
-        let _ = self.events_channel.send(VoiceJoined { id: user_id });
+        let _ = self.shared.events_tx.send(VoiceJoined { id: user_id, channel_id });
 
         // Broadcast user joined voice event to all other clients (exclude caller)
-        let joined_event = Packet::UserJoinedVoice { user_id };
-        let _ = self.broadcast_channel.send(BroadcastMessage::excluding(self.address, &joined_event));
+        let joined_event = Packet::UserJoinedVoice { user_id, channel_id };
+        let _ = self.shared.broadcast_tx.send(BroadcastMessage::excluding(self.address, &joined_event));
 
-        debug!("[{}] User joined voice channel: id={}", self.address, user_id);
+        // Clients show users joining voice as unmuted
+        if !can_speak {
+            let mute_event = Packet::UserMuteState { user_id, is_muted: true, is_deafened: false };
+            let _ = self.shared.broadcast_tx.send(BroadcastMessage::for_all(&mute_event));
+        }
+
+        debug!("[{}] User joined voice channel: id={}, channel_id={}", self.address, user_id, channel_id);
 
         Ok(())
     }
@@ -248,12 +677,12 @@ impl UserHandler {
         &mut self,
         request_id: u64,
     ) -> Result<(), ServerError> {
-        // Get user ID and update in_voice state
+        // Get user ID and clear channel membership
         let user_id = {
-            if let Some(mut user) = self.server_users.get_mut(&self.address) {
+            if let Some(mut user) = self.shared.users.get_mut(&self.address) {
                 let user_id = user.id;
-                user.in_voice = false;
-                user.is_muted = false;
+                user.channel_id = None;
+                user.is_muted = !user.can_speak();
                 user_id
             } else {
                 return Err(ServerError::UserNotFound(self.address));
@@ -262,112 +691,875 @@ impl UserHandler {
 
         // Send response to caller
         let response = Packet::LeaveVoiceChannelResponse { request_id, success: true };
-        self.socket.write_all(&response.encode()).await?;
+        self.socket.write_all(&response.encode_with(self.framing)?).await?;
         self.socket.flush().await?;
 
-        let _ = self.events_channel.send(VoiceLeft { id: user_id });
+        let _ = self.shared.events_tx.send(VoiceLeft { id: user_id });
 
         // Broadcast user left voice event to all other clients (exclude caller)
         let left_event = Packet::UserLeftVoice { user_id };
-        let _ = self.broadcast_channel.send(BroadcastMessage::excluding(self.address, &left_event));
+        let _ = self.shared.broadcast_tx.send(BroadcastMessage::excluding(self.address, &left_event));
 
         debug!("[{}] User left voice channel: id={}", self.address, user_id);
 
         Ok(())
     }
 
-    /// Handle chat message request: send response to caller and broadcast event to all clients
+    /// Handle chat message request: send response to caller and broadcast event to all clients.
+    /// Messages breaking the content rules, replies to messages the server no longer keeps and attachments
+    /// not fully uploaded by the caller are rejected. Only messages with attachments may have empty text.
     async fn handle_chat_message_request(
         &mut self,
         request_id: u64,
         message: String,
+        reply_to: Option<u64>,
+        attachments: Vec<u64>,
     ) -> Result<(), ServerError> {
         // Get user info
-        let user_id = {
-            if let Some(user) = self.server_users.get(&self.address) {
-                user.id
+        let (user_id, username, role) = {
+            if let Some(user) = self.shared.users.get(&self.address) {
+                (user.id, user.username.clone(), user.role)
             } else {
                 return Err(ServerError::UserNotFound(self.address));
             }
         };
 
-        // Send response to caller with success status
-        let response = Packet::ChatMessageResponse { request_id, success: true };
-        self.socket.write_all(&response.encode()).await?;
+        // Stored before the broadcast, so the id and timestamp in the event match the history
+        // Text may be left out when files are attached
+        let text_rejection =
+            self.check_chat_message(&message).filter(|_| !message.is_empty() || attachments.is_empty());
+        let stored = if !self.has_permission(Permissions::CHAT) {
+            Err(ChatRejectReason::NotPermitted)
+        } else if let Some(reason) = text_rejection {
+            Err(reason)
+        } else {
+            match self.check_attachments(user_id, &attachments) {
+                Ok(attachments) => {
+                    let (username, text) = (username.unwrap_or_default(), message.clone());
+                    self.change_history(move |history| {
+                        history.push(user_id, role, &username, &text, reply_to, attachments)
+                    })
+                    .await
+                    .flatten()
+                    .ok_or(ChatRejectReason::UnknownReply)
+                }
+                Err(reason) => Err(reason),
+            }
+        };
+
+        // Send response to caller with the rejection reason, if any
+        let response = Packet::ChatMessageResponse {
+            request_id,
+            message_id: stored.as_ref().map_or(0, |m| m.message_id),
+            rejection: stored.as_ref().err().copied(),
+        };
+        self.socket.write_all(&response.encode_with(self.framing)?).await?;
         self.socket.flush().await?;
 
+        let stored = match stored {
+            Ok(stored) => stored,
+            Err(reason) => {
+                debug!("[{}] Chat message rejected: id={}, reason={}", self.address, user_id, reason);
+                return Ok(());
+            }
+        };
+
+        // The message is what the user was typing
+        self.stop_typing();
+
+        // Everyone who gets the message may download its files
+        let attachment_ids: Vec<u64> = stored.attachments.iter().map(|attachment| attachment.attachment_id).collect();
+        self.shared.attachments.attach(&attachment_ids);
+
         // Broadcast user sent message event to all clients (including sender)
-        let timestamp = std::time::SystemTime::now()
-            .duration_since(std::time::UNIX_EPOCH)
-            .map(|d| d.as_millis() as u64)
-            .unwrap_or(0);
         let message_event = Packet::UserSentMessage {
+            message_id: stored.message_id,
             user_id,
-            timestamp,
+            timestamp: stored.timestamp,
             message: message.clone(),
+            reply_to,
+            mentions: self.resolve_mentions(&message),
+            attachments: stored.attachments,
         };
-        let _ = self.broadcast_channel.send(BroadcastMessage::for_all(&message_event));
+        let _ = self.shared.broadcast_tx.send(BroadcastMessage::for_all(&message_event));
 
         debug!(
-            "[{}] User sent message: id={}, len={}",
+            "[{}] User sent message: id={}, message_id={}, len={}",
             self.address,
             user_id,
+            stored.message_id,
             message.len()
         );
 
         Ok(())
     }
 
-    /// Handle ping request: immediately respond with PingResponse
+    /// Handle upload attachment request: reserve room for a file the caller sends next with `UploadChunkRequest`,
+    /// nothing before login
+    async fn handle_upload_attachment_request(
+        &mut self,
+        request_id: u64,
+        name: String,
+        mime_type: String,
+        size: u64,
+    ) -> Result<(), ServerError> {
+        let started = match self.logged_in_user_id() {
+            Some(user_id) if self.has_permission(Permissions::CHAT) => {
+                self.shared.attachments.start(user_id, &name, &mime_type, size).await
+            }
+            _ => Err(AttachmentRejectReason::NotPermitted),
+        };
+
+        let response = Packet::UploadAttachmentResponse {
+            request_id,
+            attachment_id: *started.as_ref().unwrap_or(&0),
+            rejection: started.err(),
+        };
+        self.socket.write_all(&response.encode_with(self.framing)?).await?;
+        self.socket.flush().await?;
+
+        match started {
+            Ok(attachment_id) => {
+                debug!("[{}] Upload started: attachment_id={}, size={}", self.address, attachment_id, size);
+            }
+            Err(reason) => debug!("[{}] Upload rejected: size={}, reason={}", self.address, size, reason),
+        }
+
+        Ok(())
+    }
+
+    /// Handle upload chunk request: append the chunk to one of the caller's unfinished uploads
+    async fn handle_upload_chunk_request(
+        &mut self,
+        request_id: u64,
+        attachment_id: u64,
+        offset: u64,
+        data: Vec<u8>,
+    ) -> Result<(), ServerError> {
+        let written = match self.logged_in_user_id() {
+            Some(user_id) => self.shared.attachments.write_chunk(user_id, attachment_id, offset, data).await,
+            None => false,
+        };
+
+        let response = Packet::UploadChunkResponse { request_id, success: written };
+        self.socket.write_all(&response.encode_with(self.framing)?).await?;
+        self.socket.flush().await?;
+
+        if !written {
+            debug!("[{}] Upload chunk refused: attachment_id={}, offset={}", self.address, attachment_id, offset);
+        }
+
+        Ok(())
+    }
+
+    /// Handle download chunk request: respond with a part of a complete file, only files the caller uploaded
+    /// or that are attached to a message which was not deleted, nothing before login
+    async fn handle_download_chunk_request(
+        &mut self,
+        request_id: u64,
+        attachment_id: u64,
+        offset: u64,
+    ) -> Result<(), ServerError> {
+        let data = match self.logged_in_user_id() {
+            Some(user_id) => self.shared.attachments.read_chunk(user_id, attachment_id, offset).await,
+            None => None,
+        };
+
+        let response = Packet::DownloadChunkResponse {
+            request_id,
+            success: data.is_some(),
+            data: data.unwrap_or_default(),
+        };
+        self.socket.write_all(&response.encode_with(self.framing)?).await?;
+        self.socket.flush().await?;
+
+        Ok(())
+    }
+
+    /// Handle direct message request: queue the message for the target user's handler only
+    /// and send response to caller, nothing is stored
+    async fn handle_direct_message_request(
+        &mut self,
+        request_id: u64,
+        user_id: u64,
+        message: String,
+    ) -> Result<(), ServerError> {
+        let Some(sender_id) = self.shared.users.get(&self.address).map(|user| user.id) else {
+            return Err(ServerError::UserNotFound(self.address));
+        };
+
+        // Logged in users that accept direct messages, never the sender itself
+        let target = if !self.has_permission(Permissions::CHAT) {
+            debug!("[{}] Direct message without permission: id={}", self.address, sender_id);
+            None
+        } else if let Some(reason) = self.check_chat_message(&message) {
+            debug!("[{}] Direct message rejected: id={}, reason={}", self.address, sender_id, reason);
+            None
+        } else {
+            self.shared.users
+                .iter()
+                .find(|entry| entry.value().id == user_id && entry.value().username.is_some())
+                .filter(|entry| *entry.key() != self.address)
+                .and_then(|entry| entry.value().direct_channel.clone())
+        };
+
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |d| u64::try_from(d.as_millis()).unwrap_or(u64::MAX));
+        let len = message.len();
+        let event = Packet::DirectMessageReceived { from_user_id: sender_id, timestamp, message };
+        let delivered = target.is_some_and(|channel| channel.send(event).is_ok());
+
+        let response = Packet::DirectMessageResponse {
+            request_id,
+            success: delivered,
+            timestamp: if delivered { timestamp } else { 0 },
+        };
+        self.socket.write_all(&response.encode_with(self.framing)?).await?;
+        self.socket.flush().await?;
+
+        debug!(
+            "[{}] User sent direct message: id={}, to={}, delivered={}, len={}",
+            self.address, sender_id, user_id, delivered, len
+        );
+
+        Ok(())
+    }
+
+    /// Handle set typing request: tell the other users that understand it when this user starts typing,
+    /// keep the indicator while requests keep coming and stop it once they are missing for the typing timeout
+    fn handle_set_typing_request(&mut self, is_typing: bool) {
+        if !is_typing {
+            self.stop_typing();
+            return;
+        }
+
+        let Some(user_id) = self.logged_in_user_id().filter(|_| self.has_permission(Permissions::CHAT)) else {
+            return;
+        };
+
+        let now = Instant::now();
+        if self.typing_until.is_none() {
+            // Clients toggling typing quickly are not relayed more than once per typing interval
+            let interval = Duration::from_millis(u64::from(TYPING_INTERVAL_MS));
+            if self.typing_started_at.is_some_and(|started| now.duration_since(started) < interval) {
+                return;
+            }
+
+            self.typing_started_at = Some(now);
+            let event = Packet::TypingStarted { user_id };
+            let message = BroadcastMessage::excluding(self.address, &event).requiring(Capabilities::TYPING);
+            let _ = self.shared.broadcast_tx.send(message);
+        }
+
+        self.typing_until = Some(now + Duration::from_millis(self.shared.limits.typing_timeout_ms));
+    }
+
+    /// Tell the other users this user is no longer typing, if they were told it was
+    fn stop_typing(&mut self) {
+        if self.typing_until.take().is_none() {
+            return;
+        }
+
+        if let Some(user_id) = self.logged_in_user_id() {
+            let event = Packet::TypingStopped { user_id };
+            let message = BroadcastMessage::excluding(self.address, &event).requiring(Capabilities::TYPING);
+            let _ = self.shared.broadcast_tx.send(message);
+        }
+    }
+
+    /// Handle mark read request: move this user's read marker forward to a kept message
+    /// and tell the other users that understand it
+    fn handle_mark_read_request(&mut self, message_id: u64) {
+        let Some(user_id) = self.logged_in_user_id() else {
+            return;
+        };
+        if message_id <= self.read_marker || !self.shared.chat_history.contains(message_id) {
+            return;
+        }
+
+        self.read_marker = message_id;
+        let event = Packet::MessageRead { user_id, message_id };
+        let message = BroadcastMessage::excluding(self.address, &event).requiring(Capabilities::READ_MARKERS);
+        let _ = self.shared.broadcast_tx.send(message);
+    }
+
+    /// Handle set reaction request: add or remove the caller's reaction to a kept message,
+    /// send response to caller and tell all clients that understand it
+    async fn handle_set_reaction_request(
+        &mut self,
+        request_id: u64,
+        message_id: u64,
+        emoji: String,
+        reacted: bool,
+    ) -> Result<(), ServerError> {
+        let caller = self.shared.users.get(&self.address).and_then(|user| Some((user.id, user.username.clone()?)));
+        let valid_emoji = !emoji.is_empty()
+            && emoji.len() <= MAX_REACTION_LEN
+            && !emoji.chars().any(|c| c.is_whitespace() || c.is_control());
+        let changed = match &caller {
+            Some((_, username)) if self.has_permission(Permissions::CHAT) && valid_emoji => {
+                let (username, reaction) = (username.clone(), emoji.clone());
+                self.change_history(move |history| history.react(message_id, &username, &reaction, reacted))
+                    .await
+                    .unwrap_or(false)
+            }
+            _ => false,
+        };
+
+        let response = Packet::SetReactionResponse { request_id, success: changed };
+        self.socket.write_all(&response.encode_with(self.framing)?).await?;
+        self.socket.flush().await?;
+
+        let Some((user_id, _)) = caller.filter(|_| changed) else {
+            debug!("[{}] Reaction refused: message_id={}", self.address, message_id);
+            return Ok(());
+        };
+
+        // Sent to all clients (including caller), like the message the reaction belongs to
+        let event = Packet::MessageReactionChanged { message_id, user_id, emoji, reacted };
+        let message = BroadcastMessage::for_all(&event).requiring(Capabilities::REACTIONS);
+        let _ = self.shared.broadcast_tx.send(message);
+
+        debug!("[{}] Message reaction changed: message_id={}, reacted={}", self.address, message_id, reacted);
+
+        Ok(())
+    }
+
+    /// Handle edit message request: replace the text of one of the caller's own kept messages if the new text
+    /// follows the content rules, send response to caller and broadcast event to all clients
+    async fn handle_edit_message_request(
+        &mut self,
+        request_id: u64,
+        message_id: u64,
+        message: String,
+    ) -> Result<(), ServerError> {
+        let edited = match self.logged_in_user_id() {
+            Some(user_id) if self.has_permission(Permissions::CHAT) && self.check_chat_message(&message).is_none() => {
+                let text = message.clone();
+                self.change_history(move |history| history.edit(message_id, &text, |m| m.author_id == Some(user_id)))
+                    .await
+                    .unwrap_or(false)
+            }
+            _ => false,
+        };
+
+        let response = Packet::EditMessageResponse { request_id, success: edited };
+        self.socket.write_all(&response.encode_with(self.framing)?).await?;
+        self.socket.flush().await?;
+
+        if !edited {
+            debug!("[{}] Message edit refused: message_id={}", self.address, message_id);
+            return Ok(());
+        }
+
+        // Broadcast message edited event to all clients (including caller)
+        let edited_event = Packet::MessageEdited { message_id, message };
+        let _ = self.shared.broadcast_tx.send(BroadcastMessage::for_all(&edited_event));
+
+        debug!("[{}] Message edited: message_id={}", self.address, message_id);
+
+        Ok(())
+    }
+
+    /// Handle delete message request: remove one of the caller's own kept messages, or with the moderate
+    /// permission one whose author had a lower role, send response to caller and broadcast event to all clients
+    async fn handle_delete_message_request(&mut self, request_id: u64, message_id: u64) -> Result<(), ServerError> {
+        let caller = self
+            .shared
+            .users
+            .get(&self.address)
+            .filter(|user| user.username.is_some())
+            .map(|user| (user.id, user.role, user.permissions));
+
+        let deleted = match caller {
+            Some((user_id, role, permissions)) => self
+                .change_history(move |history| {
+                    history.delete(message_id, |m| {
+                        m.author_id == Some(user_id)
+                            || (permissions.contains(Permissions::MODERATE) && Roles::outranks(role, m.author_role))
+                    })
+                })
+                .await
+                .flatten(),
+            None => None,
+        };
+
+        let response = Packet::DeleteMessageResponse { request_id, success: deleted.is_some() };
+        self.socket.write_all(&response.encode_with(self.framing)?).await?;
+        self.socket.flush().await?;
+
+        let Some(deleted) = deleted else {
+            debug!("[{}] Message delete refused: message_id={}", self.address, message_id);
+            return Ok(());
+        };
+
+        // Files of a deleted message are no longer shared through it
+        let attachment_ids: Vec<u64> =
+            deleted.message.attachments.iter().map(|attachment| attachment.attachment_id).collect();
+        self.shared.attachments.detach(&attachment_ids);
+
+        // Broadcast message deleted event to all clients (including caller)
+        let deleted_event = Packet::MessageDeleted { message_id };
+        let _ = self.shared.broadcast_tx.send(BroadcastMessage::for_all(&deleted_event));
+
+        debug!("[{}] Message deleted: message_id={}", self.address, message_id);
+
+        Ok(())
+    }
+
+    /// Handle create channel request: send response to caller and broadcast event to all clients
+    async fn handle_create_channel_request(
+        &mut self,
+        request_id: u64,
+        name: String,
+    ) -> Result<(), ServerError> {
+        let channel = if !self.has_permission(Permissions::CREATE_CHANNELS)
+            || name.trim().is_empty()
+            || name.len() > self.shared.limits.max_channel_name_len
+        {
+            None
+        } else {
+            self.shared.channels.create(name.trim().to_string())
+        };
+
+        let Some(channel) = channel else {
+            let response = Packet::CreateChannelResponse { request_id, success: false, channel_id: 0 };
+            self.socket.write_all(&response.encode_with(self.framing)?).await?;
+            self.socket.flush().await?;
+            return Ok(());
+        };
+
+        let response = Packet::CreateChannelResponse { request_id, success: true, channel_id: channel.id };
+        self.socket.write_all(&response.encode_with(self.framing)?).await?;
+        self.socket.flush().await?;
+
+        // Broadcast channel created event to all clients (including creator)
+        let created_event = Packet::ChannelCreated { channel: channel.info() };
+        let _ = self.shared.broadcast_tx.send(BroadcastMessage::for_all(&created_event));
+
+        debug!("[{}] Channel created: id={}, name={}", self.address, channel.id, channel.name);
+
+        Ok(())
+    }
+
+    /// Handle change nickname request: send response to caller and broadcast event to all clients
+    async fn handle_change_nickname_request(
+        &mut self,
+        request_id: u64,
+        username: String,
+    ) -> Result<(), ServerError> {
+        let (user_id, old_username) = {
+            if let Some(user) = self.shared.users.get(&self.address) {
+                (user.id, user.username.clone())
+            } else {
+                return Err(ServerError::UserNotFound(self.address));
+            }
+        };
+
+        let Some(old_username) = old_username else {
+            warn!("[{}] Nickname change before login", self.address);
+            return Ok(());
+        };
+
+        // Renaming skips the password, names with their own credentials need a login with a proof
+        let case_change = old_username.eq_ignore_ascii_case(&username);
+        let rejection = if username.is_empty() || username.len() > self.shared.limits.max_username_len {
+            Some(LoginRejectReason::InvalidUsername)
+        } else if self.shared.authenticator.has_credentials(&username) && !case_change {
+            Some(LoginRejectReason::InvalidCredentials)
+        } else if self.shared.moderation.is_banned(&username, self.address.ip()) {
+            warn!("[{}] User tried to rename to a banned name: username={}", self.address, username);
+            Some(LoginRejectReason::Banned)
+        } else if self.shared.moderation.is_server_muted(&old_username) && !case_change {
+            // The mute is kept by name, a new one would lift it on the next login
+            Some(LoginRejectReason::ServerMuted)
+        } else if !self.shared.usernames.rename(&old_username, &username, self.address) {
+            Some(LoginRejectReason::NameTaken)
+        } else {
+            None
+        };
+
+        if rejection.is_none() {
+            if let Some(mut user) = self.shared.users.get_mut(&self.address) {
+                user.username = Some(username.clone());
+            }
+        }
+
+        let response = Packet::ChangeNicknameResponse { request_id, rejection };
+        self.socket.write_all(&response.encode_with(self.framing)?).await?;
+        self.socket.flush().await?;
+
+        if rejection.is_some() {
+            return Ok(());
+        }
+
+        // Broadcast user renamed event to all clients (including caller)
+        let renamed_event = Packet::UserRenamed { user_id, username: username.clone() };
+        let _ = self.shared.broadcast_tx.send(BroadcastMessage::for_all(&renamed_event));
+
+        debug!("[{}] User renamed: id={}, {} -> {}", self.address, user_id, old_username, username);
+
+        Ok(())
+    }
+
+    /// Handle list channels request: respond with all voice channels
+    async fn handle_list_channels_request(&mut self, request_id: u64) -> Result<(), ServerError> {
+        let response = Packet::ListChannelsResponse { request_id, channels: self.shared.channels.list() };
+        self.socket.write_all(&response.encode_with(self.framing)?).await?;
+        self.socket.flush().await?;
+        Ok(())
+    }
+
+    /// Handle chat history request: respond with a page of stored messages, nothing before login.
+    /// Pages hold at most `MAX_CHAT_HISTORY_PAGE_LEN` messages whatever the requested limit.
+    async fn handle_chat_history_request(
+        &mut self,
+        request_id: u64,
+        before: Option<u64>,
+        after: Option<u64>,
+        limit: u16,
+    ) -> Result<(), ServerError> {
+        let logged_in = self.shared.users.get(&self.address).is_some_and(|user| user.username.is_some());
+        let (messages, has_more) = if logged_in {
+            let limit = usize::from(limit).min(MAX_CHAT_HISTORY_PAGE_LEN);
+            self.shared.chat_history.page(before, after, limit, self.framing)
+        } else {
+            (Vec::new(), false)
+        };
+
+        debug!("[{}] Sending chat history: count={}, has_more={}", self.address, messages.len(), has_more);
+
+        let response = Packet::ChatHistoryResponse { request_id, messages, has_more };
+        self.socket.write_all(&response.encode_with(self.framing)?).await?;
+        self.socket.flush().await?;
+        Ok(())
+    }
+
+    /// Handle ping request: immediately respond with `PingResponse`
     async fn handle_ping_request(&mut self, request_id: u64) -> Result<(), ServerError> {
         let response = Packet::PingResponse { request_id };
-        self.socket.write_all(&response.encode()).await?;
+        self.socket.write_all(&response.encode_with(self.framing)?).await?;
         self.socket.flush().await?;
         Ok(())
     }
 
-    /// Handle user mute state event: broadcast to all clients excluding sender
-    async fn handle_user_mute_state(
+    /// Handle set mute state request: apply the caller's own state, respond with it and broadcast it
+    /// to all clients excluding the caller. Users who may not speak stay muted.
+    async fn handle_set_mute_state_request(
         &mut self,
-        user_id: u64,
+        request_id: u64,
         is_muted: bool,
+        is_deafened: bool,
     ) -> Result<(), ServerError> {
-        // Update user's mute state
-        {
-            if let Some(mut user) = self.server_users.get_mut(&self.address) {
-                user.is_muted = is_muted;
+        let (user_id, is_muted, deafen_changed) = {
+            if let Some(mut user) = self.shared.users.get_mut(&self.address) {
+                if !is_muted && !user.can_speak() {
+                    debug!("[{}] User not allowed to speak tried to unmute: id={}", self.address, user.id);
+                }
+                user.is_muted = is_muted || !user.can_speak();
+                let deafen_changed = user.is_deafened != is_deafened;
+                user.is_deafened = is_deafened;
+                (user.id, user.is_muted, deafen_changed)
             } else {
                 return Err(ServerError::UserNotFound(self.address));
             }
+        };
+
+        if deafen_changed {
+            let _ = self.shared.events_tx.send(Event::Deafened { id: user_id, deafened: is_deafened });
         }
 
-        // Broadcast user mute state event to all clients (excluding sender)
-        let mute_event = Packet::UserMuteState { user_id, is_muted };
-        let _ = self.broadcast_channel.send(BroadcastMessage::excluding(self.address, &mute_event));
+        let response = Packet::SetMuteStateResponse { request_id, is_muted, is_deafened };
+        self.socket.write_all(&response.encode_with(self.framing)?).await?;
+        self.socket.flush().await?;
+
+        // The id comes from the connection, clients cannot change the state of others
+        let mute_event = Packet::UserMuteState { user_id, is_muted, is_deafened };
+        let _ = self.shared.broadcast_tx.send(BroadcastMessage::excluding(self.address, &mute_event));
+
+        debug!(
+            "[{}] User mute state changed: id={}, is_muted={}, is_deafened={}",
+            self.address, user_id, is_muted, is_deafened
+        );
+
+        Ok(())
+    }
+
+    /// Handle kick user request: disconnect the target after telling everyone
+    async fn handle_kick_user_request(
+        &mut self,
+        request_id: u64,
+        user_id: u64,
+        reason: String,
+    ) -> Result<(), ServerError> {
+        let (moderator_id, _) = match self.moderation_target(user_id) {
+            Ok(target) => target,
+            Err(rejection) => return self.respond_moderation(request_id, Some(rejection)).await,
+        };
+        self.respond_moderation(request_id, None).await?;
+
+        self.broadcast_moderation(user_id, moderator_id, ModerationAction::Kicked, reason);
+        let _ = self.shared.broadcast_tx.send(BroadcastMessage::disconnecting(user_id));
+
+        debug!("[{}] User kicked: id={}, moderator_id={}", self.address, user_id, moderator_id);
+        Ok(())
+    }
+
+    /// Handle ban user request: ban the target's username and optionally its address, then disconnect it
+    async fn handle_ban_user_request(
+        &mut self,
+        request_id: u64,
+        user_id: u64,
+        reason: String,
+        ban_address: bool,
+    ) -> Result<(), ServerError> {
+        let (moderator_id, target) = match self.moderation_target(user_id) {
+            Ok(target) => target,
+            Err(rejection) => return self.respond_moderation(request_id, Some(rejection)).await,
+        };
+        self.shared.moderation.ban(&target.username, ban_address.then_some(target.address.ip()));
+        self.respond_moderation(request_id, None).await?;
+
+        self.broadcast_moderation(user_id, moderator_id, ModerationAction::Banned, reason);
+        let _ = self.shared.broadcast_tx.send(BroadcastMessage::disconnecting(user_id));
+
+        debug!(
+            "[{}] User banned: id={}, username={}, address={}, moderator_id={}",
+            self.address,
+            user_id,
+            target.username,
+            if ban_address { target.address.ip().to_string() } else { "-".to_string() },
+            moderator_id
+        );
+        Ok(())
+    }
+
+    /// Handle server mute user request: stop or resume relaying the target's voice
+    async fn handle_server_mute_user_request(
+        &mut self,
+        request_id: u64,
+        user_id: u64,
+        muted: bool,
+    ) -> Result<(), ServerError> {
+        let (moderator_id, target) = match self.moderation_target(user_id) {
+            Ok(target) => target,
+            Err(rejection) => return self.respond_moderation(request_id, Some(rejection)).await,
+        };
+
+        // Unmuting leaves the user muted until it unmutes itself, and users without the speak permission muted
+        let (can_speak, is_deafened) = if let Some(mut user) = self.shared.users.get_mut(&target.address) {
+            user.server_muted = muted;
+            if muted {
+                user.is_muted = true;
+            }
+            (user.can_speak(), user.is_deafened)
+        } else {
+            (!muted, false)
+        };
+        self.shared.moderation.set_server_muted(&target.username, muted);
+        let _ = self.shared.events_tx.send(Event::ServerMuted { id: user_id, muted: !can_speak });
+        self.respond_moderation(request_id, None).await?;
+
+        if muted {
+            let mute_event = Packet::UserMuteState { user_id, is_muted: true, is_deafened };
+            let _ = self.shared.broadcast_tx.send(BroadcastMessage::for_all(&mute_event));
+        }
+        let action = if muted { ModerationAction::ServerMuted } else { ModerationAction::ServerUnmuted };
+        self.broadcast_moderation(user_id, moderator_id, action, String::new());
 
         debug!(
-            "[{}] User mute state changed: id={}, is_muted={}",
-            self.address, user_id, is_muted
+            "[{}] User server mute changed: id={}, muted={}, moderator_id={}",
+            self.address, user_id, muted, moderator_id
         );
+        Ok(())
+    }
+
+    /// Handle unban user request: lift the ban of a username the caller outranks, and of the addresses banned with it
+    async fn handle_unban_user_request(&mut self, request_id: u64, username: String) -> Result<(), ServerError> {
+        let moderator = self
+            .shared
+            .users
+            .get(&self.address)
+            .filter(|user| user.permissions.contains(Permissions::MODERATE))
+            .map(|user| (user.id, user.role));
+
+        let rejection = match moderator {
+            Some((_, role)) if !Roles::outranks(role, self.shared.roles.role_of(&username)) => {
+                Some(ModerationRejectReason::NotPermitted)
+            }
+            Some(_) if !self.shared.moderation.unban(&username) => Some(ModerationRejectReason::UnknownUser),
+            Some(_) => None,
+            None => Some(ModerationRejectReason::NotPermitted),
+        };
+        self.respond_moderation(request_id, rejection).await?;
+
+        if let (Some((moderator_id, _)), None) = (moderator, rejection) {
+            debug!("[{}] User unbanned: username={}, moderator_id={}", self.address, username, moderator_id);
+        }
+        Ok(())
+    }
+
+    /// Returns the caller's user id and the logged in user with id `user_id`
+    /// if the caller may moderate and outranks the target
+    fn moderation_target(&self, user_id: u64) -> Result<(u64, ModerationTarget), ModerationRejectReason> {
+        let (moderator_id, moderator_role) = self
+            .shared
+            .users
+            .get(&self.address)
+            .filter(|user| user.permissions.contains(Permissions::MODERATE))
+            .map(|user| (user.id, user.role))
+            .ok_or(ModerationRejectReason::NotPermitted)?;
+
+        let target = self
+            .shared
+            .users
+            .iter()
+            .find(|entry| entry.value().id == user_id)
+            .and_then(|entry| {
+                let username = entry.value().username.clone()?;
+                Some(ModerationTarget { address: *entry.key(), username, role: entry.value().role })
+            })
+            .ok_or(ModerationRejectReason::UnknownUser)?;
+
+        // Nobody can act on users of the same or a higher role, themselves included
+        if !Roles::outranks(moderator_role, target.role) {
+            return Err(ModerationRejectReason::NotPermitted);
+        }
+
+        Ok((moderator_id, target))
+    }
+
+    /// Checks a chat message, direct message or edit against the content rules, `None` if it may be sent
+    fn check_chat_message(&self, message: &str) -> Option<ChatRejectReason> {
+        if message.trim().is_empty() {
+            Some(ChatRejectReason::Empty)
+        } else if message.len() > self.shared.limits.max_chat_message_len {
+            Some(ChatRejectReason::TooLong)
+        } else if message.chars().any(|c| c.is_control() && c != '\n' && c != '\t') {
+            Some(ChatRejectReason::InvalidCharacters)
+        } else {
+            None
+        }
+    }
+
+    /// Runs a change to the chat history on a blocking thread, it waits until the change is in the history file.
+    /// Resolves to `None` if the change panicked.
+    fn change_history<T: Send + 'static>(
+        &self,
+        change: impl FnOnce(&ChatHistory) -> T + Send + 'static,
+    ) -> impl std::future::Future<Output = Option<T>> {
+        let chat_history = Arc::clone(&self.shared.chat_history);
+        async move { tokio::task::spawn_blocking(move || change(&chat_history)).await.ok() }
+    }
+
+    /// Looks up the attachments of a chat message, all of them fully uploaded by `user_id`
+    fn check_attachments(&self, user_id: u64, attachments: &[u64]) -> Result<Vec<Attachment>, ChatRejectReason> {
+        if attachments.len() > MAX_ATTACHMENTS_PER_MESSAGE {
+            return Err(ChatRejectReason::TooManyAttachments);
+        }
+
+        attachments
+            .iter()
+            .map(|&attachment_id| self.shared.attachments.complete(user_id, attachment_id))
+            .collect::<Option<Vec<_>>>()
+            .ok_or(ChatRejectReason::UnknownAttachment)
+    }
+
+    /// Ids of the logged in users mentioned with `@username` in `message`, names compared ignoring ASCII case.
+    /// The longest matching name wins, so `@alice` does not also mention a user called `ali`.
+    fn resolve_mentions(&self, message: &str) -> Vec<u64> {
+        let users: Vec<(u64, String)> = self
+            .shared
+            .users
+            .iter()
+            .filter_map(|user| Some((user.id, user.username.clone()?)))
+            .collect();
+
+        let mut mentions = Vec::new();
+        for (index, _) in message.match_indices('@') {
+            let rest = &message[index + 1..];
+            let mentions_user = |name: &str| {
+                rest.get(..name.len()).is_some_and(|prefix| prefix.eq_ignore_ascii_case(name))
+                    && !rest[name.len()..].starts_with(|c: char| c.is_alphanumeric() || c == '_')
+            };
+
+            let Some(len) = users.iter().filter(|(_, name)| mentions_user(name)).map(|(_, name)| name.len()).max()
+            else {
+                continue;
+            };
+            for (user_id, name) in &users {
+                if name.len() == len && mentions_user(name) && !mentions.contains(user_id) {
+                    mentions.push(*user_id);
+                }
+            }
+        }
+        mentions
+    }
+
+    /// Returns the id of the logged in user, `None` before login
+    fn logged_in_user_id(&self) -> Option<u64> {
+        self.shared.users.get(&self.address).filter(|user| user.username.is_some()).map(|user| user.id)
+    }
+
+    /// Returns true if the logged in user has `permission`, nothing is permitted before login
+    fn has_permission(&self, permission: Permissions) -> bool {
+        self.shared.users.get(&self.address).is_some_and(|user| user.permissions.contains(permission))
+    }
 
+    async fn respond_moderation(
+        &mut self,
+        request_id: u64,
+        rejection: Option<ModerationRejectReason>,
+    ) -> Result<(), ServerError> {
+        let response = Packet::ModerationResponse { request_id, rejection };
+        self.socket.write_all(&response.encode_with(self.framing)?).await?;
+        self.socket.flush().await?;
         Ok(())
     }
 
+    /// Tell every client that understands it about a moderation action
+    fn broadcast_moderation(&self, user_id: u64, moderator_id: u64, action: ModerationAction, reason: String) {
+        let event = Packet::UserModerated { user_id, moderator_id, action, reason };
+        let message = BroadcastMessage::for_all(&event).requiring(Capabilities::MODERATION);
+        let _ = self.shared.broadcast_tx.send(message);
+    }
+
     /// Handle user disconnection: remove from users map and broadcast left server event
-    async fn handle_disconnect(&mut self) {
+    fn handle_disconnect(&mut self) {
         // Remove user from the users DashMap
-        let user_option = self.server_users.remove(&self.address).map(|(_, user)| user);
+        let user_option = self.shared.users.remove(&self.address).map(|(_, user)| user);
 
         // If user was found, broadcast the disconnection and log
         if let Some(user) = user_option {
+            // The voice relay keeps counting the sequence numbers of a user who may resume
+            let resume_token = user.username.as_ref().and(self.resume_token.take());
+            let event = if resume_token.is_some() {
+                Event::UserSuspended { id: user.id }
+            } else {
+                Event::UserDisconnected { id: user.id }
+            };
+            let _ = self.shared.events_tx.send(event);
+
+            if let Some(username) = &user.username {
+                self.shared.usernames.release(username, self.address);
+
+                // Keep the id for a client resuming with its token
+                if let Some(token) = resume_token {
+                    for expired in self.shared.resume_tokens.reserve(token, user.id, username.clone()) {
+                        let _ = self.shared.events_tx.send(Event::UserDisconnected { id: expired });
+                    }
+                }
+            }
+
             // Broadcast user left server event to all clients
             let left_event = Packet::UserLeftServer { user_id: user.id };
-            let _ = self.broadcast_channel.send(BroadcastMessage::for_all(&left_event));
+            let _ = self.shared.broadcast_tx.send(BroadcastMessage::for_all(&left_event));
 
             // If user was in voice channel, broadcast user left voice event
-            if user.in_voice {
+            if user.channel_id.is_some() {
                 let left_voice_event = Packet::UserLeftVoice { user_id: user.id };
-                let _ = self.broadcast_channel.send(BroadcastMessage::for_all(&left_voice_event));
+                let _ = self.shared.broadcast_tx.send(BroadcastMessage::for_all(&left_voice_event));
             }
 
             debug!(
@@ -1,4 +1,12 @@
+pub mod attachments;
+pub mod auth;
 pub mod broadcast;
+pub mod channel;
+pub mod chat_history;
 pub mod handler;
+pub mod moderation;
+pub mod resume;
+pub mod role;
 pub mod server;
-pub mod user;
\ No newline at end of file
+pub mod tls;
+pub mod user;
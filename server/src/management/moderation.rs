@@ -0,0 +1,211 @@
+use dashmap::{DashMap, DashSet};
+use std::fmt;
+use std::fs::OpenOptions;
+use std::io::{ErrorKind, Write};
+use std::net::IpAddr;
+use std::path::{Path, PathBuf};
+use std::sync::{Mutex, PoisonError};
+use tracing::error;
+use crate::error::ServerError;
+
+/// Bans and server mutes, shared between all user handlers.
+/// Who may moderate is decided by [`Roles`](crate::management::role::Roles).
+///
+/// Usernames are compared ignoring ASCII case whatever the username policy,
+/// so a ban cannot be dodged by changing the case of the name.
+#[derive(Default)]
+pub struct Moderation {
+    banned_usernames: DashSet<String>,
+    banned_addresses: DashMap<IpAddr, Option<String>>, // Lowercase username banned along with the address
+    server_muted: DashSet<String>, // Kept across reconnects, and across restarts with a bans file
+    bans_file: Option<Mutex<PathBuf>>, // Bans and server mutes are saved here, one writer at a time
+}
+
+/// One line of the bans file.
+#[derive(Debug, PartialEq, Eq)]
+enum Entry {
+    User(String),
+    Address(IpAddr, Option<String>),
+    ServerMuted(String),
+}
+
+impl Entry {
+    /// Parses `user:<username>`, `ip:<address>` optionally followed by the username banned along with it,
+    /// or `mute:<username>`, usernames lowercased.
+    fn parse(line: &str) -> Option<Self> {
+        let username = |username: &str| Some(username.trim().to_ascii_lowercase()).filter(|name| !name.is_empty());
+        match line.split_once(':')? {
+            ("user", name) => username(name).map(Self::User),
+            ("mute", name) => username(name).map(Self::ServerMuted),
+            ("ip", rest) => {
+                let rest = rest.trim();
+                let (address, name) = rest.split_once(char::is_whitespace).unwrap_or((rest, ""));
+                Some(Self::Address(address.parse().ok()?, username(name)))
+            }
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for Entry {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::User(username) => write!(f, "user:{username}"),
+            Self::Address(address, Some(username)) => write!(f, "ip:{address} {username}"),
+            Self::Address(address, None) => write!(f, "ip:{address}"),
+            Self::ServerMuted(username) => write!(f, "mute:{username}"),
+        }
+    }
+}
+
+impl Moderation {
+    /// Loads bans and server mutes from `path` and saves changes to it, a missing file counts as empty.
+    /// The file has one `user:<username>`, `ip:<address>` or `mute:<username>` entry per line,
+    /// `#` starts a comment. Address bans may name the user banned along with them, `ip:<address> <username>`.
+    ///
+    /// # Errors
+    ///
+    /// Fails if the file cannot be read or has a malformed line.
+    pub fn with_bans_file(mut self, path: &Path) -> Result<Self, ServerError> {
+        let contents = match std::fs::read_to_string(path) {
+            Ok(contents) => contents,
+            Err(e) if e.kind() == ErrorKind::NotFound => String::new(),
+            Err(e) => return Err(e.into()),
+        };
+
+        for (index, line) in contents.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            match Entry::parse(line) {
+                Some(Entry::User(username)) => {
+                    self.banned_usernames.insert(username);
+                }
+                Some(Entry::Address(address, username)) => {
+                    self.banned_addresses.insert(address, username);
+                }
+                Some(Entry::ServerMuted(username)) => {
+                    self.server_muted.insert(username);
+                }
+                None => {
+                    return Err(ServerError::InvalidBansFile(format!(
+                        "{}: line {} is not `user:<username>`, `ip:<address>` or `mute:<username>`",
+                        path.display(),
+                        index + 1
+                    )));
+                }
+            }
+        }
+
+        self.bans_file = Some(Mutex::new(path.to_path_buf()));
+        Ok(self)
+    }
+
+    /// Returns true if the username or the address is banned.
+    #[must_use]
+    pub fn is_banned(&self, username: &str, address: IpAddr) -> bool {
+        self.banned_usernames.contains(&username.to_ascii_lowercase()) || self.banned_addresses.contains_key(&address)
+    }
+
+    /// Bans `username`, and `address` if set, saving the bans to the bans file if there is one.
+    pub fn ban(&self, username: &str, address: Option<IpAddr>) {
+        let username = username.to_ascii_lowercase();
+        let mut added = Vec::new();
+
+        if self.banned_usernames.insert(username.clone()) {
+            added.push(Entry::User(username.clone()));
+        }
+        if let Some(address) = address {
+            if let dashmap::Entry::Vacant(vacant) = self.banned_addresses.entry(address) {
+                vacant.insert(Some(username.clone()));
+                added.push(Entry::Address(address, Some(username)));
+            }
+        }
+
+        self.save(&added, &[]);
+    }
+
+    /// Lifts the ban of `username` and of the addresses banned along with it, removing them from the bans file
+    /// if there is one. Returns false if neither was banned.
+    pub fn unban(&self, username: &str) -> bool {
+        let username = username.to_ascii_lowercase();
+        let mut removed = Vec::new();
+
+        if self.banned_usernames.remove(&username).is_some() {
+            removed.push(Entry::User(username.clone()));
+        }
+        self.banned_addresses.retain(|address, banned| {
+            if banned.as_ref() != Some(&username) {
+                return true;
+            }
+            removed.push(Entry::Address(*address, banned.take()));
+            false
+        });
+
+        self.save(&[], &removed);
+        !removed.is_empty()
+    }
+
+    /// Sets whether the voice of users logging in with `username` is relayed, saving the change to the bans file
+    /// if there is one.
+    pub fn set_server_muted(&self, username: &str, muted: bool) {
+        let username = username.to_ascii_lowercase();
+        if muted {
+            if self.server_muted.insert(username.clone()) {
+                self.save(&[Entry::ServerMuted(username)], &[]);
+            }
+        } else if self.server_muted.remove(&username).is_some() {
+            self.save(&[], &[Entry::ServerMuted(username)]);
+        }
+    }
+
+    /// Returns true if the voice of users logging in with `username` is not relayed.
+    #[must_use]
+    pub fn is_server_muted(&self, username: &str) -> bool {
+        self.server_muted.contains(&username.to_ascii_lowercase())
+    }
+
+    /// Appends `added` to the bans file and drops the lines of `removed` from it, keeping everything else
+    fn save(&self, added: &[Entry], removed: &[Entry]) {
+        let Some(bans_file) = &self.bans_file else {
+            return;
+        };
+
+        let path = bans_file.lock().unwrap_or_else(PoisonError::into_inner);
+        if let Err(e) = Self::append(&path, added).and_then(|()| Self::remove(&path, removed)) {
+            error!("Failed to save bans to {}: {e}", path.display());
+        }
+    }
+
+    fn append(path: &Path, entries: &[Entry]) -> std::io::Result<()> {
+        if entries.is_empty() {
+            return Ok(());
+        }
+
+        let mut file = OpenOptions::new().create(true).append(true).open(path)?;
+        for entry in entries {
+            writeln!(file, "{entry}")?;
+        }
+        Ok(())
+    }
+
+    fn remove(path: &Path, entries: &[Entry]) -> std::io::Result<()> {
+        if entries.is_empty() {
+            return Ok(());
+        }
+
+        let contents = match std::fs::read_to_string(path) {
+            Ok(contents) => contents,
+            Err(e) if e.kind() == ErrorKind::NotFound => return Ok(()),
+            Err(e) => return Err(e),
+        };
+        let kept: String = contents
+            .lines()
+            .filter(|line| Entry::parse(line.trim()).is_none_or(|entry| !entries.contains(&entry)))
+            .flat_map(|line| [line, "\n"])
+            .collect();
+        std::fs::write(path, kept)
+    }
+}
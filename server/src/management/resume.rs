@@ -0,0 +1,70 @@
+use dashmap::DashMap;
+use std::time::{Duration, Instant};
+
+/// User id of a dropped connection, kept for a client resuming with its token
+#[derive(Clone, Debug)]
+pub struct Reservation {
+    pub user_id: u64,
+    pub username: String,
+    expires_at: Instant,
+}
+
+/// Ids of recently disconnected users by resume token, shared between all user handlers
+pub struct ResumeTokens {
+    window: Duration, // Resume is disabled if zero
+    reserved: DashMap<u64, Reservation>,
+}
+
+impl ResumeTokens {
+    #[must_use]
+    pub fn new(window: Duration) -> Self {
+        Self {
+            window,
+            reserved: DashMap::new(),
+        }
+    }
+
+    /// Whether logged in users get a resume token
+    #[must_use]
+    pub fn is_enabled(&self) -> bool {
+        !self.window.is_zero()
+    }
+
+    /// Keeps `user_id` for `token` until the resume window ends, forgetting reservations that already expired.
+    /// Returns the user ids of the forgotten reservations, their sessions can no longer be resumed.
+    #[must_use]
+    pub fn reserve(&self, token: u64, user_id: u64, username: String) -> Vec<u64> {
+        let now = Instant::now();
+        let mut expired = Vec::new();
+        self.reserved.retain(|_, reservation| {
+            let keep = reservation.expires_at > now;
+            if !keep {
+                expired.push(reservation.user_id);
+            }
+            keep
+        });
+        self.reserved.insert(token, Reservation {
+            user_id,
+            username,
+            expires_at: now + self.window,
+        });
+        expired
+    }
+
+    /// Returns true if `token` has a reservation that has not expired.
+    #[must_use]
+    pub fn is_reserved(&self, token: u64) -> bool {
+        let now = Instant::now();
+        self.reserved.get(&token).is_some_and(|reservation| reservation.expires_at > now)
+    }
+
+    /// Removes and returns the reservation for `token` if it has not expired and `matches` it,
+    /// others are left in place for their owner, and expired ones to `reserve`.
+    #[must_use]
+    pub fn take_if(&self, token: u64, matches: impl FnOnce(&Reservation) -> bool) -> Option<Reservation> {
+        let now = Instant::now();
+        self.reserved
+            .remove_if(&token, |_, reservation| reservation.expires_at > now && matches(reservation))
+            .map(|(_, reservation)| reservation)
+    }
+}
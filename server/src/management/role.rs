@@ -0,0 +1,128 @@
+use std::collections::HashMap;
+use serde::Deserialize;
+use voiceapp_protocol::{Permissions, Role};
+
+/// Role names used in the `[roles]` config section
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum RoleName {
+    Guest,
+    Member,
+    Moderator,
+    Admin,
+}
+
+impl From<RoleName> for Role {
+    fn from(name: RoleName) -> Self {
+        match name {
+            RoleName::Guest => Role::Guest,
+            RoleName::Member => Role::Member,
+            RoleName::Moderator => Role::Moderator,
+            RoleName::Admin => Role::Admin,
+        }
+    }
+}
+
+/// Permission names used in the `[roles.permissions]` config section
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum PermissionName {
+    Chat,
+    JoinVoice,
+    Speak,
+    Moderate,
+    CreateChannels,
+}
+
+impl From<PermissionName> for Permissions {
+    fn from(name: PermissionName) -> Self {
+        match name {
+            PermissionName::Chat => Permissions::CHAT,
+            PermissionName::JoinVoice => Permissions::JOIN_VOICE,
+            PermissionName::Speak => Permissions::SPEAK,
+            PermissionName::Moderate => Permissions::MODERATE,
+            PermissionName::CreateChannels => Permissions::CREATE_CHANNELS,
+        }
+    }
+}
+
+/// Role of every user and what each role may do, shared between all user handlers.
+///
+/// Usernames are compared ignoring ASCII case, like bans.
+pub struct Roles {
+    default: Role,
+    assigned: HashMap<String, Role>,
+    permissions: HashMap<Role, Permissions>, // Overrides of `default_permissions`
+}
+
+impl Default for Roles {
+    fn default() -> Self {
+        Self::new(Role::Member)
+    }
+}
+
+impl Roles {
+    /// Gives `default` to every user not assigned a role with [`Roles::with_user`].
+    #[must_use]
+    pub fn new(default: Role) -> Self {
+        Self {
+            default,
+            assigned: HashMap::new(),
+            permissions: HashMap::new(),
+        }
+    }
+
+    /// Assigns `role` to users logging in with `username`.
+    #[must_use]
+    pub fn with_user(mut self, username: &str, role: Role) -> Self {
+        self.assigned.insert(username.to_ascii_lowercase(), role);
+        self
+    }
+
+    /// Replaces the permissions of `role`.
+    #[must_use]
+    pub fn with_permissions(mut self, role: Role, permissions: Permissions) -> Self {
+        self.permissions.insert(role, permissions);
+        self
+    }
+
+    /// Returns the role of users logging in with `username`.
+    #[must_use]
+    pub fn role_of(&self, username: &str) -> Role {
+        self.assigned.get(&username.to_ascii_lowercase()).copied().unwrap_or(self.default)
+    }
+
+    /// Returns what users with `role` may do.
+    #[must_use]
+    pub fn permissions(&self, role: Role) -> Permissions {
+        self.permissions.get(&role).copied().unwrap_or_else(|| Self::default_permissions(role))
+    }
+
+    /// Returns true if a user with role `a` may moderate one with role `b`.
+    #[must_use]
+    pub fn outranks(a: Role, b: Role) -> bool {
+        Self::rank(a) > Self::rank(b)
+    }
+
+    /// Guests only chat and listen, members also speak and create channels,
+    /// moderators and admins may do everything.
+    fn default_permissions(role: Role) -> Permissions {
+        match role {
+            Role::Admin | Role::Moderator => Permissions::ALL,
+            Role::Member => {
+                Permissions::CHAT | Permissions::JOIN_VOICE | Permissions::SPEAK | Permissions::CREATE_CHANNELS
+            }
+            Role::Guest => Permissions::CHAT | Permissions::JOIN_VOICE,
+            _ => Permissions::NONE,
+        }
+    }
+
+    fn rank(role: Role) -> u8 {
+        match role {
+            Role::Admin => 3,
+            Role::Moderator => 2,
+            Role::Member => 1,
+            _ => 0,
+        }
+    }
+}
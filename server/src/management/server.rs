@@ -3,71 +3,271 @@ use rand::random;
 use std::net::SocketAddr;
 use std::sync::atomic::{AtomicU64, Ordering};
 use std::sync::Arc;
+use std::time::Duration;
 use tokio::net::TcpListener;
-use tokio::sync::{broadcast, mpsc};
+use tokio_rustls::TlsAcceptor;
+use tokio::sync::{broadcast, mpsc, watch};
+use tokio::task::JoinSet;
 use tokio::sync::mpsc::{UnboundedReceiver, UnboundedSender};
-use tracing::{error, info};
-use crate::config::BROADCAST_CHANNEL_CAPACITY;
+use tracing::{error, info, warn};
+use voiceapp_protocol::{Permissions, Role};
+use crate::config::Limits;
 use crate::event::Event;
-use crate::management::user::User;
-use crate::management::handler::UserHandler;
+use crate::management::broadcast::BroadcastMessage;
+use crate::management::attachments::AttachmentStore;
+use crate::management::auth::Authenticator;
+use crate::management::channel::Channels;
+use crate::management::chat_history::ChatHistory;
+use crate::management::moderation::Moderation;
+use crate::management::role::Roles;
+use crate::management::resume::ResumeTokens;
+use crate::management::user::{User, UsernamePolicy, Usernames};
+use crate::management::handler::{ManagementStream, UserHandler};
 
-/// ManagementServer handles TCP connections, user login, presence management,
+/// How long a client gets to finish the TLS handshake before its connection is dropped
+const TLS_HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Reason and reconnect hint sent to clients when the server shuts down.
+#[derive(Clone, Debug)]
+pub struct ShutdownNotice {
+    pub reason: String,
+    /// Suggested delay before reconnecting, zero if the server is not expected back
+    pub reconnect_after: Duration,
+}
+
+/// Shuts down a running `ManagementServer`, see [`ManagementServer::shutdown_handle`].
+#[derive(Clone, Debug)]
+pub struct ShutdownHandle(Arc<watch::Sender<Option<ShutdownNotice>>>);
+
+impl ShutdownHandle {
+    /// Sends the notice to every connected client, closes their connections and stops accepting new ones.
+    pub fn shutdown(&self, notice: ShutdownNotice) {
+        self.0.send_replace(Some(notice));
+    }
+}
+
+/// Resolves with the notice once the server shuts down, `None` if the server was dropped.
+pub(crate) async fn shutdown_notice(
+    shutdown_rx: &mut watch::Receiver<Option<ShutdownNotice>>,
+) -> Option<ShutdownNotice> {
+    shutdown_rx.wait_for(Option::is_some).await.ok().and_then(|notice| notice.clone())
+}
+
+/// Closes the connection of a user through a running `ManagementServer`, see [`ManagementServer::disconnect_handle`].
+#[derive(Clone, Debug)]
+pub struct DisconnectHandle(broadcast::Sender<BroadcastMessage>);
+
+impl DisconnectHandle {
+    /// Disconnects the user as if its client had closed the connection, does nothing if it is not connected.
+    pub fn disconnect(&self, user_id: u64) {
+        let _ = self.0.send(BroadcastMessage::disconnecting(user_id));
+    }
+}
+
+/// State shared by the management server and the handlers of all its connections.
+#[derive(Clone)]
+pub struct SharedState {
+    pub users: Arc<DashMap<SocketAddr, User>>,
+    pub channels: Arc<Channels>,
+    pub authenticator: Arc<Authenticator>,
+    pub limits: Limits,
+    pub motd: Option<Arc<str>>, // Sent after login to clients that support it
+    pub usernames: Arc<Usernames>,
+    pub resume_tokens: Arc<ResumeTokens>,
+    pub roles: Arc<Roles>,
+    pub moderation: Arc<Moderation>,
+    pub chat_history: Arc<ChatHistory>,
+    pub attachments: Arc<AttachmentStore>,
+    pub broadcast_tx: broadcast::Sender<BroadcastMessage>,
+    pub events_tx: UnboundedSender<Event>,
+}
+
+/// `ManagementServer` handles TCP connections, user login, presence management,
 /// and broadcasts events to all connected clients.
 pub struct ManagementServer {
-    users: Arc<DashMap<SocketAddr, User>>,
+    shared: SharedState,
+    tls_acceptor: Option<TlsAcceptor>, // Plain TCP if None
     next_user_id: Arc<AtomicU64>,
-    events_tx: UnboundedSender<Event>,
+    shutdown_tx: Arc<watch::Sender<Option<ShutdownNotice>>>,
 }
 
 impl ManagementServer {
-    /// Creates a new ManagementServer and returns the event receiver for VoiceRelayServer.
+    /// Creates a new `ManagementServer` with the given voice channels, login authenticator,
+    /// resource limits and duplicate username policy,
+    /// returns the event receiver for `VoiceRelayServer`.
     #[must_use]
-    pub fn new() -> (Self, UnboundedReceiver<Event>) {
+    pub fn new(
+        voice_channels: Vec<String>,
+        authenticator: Authenticator,
+        limits: Limits,
+        username_policy: UsernamePolicy,
+    ) -> (Self, UnboundedReceiver<Event>) {
         let (events_tx, events_rx) = mpsc::unbounded_channel();
+        let (shutdown_tx, _) = watch::channel(None);
+        let (broadcast_tx, _) = broadcast::channel(limits.broadcast_channel_capacity);
 
         let server = ManagementServer {
-            users: Arc::new(DashMap::new()),
+            shared: SharedState {
+                users: Arc::new(DashMap::new()),
+                channels: Arc::new(Channels::new(voice_channels)),
+                authenticator: Arc::new(authenticator),
+                limits,
+                motd: None,
+                usernames: Arc::new(Usernames::new(username_policy)),
+                resume_tokens: Arc::new(ResumeTokens::new(Duration::from_millis(limits.resume_window_ms))),
+                roles: Arc::new(Roles::default()),
+                moderation: Arc::new(Moderation::default()),
+                chat_history: Arc::new(ChatHistory::default()),
+                attachments: Arc::new(AttachmentStore::default()),
+                broadcast_tx,
+                events_tx,
+            },
+            tls_acceptor: None,
             next_user_id: Arc::new(AtomicU64::new(1)),
-            events_tx,
+            shutdown_tx: Arc::new(shutdown_tx),
         };
 
         (server, events_rx)
     }
 
-    /// Start the TCP listener and accept client connections on the given port.
-    pub async fn run(&self, port: u16) -> Result<(), crate::error::ServerError> {
-        let addr = format!("0.0.0.0:{}", port);
-        let listener = TcpListener::bind(&addr).await?;
+    /// Requires clients to connect over TLS, see [`crate::load_tls_acceptor`].
+    #[must_use]
+    pub fn with_tls(mut self, tls_acceptor: TlsAcceptor) -> Self {
+        self.tls_acceptor = Some(tls_acceptor);
+        self
+    }
+
+    /// Sends `motd` to every user after login.
+    #[must_use]
+    pub fn with_motd(mut self, motd: String) -> Self {
+        self.shared.motd = Some(motd.into());
+        self
+    }
+
+    /// Sets the role of every user and the permissions of each role, everyone is a member by default.
+    #[must_use]
+    pub fn with_roles(mut self, roles: Roles) -> Self {
+        self.shared.roles = Arc::new(roles);
+        self
+    }
+
+    /// Sets which users and addresses are banned, loaded from a bans file, nobody by default.
+    #[must_use]
+    pub fn with_moderation(mut self, moderation: Moderation) -> Self {
+        self.shared.moderation = Arc::new(moderation);
+        self
+    }
+
+    /// Sets where chat messages are kept for users who log in later, the newest messages in memory by default.
+    #[must_use]
+    pub fn with_chat_history(mut self, chat_history: ChatHistory) -> Self {
+        self.shared.chat_history = Arc::new(chat_history);
+        self
+    }
+
+    /// Sets where files attached to chat messages are kept and how large they may be, in memory by default.
+    #[must_use]
+    pub fn with_attachments(mut self, attachments: AttachmentStore) -> Self {
+        self.shared.attachments = Arc::new(attachments);
+        self
+    }
+
+    /// Returns a handle that shuts the server down, after which [`ManagementServer::run`]
+    /// returns once every connection is closed.
+    #[must_use]
+    pub fn shutdown_handle(&self) -> ShutdownHandle {
+        ShutdownHandle(self.shutdown_tx.clone())
+    }
+
+    /// Returns a handle that disconnects single users, e.g. for the voice relay to drop users whose voice
+    /// socket went quiet.
+    #[must_use]
+    pub fn disconnect_handle(&self) -> DisconnectHandle {
+        DisconnectHandle(self.shared.broadcast_tx.clone())
+    }
+
+    /// Start the TCP listener and accept client connections on the given address.
+    ///
+    /// # Errors
+    ///
+    /// Fails if the address cannot be bound or accepting a connection fails.
+    pub async fn run(&self, addr: SocketAddr) -> Result<(), crate::error::ServerError> {
+        let listener = TcpListener::bind(addr).await?;
         let local_addr = listener.local_addr()?;
-        let (broadcast_tx, _) = broadcast::channel(BROADCAST_CHANNEL_CAPACITY);
-        info!("ManagementServer listening on {}", local_addr);
+        info!(
+            "ManagementServer listening on {}{}",
+            local_addr,
+            if self.tls_acceptor.is_some() { " (TLS)" } else { "" }
+        );
+
+        // Files of kept messages, loaded from the history file and attachments dir, stay downloadable for everyone
+        self.shared.attachments.recount(&self.shared.chat_history.attachment_ids());
+
+        let mut shutdown_rx = self.shutdown_tx.subscribe();
+        let mut connections = JoinSet::new();
 
         loop {
-            let (socket, peer_addr) = listener.accept().await?;
+            let (socket, peer_addr) = tokio::select! {
+                accepted = listener.accept() => accepted?,
+                _ = shutdown_notice(&mut shutdown_rx) => break,
+            };
+
             let user = self.register_new_user(peer_addr);
-            let users = self.users.clone();
-            let broadcast_tx = broadcast_tx.clone();
-            let events_tx = self.events_tx.clone();
+            let shared = self.shared.clone();
+            let tls_acceptor = self.tls_acceptor.clone();
+            let mut shutdown_rx = self.shutdown_tx.subscribe();
 
-            tokio::spawn(async move {
-                let _ = events_tx.send(Event::UserConnected { id: user.id, token: user.token });
+            connections.spawn(async move {
+                let _ = shared.events_tx.send(Event::UserConnected {
+                    id: user.id,
+                    token: user.token,
+                    voice_key: user.voice_key,
+                });
 
-                let mut user_handler = UserHandler::new(
-                    users,
-                    socket,
-                    peer_addr,
-                    broadcast_tx,
-                    events_tx.clone(),
-                );
+                let socket: Box<dyn ManagementStream> = match tls_acceptor {
+                    Some(acceptor) => {
+                        let handshake = tokio::time::timeout(TLS_HANDSHAKE_TIMEOUT, acceptor.accept(socket));
+                        let accepted = tokio::select! {
+                            result = handshake => match result {
+                                Ok(Ok(stream)) => Ok(stream),
+                                Ok(Err(e)) => Err(format!("TLS handshake failed: {e}")),
+                                Err(_) => Err("TLS handshake timed out".to_string()),
+                            },
+                            Some(_) = shutdown_notice(&mut shutdown_rx) => {
+                                Err("Server shut down during the TLS handshake".to_string())
+                            }
+                        };
+                        match accepted {
+                            Ok(stream) => Box::new(stream),
+                            Err(reason) => {
+                                warn!("[{}] {}", peer_addr, reason);
+                                shared.users.remove(&peer_addr);
+                                let _ = shared.events_tx.send(Event::UserDisconnected { id: user.id });
+                                return;
+                            }
+                        }
+                    }
+                    None => Box::new(socket),
+                };
 
+                let mut user_handler = UserHandler::new(shared, socket, peer_addr, shutdown_rx);
+
+                // The handler tells the voice relay about the disconnect, the user id may have changed on resume
                 if let Err(e) = user_handler.handle().await {
                     error!("[{}] Error: {}", peer_addr, e);
                 }
-
-                let _ = events_tx.send(Event::UserDisconnected { id: user.id });
             });
+
+            // Forget connections that already closed
+            while connections.try_join_next().is_some() {}
         }
+
+        drop(listener);
+        info!("ManagementServer stopped accepting connections, closing {} connections", connections.len());
+        while connections.join_next().await.is_some() {}
+        info!("ManagementServer stopped");
+
+        Ok(())
     }
 
     fn register_new_user(&self, address: SocketAddr) -> User {
@@ -76,12 +276,18 @@ impl ManagementServer {
         let user = User {
             id: user_id,
             username: None,
-            in_voice: false,
+            channel_id: None,
             is_muted: false,
-            token: random::<u64>()
+            is_deafened: false,
+            server_muted: false,
+            role: Role::Guest,
+            permissions: Permissions::NONE,
+            token: random::<u64>(),
+            voice_key: random(),
+            direct_channel: None,
         };
 
-        self.users.insert(address, user.clone());
+        self.shared.users.insert(address, user.clone());
 
         user
     }
@@ -0,0 +1,33 @@
+use std::path::Path;
+use std::sync::Arc;
+use tokio_rustls::rustls::crypto::ring;
+use tokio_rustls::rustls::pki_types::pem::PemObject;
+use tokio_rustls::rustls::pki_types::{CertificateDer, PrivateKeyDer};
+use tokio_rustls::rustls::ServerConfig;
+use tokio_rustls::TlsAcceptor;
+use crate::error::ServerError;
+
+/// Builds a TLS acceptor for the management port from PEM encoded certificate chain and private key files.
+///
+/// # Errors
+/// Returns [`ServerError::Tls`] if a file cannot be read, holds no certificate or key, or the key does not fit
+/// the certificate.
+pub fn load_tls_acceptor(cert_path: &Path, key_path: &Path) -> Result<TlsAcceptor, ServerError> {
+    let certs = CertificateDer::pem_file_iter(cert_path)
+        .and_then(Iterator::collect::<Result<Vec<_>, _>>)
+        .map_err(|e| ServerError::Tls(format!("{}: {}", cert_path.display(), e)))?;
+
+    if certs.is_empty() {
+        return Err(ServerError::Tls(format!("{}: no certificates found", cert_path.display())));
+    }
+
+    let key = PrivateKeyDer::from_pem_file(key_path)
+        .map_err(|e| ServerError::Tls(format!("{}: {}", key_path.display(), e)))?;
+
+    let config = ServerConfig::builder_with_provider(Arc::new(ring::default_provider()))
+        .with_safe_default_protocol_versions()
+        .and_then(|builder| builder.with_no_client_auth().with_single_cert(certs, key))
+        .map_err(|e| ServerError::Tls(e.to_string()))?;
+
+    Ok(TlsAcceptor::from(Arc::new(config)))
+}
@@ -1,9 +1,124 @@
+use dashmap::mapref::entry::Entry;
+use dashmap::DashMap;
+use std::net::SocketAddr;
+use std::str::FromStr;
+use serde::Deserialize;
+use tokio::sync::mpsc::UnboundedSender;
+use voiceapp_protocol::crypto::VOICE_KEY_LEN;
+use voiceapp_protocol::{Packet, Permissions, Role};
+
 /// Represents a connected user with their voice channel status and authentication token
 #[derive(Clone, Debug)]
 pub struct User {
     pub id: u64,
     pub username: Option<String>,
-    pub in_voice: bool,
+    pub channel_id: Option<u64>, // Voice channel the user is in, if any
     pub is_muted: bool,
+    pub is_deafened: bool, // Not hearing the voice channel
+    pub server_muted: bool, // Muted by a moderator, cannot unmute itself
+    pub role: Role, // Guest without any permissions until login
+    pub permissions: Permissions,
     pub token: u64, // Authentication token for UDP connections
-}
\ No newline at end of file
+    pub voice_key: [u8; VOICE_KEY_LEN], // Key for this user's encrypted voice packets
+    // Packets for this user only, set on login if it accepts direct messages
+    pub direct_channel: Option<UnboundedSender<Packet>>,
+}
+
+impl User {
+    /// Returns true if the user's voice is relayed: it has the speak permission and is not server-muted.
+    #[must_use]
+    pub fn can_speak(&self) -> bool {
+        !self.server_muted && self.permissions.contains(Permissions::SPEAK)
+    }
+}
+
+/// How the server treats users logging in with the same name
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum UsernamePolicy {
+    /// Any number of users may share a name
+    AllowDuplicates,
+    /// Names must be unique, "Alice" and "alice" are different users
+    CaseSensitive,
+    /// Names must be unique ignoring ASCII case
+    #[default]
+    CaseInsensitive,
+}
+
+impl FromStr for UsernamePolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "allow-duplicates" => Ok(Self::AllowDuplicates),
+            "case-sensitive" => Ok(Self::CaseSensitive),
+            "case-insensitive" => Ok(Self::CaseInsensitive),
+            other => Err(format!("unknown username policy: {other}")),
+        }
+    }
+}
+
+/// Registry of usernames in use, shared between all user handlers
+pub struct Usernames {
+    policy: UsernamePolicy,
+    taken: DashMap<String, SocketAddr>, // Normalized name -> owner connection
+}
+
+impl Usernames {
+    #[must_use]
+    pub fn new(policy: UsernamePolicy) -> Self {
+        Self {
+            policy,
+            taken: DashMap::new(),
+        }
+    }
+
+    /// Reserves `name` for the connection at `owner`, returns false if another connection holds it.
+    #[must_use]
+    pub fn claim(&self, name: &str, owner: SocketAddr) -> bool {
+        if self.policy == UsernamePolicy::AllowDuplicates {
+            return true;
+        }
+
+        match self.taken.entry(self.key(name)) {
+            Entry::Occupied(entry) => *entry.get() == owner,
+            Entry::Vacant(entry) => {
+                entry.insert(owner);
+                true
+            }
+        }
+    }
+
+    /// Moves the reservation of `owner` from `old` to `new`, returns false if `new` is taken.
+    #[must_use]
+    pub fn rename(&self, old: &str, new: &str, owner: SocketAddr) -> bool {
+        if !self.claim(new, owner) {
+            return false;
+        }
+
+        // Changing only the case keeps the same key under the case-insensitive policy
+        if self.key(old) != self.key(new) {
+            self.release(old, owner);
+        }
+
+        true
+    }
+
+    /// Frees `name` if it is held by `owner`.
+    pub fn release(&self, name: &str, owner: SocketAddr) {
+        self.taken.remove_if(&self.key(name), |_, holder| *holder == owner);
+    }
+
+    /// Returns true if `a` and `b` are the same name under the policy.
+    #[must_use]
+    pub fn same(&self, a: &str, b: &str) -> bool {
+        self.key(a) == self.key(b)
+    }
+
+    fn key(&self, name: &str) -> String {
+        match self.policy {
+            UsernamePolicy::CaseInsensitive => name.to_ascii_lowercase(),
+            _ => name.to_string(),
+        }
+    }
+}
@@ -1,37 +1,71 @@
 use std::net::SocketAddr;
 use std::sync::Arc;
+use std::time::{Duration, Instant};
 use dashmap::DashMap;
 use tokio::net::UdpSocket;
 use tokio::sync::mpsc::UnboundedReceiver;
 use tracing::{debug, error, info, warn};
-use voiceapp_protocol::Packet;
-use crate::config::PACKET_BUFFER_SIZE;
+use voiceapp_protocol::crypto::{ReplayWindow, VoiceCipher, VoiceDirection};
+use voiceapp_protocol::{Packet, PacketRef, VoiceDataRef, VOICE_DATA_HEADER_LEN};
+use crate::config::DEFAULT_PACKET_BUFFER_SIZE;
 use crate::event::Event;
+use crate::management::server::DisconnectHandle;
 use crate::voice::session::VoiceSession;
 
-/// VoiceRelayServer handles UDP voice packet relaying.
-/// It depends on ManagementServer for user authentication and state.
+/// Buffers reused for every relayed voice packet, so forwarding does not allocate once they have grown
+#[derive(Default)]
+struct RelayBuffers {
+    packet: Vec<u8>, // Received packet resealed for one recipient
+    recipients: Vec<(SocketAddr, VoiceCipher)>,
+}
+
+/// `VoiceRelayServer` handles UDP voice packet relaying.
+/// It depends on `ManagementServer` for user authentication and state.
 pub struct VoiceRelayServer {
     events_channel: UnboundedReceiver<Event>,
+    packet_buffer_size: usize,
     sessions: DashMap<u64, VoiceSession>,
     ids_by_addresses: DashMap<SocketAddr, u64>, // Caching map for better performance in relay
+    suspended: DashMap<u64, u64>, // Next forwarded sequence number of users who may resume their session
+    idle_timeout: Option<(Duration, DisconnectHandle)>, // Quiet sessions are disconnected through the handle
 }
 
 impl VoiceRelayServer {
-    /// Creates a new VoiceRelayServer with the given event channel from ManagementServer.
+    /// Creates a new `VoiceRelayServer` with the given event channel from `ManagementServer`.
     #[must_use]
     pub fn new(events_channel: UnboundedReceiver<Event>) -> Self {
         VoiceRelayServer {
             events_channel,
+            packet_buffer_size: DEFAULT_PACKET_BUFFER_SIZE,
             sessions: DashMap::new(),
             ids_by_addresses: DashMap::new(),
+            suspended: DashMap::new(),
+            idle_timeout: None,
         }
     }
 
-    /// Start listening for UDP voice packets and relay them on the given port.
-    pub async fn run(&mut self, port: u16) -> Result<(), crate::error::ServerError> {
-        let addr = format!("0.0.0.0:{}", port);
-        let udp_socket = match UdpSocket::bind(&addr).await {
+    /// Disconnects heartbeat sending users whose voice socket stays quiet for `idle_timeout`,
+    /// a zero timeout disables it.
+    #[must_use]
+    pub fn with_idle_timeout(mut self, idle_timeout: Duration, disconnect: DisconnectHandle) -> Self {
+        self.idle_timeout = (!idle_timeout.is_zero()).then_some((idle_timeout, disconnect));
+        self
+    }
+
+    /// Sets the receive buffer size, datagrams larger than this are truncated.
+    #[must_use]
+    pub fn with_packet_buffer_size(mut self, packet_buffer_size: usize) -> Self {
+        self.packet_buffer_size = packet_buffer_size;
+        self
+    }
+
+    /// Start listening for UDP voice packets and relay them on the given address.
+    ///
+    /// # Errors
+    ///
+    /// Fails if the address cannot be bound.
+    pub async fn run(&mut self, addr: SocketAddr) -> Result<(), crate::error::ServerError> {
+        let udp_socket = match UdpSocket::bind(addr).await {
             Ok(socket) => {
                 info!("VoiceRelayServer listening on {}", socket.local_addr()?);
                 Arc::new(socket)
@@ -42,49 +76,82 @@ impl VoiceRelayServer {
             }
         };
 
-        let mut buf = vec![0u8; PACKET_BUFFER_SIZE];
+        let mut buf = vec![0u8; self.packet_buffer_size];
+        let mut relay_buffers = RelayBuffers::default();
+        let sweep_period = self.idle_timeout.as_ref().map_or(Duration::from_secs(1), |(timeout, _)| *timeout / 4);
+        let mut idle_sweep = tokio::time::interval(sweep_period);
 
         loop {
             tokio::select! {
                 // Handle incoming UDP voice packets
                 udp_result = udp_socket.recv_from(&mut buf) => {
                     match udp_result {
-                        Ok((n, src_addr)) => {self.handle_packet(src_addr, &buf[..n], &udp_socket).await;}
+                        Ok((n, src_addr)) => {
+                            self.handle_packet(src_addr, &mut buf[..n], &udp_socket, &mut relay_buffers).await;
+                        }
                         Err(e) => {error!("UDP receive error: {}", e);}
                     }
                 }
 
+                // Disconnect users whose voice socket went quiet
+                _ = idle_sweep.tick(), if self.idle_timeout.is_some() => {
+                    self.expire_idle_sessions();
+                }
+
                 // Handle events from management server
                 Some(event) = self.events_channel.recv() => {
                     match event {
-                        Event::UserConnected { id, token } => {
+                        Event::UserConnected { id, token, voice_key } => {
+                            // A resumed session continues the sequence numbers forwarded before
+                            let next_sequence = self.suspended.remove(&id).map_or(0, |(_, next)| next);
                             self.sessions.insert(id, VoiceSession {
                                 token,
-                                in_voice: false,
+                                channel_id: None,
                                 udp_address: None,
+                                cipher: VoiceCipher::new(&voice_key),
+                                replay_window: ReplayWindow::new(),
+                                server_muted: false,
+                                deafened: false,
+                                heartbeat: false,
+                                last_seen: Instant::now(),
+                                sequence_offset: next_sequence,
+                                next_sequence,
                             });
                         }
-                        Event::VoiceJoined { id } => {
+                        Event::HeartbeatStarted { id } => {
+                            if let Some(mut session) = self.sessions.get_mut(&id) {
+                                session.heartbeat = true;
+                                session.last_seen = Instant::now();
+                            }
+                        }
+                        Event::ServerMuted { id, muted } => {
+                            if let Some(mut session) = self.sessions.get_mut(&id) {
+                                session.server_muted = muted;
+                            }
+                        }
+                        Event::Deafened { id, deafened } => {
+                            if let Some(mut session) = self.sessions.get_mut(&id) {
+                                session.deafened = deafened;
+                            }
+                        }
+                        Event::VoiceJoined { id, channel_id } => {
                             if let Some(mut session) = self.sessions.get_mut(&id) {
-                                session.in_voice = true;
+                                session.channel_id = Some(channel_id);
                             }
                         }
                         Event::VoiceLeft { id } => {
                             if let Some(mut session) = self.sessions.get_mut(&id) {
-                                session.in_voice = false;
+                                session.channel_id = None;
                             }
                         }
                         Event::UserDisconnected { id } => {
-                            let address = self.ids_by_addresses
-                                .iter()
-                                .find(|e| *e.value() == id)
-                                .map(|e| *e.key());
-
-                            if let Some(address) = address {
-                                self.ids_by_addresses.remove(&address);
+                            self.remove_session(id);
+                            self.suspended.remove(&id);
+                        }
+                        Event::UserSuspended { id } => {
+                            if let Some(session) = self.remove_session(id) {
+                                self.suspended.insert(id, session.next_sequence);
                             }
-
-                            self.sessions.remove(&id);
                         }
                     }
                 }
@@ -92,61 +159,158 @@ impl VoiceRelayServer {
         }
     }
 
+    /// Forget the session of a user and the address it sent from
+    fn remove_session(&self, id: u64) -> Option<VoiceSession> {
+        let address = self.ids_by_addresses.iter().find(|e| *e.value() == id).map(|e| *e.key());
+
+        if let Some(address) = address {
+            self.ids_by_addresses.remove(&address);
+        }
+
+        self.sessions.remove(&id).map(|(_, session)| session)
+    }
+
+    /// Stop relaying to heartbeat sending sessions that went quiet and have the management server
+    /// disconnect their users, the relay forgets them on `UserDisconnected`
+    fn expire_idle_sessions(&self) {
+        let Some((idle_timeout, disconnect)) = &self.idle_timeout else {
+            return;
+        };
+
+        for mut session in self.sessions.iter_mut() {
+            if !session.heartbeat || session.last_seen.elapsed() < *idle_timeout {
+                continue;
+            }
+            let Some(address) = session.udp_address.take() else {
+                continue;
+            };
+
+            warn!(
+                "Voice session of user {} at {} expired after {:?} without packets",
+                session.key(),
+                address,
+                idle_timeout
+            );
+            session.heartbeat = false;
+            self.ids_by_addresses.remove(&address);
+            disconnect.disconnect(*session.key());
+        }
+    }
+
     /// Handle incoming UDP packet - either forward voice data or authenticate
     async fn handle_packet(
         &self,
         src_addr: SocketAddr,
-        packet_data: &[u8],
+        packet_data: &mut [u8],
         udp_socket: &Arc<UdpSocket>,
+        relay_buffers: &mut RelayBuffers,
     ) {
-        match Packet::decode(packet_data) {
-            Ok((packet, _)) => match packet {
-                Packet::VoiceAuthRequest { request_id, voice_token } => {
-                    let session_data = self
-                        .sessions
-                        .iter()
-                        .find(|e| e.value().token == voice_token)
-                        .map(|e| (*e.key(), *e.value())); // Copy values, drop reference
-
-                    if let Some((user_id, session)) = session_data {
-                        self.authenticate(src_addr, user_id, session, request_id, voice_token, udp_socket).await;
-                    } else {
-                        warn!("Auth failed: unknown token from {}", src_addr);
-                    }
-                }
-                Packet::VoiceData { user_id: _, sequence, timestamp, data } => {
-                    let user_id = self.ids_by_addresses.get(&src_addr).map(|e| *e.value());
-                    if let Some(user_id) = user_id {
-                        self.forward_voice_packet(user_id, sequence, timestamp, data, udp_socket).await;
-                    }
-                    // Silently ignore VoiceData from unknown addresses (race condition, not actionable)
+        // Voice data is borrowed from the receive buffer and forwarded from there
+        let (sequence, timestamp, size) = match PacketRef::decode(packet_data) {
+            Ok((PacketRef::VoiceData(voice_data), size)) => (voice_data.sequence, voice_data.timestamp, size),
+            Ok((PacketRef::Other(Packet::VoiceAuthRequest { request_id, voice_token }), _)) => {
+                let session_data = self
+                    .sessions
+                    .iter()
+                    .find(|e| e.value().token == voice_token)
+                    .map(|e| (*e.key(), e.value().clone())); // Clone values, drop reference
+
+                if let Some((user_id, session)) = session_data {
+                    self.authenticate(src_addr, user_id, session, request_id, voice_token, udp_socket).await;
+                } else {
+                    warn!("Auth failed: unknown token from {}", src_addr);
                 }
-                _ => { warn!("Invalid packet type from {}", src_addr); }
-            },
-            Err(e) => { warn!("Malformed packet from {}: {}", src_addr, e); }
+                return;
+            }
+            Ok(_) => {
+                warn!("Invalid packet type from {}", src_addr);
+                return;
+            }
+            Err(e) => {
+                warn!("Malformed packet from {}: {}", src_addr, e);
+                return;
+            }
+        };
+
+        let user_id = self.ids_by_addresses.get(&src_addr).map(|e| *e.value());
+        if let Some(user_id) = user_id {
+            if self.sessions.get(&user_id).is_some_and(|session| session.server_muted) {
+                return; // Muted by a moderator
+            }
+            let packet = &mut packet_data[..size];
+            self.forward_voice_packet(user_id, sequence, timestamp, packet, udp_socket, relay_buffers).await;
         }
+        // Silently ignore VoiceData from unknown addresses (race condition, not actionable)
     }
 
-    /// Forward voice packet to authenticated addresses of users in voice channel
-    /// Replaces user_id with sender's user_id to prevent spoofing
+    /// Forward voice packet to authenticated addresses of users in the sender's voice channel,
+    /// skipping deafened users. Replaces `user_id` with the sender's id to prevent spoofing.
+    /// The payload is opened with the sender's key and sealed again with each recipient's key,
+    /// forged and replayed packets are dropped. Sequence numbers restart on every login, so they are
+    /// forwarded past those of a resumed session and never repeat a nonce for a recipient's key.
+    /// `packet` is rewritten in place and copied into `relay_buffers` for each recipient, nothing is encoded again.
     async fn forward_voice_packet(
         &self,
         user_id: u64,
         sequence: u32,
         timestamp: u32,
-        data: Vec<u8>,
+        packet: &mut [u8],
         udp_socket: &Arc<UdpSocket>,
+        relay_buffers: &mut RelayBuffers,
     ) {
-        let packet = Packet::VoiceData { user_id, sequence, timestamp, data };
-        let encoded_packet = packet.encode();
+        let (channel_id, forwarded_sequence) = {
+            let Some(mut session) = self.sessions.get_mut(&user_id) else {
+                return;
+            };
 
-        let recipients: Vec<SocketAddr> = self.sessions.iter()
-            .filter(|e| e.value().in_voice && *e.key() != user_id)
-            .map(|e| e.value().udp_address.unwrap())
-            .collect();
+            let Some(channel_id) = session.channel_id else {
+                return; // Sender is not in a voice channel
+            };
 
-        for addr in recipients {
-            if let Err(e) = udp_socket.send_to(&encoded_packet, addr).await {
+            // Leaves the Opus frame followed by the space of its tag, which every recipient's tag takes
+            let payload = &mut packet[VOICE_DATA_HEADER_LEN..];
+            let opened = session.cipher.open_in_place(VoiceDirection::ToRelay, user_id, sequence, timestamp, payload);
+            if let Err(e) = opened {
+                debug!("Dropping voice packet from user {}: {}", user_id, e);
+                return;
+            }
+
+            if !session.replay_window.accept(sequence) {
+                debug!("Dropping replayed voice packet from user {}: sequence={}", user_id, sequence);
+                return;
+            }
+            session.last_seen = Instant::now();
+
+            let Ok(forwarded_sequence) = u32::try_from(session.sequence_offset + u64::from(sequence)) else {
+                debug!("Dropping voice packet from user {}: sequence numbers exhausted", user_id);
+                return;
+            };
+            session.next_sequence = session.next_sequence.max(u64::from(forwarded_sequence) + 1);
+
+            (channel_id, forwarded_sequence)
+        };
+
+        if let Err(e) = VoiceDataRef::rewrite_header(packet, user_id, forwarded_sequence) {
+            error!("Dropping voice packet from user {}: {}", user_id, e);
+            return;
+        }
+
+        let RelayBuffers { packet: forwarded, recipients } = relay_buffers;
+        recipients.clear();
+        recipients.extend(
+            self.sessions
+                .iter()
+                .filter(|e| e.value().channel_id == Some(channel_id) && *e.key() != user_id && !e.value().deafened)
+                .filter_map(|e| e.value().udp_address.map(|addr| (addr, e.value().cipher.clone()))),
+        );
+
+        for (addr, cipher) in recipients.iter() {
+            forwarded.clear();
+            forwarded.extend_from_slice(packet);
+            let payload = &mut forwarded[VOICE_DATA_HEADER_LEN..];
+            cipher.seal_in_place(VoiceDirection::FromRelay, user_id, forwarded_sequence, timestamp, payload);
+
+            if let Err(e) = udp_socket.send_to(forwarded, *addr).await {
                 error!("Failed to forward voice packet to {}: {}", addr, e);
             }
         }
@@ -164,7 +328,13 @@ impl VoiceRelayServer {
     ) {
         let token_valid = session.token == voice_token;
         if token_valid {
-            session.udp_address = Some(src_addr);
+            // Heartbeats re-authenticate, the address changes when a NAT mapping was renewed
+            if let Some(previous) = session.udp_address.replace(src_addr) {
+                if previous != src_addr {
+                    self.ids_by_addresses.remove(&previous);
+                }
+            }
+            session.last_seen = Instant::now();
             self.sessions.insert(user_id, session);
             self.ids_by_addresses.insert(src_addr, user_id);
         } else {
@@ -173,7 +343,13 @@ impl VoiceRelayServer {
 
         // Send response back to client
         let response_packet = Packet::VoiceAuthResponse { request_id, success: token_valid };
-        let response_data = response_packet.encode();
+        let response_data = match response_packet.encode() {
+            Ok(response_data) => response_data,
+            Err(e) => {
+                error!("Failed to encode auth response for {}: {}", src_addr, e);
+                return;
+            }
+        };
         if let Err(e) = udp_socket.send_to(&response_data, src_addr).await {
             error!("Failed to send auth response to {}: {}", src_addr, e);
         } else {
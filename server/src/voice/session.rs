@@ -1,9 +1,19 @@
 use std::net::SocketAddr;
+use std::time::Instant;
+use voiceapp_protocol::crypto::{ReplayWindow, VoiceCipher};
 
 /// Represents an authenticated voice session for UDP communication.
-#[derive(Clone, Copy, Debug)]
+#[derive(Clone, Debug)]
 pub struct VoiceSession {
     pub token: u64,
-    pub in_voice: bool,
+    pub channel_id: Option<u64>,
     pub udp_address: Option<SocketAddr>,
-}
\ No newline at end of file
+    pub cipher: VoiceCipher, // Keyed with the voice key handed out at login
+    pub replay_window: ReplayWindow, // Sequence numbers already received from this user
+    pub server_muted: bool, // Voice from this user is dropped
+    pub deafened: bool, // Voice to this user is not forwarded
+    pub heartbeat: bool, // Client keeps the socket busy, the session expires once it goes quiet
+    pub last_seen: Instant, // Last authentication or voice packet from `udp_address`
+    pub sequence_offset: u64, // Added to the sequence numbers of this login when forwarding
+    pub next_sequence: u64, // Past every sequence number forwarded for this user, over all its logins
+}
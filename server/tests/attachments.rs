@@ -0,0 +1,230 @@
+//! Attachments: files uploaded and downloaded in chunks and referenced by chat messages.
+
+use std::path::Path;
+use std::time::Duration;
+use tokio::net::TcpStream;
+use voiceapp_protocol::{Attachment, AttachmentRejectReason, ChatRejectReason, Packet, ATTACHMENT_CHUNK_LEN};
+use voiceapp_server::{AttachmentStore, ChatHistory, Limits};
+
+mod common;
+use common::{login, management_server, read_until, request, start, temp_path};
+
+async fn start_server(port: u16, attachments: AttachmentStore, chat_history: ChatHistory) {
+    let (management_server, _events_rx) = management_server(Limits::default());
+    start(port, management_server.with_attachments(attachments).with_chat_history(chat_history)).await;
+}
+
+/// Starts an upload and returns the attachment id or the rejection
+async fn start_upload(
+    stream: &mut TcpStream,
+    buf: &mut Vec<u8>,
+    name: &str,
+    size: u64,
+) -> Result<u64, AttachmentRejectReason> {
+    let is_png = Path::new(name).extension().is_some_and(|extension| extension == "png");
+    let packet = Packet::UploadAttachmentRequest {
+        request_id: 3,
+        name: name.to_string(),
+        mime_type: if is_png { "image/png" } else { "text/plain" }.to_string(),
+        size,
+    };
+    match request(stream, buf, packet, |p| matches!(p, Packet::UploadAttachmentResponse { .. })).await {
+        Packet::UploadAttachmentResponse { attachment_id, rejection: None, .. } => Ok(attachment_id),
+        Packet::UploadAttachmentResponse { rejection: Some(reason), .. } => Err(reason),
+        other => panic!("expected UploadAttachmentResponse, got {other:?}"),
+    }
+}
+
+/// Sends one chunk and returns whether the server took it
+async fn upload_chunk(stream: &mut TcpStream, buf: &mut Vec<u8>, attachment_id: u64, offset: u64, data: &[u8]) -> bool {
+    let packet = Packet::UploadChunkRequest { request_id: 4, attachment_id, offset, data: data.to_vec() };
+    match request(stream, buf, packet, |p| matches!(p, Packet::UploadChunkResponse { .. })).await {
+        Packet::UploadChunkResponse { success, .. } => success,
+        other => panic!("expected UploadChunkResponse, got {other:?}"),
+    }
+}
+
+/// Uploads a whole file and returns its attachment id
+async fn upload(stream: &mut TcpStream, buf: &mut Vec<u8>, name: &str, data: &[u8]) -> u64 {
+    let attachment_id = start_upload(stream, buf, name, data.len() as u64).await.unwrap();
+    let mut offset = 0;
+    for chunk in data.chunks(ATTACHMENT_CHUNK_LEN) {
+        assert!(upload_chunk(stream, buf, attachment_id, offset, chunk).await);
+        offset += chunk.len() as u64;
+    }
+    attachment_id
+}
+
+/// Downloads a whole file, `None` if the server refuses a chunk
+async fn download(stream: &mut TcpStream, buf: &mut Vec<u8>, attachment_id: u64, size: usize) -> Option<Vec<u8>> {
+    let mut data = Vec::new();
+    while data.len() < size {
+        let packet = Packet::DownloadChunkRequest { request_id: 5, attachment_id, offset: data.len() as u64 };
+        match request(stream, buf, packet, |p| matches!(p, Packet::DownloadChunkResponse { .. })).await {
+            Packet::DownloadChunkResponse { success: true, data: chunk, .. } if !chunk.is_empty() => {
+                data.extend_from_slice(&chunk);
+            }
+            Packet::DownloadChunkResponse { .. } => return None,
+            other => panic!("expected DownloadChunkResponse, got {other:?}"),
+        }
+    }
+    Some(data)
+}
+
+/// Sends a chat message with attachments and returns the response
+async fn send_message(stream: &mut TcpStream, buf: &mut Vec<u8>, message: &str, attachments: Vec<u64>) -> Packet {
+    let message = message.to_string();
+    let packet = Packet::ChatMessageRequest { request_id: 6, message, reply_to: None, attachments };
+    request(stream, buf, packet, |p| matches!(p, Packet::ChatMessageResponse { .. })).await
+}
+
+#[tokio::test]
+async fn screenshot_is_shared_in_chat() {
+    start_server(39821, AttachmentStore::default(), ChatHistory::default()).await;
+
+    let (mut alice, mut alice_buf, _) = login(39821, "alice").await;
+    let (mut bob, mut bob_buf, _) = login(39821, "bob").await;
+
+    let screenshot: Vec<u8> = (0..40_000u32).map(|i| (i % 251) as u8).collect();
+    let attachment_id = upload(&mut alice, &mut alice_buf, "screenshot.png", &screenshot).await;
+
+    // Files can only be attached by their uploader
+    let response = send_message(&mut bob, &mut bob_buf, "mine now", vec![attachment_id]).await;
+    let rejection = Some(ChatRejectReason::UnknownAttachment);
+    assert_eq!(response, Packet::ChatMessageResponse { request_id: 6, message_id: 0, rejection });
+
+    // Text may be left out when a file is attached
+    let response = send_message(&mut alice, &mut alice_buf, "", vec![attachment_id]).await;
+    assert_eq!(response, Packet::ChatMessageResponse { request_id: 6, message_id: 1, rejection: None });
+
+    let expected = Attachment::new(attachment_id, "screenshot.png".to_string(), "image/png".to_string(), 40_000);
+    let sent = read_until(&mut bob, &mut bob_buf, |p| matches!(p, Packet::UserSentMessage { .. })).await;
+    let Some(Packet::UserSentMessage { message, attachments, .. }) = sent else {
+        panic!("expected UserSentMessage, got {sent:?}");
+    };
+    assert_eq!(message, "");
+    assert_eq!(attachments, std::slice::from_ref(&expected));
+
+    assert_eq!(download(&mut bob, &mut bob_buf, attachment_id, screenshot.len()).await, Some(screenshot));
+
+    let history = Packet::ChatHistoryRequest { request_id: 7, before: None, after: None, limit: 10 };
+    let response = request(&mut bob, &mut bob_buf, history, |p| matches!(p, Packet::ChatHistoryResponse { .. })).await;
+    let Packet::ChatHistoryResponse { messages, .. } = response else {
+        panic!("expected ChatHistoryResponse");
+    };
+    assert_eq!(messages.len(), 1);
+    assert_eq!(messages[0].attachments, [expected]);
+
+    // Without text or attachments the message is still empty
+    let response = send_message(&mut alice, &mut alice_buf, "", Vec::new()).await;
+    let rejection = Some(ChatRejectReason::Empty);
+    assert_eq!(response, Packet::ChatMessageResponse { request_id: 6, message_id: 0, rejection });
+}
+
+#[tokio::test]
+async fn files_are_only_shared_through_messages() {
+    start_server(39822, AttachmentStore::default(), ChatHistory::default()).await;
+
+    let (mut alice, mut alice_buf, _) = login(39822, "alice").await;
+    let (mut bob, mut bob_buf, _) = login(39822, "bob").await;
+
+    // Until it is attached only the uploader can download a file, even if others guess its id
+    let notes = b"meeting notes".to_vec();
+    let attachment_id = upload(&mut alice, &mut alice_buf, "notes.txt", &notes).await;
+    assert_eq!(download(&mut bob, &mut bob_buf, attachment_id, notes.len()).await, None);
+    assert_eq!(download(&mut alice, &mut alice_buf, attachment_id, notes.len()).await, Some(notes.clone()));
+
+    let response = send_message(&mut alice, &mut alice_buf, "notes", vec![attachment_id]).await;
+    let Packet::ChatMessageResponse { message_id, rejection: None, .. } = response else {
+        panic!("expected ChatMessageResponse, got {response:?}");
+    };
+    assert_eq!(download(&mut bob, &mut bob_buf, attachment_id, notes.len()).await, Some(notes.clone()));
+
+    // Deleting the message stops sharing the file
+    let delete = Packet::DeleteMessageRequest { request_id: 8, message_id };
+    let response =
+        request(&mut alice, &mut alice_buf, delete, |p| matches!(p, Packet::DeleteMessageResponse { .. })).await;
+    assert_eq!(response, Packet::DeleteMessageResponse { request_id: 8, success: true });
+    assert_eq!(download(&mut bob, &mut bob_buf, attachment_id, notes.len()).await, None);
+    assert_eq!(download(&mut alice, &mut alice_buf, attachment_id, notes.len()).await, Some(notes));
+}
+
+#[tokio::test]
+async fn uploads_are_checked_and_oldest_files_make_room() {
+    start_server(39831, AttachmentStore::new(1000, 2500), ChatHistory::default()).await;
+
+    let (mut alice, mut alice_buf, _) = login(39831, "alice").await;
+
+    assert_eq!(start_upload(&mut alice, &mut alice_buf, "big.log", 1001).await, Err(AttachmentRejectReason::TooLarge));
+    let rejection = Err(AttachmentRejectReason::InvalidName);
+    assert_eq!(start_upload(&mut alice, &mut alice_buf, "../etc/passwd", 10).await, rejection);
+    assert_eq!(start_upload(&mut alice, &mut alice_buf, "", 10).await, rejection);
+
+    // Chunks must follow each other and stay within the announced size
+    let unfinished = start_upload(&mut alice, &mut alice_buf, "half.log", 1000).await.unwrap();
+    assert!(!upload_chunk(&mut alice, &mut alice_buf, unfinished, 10, &[1; 10]).await);
+    assert!(upload_chunk(&mut alice, &mut alice_buf, unfinished, 0, &[1; 500]).await);
+    assert!(!upload_chunk(&mut alice, &mut alice_buf, unfinished, 500, &[1; 501]).await);
+
+    // Unfinished files can neither be attached nor downloaded
+    let response = send_message(&mut alice, &mut alice_buf, "half", vec![unfinished]).await;
+    let rejection = Some(ChatRejectReason::UnknownAttachment);
+    assert_eq!(response, Packet::ChatMessageResponse { request_id: 6, message_id: 0, rejection });
+    assert_eq!(download(&mut alice, &mut alice_buf, unfinished, 500).await, None);
+
+    let first = upload(&mut alice, &mut alice_buf, "first.log", &[2; 1000]).await;
+    assert_eq!(download(&mut alice, &mut alice_buf, first, 1000).await, Some(vec![2; 1000]));
+
+    // The unfinished upload keeps its room, the oldest complete file goes
+    let second = upload(&mut alice, &mut alice_buf, "second.log", &[3; 1000]).await;
+    assert_eq!(download(&mut alice, &mut alice_buf, first, 1000).await, None);
+    assert_eq!(download(&mut alice, &mut alice_buf, second, 1000).await, Some(vec![3; 1000]));
+
+    let response = send_message(&mut alice, &mut alice_buf, "too many", vec![second; 11]).await;
+    let rejection = Some(ChatRejectReason::TooManyAttachments);
+    assert_eq!(response, Packet::ChatMessageResponse { request_id: 6, message_id: 0, rejection });
+}
+
+#[tokio::test]
+async fn attachments_are_kept_in_dir_across_restarts() {
+    let dir = temp_path("attachments");
+    let history_file = temp_path("attachments-history.log");
+
+    let attachments = AttachmentStore::default().with_dir(&dir).unwrap();
+    start_server(39841, attachments, ChatHistory::default().with_history_file(&history_file).unwrap()).await;
+    let (mut alice, mut alice_buf, _) = login(39841, "alice").await;
+    let log = b"line one\nline two\n".repeat(3000);
+    let attachment_id = upload(&mut alice, &mut alice_buf, "server log.txt", &log).await;
+    let response = send_message(&mut alice, &mut alice_buf, "logs", vec![attachment_id]).await;
+    assert_eq!(response, Packet::ChatMessageResponse { request_id: 6, message_id: 1, rejection: None });
+
+    // An upload cut short by the disconnect is not kept
+    start_upload(&mut alice, &mut alice_buf, "cut.log", 100).await.unwrap();
+    drop(alice);
+    tokio::time::sleep(Duration::from_millis(100)).await;
+
+    // A second server loading the same files stands in for a restart
+    let attachments = AttachmentStore::default().with_dir(&dir).unwrap();
+    start_server(39851, attachments, ChatHistory::default().with_history_file(&history_file).unwrap()).await;
+    let (mut bob, mut bob_buf, _) = login(39851, "bob").await;
+
+    let history = Packet::ChatHistoryRequest { request_id: 7, before: None, after: None, limit: 10 };
+    let response = request(&mut bob, &mut bob_buf, history, |p| matches!(p, Packet::ChatHistoryResponse { .. })).await;
+    let Packet::ChatHistoryResponse { messages, .. } = response else {
+        panic!("expected ChatHistoryResponse");
+    };
+    let attachment = Attachment::new(attachment_id, "server log.txt".to_string(), "text/plain".to_string(), 54_000);
+    assert_eq!(
+        messages.iter().map(|m| (m.message.clone(), m.attachments.clone())).collect::<Vec<_>>(),
+        [("logs".to_string(), vec![attachment])]
+    );
+    assert_eq!(download(&mut bob, &mut bob_buf, attachment_id, log.len()).await, Some(log));
+    assert_eq!(std::fs::read_dir(&dir).unwrap().count(), 1);
+
+    // New uploads are numbered after the kept ones
+    let next = upload(&mut bob, &mut bob_buf, "next.txt", b"next").await;
+    assert!(next > attachment_id);
+
+    let _ = std::fs::remove_dir_all(&dir);
+    let _ = std::fs::remove_file(&history_file);
+}
@@ -0,0 +1,115 @@
+//! Authentication: challenge-response proofs for a shared password and per-user credentials files.
+
+use tokio::net::TcpStream;
+use voiceapp_protocol::auth::{compute_proof, derive_key};
+use voiceapp_protocol::{Capabilities, LoginRejectReason, Packet};
+use voiceapp_server::{Authenticator, Credentials, Limits, ManagementServer, UsernamePolicy};
+
+mod common;
+use common::{hello, local, request, start, temp_path, try_login, try_login_with_password};
+
+const REJECTED: Packet = Packet::LoginRejected { request_id: 2, reason: LoginRejectReason::InvalidCredentials };
+
+async fn start_server(port: u16, password: Option<&str>, credentials: Credentials) {
+    let authenticator = Authenticator::new(password.map(str::to_string), credentials);
+    let (server, _events_rx) =
+        ManagementServer::new(vec!["General".to_string()], authenticator, Limits::default(), UsernamePolicy::default());
+    start(port, server).await;
+}
+
+/// Logs in on a new connection with a proof of `password`
+async fn login_with_password(port: u16, username: &str, password: &str) -> Packet {
+    let mut stream = TcpStream::connect(local(port)).await.unwrap();
+    try_login_with_password(&mut stream, &mut Vec::new(), username, password).await
+}
+
+#[tokio::test]
+async fn only_a_proof_for_this_connection_logs_in() {
+    start_server(39921, Some("shared"), Credentials::default().with_user("alice", "s3cret")).await;
+
+    let response = login_with_password(39921, "alice", "s3cret").await;
+    assert!(matches!(response, Packet::LoginResponse { .. }), "expected LoginResponse, got {response:?}");
+    let response = login_with_password(39921, "bob", "shared").await;
+    assert!(matches!(response, Packet::LoginResponse { .. }), "expected LoginResponse, got {response:?}");
+
+    assert_eq!(login_with_password(39921, "carol", "s3cret").await, REJECTED);
+    assert_eq!(login_with_password(39921, "dave", "guess").await, REJECTED);
+
+    let mut stream = TcpStream::connect(local(39921)).await.unwrap();
+    assert_eq!(try_login(&mut stream, &mut Vec::new(), "erin").await, REJECTED);
+
+    // A proof for the challenge of one connection is refused on another
+    let mut first = TcpStream::connect(local(39921)).await.unwrap();
+    let Packet::ServerHello { auth_challenge, auth_salt, .. } =
+        hello(&mut first, &mut Vec::new(), Capabilities::SUPPORTED).await
+    else {
+        unreachable!()
+    };
+    let auth_proof = Some(compute_proof(&derive_key("frank", "shared", &auth_salt), &auth_challenge));
+
+    let mut second = TcpStream::connect(local(39921)).await.unwrap();
+    let mut second_buf = Vec::new();
+    let Packet::ServerHello { auth_challenge: other_challenge, .. } =
+        hello(&mut second, &mut second_buf, Capabilities::SUPPORTED).await
+    else {
+        unreachable!()
+    };
+    assert_ne!(auth_challenge, other_challenge);
+    let login = Packet::LoginRequest { request_id: 2, username: "frank".to_string(), auth_proof };
+    let response = request(&mut second, &mut second_buf, login, |p| matches!(p, Packet::LoginRejected { .. })).await;
+    assert_eq!(response, REJECTED);
+}
+
+#[tokio::test]
+async fn credentials_file_keeps_salt_and_stored_keys() {
+    let path = temp_path("credentials.txt");
+    let _ = std::fs::remove_file(&path);
+
+    Credentials::add_to_file(&path, "alice", "s3cret").unwrap();
+    Credentials::add_to_file(&path, "Bob", "hunter2").unwrap();
+    let contents = std::fs::read_to_string(&path).unwrap();
+    let lines: Vec<&str> = contents.lines().collect();
+    assert_eq!(lines.len(), 3);
+    assert!(lines[0].starts_with("salt "));
+    assert!(lines[1].starts_with("alice:") && lines[2].starts_with("Bob:"));
+
+    start_server(39931, None, Credentials::load(&path).unwrap()).await;
+
+    // The salt of the file is sent to clients, users are looked up ignoring case
+    let mut stream = TcpStream::connect(local(39931)).await.unwrap();
+    let Packet::ServerHello { auth_salt, .. } = hello(&mut stream, &mut Vec::new(), Capabilities::SUPPORTED).await
+    else {
+        unreachable!()
+    };
+    assert_eq!(lines[0], format!("salt {}", auth_salt.map(|byte| format!("{byte:02x}")).concat()));
+    let response = login_with_password(39931, "bob", "hunter2").await;
+    assert!(matches!(response, Packet::LoginResponse { .. }), "expected LoginResponse, got {response:?}");
+
+    // What the file holds does not work as a password
+    let (_, stored_hex) = lines[1].split_once(':').unwrap();
+    assert_eq!(login_with_password(39931, "alice", stored_hex).await, REJECTED);
+    let response = login_with_password(39931, "alice", "s3cret").await;
+    assert!(matches!(response, Packet::LoginResponse { .. }), "expected LoginResponse, got {response:?}");
+
+    // Entries without the salt they were derived with are refused
+    std::fs::write(&path, lines[1]).unwrap();
+    assert!(Credentials::load(&path).is_err());
+
+    let _ = std::fs::remove_file(&path);
+}
+
+#[tokio::test]
+async fn concurrent_logins_with_the_shared_password_all_get_in() {
+    start_server(39991, Some("shared"), Credentials::default()).await;
+
+    // Keys are derived a few at a time, logins waiting for a derivation still succeed
+    let logins: Vec<_> = (0..8)
+        .map(|index| tokio::spawn(async move { login_with_password(39991, &format!("user{index}"), "shared").await }))
+        .collect();
+    for login in logins {
+        let response = login.await.unwrap();
+        assert!(matches!(response, Packet::LoginResponse { .. }), "expected LoginResponse, got {response:?}");
+    }
+
+    assert_eq!(login_with_password(39991, "USER0", "guess").await, REJECTED);
+}
@@ -0,0 +1,57 @@
+//! Voice channels: names are unique ignoring ASCII case, even when created at the same time.
+
+use std::sync::Barrier;
+use voiceapp_protocol::Packet;
+use voiceapp_server::management::channel::Channels;
+use voiceapp_server::Limits;
+
+mod common;
+use common::{login, management_server, request, start};
+
+#[test]
+fn concurrent_creations_of_a_name_make_one_channel() {
+    for _ in 0..1000 {
+        let channels = Channels::new(["General"]);
+        let barrier = Barrier::new(8);
+        let created = std::thread::scope(|scope| {
+            let creators: Vec<_> = (0..8)
+                .map(|_| {
+                    scope.spawn(|| {
+                        barrier.wait();
+                        channels.create("Lounge".to_string())
+                    })
+                })
+                .collect();
+            creators.into_iter().filter_map(|creator| creator.join().unwrap()).count()
+        });
+
+        assert_eq!(created, 1);
+        assert_eq!(channels.list().len(), 2);
+    }
+}
+
+#[tokio::test]
+async fn users_racing_to_create_a_name_get_one_channel() {
+    let (management_server, _events_rx) = management_server(Limits::default());
+    start(39971, management_server).await;
+
+    let creators: Vec<_> = ["Lounge", "lounge", "LOUNGE", "LoUnGe"]
+        .into_iter()
+        .enumerate()
+        .map(|(index, name)| {
+            tokio::spawn(async move {
+                let (mut stream, mut buf, _) = login(39971, &format!("user{index}")).await;
+                let create = Packet::CreateChannelRequest { request_id: 3, name: name.to_string() };
+                request(&mut stream, &mut buf, create, |p| matches!(p, Packet::CreateChannelResponse { .. })).await
+            })
+        })
+        .collect();
+
+    let mut created = Vec::new();
+    for creator in creators {
+        if let Packet::CreateChannelResponse { success: true, channel_id, .. } = creator.await.unwrap() {
+            created.push(channel_id);
+        }
+    }
+    assert_eq!(created, [2]);
+}
@@ -0,0 +1,135 @@
+//! Chat history: messages kept for users who log in later, paged by timestamp and saved to a file.
+
+use tokio::io::AsyncWriteExt;
+use tokio::net::TcpStream;
+use voiceapp_protocol::{ChatHistoryMessage, Packet, Reaction};
+use voiceapp_server::{ChatHistory, Limits};
+
+mod common;
+use common::{login, management_server, read_until, request, start, temp_path, FRAMING};
+
+async fn start_server(port: u16, chat_history: ChatHistory) {
+    let (management_server, _events_rx) = management_server(Limits::default());
+    start(port, management_server.with_chat_history(chat_history)).await;
+}
+
+/// Sends a chat message and returns the message id and timestamp of its broadcast
+async fn send_message(stream: &mut TcpStream, buf: &mut Vec<u8>, message: &str) -> (u64, u64) {
+    let request = Packet::ChatMessageRequest {
+        request_id: 3,
+        message: message.to_string(),
+        reply_to: None,
+        attachments: Vec::new(),
+    };
+    stream.write_all(&request.encode_with(FRAMING).unwrap()).await.unwrap();
+    match read_until(stream, buf, |p| matches!(p, Packet::UserSentMessage { .. })).await {
+        Some(Packet::UserSentMessage { message_id, timestamp, .. }) => (message_id, timestamp),
+        other => panic!("expected UserSentMessage, got {other:?}"),
+    }
+}
+
+/// Requests a page of history and returns the messages and `has_more`
+async fn history(
+    stream: &mut TcpStream,
+    buf: &mut Vec<u8>,
+    before: Option<u64>,
+    after: Option<u64>,
+    limit: u16,
+) -> (Vec<ChatHistoryMessage>, bool) {
+    let request = Packet::ChatHistoryRequest { request_id: 4, before, after, limit };
+    stream.write_all(&request.encode_with(FRAMING).unwrap()).await.unwrap();
+    match read_until(stream, buf, |p| matches!(p, Packet::ChatHistoryResponse { .. })).await {
+        Some(Packet::ChatHistoryResponse { messages, has_more, .. }) => (messages, has_more),
+        other => panic!("expected ChatHistoryResponse, got {other:?}"),
+    }
+}
+
+#[tokio::test]
+async fn late_user_pages_through_history() {
+    start_server(39701, ChatHistory::default()).await;
+
+    let (mut alice, mut alice_buf, _) = login(39701, "alice").await;
+    let mut ids = Vec::new();
+    let mut timestamps = Vec::new();
+    for message in ["one", "two", "three"] {
+        let (id, timestamp) = send_message(&mut alice, &mut alice_buf, message).await;
+        ids.push(id);
+        timestamps.push(timestamp);
+    }
+    assert!(ids.windows(2).all(|pair| pair[0] < pair[1]));
+    assert!(timestamps.windows(2).all(|pair| pair[0] < pair[1]));
+
+    let (mut bob, mut bob_buf, _) = login(39701, "bob").await;
+
+    let (newest, has_more) = history(&mut bob, &mut bob_buf, None, None, 2).await;
+    assert_eq!(
+        newest,
+        [
+            ChatHistoryMessage::new(ids[1], timestamps[1], "alice".to_string(), "two".to_string()),
+            ChatHistoryMessage::new(ids[2], timestamps[2], "alice".to_string(), "three".to_string()),
+        ]
+    );
+    assert!(has_more);
+
+    let (older, has_more) = history(&mut bob, &mut bob_buf, Some(timestamps[1]), None, 2).await;
+    assert_eq!(older, [ChatHistoryMessage::new(ids[0], timestamps[0], "alice".to_string(), "one".to_string())]);
+    assert!(!has_more);
+
+    let (newer, has_more) = history(&mut bob, &mut bob_buf, None, Some(timestamps[0]), 1).await;
+    assert_eq!(newer, [ChatHistoryMessage::new(ids[1], timestamps[1], "alice".to_string(), "two".to_string())]);
+    assert!(has_more);
+}
+
+#[tokio::test]
+async fn history_is_kept_in_file_across_restarts() {
+    let path = temp_path("chat-history.log");
+
+    start_server(39711, ChatHistory::default().with_history_file(&path).unwrap()).await;
+    let (mut alice, mut alice_buf, _) = login(39711, "alice").await;
+    let (kept_id, kept_timestamp) = send_message(&mut alice, &mut alice_buf, "tabs\tand\nlines \\o/").await;
+    let (deleted_id, _) = send_message(&mut alice, &mut alice_buf, "oops").await;
+
+    let edit = Packet::EditMessageRequest { request_id: 5, message_id: kept_id, message: "edited\t\\o/".to_string() };
+    let response = request(&mut alice, &mut alice_buf, edit, |p| matches!(p, Packet::EditMessageResponse { .. })).await;
+    assert_eq!(response, Packet::EditMessageResponse { request_id: 5, success: true });
+
+    let delete = Packet::DeleteMessageRequest { request_id: 6, message_id: deleted_id };
+    let response =
+        request(&mut alice, &mut alice_buf, delete, |p| matches!(p, Packet::DeleteMessageResponse { .. })).await;
+    assert_eq!(response, Packet::DeleteMessageResponse { request_id: 6, success: true });
+
+    for (emoji, reacted) in [("👍", true), ("🎉", true), ("👍", false)] {
+        let emoji = emoji.to_string();
+        let react = Packet::SetReactionRequest { request_id: 7, message_id: kept_id, emoji, reacted };
+        let response =
+            request(&mut alice, &mut alice_buf, react, |p| matches!(p, Packet::SetReactionResponse { .. })).await;
+        assert_eq!(response, Packet::SetReactionResponse { request_id: 7, success: true });
+    }
+    assert_eq!(std::fs::read_to_string(&path).unwrap().lines().count(), 7);
+
+    // A second server loading the same file stands in for a restart
+    start_server(39721, ChatHistory::default().with_history_file(&path).unwrap()).await;
+    let (mut bob, mut bob_buf, _) = login(39721, "bob").await;
+    let (messages, has_more) = history(&mut bob, &mut bob_buf, None, None, 50).await;
+    assert_eq!(
+        messages,
+        [ChatHistoryMessage::new(kept_id, kept_timestamp, "alice".to_string(), "edited\t\\o/".to_string())
+            .with_edited(true)
+            .with_reactions(vec![Reaction::new("🎉".to_string(), vec!["alice".to_string()])])]
+    );
+    assert!(!has_more);
+
+    // User ids do not survive the restart, whoever logs in as alice now is not the author
+    let (mut new_alice, mut new_alice_buf, _) = login(39721, "alice").await;
+    let edit = Packet::EditMessageRequest { request_id: 5, message_id: kept_id, message: "mine".to_string() };
+    let response =
+        request(&mut new_alice, &mut new_alice_buf, edit, |p| matches!(p, Packet::EditMessageResponse { .. })).await;
+    assert_eq!(response, Packet::EditMessageResponse { request_id: 5, success: false });
+
+    // New messages are numbered and stamped after the loaded ones, deleted ones included
+    let (id, timestamp) = send_message(&mut bob, &mut bob_buf, "later").await;
+    assert!(id > deleted_id);
+    assert!(timestamp > kept_timestamp);
+
+    let _ = std::fs::remove_file(&path);
+}
@@ -0,0 +1,157 @@
+//! Chat messages: server-assigned ids, content rules, replies, and edits and deletes by their author or a moderator.
+
+use tokio::net::TcpStream;
+use voiceapp_protocol::{ChatHistoryMessage, ChatRejectReason, Packet, Role};
+use voiceapp_server::{Limits, Roles};
+
+mod common;
+use common::{login, management_server, read_until, request, start};
+
+async fn start_server(port: u16) {
+    let (management_server, _events_rx) = management_server(Limits::default());
+    start(port, management_server.with_roles(Roles::new(Role::Member).with_user("mod", Role::Moderator))).await;
+}
+
+async fn send_message(stream: &mut TcpStream, buf: &mut Vec<u8>, message: &str, reply_to: Option<u64>) -> Packet {
+    let packet = Packet::ChatMessageRequest {
+        request_id: 3,
+        message: message.to_string(),
+        reply_to,
+        attachments: Vec::new(),
+    };
+    request(stream, buf, packet, |p| matches!(p, Packet::ChatMessageResponse { .. })).await
+}
+
+async fn edit_message(stream: &mut TcpStream, buf: &mut Vec<u8>, message_id: u64, message: &str) -> bool {
+    let packet = Packet::EditMessageRequest { request_id: 4, message_id, message: message.to_string() };
+    match request(stream, buf, packet, |p| matches!(p, Packet::EditMessageResponse { .. })).await {
+        Packet::EditMessageResponse { success, .. } => success,
+        _ => unreachable!(),
+    }
+}
+
+async fn delete_message(stream: &mut TcpStream, buf: &mut Vec<u8>, message_id: u64) -> bool {
+    let packet = Packet::DeleteMessageRequest { request_id: 5, message_id };
+    match request(stream, buf, packet, |p| matches!(p, Packet::DeleteMessageResponse { .. })).await {
+        Packet::DeleteMessageResponse { success, .. } => success,
+        _ => unreachable!(),
+    }
+}
+
+#[tokio::test]
+async fn messages_get_ids_and_replies_refer_to_them() {
+    start_server(39731).await;
+
+    let (mut alice, mut alice_buf, _) = login(39731, "alice").await;
+    let (mut bob, mut bob_buf, _) = login(39731, "bob").await;
+
+    let response = send_message(&mut alice, &mut alice_buf, "hello", None).await;
+    assert_eq!(response, Packet::ChatMessageResponse { request_id: 3, message_id: 1, rejection: None });
+
+    let response = send_message(&mut bob, &mut bob_buf, "hi alice", Some(1)).await;
+    assert_eq!(response, Packet::ChatMessageResponse { request_id: 3, message_id: 2, rejection: None });
+
+    let Some(Packet::UserSentMessage { message_id, message, reply_to, .. }) = read_until(
+        &mut alice,
+        &mut alice_buf,
+        |p| matches!(p, Packet::UserSentMessage { message_id: 2, .. }),
+    )
+    .await
+    else {
+        panic!("reply was not broadcast");
+    };
+    assert_eq!((message_id, message.as_str(), reply_to), (2, "hi alice", Some(1)));
+
+    // Replies to messages the server does not know are refused
+    let response = send_message(&mut bob, &mut bob_buf, "hi nobody", Some(99)).await;
+    let rejection = Some(ChatRejectReason::UnknownReply);
+    assert_eq!(response, Packet::ChatMessageResponse { request_id: 3, message_id: 0, rejection });
+}
+
+#[tokio::test]
+async fn messages_breaking_the_content_rules_are_rejected() {
+    start_server(39791).await;
+
+    let (mut alice, mut alice_buf, _) = login(39791, "alice").await;
+
+    let rejected = [
+        ("", ChatRejectReason::Empty),
+        (" \n\t ", ChatRejectReason::Empty),
+        ("bell\u{7}", ChatRejectReason::InvalidCharacters),
+        (&"a".repeat(2001), ChatRejectReason::TooLong),
+        // Close to the packet size limit, the broadcast of it would not fit
+        (&"a".repeat(65_000), ChatRejectReason::TooLong),
+    ];
+    for (message, reason) in rejected {
+        let response = send_message(&mut alice, &mut alice_buf, message, None).await;
+        assert_eq!(response, Packet::ChatMessageResponse { request_id: 3, message_id: 0, rejection: Some(reason) });
+    }
+
+    let response = send_message(&mut alice, &mut alice_buf, "two\nlines", None).await;
+    assert_eq!(response, Packet::ChatMessageResponse { request_id: 3, message_id: 1, rejection: None });
+
+    // Edits follow the same rules
+    assert!(!edit_message(&mut alice, &mut alice_buf, 1, " ").await);
+    assert!(!edit_message(&mut alice, &mut alice_buf, 1, &"a".repeat(2001)).await);
+    assert!(edit_message(&mut alice, &mut alice_buf, 1, &"a".repeat(2000)).await);
+}
+
+#[tokio::test]
+async fn authors_edit_and_moderators_delete() {
+    start_server(39741).await;
+
+    let (mut alice, mut alice_buf, _) = login(39741, "alice").await;
+    let (mut bob, mut bob_buf, _) = login(39741, "bob").await;
+    let (mut moderator, mut moderator_buf, _) = login(39741, "mod").await;
+
+    send_message(&mut alice, &mut alice_buf, "helo", None).await;
+    send_message(&mut bob, &mut bob_buf, "spam", None).await;
+
+    // Only the author edits
+    assert!(!edit_message(&mut bob, &mut bob_buf, 1, "hacked").await);
+    assert!(!edit_message(&mut moderator, &mut moderator_buf, 1, "hacked").await);
+    assert!(edit_message(&mut alice, &mut alice_buf, 1, "hello").await);
+    let edited = read_until(&mut bob, &mut bob_buf, |p| matches!(p, Packet::MessageEdited { .. })).await;
+    assert_eq!(edited, Some(Packet::MessageEdited { message_id: 1, message: "hello".to_string() }));
+
+    // Members delete only their own messages, moderators also those of lower roles
+    assert!(!delete_message(&mut alice, &mut alice_buf, 2).await);
+    assert!(delete_message(&mut moderator, &mut moderator_buf, 2).await);
+    let deleted = read_until(&mut alice, &mut alice_buf, |p| matches!(p, Packet::MessageDeleted { .. })).await;
+    assert_eq!(deleted, Some(Packet::MessageDeleted { message_id: 2 }));
+    assert!(!delete_message(&mut moderator, &mut moderator_buf, 2).await);
+
+    let packet = Packet::ChatHistoryRequest { request_id: 6, before: None, after: None, limit: 10 };
+    let Packet::ChatHistoryResponse { messages, .. } =
+        request(&mut bob, &mut bob_buf, packet, |p| matches!(p, Packet::ChatHistoryResponse { .. })).await
+    else {
+        unreachable!();
+    };
+    assert_eq!(messages.len(), 1);
+    assert_eq!(
+        messages[0],
+        ChatHistoryMessage::new(1, messages[0].timestamp, "alice".to_string(), "hello".to_string()).with_edited(true)
+    );
+}
+
+#[tokio::test]
+async fn messages_belong_to_their_author_not_its_name() {
+    start_server(39911).await;
+
+    let (mut alice, mut alice_buf, _) = login(39911, "alice").await;
+    let (mut bob, mut bob_buf, _) = login(39911, "bob").await;
+    send_message(&mut alice, &mut alice_buf, "hello", None).await;
+    drop(alice);
+    read_until(&mut bob, &mut bob_buf, |p| matches!(p, Packet::UserLeftServer { .. })).await.unwrap();
+
+    // Taking the name of the author once it left gives no right over its messages
+    let rename = Packet::ChangeNicknameRequest { request_id: 6, username: "alice".to_string() };
+    let response =
+        request(&mut bob, &mut bob_buf, rename, |p| matches!(p, Packet::ChangeNicknameResponse { .. })).await;
+    assert_eq!(response, Packet::ChangeNicknameResponse { request_id: 6, rejection: None });
+    assert!(!edit_message(&mut bob, &mut bob_buf, 1, "hacked").await);
+    assert!(!delete_message(&mut bob, &mut bob_buf, 1).await);
+
+    let (mut moderator, mut moderator_buf, _) = login(39911, "mod").await;
+    assert!(delete_message(&mut moderator, &mut moderator_buf, 1).await);
+}
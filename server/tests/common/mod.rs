@@ -0,0 +1,206 @@
+//! Harness shared by the integration tests: servers on local ports and a client talking to them.
+
+// Every test file uses a different part of the harness
+#![allow(dead_code)]
+
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpStream, UdpSocket};
+use tokio::sync::mpsc::UnboundedReceiver;
+use voiceapp_protocol::auth::{compute_proof, derive_key};
+use voiceapp_protocol::crypto::{VoiceCipher, VoiceDirection, VOICE_KEY_LEN};
+use voiceapp_protocol::{Capabilities, Framing, Packet, ParticipantInfo, PROTOCOL_VERSION};
+use voiceapp_server::{Authenticator, Event, Limits, ManagementServer, UsernamePolicy, VoiceRelayServer};
+
+/// Framing of packets after the handshake
+pub const FRAMING: Framing = Framing::for_version(PROTOCOL_VERSION);
+
+/// What a client learns from its `LoginResponse`
+pub struct LoginInfo {
+    pub id: u64,
+    pub voice_token: u64,
+    pub voice_key: [u8; VOICE_KEY_LEN],
+    pub participants: Vec<ParticipantInfo>,
+}
+
+/// Address of `port` on the loopback interface
+pub fn local(port: u16) -> SocketAddr {
+    SocketAddr::from(([127, 0, 0, 1], port))
+}
+
+/// Management server with a single voice channel and no password, and the events for its voice relay
+pub fn management_server(limits: Limits) -> (ManagementServer, UnboundedReceiver<Event>) {
+    ManagementServer::new(vec!["General".to_string()], Authenticator::default(), limits, UsernamePolicy::default())
+}
+
+/// Runs `server` on `port` and waits until it accepts connections
+pub async fn start(port: u16, server: ManagementServer) {
+    tokio::spawn(async move { server.run(local(port)).await });
+    wait_for_server(port).await;
+}
+
+/// Runs `relay` on `port`
+pub fn start_voice_relay(port: u16, mut relay: VoiceRelayServer) {
+    tokio::spawn(async move { relay.run(local(port)).await });
+}
+
+/// Waits until a server accepts connections on `port`
+pub async fn wait_for_server(port: u16) {
+    for _ in 0..50 {
+        if TcpStream::connect(local(port)).await.is_ok() {
+            return;
+        }
+        tokio::time::sleep(Duration::from_millis(20)).await;
+    }
+    panic!("server did not start");
+}
+
+/// Reads packets until `predicate` matches, `None` on EOF or timeout
+pub async fn read_until(
+    stream: &mut TcpStream,
+    buf: &mut Vec<u8>,
+    predicate: impl Fn(&Packet) -> bool,
+) -> Option<Packet> {
+    read_framed_until(stream, buf, FRAMING, predicate).await
+}
+
+/// Like [`read_until`] with the given framing, the handshake is always read with standard framing
+pub async fn read_framed_until(
+    stream: &mut TcpStream,
+    buf: &mut Vec<u8>,
+    framing: Framing,
+    predicate: impl Fn(&Packet) -> bool,
+) -> Option<Packet> {
+    let mut read_buf = [0u8; 1024];
+    loop {
+        while let Ok((packet, size)) = Packet::decode_with(buf, framing) {
+            buf.drain(..size);
+            if predicate(&packet) {
+                return Some(packet);
+            }
+        }
+
+        let n = tokio::time::timeout(Duration::from_secs(2), stream.read(&mut read_buf)).await.ok()?.ok()?;
+        if n == 0 {
+            return None;
+        }
+        buf.extend_from_slice(&read_buf[..n]);
+    }
+}
+
+/// Sends `packet` and returns the first packet matching `predicate`
+pub async fn request(
+    stream: &mut TcpStream,
+    buf: &mut Vec<u8>,
+    packet: Packet,
+    predicate: impl Fn(&Packet) -> bool,
+) -> Packet {
+    stream.write_all(&packet.encode_with(FRAMING).unwrap()).await.unwrap();
+    read_until(stream, buf, predicate).await.unwrap()
+}
+
+/// Sends `Hello` announcing `capabilities` and returns the `ServerHello`
+pub async fn hello(stream: &mut TcpStream, buf: &mut Vec<u8>, capabilities: Capabilities) -> Packet {
+    let hello = Packet::Hello { request_id: 1, protocol_version: PROTOCOL_VERSION, capabilities };
+    stream.write_all(&hello.encode().unwrap()).await.unwrap();
+    read_framed_until(stream, buf, Framing::Standard, |p| matches!(p, Packet::ServerHello { .. })).await.unwrap()
+}
+
+/// Says hello with every capability, sends a login request and returns the `LoginResponse` or `LoginRejected`
+pub async fn try_login(stream: &mut TcpStream, buf: &mut Vec<u8>, username: &str) -> Packet {
+    hello(stream, buf, Capabilities::SUPPORTED).await;
+    let login = Packet::LoginRequest { request_id: 2, username: username.to_string(), auth_proof: None };
+    request(stream, buf, login, |p| matches!(p, Packet::LoginResponse { .. } | Packet::LoginRejected { .. })).await
+}
+
+/// Like [`try_login`] with a proof of `password` for the server's challenge
+pub async fn try_login_with_password(
+    stream: &mut TcpStream,
+    buf: &mut Vec<u8>,
+    username: &str,
+    password: &str,
+) -> Packet {
+    let Packet::ServerHello { auth_challenge, auth_salt, .. } = hello(stream, buf, Capabilities::SUPPORTED).await
+    else {
+        unreachable!()
+    };
+    let auth_proof = Some(compute_proof(&derive_key(username, password, &auth_salt), &auth_challenge));
+    let login = Packet::LoginRequest { request_id: 2, username: username.to_string(), auth_proof };
+    request(stream, buf, login, |p| matches!(p, Packet::LoginResponse { .. } | Packet::LoginRejected { .. })).await
+}
+
+/// Logs in with every capability and returns the stream, its read buffer and the user id
+pub async fn login(port: u16, username: &str) -> (TcpStream, Vec<u8>, u64) {
+    let (stream, buf, info) = login_with(port, username, Capabilities::SUPPORTED).await;
+    (stream, buf, info.id)
+}
+
+/// Logs in announcing `capabilities` and returns the stream, its read buffer and what the login response told
+pub async fn login_with(port: u16, username: &str, capabilities: Capabilities) -> (TcpStream, Vec<u8>, LoginInfo) {
+    let mut stream = TcpStream::connect(local(port)).await.unwrap();
+    let mut buf = Vec::new();
+
+    hello(&mut stream, &mut buf, capabilities).await;
+    let login = Packet::LoginRequest { request_id: 2, username: username.to_string(), auth_proof: None };
+    let Packet::LoginResponse { id, voice_token, voice_key, participants, .. } =
+        request(&mut stream, &mut buf, login, |p| matches!(p, Packet::LoginResponse { .. })).await
+    else {
+        unreachable!()
+    };
+
+    (stream, buf, LoginInfo { id, voice_token, voice_key, participants })
+}
+
+/// A user logged in, in the first voice channel and authenticated on the relay
+pub struct VoiceUser {
+    pub stream: TcpStream,
+    pub buf: Vec<u8>,
+    pub id: u64,
+    pub cipher: VoiceCipher,
+    pub voice: UdpSocket,
+}
+
+/// Logs in, joins the first voice channel and authenticates on the relay
+pub async fn join_voice(management_port: u16, voice_port: u16, username: &str) -> VoiceUser {
+    let (mut stream, mut buf, info) = login_with(management_port, username, Capabilities::SUPPORTED).await;
+
+    let join = Packet::JoinVoiceChannelRequest { request_id: 3, channel_id: 1 };
+    let joined = request(&mut stream, &mut buf, join, |p| matches!(p, Packet::JoinVoiceChannelResponse { .. })).await;
+    assert_eq!(joined, Packet::JoinVoiceChannelResponse { request_id: 3, success: true });
+
+    let voice = connect_voice(voice_port, info.voice_token).await;
+    VoiceUser { stream, buf, id: info.id, cipher: VoiceCipher::new(&info.voice_key), voice }
+}
+
+/// Binds a voice socket and authenticates it on the relay at `port`
+pub async fn connect_voice(port: u16, voice_token: u64) -> UdpSocket {
+    let voice = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    voice.connect(local(port)).await.unwrap();
+    voice.send(&Packet::VoiceAuthRequest { request_id: 4, voice_token }.encode().unwrap()).await.unwrap();
+    assert_eq!(receive_voice(&voice).await, Some(Packet::VoiceAuthResponse { request_id: 4, success: true }));
+    voice
+}
+
+/// Returns the next datagram from the relay, `None` if nothing arrives in time
+pub async fn receive_voice(voice: &UdpSocket) -> Option<Packet> {
+    let mut datagram = [0u8; 1024];
+    let n = tokio::time::timeout(Duration::from_millis(500), voice.recv(&mut datagram)).await.ok()?.ok()?;
+    Some(Packet::decode(&datagram[..n]).unwrap().0)
+}
+
+/// Sends `frame` as the voice packet numbered `sequence`
+pub async fn speak(user: &VoiceUser, sequence: u32, frame: &[u8]) {
+    let data = user.cipher.seal(VoiceDirection::ToRelay, user.id, sequence, sequence * 960, frame);
+    let packet = Packet::VoiceData { user_id: user.id, sequence, timestamp: sequence * 960, data };
+    user.voice.send(&packet.encode().unwrap()).await.unwrap();
+}
+
+/// Path named after `name` and this test process in the temp dir, with nothing there yet
+pub fn temp_path(name: &str) -> PathBuf {
+    let path = std::env::temp_dir().join(format!("voiceapp-{}-{}", std::process::id(), name));
+    let _ = std::fs::remove_file(&path);
+    let _ = std::fs::remove_dir_all(&path);
+    path
+}
@@ -0,0 +1,157 @@
+//! Loading and validating TOML config files.
+
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use voiceapp_protocol::{Permissions, Role};
+use voiceapp_server::{
+    AttachmentsConfig, Config, Limits, RolesConfig, ServerError, TlsConfig, UsernamePolicy, DEFAULT_CHAT_HISTORY_LEN,
+    DEFAULT_PACKET_BUFFER_SIZE,
+};
+
+fn write_config(name: &str, contents: &str) -> PathBuf {
+    let path = std::env::temp_dir().join(format!("voiceapp-server-config-{}-{}.toml", name, std::process::id()));
+    std::fs::write(&path, contents).unwrap();
+    path
+}
+
+fn validation_error(config: &Config) -> String {
+    match config.validate() {
+        Err(ServerError::InvalidConfig(message)) => message,
+        other => panic!("expected InvalidConfig, got {other:?}"),
+    }
+}
+
+#[test]
+fn loads_partial_file_over_defaults() {
+    let path = write_config(
+        "partial",
+        r#"
+management_bind = "[::]:7001"
+voice_channels = ["standup", "afk"]
+username_policy = "case-sensitive"
+motd = "Welcome"
+
+[auth]
+password = "s3cret"
+
+[chat]
+history_file = "chat.log"
+
+[limits]
+max_users = 50
+"#,
+    );
+
+    let config = Config::load(&path).unwrap();
+    config.validate().unwrap();
+
+    assert_eq!(config.management_bind, "[::]:7001".parse::<SocketAddr>().unwrap());
+    assert_eq!(config.voice_bind, Config::default().voice_bind);
+    assert_eq!(config.voice_channels, ["standup", "afk"]);
+    assert_eq!(config.username_policy, UsernamePolicy::CaseSensitive);
+    assert_eq!(config.motd.as_deref(), Some("Welcome"));
+    assert_eq!(config.auth.password.as_deref(), Some("s3cret"));
+    assert_eq!(config.chat.history_file, Some(PathBuf::from("chat.log")));
+    assert_eq!(config.chat.history_len, DEFAULT_CHAT_HISTORY_LEN);
+    assert_eq!(config.limits.max_users, Some(50));
+    assert_eq!(config.limits.packet_buffer_size, DEFAULT_PACKET_BUFFER_SIZE);
+}
+
+#[test]
+fn loads_roles() {
+    let path = write_config(
+        "roles",
+        r#"
+[roles]
+default = "guest"
+admins = ["Alice"]
+members = ["bob"]
+
+[roles.permissions]
+guest = ["chat"]
+"#,
+    );
+
+    let config = Config::load(&path).unwrap();
+    config.validate().unwrap();
+
+    let roles = config.roles.roles();
+    assert_eq!(roles.role_of("alice"), Role::Admin);
+    assert_eq!(roles.role_of("BOB"), Role::Member);
+    assert_eq!(roles.role_of("carol"), Role::Guest);
+    assert_eq!(roles.permissions(Role::Guest), Permissions::CHAT);
+    assert!(roles.permissions(Role::Member).contains(Permissions::SPEAK));
+    assert!(!roles.permissions(Role::Member).contains(Permissions::MODERATE));
+    assert_eq!(roles.permissions(Role::Admin), Permissions::ALL);
+
+    let path = write_config("roles-unknown", "[roles]\ndefault = \"owner\"\n");
+    let error = Config::load(&path).unwrap_err().to_string();
+    assert!(error.contains("owner"), "{}", error);
+}
+
+#[test]
+fn reports_unknown_keys_with_location() {
+    let path = write_config("unknown", "[limits]\nmax_user = 5\n");
+
+    let error = Config::load(&path).unwrap_err().to_string();
+    assert!(error.contains("line 2"), "{}", error);
+    assert!(error.contains("max_user"), "{}", error);
+}
+
+#[test]
+fn rejects_unusable_values() {
+    let config = Config {
+        limits: Limits { broadcast_channel_capacity: 0, ..Limits::default() },
+        ..Config::default()
+    };
+    assert!(validation_error(&config).contains("broadcast_channel_capacity"));
+
+    let config = Config {
+        limits: Limits { idle_timeout_ms: 1000, ..Limits::default() },
+        ..Config::default()
+    };
+    assert!(validation_error(&config).contains("idle_timeout_ms"));
+
+    let config = Config {
+        limits: Limits { typing_timeout_ms: 2000, ..Limits::default() },
+        ..Config::default()
+    };
+    assert!(validation_error(&config).contains("typing_timeout_ms"));
+
+    let config = Config {
+        limits: Limits { max_chat_message_len: 65_000, ..Limits::default() },
+        ..Config::default()
+    };
+    assert!(validation_error(&config).contains("max_chat_message_len"));
+
+    let config = Config {
+        attachments: AttachmentsConfig { max_size: 2048, capacity: 1024, ..AttachmentsConfig::default() },
+        ..Config::default()
+    };
+    assert!(validation_error(&config).contains("attachments.max_size"));
+
+    let config = Config {
+        voice_channels: vec!["Lobby".to_string(), "lobby".to_string()],
+        ..Config::default()
+    };
+    assert!(validation_error(&config).contains("listed twice"));
+
+    let config = Config { log_level: "verbose".to_string(), ..Config::default() };
+    assert!(validation_error(&config).contains("log_level"));
+
+    let config = Config {
+        tls: TlsConfig { cert_file: Some(PathBuf::from("cert.pem")), key_file: None },
+        ..Config::default()
+    };
+    assert!(validation_error(&config).contains("set together"));
+
+    let config = Config {
+        roles: RolesConfig {
+            admins: vec!["alice".to_string()],
+            guests: vec!["Alice".to_string()],
+            ..RolesConfig::default()
+        },
+        ..Config::default()
+    };
+    assert!(validation_error(&config).contains("more than one role"));
+}
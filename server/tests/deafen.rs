@@ -0,0 +1,42 @@
+//! Deafen: the voice relay stops forwarding voice to deafened users.
+
+use voiceapp_protocol::crypto::VoiceDirection;
+use voiceapp_protocol::Packet;
+use voiceapp_server::{Limits, VoiceRelayServer};
+
+mod common;
+use common::{join_voice, management_server, receive_voice, request, speak, start, start_voice_relay, VoiceUser};
+
+async fn start_servers(management_port: u16, voice_port: u16) {
+    let (management_server, events_rx) = management_server(Limits::default());
+    start_voice_relay(voice_port, VoiceRelayServer::new(events_rx));
+    start(management_port, management_server).await;
+}
+
+async fn set_deafened(user: &mut VoiceUser, request_id: u64, is_deafened: bool) {
+    let packet = Packet::SetMuteStateRequest { request_id, is_muted: false, is_deafened };
+    let response =
+        request(&mut user.stream, &mut user.buf, packet, |p| matches!(p, Packet::SetMuteStateResponse { .. })).await;
+    assert_eq!(response, Packet::SetMuteStateResponse { request_id, is_muted: false, is_deafened });
+}
+
+#[tokio::test]
+async fn voice_is_not_forwarded_to_deafened_users() {
+    start_servers(39691, 39692).await;
+
+    let alice = join_voice(39691, 39692, "alice").await;
+    let mut bob = join_voice(39691, 39692, "bob").await;
+
+    set_deafened(&mut bob, 5, true).await;
+    speak(&alice, 1, b"unheard").await;
+    assert_eq!(receive_voice(&bob.voice).await, None);
+
+    set_deafened(&mut bob, 6, false).await;
+    speak(&alice, 2, b"heard").await;
+    let Some(Packet::VoiceData { user_id, sequence, timestamp, data }) = receive_voice(&bob.voice).await else {
+        panic!("voice was not forwarded");
+    };
+    assert_eq!(user_id, alice.id);
+    let frame = bob.cipher.open(VoiceDirection::FromRelay, user_id, sequence, timestamp, &data).unwrap();
+    assert_eq!(frame, b"heard");
+}
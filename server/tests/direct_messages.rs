@@ -0,0 +1,70 @@
+//! Direct messages: delivered to the target user only, and only to clients that accept them.
+
+use tokio::io::AsyncWriteExt;
+use tokio::net::TcpStream;
+use voiceapp_protocol::{Capabilities, Packet};
+use voiceapp_server::Limits;
+
+mod common;
+use common::{login, login_with, management_server, read_until, request, start, FRAMING};
+
+async fn start_server(port: u16) {
+    start(port, management_server(Limits::default()).0).await;
+}
+
+/// Sends a direct message and returns whether it was delivered
+async fn send_direct_message(stream: &mut TcpStream, buf: &mut Vec<u8>, user_id: u64, message: &str) -> bool {
+    let packet = Packet::DirectMessageRequest { request_id: 3, user_id, message: message.to_string() };
+    match request(stream, buf, packet, |p| matches!(p, Packet::DirectMessageResponse { .. })).await {
+        Packet::DirectMessageResponse { success, timestamp, .. } => {
+            assert_eq!(success, timestamp > 0);
+            success
+        }
+        _ => panic!("no DirectMessageResponse"),
+    }
+}
+
+#[tokio::test]
+async fn direct_messages_reach_only_the_target() {
+    start_server(39751).await;
+
+    let (mut alice, mut alice_buf, alice_id) = login(39751, "alice").await;
+    let (mut bob, mut bob_buf, bob_id) = login(39751, "bob").await;
+    let (mut carol, mut carol_buf, _) = login(39751, "carol").await;
+
+    assert!(send_direct_message(&mut alice, &mut alice_buf, bob_id, "psst").await);
+    let received = read_until(&mut bob, &mut bob_buf, |p| matches!(p, Packet::DirectMessageReceived { .. })).await;
+    let Some(Packet::DirectMessageReceived { from_user_id, message, .. }) = received else {
+        panic!("direct message was not delivered");
+    };
+    assert_eq!((from_user_id, message.as_str()), (alice_id, "psst"));
+
+    // A chat message sent afterwards reaches carol, the direct message never does
+    let chat = Packet::ChatMessageRequest {
+        request_id: 4,
+        message: "hello all".to_string(),
+        reply_to: None,
+        attachments: Vec::new(),
+    };
+    alice.write_all(&chat.encode_with(FRAMING).unwrap()).await.unwrap();
+    let seen = read_until(&mut carol, &mut carol_buf, |p| {
+        matches!(p, Packet::DirectMessageReceived { .. } | Packet::UserSentMessage { .. })
+    })
+    .await;
+    assert!(matches!(seen, Some(Packet::UserSentMessage { .. })));
+
+    // Unknown users and the sender itself cannot be messaged
+    assert!(!send_direct_message(&mut alice, &mut alice_buf, 999, "hello?").await);
+    assert!(!send_direct_message(&mut alice, &mut alice_buf, alice_id, "note to self").await);
+}
+
+#[tokio::test]
+async fn clients_without_direct_messages_are_not_messaged() {
+    start_server(39761).await;
+
+    let capabilities = Capabilities::from_bits(Capabilities::SUPPORTED.bits() & !Capabilities::DIRECT_MESSAGES.bits());
+    let (mut alice, mut alice_buf, _) = login(39761, "alice").await;
+    let (_old_client, _, old_client) = login_with(39761, "bob", capabilities).await;
+
+    assert!(!send_direct_message(&mut alice, &mut alice_buf, old_client.id, "psst").await);
+}
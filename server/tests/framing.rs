@@ -0,0 +1,113 @@
+//! Extended framing: clients speaking a newer protocol version get packets larger than 64 KiB,
+//! older clients keep standard framing and smaller pages. Packets that cannot be decoded close the connection.
+
+use tokio::io::AsyncWriteExt;
+use tokio::net::TcpStream;
+use voiceapp_protocol::{Capabilities, Framing, Packet, MIN_PROTOCOL_VERSION, PROTOCOL_VERSION};
+use voiceapp_server::Limits;
+
+mod common;
+use common::{local, management_server, read_framed_until, read_until, start, FRAMING};
+
+const MESSAGE_LEN: usize = 20_000;
+const MESSAGE_COUNT: usize = 5;
+
+async fn start_server(port: u16) {
+    let limits = Limits { max_chat_message_len: MESSAGE_LEN, ..Limits::default() };
+    let (management_server, _events_rx) = management_server(limits);
+    start(port, management_server).await;
+}
+
+/// Logs in speaking `protocol_version` and returns the stream, its read buffer and the framing after the handshake
+async fn login(port: u16, username: &str, protocol_version: u16) -> (TcpStream, Vec<u8>, Framing) {
+    let mut stream = TcpStream::connect(local(port)).await.unwrap();
+    let mut buf = Vec::new();
+
+    let hello = Packet::Hello { request_id: 1, protocol_version, capabilities: Capabilities::SUPPORTED };
+    stream.write_all(&hello.encode().unwrap()).await.unwrap();
+    let Some(Packet::ServerHello { protocol_version: server_version, accepted: true, .. }) =
+        read_framed_until(&mut stream, &mut buf, Framing::Standard, |p| matches!(p, Packet::ServerHello { .. })).await
+    else {
+        panic!("hello rejected");
+    };
+    let framing = Framing::for_version(protocol_version.min(server_version));
+
+    let login = Packet::LoginRequest { request_id: 2, username: username.to_string(), auth_proof: None };
+    stream.write_all(&login.encode_with(framing).unwrap()).await.unwrap();
+    read_framed_until(&mut stream, &mut buf, framing, |p| matches!(p, Packet::LoginResponse { .. })).await.unwrap();
+
+    (stream, buf, framing)
+}
+
+/// Requests the newest messages and returns how many came back, `has_more` and the size of the response
+async fn history(stream: &mut TcpStream, buf: &mut Vec<u8>, framing: Framing) -> (usize, bool, usize) {
+    let request = Packet::ChatHistoryRequest { request_id: 4, before: None, after: None, limit: 100 };
+    stream.write_all(&request.encode_with(framing).unwrap()).await.unwrap();
+    match read_framed_until(stream, buf, framing, |p| matches!(p, Packet::ChatHistoryResponse { .. })).await {
+        Some(response @ Packet::ChatHistoryResponse { .. }) => {
+            let size = response.encode_with(framing).unwrap().len();
+            let Packet::ChatHistoryResponse { messages, has_more, .. } = response else { unreachable!() };
+            (messages.len(), has_more, size)
+        }
+        other => panic!("expected ChatHistoryResponse, got {other:?}"),
+    }
+}
+
+#[tokio::test]
+async fn large_history_pages_need_extended_framing() {
+    start_server(39861).await;
+    let (mut alice, mut alice_buf, framing) = login(39861, "alice", PROTOCOL_VERSION).await;
+    assert_eq!(framing, Framing::Extended);
+
+    for i in 0..MESSAGE_COUNT {
+        let message = char::from(b'a' + u8::try_from(i).unwrap()).to_string().repeat(MESSAGE_LEN);
+        let request = Packet::ChatMessageRequest { request_id: 3, message, reply_to: None, attachments: Vec::new() };
+        alice.write_all(&request.encode_with(framing).unwrap()).await.unwrap();
+        read_framed_until(&mut alice, &mut alice_buf, framing, |p| matches!(p, Packet::UserSentMessage { .. }))
+            .await
+            .unwrap();
+    }
+
+    // All messages fit in one page bigger than a standard packet
+    let (count, has_more, size) = history(&mut alice, &mut alice_buf, framing).await;
+    assert_eq!(count, MESSAGE_COUNT);
+    assert!(!has_more);
+    assert!(size > usize::from(u16::MAX));
+
+    // An older client gets the newest messages that fit and pages for the rest
+    let (mut bob, mut bob_buf, framing) = login(39861, "bob", MIN_PROTOCOL_VERSION).await;
+    assert_eq!(framing, Framing::Standard);
+    let (count, has_more, size) = history(&mut bob, &mut bob_buf, framing).await;
+    assert_eq!(count, 3);
+    assert!(has_more);
+    assert!(u16::try_from(size).is_ok());
+
+    // It keeps standard framing for everything after the handshake
+    bob.write_all(&Packet::PingRequest { request_id: 5 }.encode_with(framing).unwrap()).await.unwrap();
+    let pong = read_framed_until(&mut bob, &mut bob_buf, framing, |p| matches!(p, Packet::PingResponse { .. })).await;
+    assert!(pong.is_some());
+}
+
+#[tokio::test]
+async fn undecodable_packets_close_the_connection() {
+    start_server(39862).await;
+    let (mut alice, mut alice_buf, _) = common::login(39862, "alice").await;
+
+    // A complete packet whose payload ends too early, followed by a valid one
+    let (mut bob, mut bob_buf, bob_id) = common::login(39862, "bob").await;
+    let ping = Packet::PingRequest { request_id: 3 }.encode_with(FRAMING).unwrap();
+    let mut truncated = ping[..FRAMING.header_len() + 2].to_vec();
+    truncated[FRAMING.header_len() - 1] = 2; // Payload length, big-endian
+    bob.write_all(&[truncated, ping].concat()).await.unwrap();
+    assert_eq!(read_until(&mut bob, &mut bob_buf, |p| matches!(p, Packet::PingResponse { .. })).await, None);
+    let left = read_until(&mut alice, &mut alice_buf, |p| matches!(p, Packet::UserLeftServer { .. })).await;
+    assert_eq!(left, Some(Packet::UserLeftServer { user_id: bob_id }));
+
+    // A packet id nobody knows
+    let (mut carol, _, carol_id) = common::login(39862, "carol").await;
+    let mut unknown = vec![0u8; FRAMING.header_len()];
+    unknown[0] = 0xff;
+    carol.write_all(&unknown).await.unwrap();
+    let left = read_until(&mut alice, &mut alice_buf, |p| matches!(p, Packet::UserLeftServer { .. })).await;
+    assert_eq!(left, Some(Packet::UserLeftServer { user_id: carol_id }));
+}
@@ -0,0 +1,75 @@
+//! Handshake: the protocol version and capabilities are negotiated once, before anything else.
+
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+use voiceapp_protocol::{Capabilities, Framing, Packet, MIN_PROTOCOL_VERSION, PROTOCOL_VERSION};
+use voiceapp_server::Limits;
+
+mod common;
+use common::{local, login, login_with, management_server, read_framed_until, read_until, request, start, FRAMING};
+
+/// Says hello speaking `protocol_version` and returns the `ServerHello`
+async fn hello_with_version(stream: &mut TcpStream, buf: &mut Vec<u8>, protocol_version: u16) -> Packet {
+    let hello = Packet::Hello { request_id: 1, protocol_version, capabilities: Capabilities::SUPPORTED };
+    stream.write_all(&hello.encode().unwrap()).await.unwrap();
+    read_framed_until(stream, buf, Framing::Standard, |p| matches!(p, Packet::ServerHello { .. })).await.unwrap()
+}
+
+/// Returns true once the server closed the connection
+async fn is_closed(stream: &mut TcpStream) -> bool {
+    let read = tokio::time::timeout(std::time::Duration::from_secs(2), stream.read(&mut [0u8; 1024])).await;
+    matches!(read, Ok(Ok(0) | Err(_)))
+}
+
+#[tokio::test]
+async fn too_old_versions_are_refused_and_newer_ones_speak_ours() {
+    let (management_server, _events_rx) = management_server(Limits::default());
+    start(39981, management_server).await;
+
+    let mut stream = TcpStream::connect(local(39981)).await.unwrap();
+    let response = hello_with_version(&mut stream, &mut Vec::new(), MIN_PROTOCOL_VERSION - 1).await;
+    assert!(matches!(response, Packet::ServerHello { accepted: false, protocol_version: PROTOCOL_VERSION, .. }));
+    assert!(is_closed(&mut stream).await);
+
+    let mut stream = TcpStream::connect(local(39981)).await.unwrap();
+    let response = hello_with_version(&mut stream, &mut Vec::new(), PROTOCOL_VERSION + 1).await;
+    assert!(matches!(response, Packet::ServerHello { accepted: true, protocol_version: PROTOCOL_VERSION, .. }));
+}
+
+#[tokio::test]
+async fn only_capabilities_both_sides_support_are_used() {
+    let (management_server, _events_rx) = management_server(Limits::default());
+    start(39982, management_server.with_motd("Welcome".to_string())).await;
+
+    // Bits the server does not know are ignored, the ones it knows still apply
+    let (mut alice, mut alice_buf, _) = login_with(39982, "alice", Capabilities::from_bits(u32::MAX)).await;
+    let motd = read_until(&mut alice, &mut alice_buf, |p| matches!(p, Packet::Motd { .. })).await;
+    assert_eq!(motd, Some(Packet::Motd { message: "Welcome".to_string() }));
+
+    // Features the client did not announce are not used
+    let without_motd = Capabilities::from_bits(Capabilities::SUPPORTED.bits() & !Capabilities::MOTD.bits());
+    let (mut bob, mut bob_buf, _) = login_with(39982, "bob", without_motd).await;
+    let list = Packet::ListChannelsRequest { request_id: 3 };
+    let response = request(&mut bob, &mut bob_buf, list, |p| {
+        matches!(p, Packet::Motd { .. } | Packet::ListChannelsResponse { .. })
+    })
+    .await;
+    assert!(matches!(response, Packet::ListChannelsResponse { .. }), "expected ListChannelsResponse, got {response:?}");
+}
+
+#[tokio::test]
+async fn second_hello_closes_the_connection() {
+    let (management_server, _events_rx) = management_server(Limits::default());
+    start(39983, management_server).await;
+
+    let (mut alice, mut alice_buf, _) = login(39983, "alice").await;
+    let (mut bob, mut bob_buf, bob_id) = login(39983, "bob").await;
+
+    let again =
+        Packet::Hello { request_id: 3, protocol_version: MIN_PROTOCOL_VERSION, capabilities: Capabilities::NONE };
+    bob.write_all(&again.encode_with(FRAMING).unwrap()).await.unwrap();
+    assert_eq!(read_until(&mut bob, &mut bob_buf, |p| matches!(p, Packet::ServerHello { .. })).await, None);
+
+    let left = read_until(&mut alice, &mut alice_buf, |p| matches!(p, Packet::UserLeftServer { .. })).await;
+    assert_eq!(left, Some(Packet::UserLeftServer { user_id: bob_id }));
+}
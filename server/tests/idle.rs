@@ -0,0 +1,60 @@
+//! Idle timeouts: clients sending heartbeats are disconnected once they go quiet.
+
+use std::time::Duration;
+use tokio::io::AsyncWriteExt;
+use tokio::net::TcpStream;
+use voiceapp_protocol::{Capabilities, Packet};
+use voiceapp_server::{Limits, VoiceRelayServer};
+
+mod common;
+use common::{connect_voice, login, login_with, management_server, read_until, start, start_voice_relay, FRAMING};
+
+const IDLE_TIMEOUT: Duration = Duration::from_millis(300);
+
+async fn start_servers(management_port: u16, voice_port: u16) {
+    let (management_server, events_rx) = management_server(Limits { idle_timeout_ms: 300, ..Limits::default() });
+    let disconnect = management_server.disconnect_handle();
+    start_voice_relay(voice_port, VoiceRelayServer::new(events_rx).with_idle_timeout(IDLE_TIMEOUT, disconnect));
+    start(management_port, management_server).await;
+}
+
+/// Sends a ping and waits for the response, false once the connection is closed
+async fn ping(stream: &mut TcpStream, buf: &mut Vec<u8>) -> bool {
+    if stream.write_all(&Packet::PingRequest { request_id: 3 }.encode_with(FRAMING).unwrap()).await.is_err() {
+        return false;
+    }
+    read_until(stream, buf, |p| matches!(p, Packet::PingResponse { .. })).await.is_some()
+}
+
+#[tokio::test]
+async fn silent_client_is_disconnected() {
+    start_servers(39501, 39502).await;
+
+    let (mut alice, mut alice_buf, alice_id) = login(39501, "alice").await;
+    let (mut legacy, mut legacy_buf, _) = login_with(39501, "bob", Capabilities::NONE).await;
+
+    let left = read_until(&mut legacy, &mut legacy_buf, |p| matches!(p, Packet::UserLeftServer { .. })).await;
+    assert_eq!(left, Some(Packet::UserLeftServer { user_id: alice_id }));
+    assert_eq!(read_until(&mut alice, &mut alice_buf, |_| false).await, None);
+
+    // Clients without the capability never time out
+    tokio::time::sleep(IDLE_TIMEOUT * 2).await;
+    assert!(ping(&mut legacy, &mut legacy_buf).await);
+}
+
+#[tokio::test]
+async fn quiet_voice_session_disconnects_user() {
+    start_servers(39511, 39512).await;
+
+    let (mut alice, mut alice_buf, info) = login_with(39511, "alice", Capabilities::SUPPORTED).await;
+    let _voice = connect_voice(39512, info.voice_token).await;
+
+    // The management connection stays busy, the voice socket does not
+    let closed = tokio::time::timeout(Duration::from_secs(3), async {
+        while ping(&mut alice, &mut alice_buf).await {
+            tokio::time::sleep(IDLE_TIMEOUT / 3).await;
+        }
+    })
+    .await;
+    assert!(closed.is_ok(), "connection was not closed");
+}
@@ -0,0 +1,215 @@
+//! Moderation: kicks, bans that outlive the connection and the bans file, unbans, and server mutes.
+
+use tokio::io::AsyncWriteExt;
+use tokio::net::TcpStream;
+use voiceapp_protocol::{LoginRejectReason, ModerationAction, ModerationRejectReason, Packet, Role};
+use voiceapp_server::{Limits, Moderation, Roles};
+
+mod common;
+use common::{login, management_server, read_until, request, start, temp_path, try_login, FRAMING};
+
+async fn start_server(port: u16, roles: Roles, moderation: Moderation) {
+    let (management_server, _events_rx) = management_server(Limits::default());
+    start(port, management_server.with_roles(roles).with_moderation(moderation)).await;
+}
+
+/// Sends a moderation request and returns the rejection from its response
+async fn moderate(stream: &mut TcpStream, buf: &mut Vec<u8>, packet: Packet) -> Option<ModerationRejectReason> {
+    match request(stream, buf, packet, |p| matches!(p, Packet::ModerationResponse { .. })).await {
+        Packet::ModerationResponse { rejection, .. } => rejection,
+        other => panic!("expected ModerationResponse, got {other:?}"),
+    }
+}
+
+#[tokio::test]
+async fn moderator_kicks_user() {
+    start_server(39601, Roles::default().with_user("Mod", Role::Moderator), Moderation::default()).await;
+
+    let (mut moderator, mut moderator_buf, moderator_id) = login(39601, "mod").await;
+    let (mut bob, mut bob_buf, bob_id) = login(39601, "bob").await;
+
+    let kick = Packet::KickUserRequest { request_id: 3, user_id: bob_id, reason: "spam".to_string() };
+    assert_eq!(moderate(&mut moderator, &mut moderator_buf, kick).await, None);
+
+    let moderated = read_until(&mut bob, &mut bob_buf, |p| matches!(p, Packet::UserModerated { .. })).await;
+    assert_eq!(
+        moderated,
+        Some(Packet::UserModerated {
+            user_id: bob_id,
+            moderator_id,
+            action: ModerationAction::Kicked,
+            reason: "spam".to_string(),
+        })
+    );
+    assert_eq!(read_until(&mut bob, &mut bob_buf, |_| false).await, None);
+
+    let left = read_until(&mut moderator, &mut moderator_buf, |p| matches!(p, Packet::UserLeftServer { .. })).await;
+    assert_eq!(left, Some(Packet::UserLeftServer { user_id: bob_id }));
+
+    // A kick is not a ban
+    login(39601, "bob").await;
+}
+
+#[tokio::test]
+async fn ban_is_saved_and_refuses_login() {
+    let bans_file = temp_path("bans.txt");
+    let moderation = Moderation::default().with_bans_file(&bans_file).unwrap();
+    start_server(39611, Roles::default().with_user("mod", Role::Moderator), moderation).await;
+
+    let (mut moderator, mut moderator_buf, _) = login(39611, "mod").await;
+    let (mut bob, mut bob_buf, bob_id) = login(39611, "bob").await;
+
+    let ban = Packet::BanUserRequest { request_id: 3, user_id: bob_id, reason: String::new(), ban_address: false };
+    assert_eq!(moderate(&mut moderator, &mut moderator_buf, ban).await, None);
+    assert_eq!(read_until(&mut bob, &mut bob_buf, |_| false).await, None);
+
+    let mut stream = TcpStream::connect(("127.0.0.1", 39611)).await.unwrap();
+    let mut buf = Vec::new();
+    assert_eq!(
+        try_login(&mut stream, &mut buf, "BOB").await,
+        Packet::LoginRejected { request_id: 2, reason: LoginRejectReason::Banned }
+    );
+
+    // The ban survives a restart through the bans file
+    assert_eq!(std::fs::read_to_string(&bans_file).unwrap(), "user:bob\n");
+    let reloaded = Moderation::default().with_bans_file(&bans_file).unwrap();
+    assert!(reloaded.is_banned("bob", "10.0.0.1".parse().unwrap()));
+    assert!(!reloaded.is_banned("carol", "10.0.0.1".parse().unwrap()));
+
+    std::fs::remove_file(&bans_file).unwrap();
+}
+
+#[tokio::test]
+async fn server_mute_outlasts_reconnect() {
+    start_server(39621, Roles::default().with_user("mod", Role::Moderator), Moderation::default()).await;
+
+    let (mut moderator, mut moderator_buf, _) = login(39621, "mod").await;
+    let (mut bob, mut bob_buf, bob_id) = login(39621, "bob").await;
+
+    let mute = Packet::ServerMuteUserRequest { request_id: 3, user_id: bob_id, muted: true };
+    assert_eq!(moderate(&mut moderator, &mut moderator_buf, mute).await, None);
+    let muted = read_until(&mut bob, &mut bob_buf, |p| matches!(p, Packet::UserMuteState { .. })).await;
+    assert_eq!(muted, Some(Packet::UserMuteState { user_id: bob_id, is_muted: true, is_deafened: false }));
+
+    // Unmuting itself is refused while server-muted
+    let unmute = Packet::SetMuteStateRequest { request_id: 4, is_muted: false, is_deafened: false };
+    bob.write_all(&unmute.encode_with(FRAMING).unwrap()).await.unwrap();
+    let applied = read_until(&mut bob, &mut bob_buf, |p| matches!(p, Packet::SetMuteStateResponse { .. })).await;
+    assert_eq!(applied, Some(Packet::SetMuteStateResponse { request_id: 4, is_muted: true, is_deafened: false }));
+    drop(bob);
+    read_until(&mut moderator, &mut moderator_buf, |p| matches!(p, Packet::UserLeftServer { .. })).await.unwrap();
+
+    let (_bob, _, bob_id) = login(39621, "bob").await;
+    let mut carol = TcpStream::connect(("127.0.0.1", 39621)).await.unwrap();
+    let mut carol_buf = Vec::new();
+    let Packet::LoginResponse { participants, .. } = try_login(&mut carol, &mut carol_buf, "carol").await else {
+        panic!("login failed");
+    };
+    assert!(participants.iter().any(|p| p.user_id == bob_id && p.is_muted));
+}
+
+#[tokio::test]
+async fn only_moderators_may_moderate() {
+    let roles = Roles::default().with_user("mod", Role::Moderator).with_user("mod2", Role::Moderator);
+    start_server(39631, roles, Moderation::default()).await;
+
+    let (mut moderator, mut moderator_buf, _) = login(39631, "mod").await;
+    let (_mod2, _, mod2_id) = login(39631, "mod2").await;
+    let (mut bob, mut bob_buf, _) = login(39631, "bob").await;
+    let (_carol, _, carol_id) = login(39631, "carol").await;
+
+    let kick = Packet::KickUserRequest { request_id: 3, user_id: carol_id, reason: String::new() };
+    assert_eq!(moderate(&mut bob, &mut bob_buf, kick).await, Some(ModerationRejectReason::NotPermitted));
+
+    // Moderators cannot act on each other
+    let ban = Packet::BanUserRequest { request_id: 4, user_id: mod2_id, reason: String::new(), ban_address: true };
+    assert_eq!(moderate(&mut moderator, &mut moderator_buf, ban).await, Some(ModerationRejectReason::NotPermitted));
+
+    let mute = Packet::ServerMuteUserRequest { request_id: 5, user_id: u64::MAX, muted: true };
+    assert_eq!(moderate(&mut moderator, &mut moderator_buf, mute).await, Some(ModerationRejectReason::UnknownUser));
+}
+
+#[tokio::test]
+async fn unban_lifts_username_and_address_bans() {
+    let bans_file = temp_path("unbans.txt");
+    std::fs::write(&bans_file, "# Kept when entries are removed\n").unwrap();
+    let moderation = Moderation::default().with_bans_file(&bans_file).unwrap();
+    let roles = Roles::default().with_user("mod", Role::Moderator).with_user("boss", Role::Admin);
+    start_server(39951, roles, moderation).await;
+
+    let (mut moderator, mut moderator_buf, _) = login(39951, "mod").await;
+    let (mut bob, mut bob_buf, bob_id) = login(39951, "bob").await;
+
+    // Only moderators may unban, and only names of a lower role
+    let unban = Packet::UnbanUserRequest { request_id: 4, username: "mod".to_string() };
+    assert_eq!(moderate(&mut bob, &mut bob_buf, unban).await, Some(ModerationRejectReason::NotPermitted));
+    let unban = Packet::UnbanUserRequest { request_id: 5, username: "boss".to_string() };
+    assert_eq!(moderate(&mut moderator, &mut moderator_buf, unban).await, Some(ModerationRejectReason::NotPermitted));
+
+    let ban = Packet::BanUserRequest { request_id: 3, user_id: bob_id, reason: String::new(), ban_address: true };
+    assert_eq!(moderate(&mut moderator, &mut moderator_buf, ban).await, None);
+    let contents = std::fs::read_to_string(&bans_file).unwrap();
+    assert_eq!(contents, "# Kept when entries are removed\nuser:bob\nip:127.0.0.1 bob\n");
+    let mut stream = TcpStream::connect(("127.0.0.1", 39951)).await.unwrap();
+    let response = try_login(&mut stream, &mut Vec::new(), "carol").await;
+    assert_eq!(response, Packet::LoginRejected { request_id: 2, reason: LoginRejectReason::Banned });
+
+    let unban = Packet::UnbanUserRequest { request_id: 6, username: "BOB".to_string() };
+    assert_eq!(moderate(&mut moderator, &mut moderator_buf, unban).await, None);
+    assert_eq!(std::fs::read_to_string(&bans_file).unwrap(), "# Kept when entries are removed\n");
+    login(39951, "bob").await;
+
+    let unban = Packet::UnbanUserRequest { request_id: 7, username: "bob".to_string() };
+    assert_eq!(moderate(&mut moderator, &mut moderator_buf, unban).await, Some(ModerationRejectReason::UnknownUser));
+
+    std::fs::remove_file(&bans_file).unwrap();
+}
+
+#[tokio::test]
+async fn server_mute_is_saved_in_bans_file() {
+    let bans_file = temp_path("mutes.txt");
+    let moderation = Moderation::default().with_bans_file(&bans_file).unwrap();
+    start_server(39961, Roles::default().with_user("mod", Role::Moderator), moderation).await;
+
+    let (mut moderator, mut moderator_buf, _) = login(39961, "mod").await;
+    let (_bob, _, bob_id) = login(39961, "Bob").await;
+
+    let mute = Packet::ServerMuteUserRequest { request_id: 3, user_id: bob_id, muted: true };
+    assert_eq!(moderate(&mut moderator, &mut moderator_buf, mute).await, None);
+    assert_eq!(std::fs::read_to_string(&bans_file).unwrap(), "mute:bob\n");
+    assert!(Moderation::default().with_bans_file(&bans_file).unwrap().is_server_muted("BOB"));
+
+    let unmute = Packet::ServerMuteUserRequest { request_id: 4, user_id: bob_id, muted: false };
+    assert_eq!(moderate(&mut moderator, &mut moderator_buf, unmute).await, None);
+    assert_eq!(std::fs::read_to_string(&bans_file).unwrap(), "");
+    assert!(!Moderation::default().with_bans_file(&bans_file).unwrap().is_server_muted("bob"));
+
+    std::fs::remove_file(&bans_file).unwrap();
+}
+
+/// Asks to change the username and returns the rejection from the response
+async fn rename(stream: &mut TcpStream, buf: &mut Vec<u8>, username: &str) -> Option<LoginRejectReason> {
+    let packet = Packet::ChangeNicknameRequest { request_id: 4, username: username.to_string() };
+    match request(stream, buf, packet, |p| matches!(p, Packet::ChangeNicknameResponse { .. })).await {
+        Packet::ChangeNicknameResponse { rejection, .. } => rejection,
+        other => panic!("expected ChangeNicknameResponse, got {other:?}"),
+    }
+}
+
+#[tokio::test]
+async fn server_muted_user_keeps_its_name() {
+    start_server(39952, Roles::default().with_user("mod", Role::Moderator), Moderation::default()).await;
+
+    let (mut moderator, mut moderator_buf, _) = login(39952, "mod").await;
+    let (mut bob, mut bob_buf, bob_id) = login(39952, "bob").await;
+    let mute = Packet::ServerMuteUserRequest { request_id: 3, user_id: bob_id, muted: true };
+    assert_eq!(moderate(&mut moderator, &mut moderator_buf, mute).await, None);
+
+    // Logging in again under a new name would lift the mute, changing the case keeps it
+    assert_eq!(rename(&mut bob, &mut bob_buf, "robert").await, Some(LoginRejectReason::ServerMuted));
+    assert_eq!(rename(&mut bob, &mut bob_buf, "Bob").await, None);
+
+    let unmute = Packet::ServerMuteUserRequest { request_id: 5, user_id: bob_id, muted: false };
+    assert_eq!(moderate(&mut moderator, &mut moderator_buf, unmute).await, None);
+    assert_eq!(rename(&mut bob, &mut bob_buf, "robert").await, None);
+}
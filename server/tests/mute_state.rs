@@ -0,0 +1,56 @@
+//! Mute state: set by each client for itself with `SetMuteStateRequest`, never claimed for another user.
+
+use tokio::io::AsyncWriteExt;
+use voiceapp_protocol::{Capabilities, Packet};
+use voiceapp_server::Limits;
+
+mod common;
+use common::{login, login_with, management_server, read_until, start, LoginInfo, FRAMING};
+
+async fn start_server(port: u16) {
+    start(port, management_server(Limits::default()).0).await;
+}
+
+#[tokio::test]
+async fn mute_state_is_applied_to_the_sender() {
+    start_server(39671).await;
+
+    let (mut alice, mut alice_buf, alice_id) = login(39671, "alice").await;
+    let (mut bob, mut bob_buf, bob_id) = login(39671, "bob").await;
+
+    let request = Packet::SetMuteStateRequest { request_id: 3, is_muted: true, is_deafened: true };
+    bob.write_all(&request.encode_with(FRAMING).unwrap()).await.unwrap();
+    let response = read_until(&mut bob, &mut bob_buf, |p| matches!(p, Packet::SetMuteStateResponse { .. })).await;
+    assert_eq!(response, Some(Packet::SetMuteStateResponse { request_id: 3, is_muted: true, is_deafened: true }));
+
+    let event = read_until(&mut alice, &mut alice_buf, |p| matches!(p, Packet::UserMuteState { .. })).await;
+    assert_eq!(event, Some(Packet::UserMuteState { user_id: bob_id, is_muted: true, is_deafened: true }));
+
+    let (_, _, LoginInfo { participants, .. }) = login_with(39671, "carol", Capabilities::SUPPORTED).await;
+    let bob = participants.iter().find(|p| p.user_id == bob_id).unwrap();
+    assert!(bob.is_muted);
+    assert!(bob.is_deafened);
+    let alice = participants.iter().find(|p| p.user_id == alice_id).unwrap();
+    assert!(!alice.is_muted);
+    assert!(!alice.is_deafened);
+}
+
+#[tokio::test]
+async fn claimed_mute_state_of_another_user_is_ignored() {
+    start_server(39681).await;
+
+    let (_alice, _alice_buf, alice_id) = login(39681, "alice").await;
+    let (mut mallory, mut mallory_buf, _) = login(39681, "mallory").await;
+
+    let spoofed = Packet::UserMuteState { user_id: alice_id, is_muted: true, is_deafened: true };
+    mallory.write_all(&spoofed.encode_with(FRAMING).unwrap()).await.unwrap();
+
+    // Requests are handled in order, so the spoofed event was processed once the ping is answered
+    mallory.write_all(&Packet::PingRequest { request_id: 3 }.encode_with(FRAMING).unwrap()).await.unwrap();
+    read_until(&mut mallory, &mut mallory_buf, |p| matches!(p, Packet::PingResponse { .. })).await.unwrap();
+
+    let (_, _, LoginInfo { participants, .. }) = login_with(39681, "carol", Capabilities::SUPPORTED).await;
+    let alice = participants.iter().find(|p| p.user_id == alice_id).unwrap();
+    assert!(!alice.is_muted);
+    assert!(!alice.is_deafened);
+}
@@ -0,0 +1,98 @@
+//! Rich chat: `@username` mentions resolved by the server and emoji reactions kept with the messages.
+
+use tokio::io::AsyncWriteExt;
+use tokio::net::TcpStream;
+use voiceapp_protocol::{Packet, Reaction};
+use voiceapp_server::Limits;
+
+mod common;
+use common::{login, management_server, read_until, request, start, FRAMING};
+
+async fn start_server(port: u16) {
+    start(port, management_server(Limits::default()).0).await;
+}
+
+async fn send_message(stream: &mut TcpStream, buf: &mut Vec<u8>, message: &str) {
+    let packet = Packet::ChatMessageRequest {
+        request_id: 3,
+        message: message.to_string(),
+        reply_to: None,
+        attachments: Vec::new(),
+    };
+    request(stream, buf, packet, |p| matches!(p, Packet::ChatMessageResponse { .. })).await;
+}
+
+/// Adds or removes a reaction and returns whether the server changed it
+async fn set_reaction(stream: &mut TcpStream, buf: &mut Vec<u8>, message_id: u64, emoji: &str, reacted: bool) -> bool {
+    let packet = Packet::SetReactionRequest { request_id: 4, message_id, emoji: emoji.to_string(), reacted };
+    match request(stream, buf, packet, |p| matches!(p, Packet::SetReactionResponse { .. })).await {
+        Packet::SetReactionResponse { success, .. } => success,
+        _ => panic!("no SetReactionResponse"),
+    }
+}
+
+#[tokio::test]
+async fn mentions_resolve_to_logged_in_users() {
+    start_server(39801).await;
+
+    let (mut alice, mut alice_buf, _) = login(39801, "alice").await;
+    let (mut bob, mut bob_buf, bob_id) = login(39801, "bob").await;
+    let (_bobby, _, bobby_id) = login(39801, "bobby").await;
+
+    // Names are compared ignoring case, the longest name wins and users who are not logged in are skipped
+    send_message(&mut alice, &mut alice_buf, "hey @BOB, @bobby and @carol! mail@bob.com @bobcat").await;
+    let Some(Packet::UserSentMessage { mentions, .. }) =
+        read_until(&mut bob, &mut bob_buf, |p| matches!(p, Packet::UserSentMessage { .. })).await
+    else {
+        panic!("message was not broadcast");
+    };
+    assert_eq!(mentions, vec![bob_id, bobby_id]);
+}
+
+#[tokio::test]
+async fn reactions_are_relayed_and_kept() {
+    start_server(39811).await;
+
+    let (mut alice, mut alice_buf, _) = login(39811, "alice").await;
+    let (mut bob, mut bob_buf, bob_id) = login(39811, "bob").await;
+
+    send_message(&mut alice, &mut alice_buf, "lunch?").await;
+
+    assert!(set_reaction(&mut bob, &mut bob_buf, 1, "👍", true).await);
+    let changed = read_until(&mut alice, &mut alice_buf, |p| matches!(p, Packet::MessageReactionChanged { .. })).await;
+    assert_eq!(
+        changed,
+        Some(Packet::MessageReactionChanged { message_id: 1, user_id: bob_id, emoji: "👍".to_string(), reacted: true })
+    );
+
+    // Reacting twice, removing a missing reaction, unknown messages and invalid emoji change nothing
+    assert!(!set_reaction(&mut bob, &mut bob_buf, 1, "👍", true).await);
+    assert!(!set_reaction(&mut bob, &mut bob_buf, 1, "🎉", false).await);
+    assert!(!set_reaction(&mut bob, &mut bob_buf, 99, "👍", true).await);
+    assert!(!set_reaction(&mut bob, &mut bob_buf, 1, "", true).await);
+    assert!(!set_reaction(&mut bob, &mut bob_buf, 1, "two words", true).await);
+
+    assert!(set_reaction(&mut alice, &mut alice_buf, 1, "👍", true).await);
+    assert!(set_reaction(&mut alice, &mut alice_buf, 1, "🎉", true).await);
+    assert!(set_reaction(&mut bob, &mut bob_buf, 1, "👍", false).await);
+    let removed = read_until(&mut alice, &mut alice_buf, |p| {
+        matches!(p, Packet::MessageReactionChanged { reacted: false, .. })
+    })
+    .await;
+    assert!(matches!(removed, Some(Packet::MessageReactionChanged { user_id, .. }) if user_id == bob_id));
+
+    let packet = Packet::ChatHistoryRequest { request_id: 5, before: None, after: None, limit: 10 };
+    bob.write_all(&packet.encode_with(FRAMING).unwrap()).await.unwrap();
+    let Some(Packet::ChatHistoryResponse { messages, .. }) =
+        read_until(&mut bob, &mut bob_buf, |p| matches!(p, Packet::ChatHistoryResponse { .. })).await
+    else {
+        panic!("no ChatHistoryResponse");
+    };
+    assert_eq!(
+        messages[0].reactions,
+        vec![
+            Reaction::new("👍".to_string(), vec!["alice".to_string()]),
+            Reaction::new("🎉".to_string(), vec!["alice".to_string()]),
+        ]
+    );
+}
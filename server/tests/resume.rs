@@ -0,0 +1,103 @@
+//! Session resume: a client reconnecting with its resume token keeps its user id, and listeners keep hearing it.
+
+use std::time::Duration;
+use tokio::net::TcpStream;
+use voiceapp_protocol::crypto::{ReplayWindow, VoiceCipher, VoiceDirection};
+use voiceapp_protocol::{Capabilities, Packet};
+use voiceapp_server::{Limits, VoiceRelayServer};
+
+mod common;
+use common::{
+    connect_voice, hello, join_voice, local, management_server, read_until, receive_voice, request, speak, start,
+    start_voice_relay, VoiceUser,
+};
+
+/// Connects, resuming the session of `token` if given, logs in as `username` and joins the first voice channel.
+/// Returns the user and the token to resume its session with.
+async fn join_resumable(management_port: u16, voice_port: u16, username: &str, token: Option<u64>) -> (VoiceUser, u64) {
+    let mut stream = TcpStream::connect(local(management_port)).await.unwrap();
+    let mut buf = Vec::new();
+    hello(&mut stream, &mut buf, Capabilities::SUPPORTED).await;
+
+    if let Some(token) = token {
+        let resume = Packet::ResumeSessionRequest { request_id: 5, token };
+        let response =
+            request(&mut stream, &mut buf, resume, |p| matches!(p, Packet::ResumeSessionResponse { .. })).await;
+        assert_eq!(response, Packet::ResumeSessionResponse { request_id: 5, accepted: true });
+    }
+
+    let login = Packet::LoginRequest { request_id: 6, username: username.to_string(), auth_proof: None };
+    let Packet::LoginResponse { id, voice_token, voice_key, .. } =
+        request(&mut stream, &mut buf, login, |p| matches!(p, Packet::LoginResponse { .. })).await
+    else {
+        unreachable!()
+    };
+    let Some(Packet::ResumeToken { token }) =
+        read_until(&mut stream, &mut buf, |p| matches!(p, Packet::ResumeToken { .. })).await
+    else {
+        panic!("no resume token");
+    };
+
+    let join = Packet::JoinVoiceChannelRequest { request_id: 7, channel_id: 1 };
+    let joined = request(&mut stream, &mut buf, join, |p| matches!(p, Packet::JoinVoiceChannelResponse { .. })).await;
+    assert_eq!(joined, Packet::JoinVoiceChannelResponse { request_id: 7, success: true });
+
+    let voice = connect_voice(voice_port, voice_token).await;
+    (VoiceUser { stream, buf, id, cipher: VoiceCipher::new(&voice_key), voice }, token)
+}
+
+/// Receives the next voice packet like a client would, `None` if it was not heard or dropped as a replay
+async fn hear(listener: &VoiceUser, replay_window: &mut ReplayWindow) -> Option<(u64, Vec<u8>)> {
+    let Some(Packet::VoiceData { user_id, sequence, timestamp, data }) = receive_voice(&listener.voice).await else {
+        return None;
+    };
+    let frame = listener.cipher.open(VoiceDirection::FromRelay, user_id, sequence, timestamp, &data).ok()?;
+    replay_window.accept(sequence).then_some((user_id, frame))
+}
+
+#[tokio::test]
+async fn listeners_keep_hearing_a_user_who_resumes_mid_talk() {
+    let (management_server, events_rx) = management_server(Limits::default());
+    start_voice_relay(39942, VoiceRelayServer::new(events_rx));
+    start(39941, management_server).await;
+
+    let (alice, token) = join_resumable(39941, 39942, "alice", None).await;
+    let bob = join_voice(39941, 39942, "bob").await;
+
+    let mut replay_window = ReplayWindow::new();
+    for sequence in 0..3 {
+        speak(&alice, sequence, b"before").await;
+        assert_eq!(hear(&bob, &mut replay_window).await, Some((alice.id, b"before".to_vec())));
+    }
+
+    // The connection drops and the client comes back, numbering its packets from zero again
+    let id = alice.id;
+    drop(alice);
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    let (alice, _) = join_resumable(39941, 39942, "alice", Some(token)).await;
+    assert_eq!(alice.id, id);
+
+    for sequence in 0..3 {
+        speak(&alice, sequence, b"after").await;
+        assert_eq!(hear(&bob, &mut replay_window).await, Some((alice.id, b"after".to_vec())));
+    }
+}
+
+#[tokio::test]
+async fn only_a_login_with_the_same_name_takes_over_a_session() {
+    let (management_server, events_rx) = management_server(Limits::default());
+    start_voice_relay(39944, VoiceRelayServer::new(events_rx));
+    start(39943, management_server).await;
+
+    let (alice, token) = join_resumable(39943, 39944, "alice", None).await;
+    let id = alice.id;
+    drop(alice);
+    tokio::time::sleep(Duration::from_millis(100)).await;
+
+    // Someone else holding the token logs in under its own name, the session stays reserved for its owner
+    let (mallory, _) = join_resumable(39943, 39944, "mallory", Some(token)).await;
+    assert_ne!(mallory.id, id);
+
+    let (alice, _) = join_resumable(39943, 39944, "alice", Some(token)).await;
+    assert_eq!(alice.id, id);
+}
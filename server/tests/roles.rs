@@ -0,0 +1,175 @@
+//! Roles: permissions checked by the server and roles sent to clients in `ParticipantInfo`.
+
+use tokio::net::TcpStream;
+use voiceapp_protocol::{
+    Capabilities, ChatRejectReason, LoginRejectReason, ModerationRejectReason, Packet, Permissions, Role,
+};
+use voiceapp_server::{Authenticator, Credentials, Limits, ManagementServer, Roles, UsernamePolicy};
+
+mod common;
+use common::{
+    local, login, login_with, management_server, read_until, request, start, try_login_with_password, LoginInfo,
+};
+
+async fn start_server(port: u16, roles: Roles) {
+    let (management_server, _events_rx) = management_server(Limits::default());
+    start(port, management_server.with_roles(roles)).await;
+}
+
+#[tokio::test]
+async fn guest_chats_and_listens() {
+    start_server(39641, Roles::new(Role::Guest).with_user("alice", Role::Member)).await;
+
+    let (mut alice, mut alice_buf, LoginInfo { participants, .. }) =
+        login_with(39641, "alice", Capabilities::SUPPORTED).await;
+    assert_eq!(participants[0].role, Role::Member);
+    assert!(participants[0].permissions.contains(Permissions::SPEAK));
+
+    let (mut guest, mut guest_buf, guest_id) = login(39641, "visitor").await;
+    let joined = read_until(&mut alice, &mut alice_buf, |p| matches!(p, Packet::UserJoinedServer { .. })).await;
+    let Some(Packet::UserJoinedServer { participant }) = joined else {
+        panic!("expected UserJoinedServer");
+    };
+    assert_eq!(participant.role, Role::Guest);
+    assert_eq!(participant.permissions, Permissions::CHAT | Permissions::JOIN_VOICE);
+    assert!(participant.is_muted);
+
+    let response = request(
+        &mut guest,
+        &mut guest_buf,
+        Packet::ChatMessageRequest {
+            request_id: 3,
+            message: "hi".to_string(),
+            reply_to: None,
+            attachments: Vec::new(),
+        },
+        |p| matches!(p, Packet::ChatMessageResponse { .. }),
+    )
+    .await;
+    assert_eq!(response, Packet::ChatMessageResponse { request_id: 3, message_id: 1, rejection: None });
+
+    let response = request(
+        &mut guest,
+        &mut guest_buf,
+        Packet::CreateChannelRequest { request_id: 4, name: "Lounge".to_string() },
+        |p| matches!(p, Packet::CreateChannelResponse { .. }),
+    )
+    .await;
+    assert_eq!(response, Packet::CreateChannelResponse { request_id: 4, success: false, channel_id: 0 });
+
+    let response = request(
+        &mut guest,
+        &mut guest_buf,
+        Packet::JoinVoiceChannelRequest { request_id: 5, channel_id: 1 },
+        |p| matches!(p, Packet::JoinVoiceChannelResponse { .. }),
+    )
+    .await;
+    assert_eq!(response, Packet::JoinVoiceChannelResponse { request_id: 5, success: true });
+    let muted = read_until(&mut alice, &mut alice_buf, |p| matches!(p, Packet::UserMuteState { .. })).await;
+    assert_eq!(muted, Some(Packet::UserMuteState { user_id: guest_id, is_muted: true, is_deafened: false }));
+
+    // Guests cannot speak, unmuting is refused
+    let response = request(
+        &mut guest,
+        &mut guest_buf,
+        Packet::SetMuteStateRequest { request_id: 6, is_muted: false, is_deafened: false },
+        |p| matches!(p, Packet::SetMuteStateResponse { .. }),
+    )
+    .await;
+    assert_eq!(response, Packet::SetMuteStateResponse { request_id: 6, is_muted: true, is_deafened: false });
+
+    let (_, _, LoginInfo { participants, .. }) = login_with(39641, "carol", Capabilities::SUPPORTED).await;
+    let guest = participants.iter().find(|p| p.user_id == guest_id).unwrap();
+    assert_eq!(guest.channel_id, Some(1));
+    assert!(guest.is_muted);
+}
+
+#[tokio::test]
+async fn configured_permissions_are_enforced() {
+    start_server(39651, Roles::new(Role::Guest).with_permissions(Role::Guest, Permissions::NONE)).await;
+
+    let (mut guest, mut guest_buf, _) = login(39651, "visitor").await;
+
+    let response = request(
+        &mut guest,
+        &mut guest_buf,
+        Packet::ChatMessageRequest {
+            request_id: 3,
+            message: "hi".to_string(),
+            reply_to: None,
+            attachments: Vec::new(),
+        },
+        |p| matches!(p, Packet::ChatMessageResponse { .. } | Packet::UserSentMessage { .. }),
+    )
+    .await;
+    let rejection = Some(ChatRejectReason::NotPermitted);
+    assert_eq!(response, Packet::ChatMessageResponse { request_id: 3, message_id: 0, rejection });
+
+    let response = request(
+        &mut guest,
+        &mut guest_buf,
+        Packet::JoinVoiceChannelRequest { request_id: 4, channel_id: 1 },
+        |p| matches!(p, Packet::JoinVoiceChannelResponse { .. }),
+    )
+    .await;
+    assert_eq!(response, Packet::JoinVoiceChannelResponse { request_id: 4, success: false });
+}
+
+#[tokio::test]
+async fn moderation_follows_rank() {
+    let roles = Roles::default().with_user("root", Role::Admin).with_user("mod", Role::Moderator);
+    start_server(39661, roles).await;
+
+    let (mut admin, mut admin_buf, admin_id) = login(39661, "root").await;
+    let (mut moderator, mut moderator_buf, moderator_id) = login(39661, "mod").await;
+
+    let response = request(
+        &mut moderator,
+        &mut moderator_buf,
+        Packet::KickUserRequest { request_id: 3, user_id: admin_id, reason: String::new() },
+        |p| matches!(p, Packet::ModerationResponse { .. }),
+    )
+    .await;
+    assert_eq!(
+        response,
+        Packet::ModerationResponse { request_id: 3, rejection: Some(ModerationRejectReason::NotPermitted) }
+    );
+
+    let response = request(
+        &mut admin,
+        &mut admin_buf,
+        Packet::KickUserRequest { request_id: 4, user_id: moderator_id, reason: String::new() },
+        |p| matches!(p, Packet::ModerationResponse { .. }),
+    )
+    .await;
+    assert_eq!(response, Packet::ModerationResponse { request_id: 4, rejection: None });
+    assert_eq!(read_until(&mut moderator, &mut moderator_buf, |_| false).await, None);
+}
+
+#[tokio::test]
+async fn shared_password_never_logs_in_as_a_credentialed_user() {
+    let credentials = Credentials::default().with_user("admin", "hunter2");
+    let authenticator = Authenticator::new(Some("shared".to_string()), credentials);
+    let (server, _events_rx) =
+        ManagementServer::new(vec!["General".to_string()], authenticator, Limits::default(), UsernamePolicy::default());
+    start(39871, server.with_roles(Roles::default().with_user("admin", Role::Admin))).await;
+
+    for username in ["admin", "Admin", "ADMIN"] {
+        let mut stream = TcpStream::connect(local(39871)).await.unwrap();
+        let response = try_login_with_password(&mut stream, &mut Vec::new(), username, "shared").await;
+        let rejected = Packet::LoginRejected { request_id: 2, reason: LoginRejectReason::InvalidCredentials };
+        assert_eq!(response, rejected, "{username} logged in with the shared password");
+    }
+
+    let mut admin = TcpStream::connect(local(39871)).await.unwrap();
+    let response = try_login_with_password(&mut admin, &mut Vec::new(), "admin", "hunter2").await;
+    let Packet::LoginResponse { participants, .. } = response else {
+        panic!("expected LoginResponse, got {response:?}");
+    };
+    assert_eq!(participants[0].role, Role::Admin);
+
+    // Everyone else still uses the shared password
+    let mut visitor = TcpStream::connect(local(39871)).await.unwrap();
+    let response = try_login_with_password(&mut visitor, &mut Vec::new(), "visitor", "shared").await;
+    assert!(matches!(response, Packet::LoginResponse { .. }), "expected LoginResponse, got {response:?}");
+}
@@ -0,0 +1,44 @@
+//! Graceful shutdown: connected clients are told why the connection closes and `run` returns.
+
+use std::time::Duration;
+use tokio::net::TcpStream;
+use voiceapp_protocol::{Capabilities, Packet};
+use voiceapp_server::{Limits, ShutdownNotice};
+
+mod common;
+use common::{local, login_with, management_server, read_until, wait_for_server};
+
+#[tokio::test]
+async fn clients_are_notified_and_run_returns() {
+    let port = 39301;
+    let (server, _events_rx) = management_server(Limits::default());
+    let shutdown = server.shutdown_handle();
+    let server_task = tokio::spawn(async move { server.run(local(port)).await });
+    wait_for_server(port).await;
+
+    let (mut alice, mut alice_buf, _) = login_with(port, "alice", Capabilities::SUPPORTED).await;
+    let (mut legacy, mut legacy_buf, _) = login_with(port, "bob", Capabilities::NONE).await;
+
+    shutdown.shutdown(ShutdownNotice {
+        reason: "Restarting for an update".to_string(),
+        reconnect_after: Duration::from_secs(3),
+    });
+
+    let notice = read_until(&mut alice, &mut alice_buf, |p| matches!(p, Packet::ServerShuttingDown { .. })).await;
+    assert_eq!(
+        notice,
+        Some(Packet::ServerShuttingDown {
+            reason: "Restarting for an update".to_string(),
+            reconnect_after_ms: 3000,
+        })
+    );
+    assert_eq!(read_until(&mut alice, &mut alice_buf, |_| true).await, None);
+
+    // Clients without the capability only see the connection close
+    let notice = read_until(&mut legacy, &mut legacy_buf, |p| matches!(p, Packet::ServerShuttingDown { .. })).await;
+    assert_eq!(notice, None);
+
+    let result = tokio::time::timeout(Duration::from_secs(2), server_task).await.expect("run did not return");
+    assert!(result.unwrap().is_ok());
+    assert!(TcpStream::connect(local(port)).await.is_err());
+}
@@ -0,0 +1,121 @@
+//! Management port over TLS with a self-signed certificate generated at test time.
+
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::net::TcpStream;
+use tokio_rustls::rustls::crypto::ring;
+use tokio_rustls::rustls::pki_types::ServerName;
+use tokio_rustls::rustls::{ClientConfig, RootCertStore};
+use tokio_rustls::TlsConnector;
+use voiceapp_protocol::{Capabilities, Packet, PROTOCOL_VERSION};
+use voiceapp_server::{load_tls_acceptor, Limits, ShutdownNotice};
+
+mod common;
+use common::{local, management_server, start, temp_path, wait_for_server};
+
+/// Writes a fresh self-signed certificate for localhost, returns (cert path, key path, DER)
+fn self_signed_cert(name: &str) -> (PathBuf, PathBuf, Vec<u8>) {
+    let certified = rcgen::generate_simple_self_signed(vec!["localhost".to_string()]).unwrap();
+    let dir = temp_path(&format!("tls-{name}"));
+    std::fs::create_dir_all(&dir).unwrap();
+
+    let cert_path = dir.join("cert.pem");
+    let key_path = dir.join("key.pem");
+    std::fs::write(&cert_path, certified.cert.pem()).unwrap();
+    std::fs::write(&key_path, certified.key_pair.serialize_pem()).unwrap();
+
+    (cert_path, key_path, certified.cert.der().to_vec())
+}
+
+/// Starts a TLS management server on `port` and waits until it accepts connections
+async fn start_server(port: u16, cert_path: &Path, key_path: &Path) {
+    let acceptor = load_tls_acceptor(cert_path, key_path).unwrap();
+    let (server, _events_rx) = management_server(Limits::default());
+    start(port, server.with_tls(acceptor)).await;
+}
+
+async fn exchange_hello<S: AsyncRead + AsyncWrite + Unpin>(stream: &mut S) -> Option<Packet> {
+    let capabilities = Capabilities::SUPPORTED;
+    let hello = Packet::Hello { request_id: 1, protocol_version: PROTOCOL_VERSION, capabilities };
+    stream.write_all(&hello.encode().unwrap()).await.ok()?;
+
+    let mut buf = Vec::new();
+    let mut read_buf = [0u8; 1024];
+    loop {
+        let n = tokio::time::timeout(Duration::from_secs(2), stream.read(&mut read_buf)).await.ok()?.ok()?;
+        if n == 0 {
+            return None;
+        }
+        buf.extend_from_slice(&read_buf[..n]);
+        if let Ok((packet, _)) = Packet::decode(&buf) {
+            return Some(packet);
+        }
+    }
+}
+
+#[tokio::test]
+async fn handshake_over_tls_with_trusted_certificate() {
+    let (cert_path, key_path, cert_der) = self_signed_cert("trusted");
+    start_server(39101, &cert_path, &key_path).await;
+
+    let mut roots = RootCertStore::empty();
+    roots.add(cert_der.into()).unwrap();
+    let config = ClientConfig::builder_with_provider(Arc::new(ring::default_provider()))
+        .with_safe_default_protocol_versions()
+        .unwrap()
+        .with_root_certificates(roots)
+        .with_no_client_auth();
+
+    let socket = TcpStream::connect(local(39101)).await.unwrap();
+    let mut stream = TlsConnector::from(Arc::new(config))
+        .connect(ServerName::try_from("localhost").unwrap(), socket)
+        .await
+        .unwrap();
+
+    match exchange_hello(&mut stream).await {
+        Some(Packet::ServerHello { request_id, accepted, .. }) => {
+            assert_eq!(request_id, 1);
+            assert!(accepted);
+        }
+        other => panic!("expected ServerHello, got {other:?}"),
+    }
+}
+
+#[tokio::test]
+async fn plaintext_client_is_not_served() {
+    let (cert_path, key_path, _) = self_signed_cert("plaintext");
+    start_server(39102, &cert_path, &key_path).await;
+
+    let mut socket = TcpStream::connect(local(39102)).await.unwrap();
+    assert_eq!(exchange_hello(&mut socket).await, None);
+}
+
+#[tokio::test]
+async fn stalled_handshake_does_not_hold_up_shutdown() {
+    let (cert_path, key_path, _) = self_signed_cert("stalled");
+    let acceptor = load_tls_acceptor(&cert_path, &key_path).unwrap();
+    let (server, _events_rx) = management_server(Limits::default());
+    let server = server.with_tls(acceptor);
+    let shutdown = server.shutdown_handle();
+    let server_task = tokio::spawn(async move { server.run(local(39103)).await });
+    wait_for_server(39103).await;
+
+    // Connects but never sends a ClientHello
+    let _socket = TcpStream::connect(local(39103)).await.unwrap();
+    tokio::time::sleep(Duration::from_millis(100)).await;
+
+    shutdown.shutdown(ShutdownNotice { reason: "Stopping".to_string(), reconnect_after: Duration::ZERO });
+    let result = tokio::time::timeout(Duration::from_secs(2), server_task).await.expect("run did not return");
+    assert!(result.unwrap().is_ok());
+}
+
+#[test]
+fn missing_key_file_is_reported() {
+    let (cert_path, _, _) = self_signed_cert("missing");
+    let missing = cert_path.with_file_name("does-not-exist.pem");
+
+    let error = load_tls_acceptor(&cert_path, &missing).err().expect("loading should fail");
+    assert!(error.to_string().contains("does-not-exist.pem"));
+}
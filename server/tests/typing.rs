@@ -0,0 +1,85 @@
+//! Typing indicators and read markers: relayed to other users, rate-limited and expired by the server.
+
+use tokio::io::AsyncWriteExt;
+use voiceapp_protocol::Packet;
+use voiceapp_server::Limits;
+
+mod common;
+use common::{login, management_server, read_until, start, FRAMING};
+
+async fn start_server(port: u16) {
+    let (management_server, _events_rx) = management_server(Limits { typing_timeout_ms: 300, ..Limits::default() });
+    start(port, management_server).await;
+}
+
+fn is_typing_event(packet: &Packet) -> bool {
+    matches!(packet, Packet::TypingStarted { .. } | Packet::TypingStopped { .. })
+}
+
+#[tokio::test]
+async fn typing_is_relayed_and_expires() {
+    start_server(39771).await;
+
+    let (mut alice, _, alice_id) = login(39771, "alice").await;
+    let (mut bob, mut bob_buf, _) = login(39771, "bob").await;
+
+    let typing = Packet::SetTypingRequest { request_id: 3, is_typing: true };
+    alice.write_all(&typing.encode_with(FRAMING).unwrap()).await.unwrap();
+    alice.write_all(&typing.encode_with(FRAMING).unwrap()).await.unwrap();
+    let started = read_until(&mut bob, &mut bob_buf, is_typing_event).await;
+    assert_eq!(started, Some(Packet::TypingStarted { user_id: alice_id }));
+
+    // Repeated requests only keep the indicator, it stops once they are missing for the typing timeout
+    let stopped = read_until(&mut bob, &mut bob_buf, is_typing_event).await;
+    assert_eq!(stopped, Some(Packet::TypingStopped { user_id: alice_id }));
+
+    // Starting again right away is not relayed, it is within the typing interval
+    alice.write_all(&typing.encode_with(FRAMING).unwrap()).await.unwrap();
+    let chat = Packet::ChatMessageRequest {
+        request_id: 4,
+        message: "hi".to_string(),
+        reply_to: None,
+        attachments: Vec::new(),
+    };
+    alice.write_all(&chat.encode_with(FRAMING).unwrap()).await.unwrap();
+    let seen =
+        read_until(&mut bob, &mut bob_buf, |p| is_typing_event(p) || matches!(p, Packet::UserSentMessage { .. })).await;
+    assert!(matches!(seen, Some(Packet::UserSentMessage { .. })));
+}
+
+#[tokio::test]
+async fn sending_stops_typing_and_read_markers_move_forward() {
+    start_server(39781).await;
+
+    let (mut alice, mut alice_buf, _) = login(39781, "alice").await;
+    let (mut bob, _, bob_id) = login(39781, "bob").await;
+
+    let typing = Packet::SetTypingRequest { request_id: 3, is_typing: true };
+    bob.write_all(&typing.encode_with(FRAMING).unwrap()).await.unwrap();
+    read_until(&mut alice, &mut alice_buf, is_typing_event).await.unwrap();
+
+    let chat = Packet::ChatMessageRequest {
+        request_id: 4,
+        message: "hello".to_string(),
+        reply_to: None,
+        attachments: Vec::new(),
+    };
+    bob.write_all(&chat.encode_with(FRAMING).unwrap()).await.unwrap();
+    let stopped = read_until(&mut alice, &mut alice_buf, |p| matches!(p, Packet::TypingStopped { .. })).await;
+    assert_eq!(stopped, Some(Packet::TypingStopped { user_id: bob_id }));
+
+    // Unknown messages and markers moving back are ignored
+    for message_id in [99, 1, 1] {
+        let mark_read = Packet::MarkReadRequest { request_id: 5, message_id };
+        bob.write_all(&mark_read.encode_with(FRAMING).unwrap()).await.unwrap();
+    }
+    bob.write_all(&chat.encode_with(FRAMING).unwrap()).await.unwrap();
+
+    let read = read_until(&mut alice, &mut alice_buf, |p| matches!(p, Packet::MessageRead { .. })).await;
+    assert_eq!(read, Some(Packet::MessageRead { user_id: bob_id, message_id: 1 }));
+    let next = read_until(&mut alice, &mut alice_buf, |p| {
+        matches!(p, Packet::MessageRead { .. } | Packet::UserSentMessage { message_id: 2, .. })
+    })
+    .await;
+    assert!(matches!(next, Some(Packet::UserSentMessage { .. })));
+}
@@ -0,0 +1,88 @@
+//! Usernames: unique names under the username policy and nickname changes checked like logins.
+
+use std::time::Duration;
+use tokio::net::TcpStream;
+use voiceapp_protocol::{LoginRejectReason, Packet};
+use voiceapp_server::{Authenticator, Credentials, Limits, ManagementServer, Moderation, UsernamePolicy};
+
+mod common;
+use common::{local, login, management_server, read_until, request, start, try_login, try_login_with_password};
+
+/// Asks to be renamed to `username` and returns the rejection, `None` if the name was changed
+async fn rename(stream: &mut TcpStream, buf: &mut Vec<u8>, username: &str) -> Option<LoginRejectReason> {
+    let packet = Packet::ChangeNicknameRequest { request_id: 3, username: username.to_string() };
+    match request(stream, buf, packet, |p| matches!(p, Packet::ChangeNicknameResponse { .. })).await {
+        Packet::ChangeNicknameResponse { rejection, .. } => rejection,
+        other => panic!("expected ChangeNicknameResponse, got {other:?}"),
+    }
+}
+
+#[tokio::test]
+async fn names_are_unique_until_released() {
+    let (management_server, _events_rx) = management_server(Limits::default());
+    start(39881, management_server).await;
+
+    let (mut alice, mut alice_buf, _) = login(39881, "alice").await;
+    let (mut bob, mut bob_buf, bob_id) = login(39881, "bob").await;
+
+    // Names differing only in case are the same user by default
+    let mut stream = TcpStream::connect(local(39881)).await.unwrap();
+    let response = try_login(&mut stream, &mut Vec::new(), "ALICE").await;
+    assert_eq!(response, Packet::LoginRejected { request_id: 2, reason: LoginRejectReason::NameTaken });
+    assert_eq!(rename(&mut bob, &mut bob_buf, "Alice").await, Some(LoginRejectReason::NameTaken));
+    assert_eq!(rename(&mut bob, &mut bob_buf, "").await, Some(LoginRejectReason::InvalidUsername));
+
+    assert_eq!(rename(&mut bob, &mut bob_buf, "carol").await, None);
+    let renamed = read_until(&mut alice, &mut alice_buf, |p| matches!(p, Packet::UserRenamed { .. })).await;
+    assert_eq!(renamed, Some(Packet::UserRenamed { user_id: bob_id, username: "carol".to_string() }));
+
+    // The old name is free once renamed, the name of a closed connection once it is gone
+    login(39881, "bob").await;
+    drop(alice);
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    login(39881, "alice").await;
+}
+
+#[tokio::test]
+async fn duplicates_are_allowed_by_policy() {
+    let (server, _events_rx) = ManagementServer::new(
+        vec!["General".to_string()],
+        Authenticator::default(),
+        Limits::default(),
+        UsernamePolicy::AllowDuplicates,
+    );
+    start(39891, server).await;
+
+    let (_alice, _, first) = login(39891, "alice").await;
+    let (mut other, mut other_buf, second) = login(39891, "alice").await;
+    assert_ne!(first, second);
+    assert_eq!(rename(&mut other, &mut other_buf, "alice").await, None);
+}
+
+#[tokio::test]
+async fn renames_are_checked_like_logins() {
+    let credentials = Credentials::default().with_user("admin", "hunter2");
+    let authenticator = Authenticator::new(Some("shared".to_string()), credentials);
+    let (server, _events_rx) =
+        ManagementServer::new(vec!["General".to_string()], authenticator, Limits::default(), UsernamePolicy::default());
+    let moderation = Moderation::default();
+    moderation.ban("mallory", None);
+    start(39901, server.with_moderation(moderation)).await;
+
+    let mut visitor = TcpStream::connect(local(39901)).await.unwrap();
+    let mut visitor_buf = Vec::new();
+    let response = try_login_with_password(&mut visitor, &mut visitor_buf, "visitor", "shared").await;
+    assert!(matches!(response, Packet::LoginResponse { .. }), "expected LoginResponse, got {response:?}");
+
+    // Taking a name with its own password or a banned name is refused whatever the case
+    assert_eq!(rename(&mut visitor, &mut visitor_buf, "Admin").await, Some(LoginRejectReason::InvalidCredentials));
+    assert_eq!(rename(&mut visitor, &mut visitor_buf, "MALLORY").await, Some(LoginRejectReason::Banned));
+    assert_eq!(rename(&mut visitor, &mut visitor_buf, "guest").await, None);
+
+    // Users logged in with their own password may still change the case of their name
+    let mut admin = TcpStream::connect(local(39901)).await.unwrap();
+    let mut admin_buf = Vec::new();
+    let response = try_login_with_password(&mut admin, &mut admin_buf, "admin", "hunter2").await;
+    assert!(matches!(response, Packet::LoginResponse { .. }), "expected LoginResponse, got {response:?}");
+    assert_eq!(rename(&mut admin, &mut admin_buf, "Admin").await, None);
+}